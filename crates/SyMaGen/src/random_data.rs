@@ -89,8 +89,10 @@ pub fn random_string(cardinality: usize, min_len: usize, max_len: usize, alphabe
 /// inside an n-dimensional ball of given radius.
 ///
 /// This function produces points in a uniform distribution inside the n-ball, and does
-/// so in linear time in the dimensionality of the ball. The algorithm is based on the
-/// method described in [this wikipedia article](https://en.wikipedia.org/wiki/N-sphere#Spherical_coordinates).
+/// so in linear time in the dimensionality of the ball. The direction is that of a
+/// vector of independent standard normal coordinates, which is uniform on the
+/// sphere, and the distance from the origin is `radius * u^(1/dim)` for a uniform
+/// `u`, so that the number of points within a distance `r` grows as `r^dim`.
 ///
 /// # Arguments:
 ///
@@ -102,27 +104,21 @@ pub fn random_string(cardinality: usize, min_len: usize, max_len: usize, alphabe
 ///
 /// * `Vec<T>`: the generated point
 pub fn n_ball<R: Rng>(dim: usize, radius: f64, rng: &mut R) -> Vec<f64> {
-    // sample random angles from 0 to 2π for the last angle and from 0 to π for the other angles.
-    let angles = {
-        let mut angles = (0..dim).map(|_| f64::next_random(rng) * PI).collect::<Vec<_>>();
-        angles[dim - 1] *= 2.;
-        angles
-    };
-
-    // The `scan` method is used to accumulate the product of the sine of the angles.
-    let sine_products = angles.iter().scan(1., |sine_product, &x| {
-        // each iteration, we'll multiply the state by the element ...
-        *sine_product *= f64::sin(x);
-        Some(*sine_product)
-    });
-
-    let cosines = angles.iter().map(|&x| f64::cos(x));
+    // sample standard normal coordinates with the Box-Muller transform.
+    let direction = (0..dim)
+        .map(|_| {
+            // `1 - x` is in (0, 1], so the logarithm is finite.
+            let u = 1. - f64::next_random(rng);
+            let v = f64::next_random(rng);
+            (-2. * u.ln()).sqrt() * (2. * PI * v).cos()
+        })
+        .collect::<Vec<_>>();
+    let norm = direction.iter().map(|x| x * x).sum::<f64>().sqrt();
 
-    // sample a random radius value from 0 to the given radius
-    let r = radius * f64::next_random(rng);
+    // sample a random radius value so that the volume, rather than the radius, is uniform.
+    let r = radius * f64::next_random(rng).powf(1. / dim.as_f64());
 
-    sine_products
-        .zip(cosines)
-        .map(|(sine_product, cosine)| r * sine_product * cosine)
-        .collect()
+    // the norm is zero only if every coordinate is, which is vanishingly rare.
+    let scale = if norm > 0. { r / norm } else { 0. };
+    direction.into_iter().map(|x| x * scale).collect()
 }
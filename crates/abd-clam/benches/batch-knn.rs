@@ -0,0 +1,65 @@
+use criterion::*;
+
+use rand::prelude::*;
+use symagen::random_data;
+
+use abd_clam::{cakes::knn, Cakes, PartitionCriteria, VecDataset};
+
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+fn batch(c: &mut Criterion) {
+    let seed = 42;
+    let (cardinality, dimensionality) = (100_000, 10);
+    let (min_val, max_val) = (-1., 1.);
+    let (num_groups, group_size, spread) = (10, 100, 0.05);
+
+    let data = random_data::random_tabular(
+        cardinality,
+        dimensionality,
+        min_val,
+        max_val,
+        &mut rand::rngs::StdRng::seed_from_u64(seed),
+    );
+
+    // Queries come in tight groups, as with reads from the same region of a
+    // genome, so that the tree over the queries has something to exploit.
+    let mut rng = rand::rngs::StdRng::seed_from_u64(seed + 1);
+    let queries = (0..num_groups)
+        .flat_map(|_| {
+            let center = data[rng.gen_range(0..cardinality)].clone();
+            random_data::random_tabular(group_size, dimensionality, -spread, spread, &mut rng)
+                .into_iter()
+                .map(move |offset| center.iter().zip(offset).map(|(&c, o)| c + o).collect::<Vec<_>>())
+        })
+        .collect::<Vec<_>>();
+    let queries = queries.iter().collect::<Vec<_>>();
+
+    let dataset = VecDataset::new("batch".to_string(), data, euclidean, false);
+    let cakes = Cakes::new(dataset, Some(seed), &PartitionCriteria::default());
+
+    let mut group = c.benchmark_group("batch-knn");
+    group
+        .sample_size(10)
+        .sampling_mode(SamplingMode::Flat)
+        .throughput(Throughput::Elements(queries.len() as u64))
+        .plot_config(PlotConfiguration::default().summary_scale(AxisScale::Logarithmic));
+
+    for k in (0..3).map(|v| 10_usize.pow(v)) {
+        let id = BenchmarkId::new("PerQuery", k);
+        group.bench_with_input(id, &k, |b, &k| {
+            b.iter_with_large_drop(|| cakes.batch_knn_search(&queries, k, knn::Algorithm::default()));
+        });
+
+        let id = BenchmarkId::new("DualTree", k);
+        group.bench_with_input(id, &k, |b, &k| {
+            b.iter_with_large_drop(|| cakes.batch_dual_tree_knn_search(&queries, k, Some(seed)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, batch);
+criterion_main!(benches);
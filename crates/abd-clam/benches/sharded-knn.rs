@@ -0,0 +1,76 @@
+use criterion::*;
+
+use rand::prelude::*;
+use symagen::random_data;
+
+use abd_clam::{
+    cakes::{knn, ShardStrategy},
+    Cakes, Dataset, PartitionCriteria, VecDataset,
+};
+
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::simd::euclidean_f32(x, y)
+}
+
+fn sharded(c: &mut Criterion) {
+    let seed = 42;
+    let (cardinality, dimensionality) = (100_000, 10);
+    let (min_val, max_val) = (-1., 1.);
+    let num_shards = 10;
+
+    let data = random_data::random_tabular(
+        cardinality,
+        dimensionality,
+        min_val,
+        max_val,
+        &mut rand::rngs::StdRng::seed_from_u64(seed),
+    );
+
+    let queries = random_data::random_tabular(
+        10,
+        dimensionality,
+        min_val,
+        max_val,
+        &mut rand::rngs::StdRng::seed_from_u64(seed + 1),
+    );
+    let queries = queries.iter().collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let max_cardinality = cardinality / num_shards;
+
+    let random_shards =
+        VecDataset::new("random".to_string(), data.clone(), euclidean, false).make_shards(max_cardinality);
+    let random_cakes = Cakes::new_randomly_sharded(random_shards, Some(seed), &criteria);
+
+    let cluster_data = VecDataset::new("cluster".to_string(), data, euclidean, false);
+    let cluster_cakes = Cakes::new_cluster_sharded(cluster_data, max_cardinality, Some(seed), &criteria);
+
+    for (name, mut cakes) in [("random", random_cakes), ("cluster", cluster_cakes)] {
+        let mut group = c.benchmark_group(format!("sharded-knn-{name}"));
+        group
+            .sampling_mode(SamplingMode::Flat)
+            .throughput(Throughput::Elements(queries.len() as u64))
+            .plot_config(PlotConfiguration::default().summary_scale(AxisScale::Logarithmic));
+
+        for strategy in [ShardStrategy::Sequential, ShardStrategy::Parallel] {
+            cakes.set_shard_strategy(strategy);
+
+            for k in (0..3).map(|v| 10_usize.pow(v)) {
+                let id = BenchmarkId::new(strategy.name(), k);
+                group.bench_with_input(id, &k, |b, &k| {
+                    b.iter_with_large_drop(|| {
+                        queries
+                            .iter()
+                            .map(|&q| cakes.knn_search(q, k, knn::Algorithm::default()))
+                            .collect::<Vec<_>>()
+                    });
+                });
+            }
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, sharded);
+criterion_main!(benches);
@@ -0,0 +1,260 @@
+//! Limits on the time and number of distance computations used by a search.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use std::time::Instant;
+
+use distances::Number;
+
+use crate::{Dataset, Instance};
+
+/// The number of instances to scan between checks of a limited budget.
+const CHUNK_SIZE: usize = 256;
+
+/// Limits on the work done by a single search.
+///
+/// When a search exhausts its budget, it stops and returns the best hits it
+/// has found so far. These are flagged as possibly incomplete in the
+/// `BudgetedHits`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Budget {
+    /// The maximum wall-clock time for the search.
+    deadline: Option<Duration>,
+    /// The maximum number of distance computations for the search.
+    max_distances: Option<usize>,
+}
+
+impl Budget {
+    /// Creates a budget with no limits.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            deadline: None,
+            max_distances: None,
+        }
+    }
+
+    /// Limits the wall-clock time for the search.
+    #[must_use]
+    pub const fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Limits the number of distance computations for the search.
+    #[must_use]
+    pub const fn with_max_distances(mut self, max_distances: usize) -> Self {
+        self.max_distances = Some(max_distances);
+        self
+    }
+
+    /// Returns the maximum wall-clock time for the search.
+    #[must_use]
+    pub const fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+
+    /// Returns the maximum number of distance computations for the search.
+    #[must_use]
+    pub const fn max_distances(&self) -> Option<usize> {
+        self.max_distances
+    }
+
+    /// Starts tracking the work done by a search against this budget.
+    #[must_use]
+    pub fn start(&self) -> Tracker {
+        Tracker {
+            deadline: self.deadline.map(|d| Instant::now() + d),
+            max_distances: self.max_distances,
+            num_distances: AtomicUsize::new(0),
+            is_exhausted: AtomicBool::new(false),
+            is_counted: true,
+        }
+    }
+}
+
+/// The hits from a search with a `Budget`.
+#[derive(Clone, Debug)]
+pub struct BudgetedHits<U: Number> {
+    /// The hits, as 2-tuples of the index of the instance and its distance to
+    /// the query.
+    pub hits: Vec<(usize, U)>,
+    /// Whether the search finished within its budget. If this is `false`, the
+    /// hits are the best found before the budget ran out and may be missing
+    /// some true hits.
+    pub is_complete: bool,
+    /// The number of distance computations counted against the budget.
+    pub num_distances: usize,
+}
+
+/// Tracks the work done by a search against a `Budget`.
+///
+/// This is shared between the threads searching for the same query, e.g. when
+/// searching the shards of a dataset in parallel. Implementations of `Search`
+/// should ask the tracker before computing distances and stop when it refuses.
+#[derive(Debug)]
+pub struct Tracker {
+    /// The instant after which no more work may be done.
+    deadline: Option<Instant>,
+    /// The maximum number of distance computations.
+    max_distances: Option<usize>,
+    /// The number of distance computations so far.
+    num_distances: AtomicUsize,
+    /// Whether some work was refused because the budget ran out.
+    is_exhausted: AtomicBool,
+    /// Whether the distance computations are counted.
+    is_counted: bool,
+}
+
+impl Tracker {
+    /// Creates a tracker with no limits for a search without a budget.
+    ///
+    /// This allows all work without touching any shared state, so it does not
+    /// count the distance computations. Use `Budget::unlimited().start()` to
+    /// count them.
+    #[must_use]
+    pub const fn unlimited() -> Self {
+        Self {
+            deadline: None,
+            max_distances: None,
+            num_distances: AtomicUsize::new(0),
+            is_exhausted: AtomicBool::new(false),
+            is_counted: false,
+        }
+    }
+
+    /// Whether the search has any limits.
+    pub const fn is_limited(&self) -> bool {
+        self.deadline.is_some() || self.max_distances.is_some()
+    }
+
+    /// The number of instances to scan between checks of the budget, out of
+    /// `n` instances.
+    pub const fn chunk_size(&self, n: usize) -> usize {
+        if self.is_limited() && n > CHUNK_SIZE {
+            CHUNK_SIZE
+        } else if n == 0 {
+            1
+        } else {
+            n
+        }
+    }
+
+    /// Whether the deadline has passed.
+    fn is_late(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() > deadline)
+    }
+
+    /// Reserves up to `n` distance computations.
+    ///
+    /// # Returns
+    ///
+    /// The number of distance computations which may be performed. If this is
+    /// less than `n`, the budget has run out.
+    pub fn reserve(&self, n: usize) -> usize {
+        if !self.is_counted {
+            return n;
+        }
+        if self.is_exhausted() || self.is_late() {
+            self.is_exhausted.store(true, Ordering::Relaxed);
+            return 0;
+        }
+
+        let reserved = self.max_distances.map_or_else(
+            || {
+                self.num_distances.fetch_add(n, Ordering::Relaxed);
+                n
+            },
+            |max| {
+                let previous = self
+                    .num_distances
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                        Some(count + n.min(max.saturating_sub(count)))
+                    })
+                    .unwrap_or_else(|_| unreachable!("The update always succeeds."));
+                n.min(max.saturating_sub(previous))
+            },
+        );
+
+        if reserved < n {
+            self.is_exhausted.store(true, Ordering::Relaxed);
+        }
+        reserved
+    }
+
+    /// Reserves exactly `n` distance computations, or none of them.
+    ///
+    /// # Returns
+    ///
+    /// Whether the distance computations may be performed.
+    pub fn spend(&self, n: usize) -> bool {
+        if !self.is_counted {
+            return true;
+        }
+        if self.is_exhausted() || self.is_late() {
+            self.is_exhausted.store(true, Ordering::Relaxed);
+            return false;
+        }
+
+        let spent = self.max_distances.map_or_else(
+            || {
+                self.num_distances.fetch_add(n, Ordering::Relaxed);
+                true
+            },
+            |max| {
+                self.num_distances
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                        (count + n <= max).then_some(count + n)
+                    })
+                    .is_ok()
+            },
+        );
+
+        if !spent {
+            self.is_exhausted.store(true, Ordering::Relaxed);
+        }
+        spent
+    }
+
+    /// Computes the distances from the query to as many of the indexed
+    /// instances as the budget allows, checking the budget between chunks.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the
+    /// instance and the second element is the distance from the query to the
+    /// instance. If the budget runs out, this has fewer elements than `indices`.
+    pub fn query_to_many<I, U, D>(&self, data: &D, query: &I, indices: &[usize]) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+    {
+        let mut hits = Vec::with_capacity(indices.len());
+        for chunk in indices.chunks(self.chunk_size(indices.len())) {
+            let n = self.reserve(chunk.len());
+            hits.extend(chunk.iter().copied().zip(data.query_to_many(query, &chunk[..n])));
+            if n < chunk.len() {
+                break;
+            }
+        }
+        hits
+    }
+
+    /// Whether some work was refused because the budget ran out.
+    pub fn is_exhausted(&self) -> bool {
+        self.is_exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Wraps the hits from a search with the state of the budget.
+    pub fn finish<U: Number>(&self, hits: Vec<(usize, U)>) -> BudgetedHits<U> {
+        BudgetedHits {
+            hits,
+            is_complete: !self.is_exhausted(),
+            num_distances: self.num_distances.load(Ordering::Relaxed),
+        }
+    }
+}
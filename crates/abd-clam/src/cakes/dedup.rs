@@ -0,0 +1,108 @@
+//! Near-duplicate detection and deduplication.
+//!
+//! Both start from the neighbors of every instance within a threshold, found
+//! with RNN search on the tree, i.e. from the edges of the graph in which two
+//! instances are adjacent if they are within the threshold of each other.
+
+use std::collections::HashMap;
+
+/// The result of deduplicating a dataset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dedup {
+    /// The indices of the instances which were kept, in increasing order.
+    pub representatives: Vec<usize>,
+    /// Maps the index of each removed instance to the index of the
+    /// representative which replaced it. Every removed instance is within the
+    /// threshold of its representative.
+    pub removed: HashMap<usize, usize>,
+}
+
+impl Dedup {
+    /// Returns the index of the representative of the given instance, which is
+    /// the instance itself if it was kept.
+    #[must_use]
+    pub fn representative_of(&self, index: usize) -> usize {
+        self.removed.get(&index).copied().unwrap_or(index)
+    }
+}
+
+/// Finds the connected components, with at least two instances, of the graph
+/// with the given adjacency lists.
+///
+/// # Returns
+///
+/// The components, each sorted by index, and sorted by their first index.
+pub(crate) fn groups(neighbors: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut parents = (0..neighbors.len()).collect::<Vec<_>>();
+    for (i, adjacent) in neighbors.iter().enumerate() {
+        for &j in adjacent {
+            let (ri, rj) = (find(&mut parents, i), find(&mut parents, j));
+            // The smaller index becomes the root so that the roots are the
+            // first members of their components.
+            if ri < rj {
+                parents[rj] = ri;
+            } else {
+                parents[ri] = rj;
+            }
+        }
+    }
+
+    let mut components = HashMap::<usize, Vec<usize>>::new();
+    for i in 0..neighbors.len() {
+        let root = find(&mut parents, i);
+        components.entry(root).or_default().push(i);
+    }
+
+    let mut groups = components.into_values().filter(|g| g.len() > 1).collect::<Vec<_>>();
+    groups.sort_unstable_by_key(|g| g[0]);
+    groups
+}
+
+/// Returns the root of the set containing `i`, compressing the path to it.
+fn find(parents: &mut [usize], mut i: usize) -> usize {
+    let mut root = i;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    while parents[i] != root {
+        let next = parents[i];
+        parents[i] = root;
+        i = next;
+    }
+    root
+}
+
+/// Greedily chooses representatives from the graph with the given adjacency
+/// lists.
+///
+/// Instances are visited in decreasing order of degree, breaking ties by
+/// index. Each instance which has not yet been removed is kept, and its
+/// neighbors which have not yet been kept or removed are removed in its favor.
+pub(crate) fn greedy(neighbors: &[Vec<usize>]) -> Dedup {
+    let mut order = (0..neighbors.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| (core::cmp::Reverse(neighbors[i].len()), i));
+
+    let mut is_kept = vec![false; neighbors.len()];
+    let mut removed = HashMap::new();
+    for i in order {
+        if removed.contains_key(&i) {
+            continue;
+        }
+        is_kept[i] = true;
+        for &j in &neighbors[i] {
+            if !is_kept[j] {
+                removed.entry(j).or_insert(i);
+            }
+        }
+    }
+
+    let representatives = is_kept
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, k)| k.then_some(i))
+        .collect();
+    Dedup {
+        representatives,
+        removed,
+    }
+}
@@ -0,0 +1,73 @@
+//! Best-first search for the k farthest neighbors of a query.
+
+use distances::Number;
+use priority_queue::PriorityQueue;
+
+use crate::{cakes::knn::OrdNumber, Cluster, Dataset, Instance, Tree};
+
+use super::Hits;
+
+/// K-Farthest Neighbor search using the `d_max` bounds of clusters.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let (data, root) = (tree.data(), &tree.root);
+
+    // The top candidate is the cluster with the largest `d_max`.
+    let mut candidates = PriorityQueue::<&C, OrdNumber<U>>::new();
+    let mut hits = Hits::new(k);
+
+    let d = root.distance_to_instance(data, query);
+    candidates.push(root, OrdNumber(d_max(root, d)));
+
+    while let Some((c, OrdNumber(d_max_c))) = candidates.pop() {
+        // No instance in the remaining clusters can displace any of the hits.
+        if hits.is_full() && d_max_c <= hits.peek() {
+            break;
+        }
+
+        if c.is_leaf() {
+            let distances = if c.is_singleton() {
+                vec![d_max_c; c.cardinality()]
+            } else {
+                data.query_to_many(query, &c.indices().collect::<Vec<_>>())
+            };
+            hits.push_batch(c.indices().zip(distances));
+        } else {
+            let children = c
+                .children()
+                .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
+            for child in children {
+                let d = child.distance_to_instance(data, query);
+                candidates.push(child, OrdNumber(d_max(child, d)));
+            }
+        }
+    }
+
+    hits.extract()
+}
+
+/// Calculates the theoretical worst case distance for a point in a cluster,
+/// i.e., the farthest a point in a given cluster could possibly be from the query.
+pub fn d_max<U: Number, C: Cluster<U>>(c: &C, d: U) -> U {
+    d + c.radius()
+}
@@ -0,0 +1,157 @@
+//! Algorithms for K-Farthest Neighbor search.
+//!
+//! The stable algorithms are `Linear` and `Clustered`, with the default being `Clustered`.
+//!
+//! K-Farthest Neighbor search is useful for outlier analysis, where we want to
+//! find the instances that are least like the query.
+
+use core::hash::Hash;
+
+use distances::Number;
+use priority_queue::PriorityQueue;
+
+use crate::{cakes::knn::RevNumber, Cluster, Dataset, Instance, Tree};
+
+pub(crate) mod clustered;
+pub(crate) mod linear;
+
+/// The algorithm to use for K-Farthest Neighbor search.
+///
+/// The default is `Clustered`.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Algorithm {
+    /// Use linear search on the entire dataset.
+    ///
+    /// This is a stable algorithm.
+    Linear,
+
+    /// Use a best-first search over the tree, ordered by the largest possible
+    /// distance from the query to any point in a cluster.
+    ///
+    /// This is a stable algorithm.
+    ///
+    /// For a cluster with center `c` and radius `r`, no instance in the cluster
+    /// can be farther from the query than `d_max = d(q, c) + r`. Clusters are
+    /// visited in decreasing order of `d_max` and the search stops once `k`
+    /// hits have been found and the closest of them is no closer than the
+    /// `d_max` of the next cluster.
+    #[default]
+    Clustered,
+}
+
+impl Algorithm {
+    /// Searches for the farthest neighbors of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the instance
+    /// and the second element is the distance from the query to the instance.
+    pub fn search<I, U, D, C>(self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        match self {
+            Self::Linear => {
+                let indices = (0..tree.cardinality()).collect::<Vec<_>>();
+                linear::search(tree.data(), query, k, &indices)
+            }
+            Self::Clustered => clustered::search(tree, query, k),
+        }
+    }
+
+    /// Returns the name of the algorithm.
+    #[must_use]
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Linear => "Linear",
+            Self::Clustered => "Clustered",
+        }
+    }
+
+    /// Returns the algorithm from a string representation of the name.
+    ///
+    /// The string is case-insensitive.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The string representation of the algorithm.
+    ///
+    /// # Returns
+    ///
+    /// The algorithm variant.
+    ///
+    /// # Errors
+    ///
+    /// If the string does not match any of the algorithms.
+    pub fn from_name(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(Self::Linear),
+            "clustered" => Ok(Self::Clustered),
+            _ => Err(format!("Unknown algorithm: {s}")),
+        }
+    }
+
+    /// Returns a list of all the algorithms, excluding Linear.
+    #[must_use]
+    pub const fn variants<'a>() -> &'a [Self] {
+        &[Self::Clustered]
+    }
+}
+
+/// A priority queue of hits for K-Farthest Neighbor search.
+///
+/// The top of the queue is the *closest* of the hits, so that it can be evicted
+/// when a farther hit is found.
+pub(crate) struct Hits<I: Hash + Eq + Copy, U: Number> {
+    /// The priority queue of hits.
+    pub queue: PriorityQueue<I, RevNumber<U>>,
+    /// The number of neighbors to search for.
+    pub capacity: usize,
+}
+
+impl<I: Hash + Eq + Copy, U: Number> Hits<I, U> {
+    /// Creates a new priority queue of hits with the given `capacity`.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            queue: PriorityQueue::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Whether the queue holds `capacity` hits.
+    pub fn is_full(&self) -> bool {
+        self.queue.len() >= self.capacity
+    }
+
+    /// Returns the distance of the closest hit in the queue.
+    ///
+    /// If the queue is empty, returns zero.
+    pub fn peek(&self) -> U {
+        self.queue.peek().map_or_else(U::zero, |(_, &RevNumber(d))| d)
+    }
+
+    /// Push a batch of items onto the queue and reconcile with capacity at the
+    /// end.
+    pub fn push_batch(&mut self, items: impl Iterator<Item = (I, U)>) {
+        items.for_each(|(i, d)| {
+            self.queue.push(i, RevNumber(d));
+        });
+        while self.queue.len() > self.capacity {
+            self.queue.pop();
+        }
+    }
+
+    /// Extracts the hits from the queue.
+    pub fn extract(&self) -> Vec<(I, U)> {
+        self.queue.iter().map(|(&i, &RevNumber(d))| (i, d)).collect()
+    }
+}
@@ -0,0 +1,213 @@
+//! Batched K-Nearest Neighbor search which traverses a tree over the queries
+//! together with the tree over the dataset.
+//!
+//! Queries in the same query cluster share the decisions about which clusters
+//! in the dataset may contain their neighbors. These decisions are made once
+//! for the query cluster, using the distance between the centers of the two
+//! clusters, and are then refined for its children. Only at the leaves of the
+//! query tree does the search fall back to individual queries, starting from
+//! the clusters which survived the pruning.
+
+use distances::Number;
+
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, PartitionCriteria, Tree, UniBall, VecDataset};
+
+use super::{greedy_sieve::d_min, leaf_hits, Hits, RevNumber};
+
+/// A tree built over a batch of queries.
+pub type QueryTree<I, U> = Tree<I, U, VecDataset<I, U, usize>, UniBall<U>>;
+
+/// Builds a tree over a batch of queries.
+///
+/// # Arguments
+///
+/// * `queries` - The queries.
+/// * `metric` - The metric used by the dataset being searched.
+/// * `is_expensive` - Whether the metric is expensive to compute.
+/// * `seed` - The seed for the random number generator.
+pub fn query_tree<I: Instance, U: Number>(
+    queries: &[&I],
+    metric: fn(&I, &I) -> U,
+    is_expensive: bool,
+    seed: Option<u64>,
+) -> QueryTree<I, U> {
+    let queries = queries.iter().map(|&q| q.clone()).collect();
+    let data = VecDataset::new("queries".to_string(), queries, metric, is_expensive);
+    Tree::new(data, seed).partition(&PartitionCriteria::default(), seed)
+}
+
+/// K-Nearest Neighbor search for a batch of queries using a tree over the
+/// queries.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query_tree` - The tree over the queries.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// For each query, in the order in which the queries were given to build the
+/// query tree, a vector of 2-tuples, where the first element is the index of
+/// the instance and the second element is the distance from the query to the
+/// instance.
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query_tree: &QueryTree<I, U>, k: usize) -> Vec<Vec<(usize, U)>>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let (data, queries) = (tree.data(), query_tree.data());
+    let (root, query_root) = (tree.root(), query_tree.root());
+
+    let d = data.query_to_one(&queries[query_root.arg_center()], root.arg_center());
+    let mut hits = vec![Vec::new(); queries.cardinality()];
+    for (i, query_hits) in traverse(data, queries, query_root, vec![(root, d)], k) {
+        hits[queries.original_index(i)] = query_hits;
+    }
+    hits
+}
+
+/// Refines the candidate clusters for a query cluster and recurses into its
+/// children.
+///
+/// # Arguments
+///
+/// * `data` - The dataset to search.
+/// * `queries` - The dataset of queries.
+/// * `q` - The query cluster.
+/// * `candidates` - The clusters which may contain neighbors of the queries
+///   in `q`, paired with the distance between their centers and the center of
+///   `q`.
+/// * `k` - The number of neighbors to search for.
+///
+/// # Returns
+///
+/// The hits for each query in `q`, paired with the index of the query.
+fn traverse<I, U, D, C>(
+    data: &D,
+    queries: &VecDataset<I, U, usize>,
+    q: &UniBall<U>,
+    candidates: Vec<(&C, U)>,
+    k: usize,
+) -> Vec<(usize, Vec<(usize, U)>)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let center = &queries[q.arg_center()];
+    let mut candidates = prune(candidates, q.radius(), k);
+
+    // Descend the data tree until its clusters are no larger than `q`.
+    loop {
+        let (split, kept) = candidates
+            .into_iter()
+            .partition::<Vec<_>, _>(|(c, _)| !c.is_leaf() && c.radius() > q.radius());
+        if split.is_empty() {
+            candidates = kept;
+            break;
+        }
+        let children = split
+            .into_iter()
+            .flat_map(|(c, _)| {
+                c.children()
+                    .unwrap_or_else(|| unreachable!("Non-leaf cluster without children"))
+            })
+            .map(|c| (c, data.query_to_one(center, c.arg_center())));
+        candidates = prune(kept.into_iter().chain(children).collect(), q.radius(), k);
+    }
+
+    let Some(children) = q.children() else {
+        return q
+            .indices()
+            .map(|i| (i, leaf_search(data, &queries[i], &candidates, k)))
+            .collect();
+    };
+
+    let [left, right] = children.map(|child| {
+        let child_center = &queries[child.arg_center()];
+        let child_candidates = candidates
+            .iter()
+            .map(|&(c, _)| (c, data.query_to_one(child_center, c.arg_center())))
+            .collect::<Vec<_>>();
+        (child, child_candidates)
+    });
+    let (mut left, mut right) = rayon::join(
+        || traverse(data, queries, left.0, left.1, k),
+        || traverse(data, queries, right.0, right.1, k),
+    );
+    left.append(&mut right);
+    left
+}
+
+/// Removes the clusters which cannot contain any of the k nearest neighbors of
+/// any query in a query cluster of radius `query_radius`.
+///
+/// For every query in the query cluster, the distance to its k-th nearest
+/// neighbor is bounded above by the smallest distance from the center of the
+/// query cluster within which the candidates hold at least k instances, plus
+/// `query_radius`. A candidate is pruned if every query is farther than this
+/// bound from every instance in the candidate.
+fn prune<U: Number, C: Cluster<U>>(mut candidates: Vec<(&C, U)>, query_radius: U, k: usize) -> Vec<(&C, U)> {
+    candidates.sort_by(|(a, da), (b, db)| {
+        (*da + a.radius())
+            .partial_cmp(&(*db + b.radius()))
+            .unwrap_or(core::cmp::Ordering::Less)
+    });
+
+    let mut count = 0;
+    let threshold = candidates.iter().find_map(|&(c, d)| {
+        count += c.cardinality();
+        (count >= k).then_some(d + c.radius())
+    });
+
+    // Every query is within `query_radius` of the center, so this compares the
+    // smallest possible distance from a query to the cluster with the largest
+    // possible distance to the k-th neighbor.
+    if let Some(threshold) = threshold {
+        let bound = threshold + query_radius + query_radius;
+        candidates.retain(|&(c, d)| d <= bound + c.radius());
+    }
+    candidates
+}
+
+/// Best-first search for the k nearest neighbors of a single query, starting
+/// from the candidate clusters of its query cluster.
+fn leaf_search<I, U, D, C>(data: &D, query: &I, candidates: &[(&C, U)], k: usize) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let mut queue = priority_queue::PriorityQueue::<&C, RevNumber<U>>::new();
+    for &(c, _) in candidates {
+        let d = data.query_to_one(query, c.arg_center());
+        queue.push(c, RevNumber(d_min(c, d)));
+    }
+
+    let mut hits = Hits::new(k);
+    while let Some((c, RevNumber(d))) = queue.pop() {
+        if hits.len() == k && d > hits.peek() {
+            break;
+        }
+
+        match c.children() {
+            Some(children) => {
+                for child in children {
+                    let d = data.query_to_one(query, child.arg_center());
+                    queue.push(child, RevNumber(d_min(child, d)));
+                }
+            }
+            None if c.is_singleton() => c.indices().for_each(|i| hits.push(i, d)),
+            None => leaf_hits(data, query, c, hits.threshold(), &Tracker::unlimited())
+                .into_iter()
+                .for_each(|(i, d)| hits.push(i, d)),
+        }
+    }
+
+    hits.extract()
+}
@@ -2,9 +2,9 @@
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, Tree};
 
-use super::{OrdNumber, RevNumber};
+use super::{leaf_hits, OrdNumber, RevNumber};
 
 /// K-Nearest Neighbor search with expanding threshold.
 ///
@@ -13,6 +13,7 @@ use super::{OrdNumber, RevNumber};
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
@@ -20,7 +21,10 @@ use super::{OrdNumber, RevNumber};
 /// and the second element is the distance from the query to the instance.
 ///
 /// Contrast this to `SieveV1` and `SieveV2`, which use a (mostly) decreasing threshold.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+///
+/// If the budget runs out, the best hits among the instances and cluster
+/// centers compared to the query so far are returned.
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize, tracker: &Tracker) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
@@ -30,25 +34,50 @@ where
     let mut candidates = priority_queue::PriorityQueue::<&C, RevNumber<U>>::new();
     let mut hits = priority_queue::PriorityQueue::<usize, OrdNumber<U>>::new();
 
+    // The cluster centers compared to the query, kept only under a limited budget.
+    let mut centers = Vec::new();
+
     let (data, root) = (tree.data(), &tree.root);
 
+    if !tracker.spend(1) {
+        return Vec::new();
+    }
     let d = root.distance_to_instance(data, query);
     candidates.push(root, RevNumber(d_min(root, d)));
+    if tracker.is_limited() {
+        centers.push((root.arg_center(), d));
+    }
 
-    // Stop if we have enough hits and the farthest hit is closer than the closest cluster (closeness determined by d_min).
-    while hits.len() < k
-        || (!candidates.is_empty()
-            && hits
+    // Stop if we have enough hits and the farthest hit is closer than the closest cluster (closeness determined by d_min),
+    // or if every instance is already a hit, i.e. when `k` is at least the cardinality of the tree.
+    while !candidates.is_empty()
+        && (hits.len() < k
+            || hits
                 .peek()
                 .map_or_else(|| unreachable!("`hits` is non-empty."), |(_, &OrdNumber(d))| d)
                 >= candidates
                     .peek()
                     .map_or_else(|| unreachable!("`candidates` is non-empty."), |(_, &RevNumber(d))| d))
     {
-        pop_till_leaf(tree, query, &mut candidates);
-        leaf_into_hits(tree, query, &mut hits, &mut candidates);
+        if !pop_till_leaf(tree, query, &mut candidates, tracker, &mut centers) {
+            break;
+        }
+        leaf_into_hits(tree, query, k, &mut hits, &mut candidates, tracker);
         trim_hits(k, &mut hits);
+        if tracker.is_exhausted() {
+            break;
+        }
     }
+
+    if tracker.is_exhausted() {
+        for (i, d) in centers {
+            if hits.get(&i).is_none() {
+                hits.push(i, OrdNumber(d));
+            }
+        }
+        trim_hits(k, &mut hits);
+    }
+
     hits.into_iter().map(|(i, OrdNumber(d))| (i, d)).collect()
 }
 
@@ -63,11 +92,21 @@ pub fn d_min<U: Number, C: Cluster<U>>(c: &C, d: U) -> U {
 }
 
 /// Pops from the top of `candidates` until the top candidate is a leaf cluster.
+///
+/// Under a limited budget, the distances to the centers of the children are
+/// recorded in `centers`.
+///
+/// # Returns
+///
+/// Whether the top candidate is a leaf, i.e. `false` if the budget ran out.
 fn pop_till_leaf<I, U, D, C>(
     tree: &Tree<I, U, D, C>,
     query: &I,
     candidates: &mut priority_queue::PriorityQueue<&C, RevNumber<U>>,
-) where
+    tracker: &Tracker,
+    centers: &mut Vec<(usize, U)>,
+) -> bool
+where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
@@ -77,6 +116,9 @@ fn pop_till_leaf<I, U, D, C>(
         .peek()
         .map_or_else(|| unreachable!("`candidates` is non-empty"), |(c, _)| c.is_leaf())
     {
+        if !tracker.spend(2) {
+            return false;
+        }
         let [l, r] = candidates.pop().map_or_else(
             || unreachable!("`candidates` is non-empty"),
             |(c, _)| c.children().unwrap_or_else(|| unreachable!("elements are non-leaves")),
@@ -87,15 +129,25 @@ fn pop_till_leaf<I, U, D, C>(
         ];
         candidates.push(l, RevNumber(d_min(l, dl)));
         candidates.push(r, RevNumber(d_min(r, dr)));
+        if tracker.is_limited() {
+            centers.extend([(l.arg_center(), dl), (r.arg_center(), dr)]);
+        }
     }
+    true
 }
 
 /// Pops a single leaf from the top of `candidates` and add those points to `hits`.
+///
+/// Once there are `k` hits, points which the lower bound on the metric puts
+/// farther than the farthest hit are skipped. If the budget runs out, only some
+/// of the points are added.
 fn leaf_into_hits<I, U, D, C>(
     tree: &Tree<I, U, D, C>,
     query: &I,
+    k: usize,
     hits: &mut priority_queue::PriorityQueue<usize, OrdNumber<U>>,
     candidates: &mut priority_queue::PriorityQueue<&C, RevNumber<U>>,
+    tracker: &Tracker,
 ) where
     I: Instance,
     U: Number,
@@ -105,14 +157,16 @@ fn leaf_into_hits<I, U, D, C>(
     let (leaf, RevNumber(d)) = candidates
         .pop()
         .unwrap_or_else(|| unreachable!("candidates is non-empty"));
-    let distances = if leaf.is_singleton() {
-        vec![d; leaf.indices().len()]
+    if leaf.is_singleton() {
+        leaf.indices().for_each(|i| {
+            hits.push(i, OrdNumber(d));
+        });
     } else {
-        tree.data().query_to_many(query, &leaf.indices().collect::<Vec<_>>())
-    };
-    leaf.indices().zip(distances).for_each(|(i, d)| {
-        hits.push(i, OrdNumber(d));
-    });
+        let threshold = hits.peek().filter(|_| hits.len() >= k).map(|(_, &OrdNumber(d))| d);
+        for (i, d) in leaf_hits(tree.data(), query, leaf, threshold, tracker) {
+            hits.push(i, OrdNumber(d));
+        }
+    }
 }
 
 /// Trims `hits` to contain only the k nearest neighbors.
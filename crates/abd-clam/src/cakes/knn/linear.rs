@@ -2,7 +2,7 @@
 
 use distances::Number;
 
-use crate::{Dataset, Instance};
+use crate::{cakes::budget::Tracker, Dataset, Instance};
 
 use super::Hits;
 
@@ -25,12 +25,35 @@ where
     U: Number,
     D: Dataset<I, U>,
 {
-    let distances = data.query_to_many(query, indices);
+    budgeted_search(data, query, k, indices, &Tracker::unlimited())
+}
 
+/// Linear search, under a budget, for the nearest neighbors of a query.
+///
+/// The indices are scanned in chunks, checking the budget before each chunk.
+/// If the budget runs out, the best hits among the scanned instances are
+/// returned. Once there are `k` hits, instances ruled out by the dataset's
+/// lower bound on the metric are skipped.
+///
+/// # Arguments
+///
+/// * `data` - The dataset to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `indices` - The indices to search.
+/// * `tracker` - Tracks the work done against the budget.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn budgeted_search<I, U, D>(data: &D, query: &I, k: usize, indices: &[usize], tracker: &Tracker) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+{
     let mut hits = Hits::new(k);
-    indices
-        .iter()
-        .zip(distances.iter())
-        .for_each(|(&i, &d)| hits.push(i, d));
+    hits.push_instances(data, query, indices, tracker);
     hits.extract()
 }
@@ -13,14 +13,17 @@ use core::{cmp::Ordering, hash::Hash};
 use distances::Number;
 use priority_queue::PriorityQueue;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, Tree};
 
+pub(crate) mod dual_tree;
 pub(crate) mod greedy_sieve;
 pub(crate) mod linear;
 pub(crate) mod repeated_rnn;
 pub(crate) mod sieve;
 pub(crate) mod sieve_sep_center;
 
+pub use dual_tree::QueryTree;
+
 /// The algorithm to use for K-Nearest Neighbor search.
 // TODO(Morgan): Update the docs for each algorithm.
 #[derive(Clone, Copy, Debug)]
@@ -108,6 +111,37 @@ impl Algorithm {
     /// A vector of 2-tuples, where the first element is the index of the instance
     /// and the second element is the distance from the query to the instance.
     pub fn search<I, U, D, C>(self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        self.budgeted_search(tree, query, k, &Tracker::unlimited())
+    }
+
+    /// Searches for the nearest neighbors of a query under a budget.
+    ///
+    /// If the budget runs out, the best hits found so far are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors to search for.
+    /// * `tracker` - Tracks the work done against the budget.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the instance
+    /// and the second element is the distance from the query to the instance.
+    pub(crate) fn budgeted_search<I, U, D, C>(
+        self,
+        tree: &Tree<I, U, D, C>,
+        query: &I,
+        k: usize,
+        tracker: &Tracker,
+    ) -> Vec<(usize, U)>
     where
         I: Instance,
         U: Number,
@@ -117,12 +151,12 @@ impl Algorithm {
         match self {
             Self::Linear => {
                 let indices = (0..tree.cardinality()).collect::<Vec<_>>();
-                linear::search(tree.data(), query, k, &indices)
+                linear::budgeted_search(tree.data(), query, k, &indices, tracker)
             }
-            Self::RepeatedRnn => repeated_rnn::search(tree, query, k),
-            Self::GreedySieve => greedy_sieve::search(tree, query, k),
-            Self::Sieve => sieve::search(tree, query, k),
-            Self::SieveSepCenter => sieve_sep_center::search(tree, query, k),
+            Self::RepeatedRnn => repeated_rnn::search(tree, query, k, tracker),
+            Self::GreedySieve => greedy_sieve::search(tree, query, k, tracker),
+            Self::Sieve => sieve::search(tree, query, k, tracker),
+            Self::SieveSepCenter => sieve_sep_center::search(tree, query, k, tracker),
         }
     }
 
@@ -171,6 +205,42 @@ impl Algorithm {
     }
 }
 
+/// The number of instances to scan between updates of the distance of the
+/// farthest hit when skipping instances by the lower bound on the metric.
+const BOUND_CHUNK_SIZE: usize = 256;
+
+/// Computes the distances, under the budget, from the query to the instances
+/// in a leaf.
+///
+/// If a `threshold` is given, the instances which the dataset's lower bound on
+/// the metric puts farther than it from the query are skipped. The caller must
+/// know that every one of the k nearest neighbors is within the threshold.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub(crate) fn leaf_hits<I, U, D, C>(
+    data: &D,
+    query: &I,
+    leaf: &C,
+    threshold: Option<U>,
+    tracker: &Tracker,
+) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let indices = leaf.indices().collect::<Vec<_>>();
+    let indices = match threshold {
+        Some(threshold) => data.prefilter(query, &indices, threshold),
+        None => indices,
+    };
+    tracker.query_to_many(data, query, &indices)
+}
+
 /// A priority queue of hits for K-Nearest Neighbor search.
 pub(crate) struct Hits<I: Hash + Eq + Copy, U: Number> {
     /// The priority queue of hits.
@@ -270,6 +340,44 @@ impl<I: Hash + Eq + Copy, U: Number> Hits<I, U> {
     pub fn extract(&self) -> Vec<(I, U)> {
         self.queue.iter().map(|(&i, &OrdNumber(d))| (i, d)).collect()
     }
+
+    /// The distance of the farthest hit, if the queue is full.
+    pub fn threshold(&self) -> Option<U> {
+        (self.queue.len() >= self.capacity).then(|| self.peek())
+    }
+}
+
+impl<U: Number> Hits<usize, U> {
+    /// Computes the distances, under the budget, from the query to the
+    /// indexed instances and pushes them onto the queue.
+    ///
+    /// If the dataset has a lower bound on the metric, the instances are
+    /// scanned in chunks and, once the queue is full, those which the bound
+    /// puts farther than the farthest hit are skipped.
+    pub fn push_instances<I: Instance, D: Dataset<I, U>>(
+        &mut self,
+        data: &D,
+        query: &I,
+        indices: &[usize],
+        tracker: &Tracker,
+    ) {
+        if data.lower_bound().is_none() {
+            self.push_batch(tracker.query_to_many(data, query, indices).into_iter());
+            return;
+        }
+
+        for chunk in indices.chunks(BOUND_CHUNK_SIZE) {
+            let chunk = self
+                .threshold()
+                .map_or_else(|| chunk.to_vec(), |threshold| data.prefilter(query, chunk, threshold));
+            let hits = tracker.query_to_many(data, query, &chunk);
+            let is_cut_short = hits.len() < chunk.len();
+            self.push_batch(hits.into_iter());
+            if is_cut_short {
+                break;
+            }
+        }
+    }
 }
 
 /// Field by which we rank elements in priority queue of hits.
@@ -2,7 +2,10 @@
 
 use distances::Number;
 
-use crate::{cakes::rnn::clustered, utils, Cluster, Dataset, Instance, Tree};
+use crate::{
+    cakes::{budget::Tracker, rnn::clustered},
+    utils, Cluster, Dataset, Instance, Tree,
+};
 
 use super::Hits;
 
@@ -16,30 +19,34 @@ const MULTIPLIER: f64 = 2.0;
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize, tracker: &Tracker) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
 {
+    // The radius can never confirm more hits than there are instances.
+    let k = k.min(tree.cardinality());
     let mut radius = f64::EPSILON + tree.radius().as_f64() / tree.cardinality().as_f64();
-    let [mut confirmed, mut straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius));
+    let [mut confirmed, mut straddlers] =
+        clustered::tree_search(tree.data(), &tree.root, query, U::from(radius), tracker);
 
     let mut num_confirmed = count_hits(&confirmed);
 
-    while num_confirmed == 0 {
+    while num_confirmed == 0 && !tracker.is_exhausted() {
         radius *= MULTIPLIER;
-        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius));
+        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius), tracker);
         num_confirmed = count_hits(&confirmed);
     }
 
-    while num_confirmed < k {
+    while num_confirmed < k && !tracker.is_exhausted() {
         let lfd = utils::mean(
             &confirmed
                 .iter()
@@ -50,15 +57,34 @@ where
         let factor = (k.as_f64() / num_confirmed.as_f64()).powf(1. / (lfd + f64::EPSILON));
 
         radius *= if factor < MULTIPLIER { factor } else { MULTIPLIER };
-        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius));
+        [confirmed, straddlers] = clustered::tree_search(tree.data(), &tree.root, query, U::from(radius), tracker);
         num_confirmed = count_hits(&confirmed);
     }
 
-    Hits::from_vec(
+    // If the budget runs out, the centers of the clusters from the last tree
+    // search are the best hits we have, even if they are outside the radius.
+    let centers = if tracker.is_limited() {
+        confirmed
+            .iter()
+            .chain(straddlers.iter())
+            .map(|&(c, d)| (c.arg_center(), d))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut hits = Hits::from_vec(
         k,
-        clustered::leaf_search(&tree.data, confirmed, straddlers, query, U::from(radius)),
-    )
-    .extract()
+        clustered::leaf_search(&tree.data, confirmed, straddlers, query, U::from(radius), tracker),
+    );
+    if tracker.is_exhausted() {
+        for (i, d) in centers {
+            if hits.queue.get(&i).is_none() {
+                hits.push(i, d);
+            }
+        }
+    }
+    hits.extract()
 }
 
 /// Count the total cardinality of the clusters.
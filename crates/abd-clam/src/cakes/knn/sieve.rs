@@ -4,7 +4,9 @@
 use core::cmp::{min, Ordering};
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, Tree};
+
+use super::{leaf_hits, Hits};
 
 /// A Grain is an element of the sieve. It is either a hit or a cluster.
 #[derive(Clone, Copy, Debug)]
@@ -55,7 +57,14 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
     fn d_min(&self) -> U {
         match self {
             Grain::Hit { d, .. } => *d,
-            Grain::Cluster { d, diameter, .. } => *d - *diameter,
+            // The distance is saturated at zero for unsigned distances.
+            Grain::Cluster { d, diameter, .. } => {
+                if *d > *diameter {
+                    *d - *diameter
+                } else {
+                    U::zero()
+                }
+            }
         }
     }
 
@@ -85,16 +94,23 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 
     /// Returns the indices of the instances in the cluster if the `Grain` is of
     /// the `Cluster` variant
-    fn cluster_to_hits<I: Instance, D: Dataset<I, U>>(self, data: &D, query: &I) -> Vec<Self> {
+    ///
+    /// Instances which the lower bound on the metric puts farther than the
+    /// `threshold` are skipped. If the budget runs out, only some of the
+    /// instances are returned.
+    fn cluster_to_hits<I: Instance, D: Dataset<I, U>>(
+        self,
+        data: &D,
+        query: &I,
+        threshold: U,
+        tracker: &Tracker,
+    ) -> Vec<Self> {
         match self {
             Grain::Hit { .. } => unreachable!("This is only called on non-hits."),
-            Grain::Cluster { c, .. } => {
-                let distances = data.query_to_many(query, &c.indices().collect::<Vec<_>>());
-                c.indices()
-                    .zip(distances)
-                    .map(|(index, d)| Grain::new_hit(d, index))
-                    .collect::<Vec<_>>()
-            }
+            Grain::Cluster { c, .. } => leaf_hits(data, query, c, Some(threshold), tracker)
+                .into_iter()
+                .map(|(index, d)| Grain::new_hit(d, index))
+                .collect::<Vec<_>>(),
         }
     }
 
@@ -196,6 +212,16 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
             Grain::Cluster { .. } => unreachable!("This is only called on hits."),
         }
     }
+
+    /// Returns the `k` best hits among the hit grains and the centers of the
+    /// cluster grains. This is used when the budget runs out.
+    fn best_found(grains: Vec<Self>, k: usize) -> Vec<(usize, U)> {
+        let found = grains.into_iter().map(|g| match g {
+            Grain::Hit { d, index } => (index, d),
+            Grain::Cluster { c, d, .. } => (c.arg_center(), d - c.radius()),
+        });
+        Hits::from_vec(k, found.collect()).extract()
+    }
 }
 /// K-Nearest Neighbor search using a thresholds approach with no separate centers.
 ///
@@ -204,13 +230,17 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is an index of an instance,
 /// and the second element is the distance from the query to the instance.
+///
+/// If the budget runs out, the best hits among the instances and cluster
+/// centers compared to the query so far are returned.
 #[allow(clippy::many_single_char_names)]
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize, tracker: &Tracker) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
@@ -219,12 +249,19 @@ where
 {
     let data = tree.data();
     let c = &tree.root;
+    if !tracker.spend(1) {
+        return Vec::new();
+    }
     let d = c.distance_to_instance(data, query);
 
     let mut grains = vec![Grain::new_cluster(c, d)];
     let [mut insiders, mut non_insiders]: [Vec<_>; 2];
 
     loop {
+        if tracker.is_exhausted() {
+            return Grain::best_found(grains, k);
+        }
+
         // The threshold is the minimum distance, so far, which guarantees that
         // the k nearest neighbors are within the threshold.
         let i = Grain::partition(&mut grains, k);
@@ -246,7 +283,11 @@ where
 
         // Convert small clusters to hits.
         for cluster in small_clusters {
-            hits.append(&mut cluster.cluster_to_hits(data, query));
+            hits.append(&mut cluster.cluster_to_hits(data, query, threshold, tracker));
+        }
+
+        if tracker.is_exhausted() {
+            return Grain::best_found(clusters.into_iter().chain(hits).collect(), k);
         }
 
         // If there are no more cluster grains, then the search is complete.
@@ -263,6 +304,7 @@ where
         grains = clusters
             .into_iter()
             .flat_map(Grain::cluster_to_children)
+            .take_while(|_| tracker.spend(1))
             .map(|c| (c, c.distance_to_instance(data, query)))
             .map(|(c, d)| Grain::new_cluster(c, d))
             .chain(hits)
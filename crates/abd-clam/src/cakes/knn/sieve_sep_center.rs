@@ -5,7 +5,9 @@ use core::cmp::{min, Ordering};
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, Tree};
+
+use super::{leaf_hits, Hits};
 
 /// A Grain is an element of the sieve. It is either a hit or a cluster.
 #[derive(Debug)]
@@ -63,13 +65,27 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
     }
 
     /// Creates center and cluster grains from a cluster.
-    fn new_grains<I: Instance, D: Dataset<I, U>>(c: &'a C, data: &D, query: &I) -> Vec<Self> {
-        if c.is_singleton() {
+    ///
+    /// The instances of a leaf which the lower bound on the metric puts farther
+    /// than the `threshold`, if any, are skipped. If the budget runs out, fewer
+    /// grains are returned.
+    fn new_grains<I: Instance, D: Dataset<I, U>>(
+        c: &'a C,
+        data: &D,
+        query: &I,
+        threshold: Option<U>,
+        tracker: &Tracker,
+    ) -> Vec<Self> {
+        if c.is_leaf() && !c.is_singleton() {
+            leaf_hits(data, query, c, threshold, tracker)
+                .into_iter()
+                .map(|(i, d)| Self::new_hit(d, i))
+                .collect()
+        } else if !tracker.spend(1) {
+            Vec::new()
+        } else if c.is_singleton() {
             let d = c.distance_to_instance(data, query);
             c.indices().map(|i| Self::new_hit(d, i)).collect()
-        } else if c.is_leaf() {
-            let distances = data.query_to_many(query, &c.indices().collect::<Vec<_>>());
-            c.indices().zip(distances).map(|(i, d)| Self::new_hit(d, i)).collect()
         } else {
             let d = c.distance_to_instance(data, query);
             vec![Self::new_cluster(c, d), Self::new_center(d)]
@@ -111,7 +127,17 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 
     /// Returns the indices of the instances in the cluster if the `Grain` is of
     /// the `Cluster` variant
-    fn cluster_to_hits<I: Instance, D: Dataset<I, U>>(self, data: &D, query: &I) -> Vec<Self> {
+    ///
+    /// Instances which the lower bound on the metric puts farther than the
+    /// `threshold` are skipped. If the budget runs out, only some of the
+    /// instances are returned.
+    fn cluster_to_hits<I: Instance, D: Dataset<I, U>>(
+        self,
+        data: &D,
+        query: &I,
+        threshold: U,
+        tracker: &Tracker,
+    ) -> Vec<Self> {
         match self {
             Grain::Hit { .. } | Grain::Center { .. } => unreachable!("This is only called on Clusters."),
             Grain::Cluster { c, d_max, .. } => {
@@ -119,9 +145,8 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
                     let d = d_max - c.radius();
                     c.indices().map(|index| Grain::new_hit(d, index)).collect()
                 } else {
-                    let distances = data.query_to_many(query, &c.indices().collect::<Vec<_>>());
-                    c.indices()
-                        .zip(distances)
+                    leaf_hits(data, query, c, Some(threshold), tracker)
+                        .into_iter()
                         .map(|(index, d)| Grain::new_hit(d, index))
                         .collect()
                 }
@@ -230,6 +255,17 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
             Grain::Cluster { .. } | Grain::Center { .. } => unreachable!("This is only called on hits."),
         }
     }
+
+    /// Returns the `k` best hits among the hit grains and the centers of the
+    /// cluster grains. This is used when the budget runs out.
+    fn best_found(grains: Vec<Self>, k: usize) -> Vec<(usize, U)> {
+        let found = grains.into_iter().filter_map(|g| match g {
+            Grain::Hit { d, index } => Some((index, d)),
+            Grain::Cluster { c, d_max, .. } => Some((c.arg_center(), d_max - c.radius())),
+            Grain::Center { .. } => None,
+        });
+        Hits::from_vec(k, found.collect()).extract()
+    }
 }
 
 /// K-Nearest Neighbor search using a thresholds approach with no separate centers.
@@ -239,12 +275,16 @@ impl<'a, U: Number, C: Cluster<U>> Grain<'a, U, C> {
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `k` - The number of neighbors to search for.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+///
+/// If the budget runs out, the best hits among the instances and cluster
+/// centers compared to the query so far are returned.
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize, tracker: &Tracker) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
@@ -252,10 +292,14 @@ where
     C: Cluster<U>,
 {
     let data = tree.data();
-    let mut grains = Grain::new_grains(&tree.root, data, query);
+    let mut grains = Grain::new_grains(&tree.root, data, query, None, tracker);
     let [mut insiders, mut non_insiders]: [Vec<_>; 2];
 
     loop {
+        if tracker.is_exhausted() {
+            return Grain::best_found(grains, k);
+        }
+
         // The threshold is the minimum distance, so far, which guarantees that
         // the k nearest neighbors are within the threshold.
         let i = Grain::partition(&mut grains, k);
@@ -278,7 +322,11 @@ where
 
         // Convert small clusters to hits.
         for cluster in small_clusters {
-            hits.append(&mut cluster.cluster_to_hits(data, query));
+            hits.append(&mut cluster.cluster_to_hits(data, query, threshold, tracker));
+        }
+
+        if tracker.is_exhausted() {
+            return Grain::best_found(clusters.into_iter().chain(hits).collect(), k);
         }
 
         // If there are no more cluster grains, then the search is complete.
@@ -295,7 +343,7 @@ where
         grains = clusters
             .into_iter()
             .flat_map(Grain::cluster_to_children)
-            .flat_map(|c| Grain::new_grains(c, data, query))
+            .flat_map(|c| Grain::new_grains(c, data, query, Some(threshold), tracker))
             .chain(hits)
             .collect();
     }
@@ -0,0 +1,640 @@
+//! CAKES search with sharded datasets which are loaded from disk on demand.
+
+use std::{
+    borrow::Cow,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use distances::Number;
+use rayon::prelude::*;
+
+use super::{Search, SingleShard};
+use crate::{
+    cakes::budget::Tracker, cakes::kfn, cakes::knn, cakes::knn::QueryTree, cakes::rknn, cakes::rnn,
+    cakes::tuning::TuningProfile, Cluster, Dataset, Instance, Tree, UniBall,
+};
+
+/// The name of the file, in the `shards` directory, holding the roots of the shards.
+pub const ROOTS_FILE: &str = "roots.bin";
+
+/// The number of shards, other than the sample shard, which are kept in memory
+/// when lazily sharded Cakes are loaded through `Search::load`.
+pub const DEFAULT_MAX_RESIDENT: usize = 4;
+
+/// The center, radius and cardinality of the root of a shard.
+#[derive(Debug, Clone)]
+pub struct ShardRoot<I: Instance, U: Number> {
+    /// The center of the root.
+    pub center: I,
+    /// The radius of the root.
+    pub radius: U,
+    /// The number of instances in the shard.
+    pub cardinality: usize,
+}
+
+impl<I: Instance, U: Number> ShardRoot<I, U> {
+    /// Reads the root of the tree of the given shard.
+    pub fn of<D: Dataset<I, U>>(shard: &SingleShard<I, U, D>) -> Self {
+        let root = shard.tree().root();
+        Self {
+            center: shard.data()[root.arg_center()].clone(),
+            radius: root.radius(),
+            cardinality: root.cardinality(),
+        }
+    }
+
+    /// The minimum possible distance from the query to an instance in the shard.
+    fn d_min(&self, d: U) -> U {
+        if d > self.radius {
+            d - self.radius
+        } else {
+            U::zero()
+        }
+    }
+
+    /// Saves the roots of the shards to the given directory.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be written.
+    pub fn save_all(roots: &[Self], dir: &Path) -> Result<(), String> {
+        let mut file = std::fs::File::create(dir.join(ROOTS_FILE)).map_err(|e| e.to_string())?;
+        file.write_all(&roots.len().to_be_bytes()).map_err(|e| e.to_string())?;
+        for root in roots {
+            root.center.save(&mut file)?;
+            file.write_all(&root.radius.to_be_bytes()).map_err(|e| e.to_string())?;
+            file.write_all(&root.cardinality.to_be_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// Loads the roots of the shards from the given directory.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be read.
+    /// * If the file is not a valid roots file.
+    pub fn load_all(dir: &Path) -> Result<Vec<Self>, String> {
+        let mut file = std::fs::File::open(dir.join(ROOTS_FILE)).map_err(|e| e.to_string())?;
+
+        let mut usize_bytes = vec![0; <usize as Number>::num_bytes()];
+        file.read_exact(&mut usize_bytes).map_err(|e| e.to_string())?;
+        let num_shards = <usize as Number>::from_be_bytes(&usize_bytes);
+
+        let mut radius_bytes = vec![0; U::num_bytes()];
+        (0..num_shards)
+            .map(|_| {
+                let center = I::load(&mut file)?;
+                file.read_exact(&mut radius_bytes).map_err(|e| e.to_string())?;
+                let radius = U::from_be_bytes(&radius_bytes);
+                file.read_exact(&mut usize_bytes).map_err(|e| e.to_string())?;
+                let cardinality = <usize as Number>::from_be_bytes(&usize_bytes);
+                Ok(Self {
+                    center,
+                    radius,
+                    cardinality,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A least-recently-used cache of shards loaded from disk.
+#[derive(Debug)]
+struct ShardCache<I: Instance, U: Number, D: Dataset<I, U>> {
+    /// The maximum number of shards to keep in memory.
+    capacity: usize,
+    /// The resident shards, ordered from most to least recently used.
+    #[allow(clippy::type_complexity)]
+    resident: Vec<(usize, Arc<SingleShard<I, U, D>>)>,
+    /// The number of times a shard was loaded from disk.
+    num_loads: usize,
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> ShardCache<I, U, D> {
+    /// Returns the shard at the given index, if it is resident, and marks it
+    /// as the most recently used.
+    fn get(&mut self, index: usize) -> Option<Arc<SingleShard<I, U, D>>> {
+        let position = self.resident.iter().position(|(i, _)| *i == index)?;
+        let entry = self.resident.remove(position);
+        let shard = Arc::clone(&entry.1);
+        self.resident.insert(0, entry);
+        Some(shard)
+    }
+
+    /// Inserts a newly loaded shard, evicting the least recently used shards
+    /// if the cache is over capacity.
+    fn insert(&mut self, index: usize, shard: Arc<SingleShard<I, U, D>>) -> Arc<SingleShard<I, U, D>> {
+        // Another thread may have loaded the same shard in the meantime.
+        if let Some(shard) = self.get(index) {
+            return shard;
+        }
+
+        self.num_loads += 1;
+        self.resident.insert(0, (index, Arc::clone(&shard)));
+        self.resident.truncate(self.capacity);
+        shard
+    }
+}
+
+/// Cakes search with sharded datasets which are loaded from disk on demand.
+///
+/// Only the sample shard and the roots (center, radius and cardinality) of the
+/// other shards are kept in memory. The other shards are loaded when a query
+/// may have hits in them, and at most `max_resident` of them are kept in
+/// memory at a time, evicting the least recently used shard first.
+///
+/// Instances outside the sample shard which are accessed by index, e.g. with
+/// `Cakes::instance`, are copied out of their shard, which is loaded through
+/// the same cache.
+///
+/// A shard may fail to load, e.g. because its files were removed while it was
+/// open. The `try_` methods of `Search` then return the error, and the other
+/// searches log it and return no hits.
+///
+/// # Type parameters
+///
+/// - `I`: The type of the dataset elements.
+/// - `U`: The type of the distance values.
+/// - `D`: The type of the dataset.
+#[derive(Debug)]
+pub struct LazySharded<I: Instance, U: Number, D: Dataset<I, U>> {
+    /// A random sample of the full dataset.
+    sample_shard: SingleShard<I, U, D>,
+    /// The roots of the other shards.
+    roots: Vec<ShardRoot<I, U>>,
+    /// The offsets of the indices of every shard, starting with the sample shard.
+    offsets: Vec<usize>,
+    /// The directory from which the other shards are loaded.
+    shards_dir: PathBuf,
+    /// The metric used for the shards.
+    metric: fn(&I, &I) -> U,
+    /// Whether the metric is expensive to compute.
+    is_expensive: bool,
+    /// The shards which are currently in memory.
+    cache: Mutex<ShardCache<I, U, D>>,
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> LazySharded<I, U, D> {
+    /// Opens sharded Cakes, which were saved to the given path, without loading
+    /// any shard other than the sample shard.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to which the sharded Cakes were saved.
+    /// * `metric` - The metric to use for the search.
+    /// * `is_expensive` - Whether the metric is expensive to compute.
+    /// * `max_resident` - The maximum number of shards, other than the sample
+    ///   shard, to keep in memory at a time.
+    ///
+    /// # Errors
+    ///
+    /// * If the `path` does not exist or is not a directory.
+    /// * If the sample shard cannot be loaded.
+    /// * If the roots of the shards cannot be loaded or computed.
+    #[allow(clippy::similar_names)]
+    pub fn open(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool, max_resident: usize) -> Result<Self, String> {
+        if !path.exists() {
+            return Err(format!("Path '{}' does not exist.", path.display()));
+        }
+
+        if !path.is_dir() {
+            return Err(format!("Path '{}' is not a directory.", path.display()));
+        }
+
+        let sample_shard = SingleShard::<I, U, D>::load(&path.join("sample_shard"), metric, is_expensive)?;
+
+        let shards_dir = path.join("shards");
+        let roots = if shards_dir.join(ROOTS_FILE).exists() {
+            ShardRoot::load_all(&shards_dir)?
+        } else {
+            // Older saves do not have the roots, so we load each shard once to
+            // read them.
+            let mut roots = Vec::new();
+            for i in 0.. {
+                let shard_dir = shards_dir.join(format!("shard_{i}"));
+                if !shard_dir.exists() {
+                    break;
+                }
+                let shard = SingleShard::<I, U, D>::load(&shard_dir, metric, is_expensive)?;
+                roots.push(ShardRoot::of(&shard));
+            }
+            roots
+        };
+
+        let offsets = core::iter::once(sample_shard.data().cardinality())
+            .chain(roots.iter().map(|r| r.cardinality))
+            .scan(0, |o, c| {
+                let offset = *o;
+                *o += c;
+                Some(offset)
+            })
+            .collect();
+
+        Ok(Self {
+            sample_shard,
+            roots,
+            offsets,
+            shards_dir,
+            metric,
+            is_expensive,
+            cache: Mutex::new(ShardCache {
+                capacity: max_resident.max(1),
+                resident: Vec::new(),
+                num_loads: 0,
+            }),
+        })
+    }
+
+    /// Returns the sample shard.
+    pub const fn sample_shard(&self) -> &SingleShard<I, U, D> {
+        &self.sample_shard
+    }
+
+    /// Returns the offset of the indices of each shard, starting with the
+    /// sample shard, i.e. the global index of the first instance of the shard.
+    pub fn shard_offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    /// Returns the number of shards, other than the sample shard, which are
+    /// currently in memory.
+    pub fn num_resident(&self) -> usize {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner).resident.len()
+    }
+
+    /// Returns the number of times a shard has been loaded from disk.
+    pub fn num_loads(&self) -> usize {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner).num_loads
+    }
+
+    /// Returns the shard at the given index, loading it from disk if it is not
+    /// already in memory.
+    ///
+    /// # Errors
+    ///
+    /// * If the shard cannot be loaded.
+    fn shard(&self, index: usize) -> Result<Arc<SingleShard<I, U, D>>, String> {
+        let resident = self.cache.lock().unwrap_or_else(PoisonError::into_inner).get(index);
+        if let Some(shard) = resident {
+            return Ok(shard);
+        }
+
+        // The shard is loaded without holding the lock so that other threads
+        // may use the resident shards in the meantime.
+        let shard_dir = self.shards_dir.join(format!("shard_{index}"));
+        let shard = SingleShard::load(&shard_dir, self.metric, self.is_expensive)
+            .map_err(|e| format!("Failed to load shard {index} from '{}': {e}", shard_dir.display()))?;
+
+        Ok(self
+            .cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(index, Arc::new(shard)))
+    }
+
+    /// Returns the total number of instances in all the shards.
+    fn cardinality(&self) -> usize {
+        self.sample_shard.data().cardinality() + self.roots.iter().map(|r| r.cardinality).sum::<usize>()
+    }
+
+    /// Returns the indices of the other shards, with the offsets of their
+    /// indices, and the distances from the query to the centers of their roots.
+    fn root_distances(&self, query: &I) -> Vec<(usize, usize, U)> {
+        self.roots
+            .par_iter()
+            .zip(self.offsets[1..].par_iter())
+            .enumerate()
+            .map(|(i, (r, &o))| (i, o, (self.metric)(query, &r.center)))
+            .collect()
+    }
+
+    /// Returns the candidates for reverse k-nearest neighbors of the query in
+    /// the given shard, with their global indices and copies of the instances.
+    fn rknn_candidates(
+        shard: &SingleShard<I, U, D>,
+        o: usize,
+        query: &I,
+        k: usize,
+        algo: rknn::Algorithm,
+    ) -> Vec<(usize, U, I)> {
+        let data = shard.data();
+        let candidates = match algo {
+            rknn::Algorithm::Linear => {
+                let indices = (0..data.cardinality()).collect::<Vec<_>>();
+                let distances = data.query_to_many(query, &indices);
+                indices.into_iter().zip(distances).collect()
+            }
+            rknn::Algorithm::Clustered => rknn::clustered::tree_search(data, shard.tree().root(), query, k),
+        };
+        candidates
+            .into_iter()
+            .map(|(i, d)| (i + o, d, data[i].clone()))
+            .collect()
+    }
+
+    /// Adds the k-nearest neighbors in the given shard of each candidate to
+    /// the neighbors of that candidate.
+    fn rknn_neighbors(
+        shard: &SingleShard<I, U, D>,
+        o: usize,
+        k: usize,
+        algo: rknn::Algorithm,
+        candidates: &[(usize, U, I)],
+        neighbors: &mut [knn::Hits<usize, U>],
+    ) {
+        let algo = match algo {
+            rknn::Algorithm::Linear => knn::Algorithm::Linear,
+            rknn::Algorithm::Clustered => knn::Algorithm::default(),
+        };
+        let new_neighbors = candidates
+            .par_iter()
+            .map(|(_, _, instance)| shard.knn_search(instance, k + 1, algo))
+            .collect::<Vec<_>>();
+        for (neighbors, new_neighbors) in neighbors.iter_mut().zip(new_neighbors) {
+            neighbors.push_batch(new_neighbors.into_iter().map(|(j, d)| (j + o, d)));
+        }
+    }
+}
+
+impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for LazySharded<I, U, D> {
+    #[allow(clippy::similar_names)]
+    fn save(&self, path: &Path) -> Result<(), String> {
+        if !path.exists() {
+            return Err(format!("Path '{}' does not exist.", path.display()));
+        }
+
+        if !path.is_dir() {
+            return Err(format!("Path '{}' is not a directory.", path.display()));
+        }
+
+        let sample_shard_dir = path.join("sample_shard");
+        if !sample_shard_dir.exists() {
+            std::fs::create_dir(&sample_shard_dir).map_err(|e| format!("Failed to create directory: {e:?}"))?;
+        }
+        self.sample_shard.save(&sample_shard_dir)?;
+
+        let shards_dir = path.join("shards");
+        if !shards_dir.exists() {
+            std::fs::create_dir(&shards_dir).map_err(|e| format!("Failed to create directory: {e:?}"))?;
+        }
+        for i in 0..self.roots.len() {
+            let shard_dir = shards_dir.join(format!("shard_{i}"));
+            if !shard_dir.exists() {
+                std::fs::create_dir(&shard_dir).map_err(|e| format!("Failed to create directory: {e:?}"))?;
+            }
+            self.shard(i)?.save(&shard_dir)?;
+        }
+        ShardRoot::save_all(&self.roots, &shards_dir)
+    }
+
+    fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, String>
+    where
+        Self: Sized,
+    {
+        Self::open(path, metric, is_expensive, DEFAULT_MAX_RESIDENT)
+    }
+
+    fn trees(&self) -> Vec<&Tree<I, U, D, UniBall<U>>> {
+        vec![self.sample_shard.tree()]
+    }
+
+    fn instance(&self, index: usize) -> Result<Cow<'_, I>, String> {
+        let sample = self.sample_shard.data();
+        if index < sample.cardinality() {
+            Ok(Cow::Borrowed(&sample[index]))
+        } else {
+            let mut instances = self.try_instances(&[index])?;
+            let instance = instances
+                .pop()
+                .unwrap_or_else(|| unreachable!("One instance was loaded."));
+            Ok(Cow::Owned(instance))
+        }
+    }
+
+    // The indices are grouped by shard so that each shard is loaded at most
+    // once per call.
+    fn try_instances(&self, indices: &[usize]) -> Result<Vec<I>, String> {
+        let cardinality = self.cardinality();
+        let mut located = indices
+            .iter()
+            .enumerate()
+            .map(|(p, &i)| {
+                if i < cardinality {
+                    let s = self.offsets.partition_point(|&o| o <= i) - 1;
+                    Ok((s, i - self.offsets[s], p))
+                } else {
+                    Err(format!("Index {i} is out of range for a cardinality of {cardinality}."))
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        located.sort_unstable();
+
+        let mut instances = Vec::with_capacity(located.len());
+        // The indices are sorted by shard, so the shard which was loaded last
+        // is the only one which may be needed again.
+        let (mut current, mut shard) = (0, None);
+        for (s, j, p) in located {
+            let instance = if s == 0 {
+                self.sample_shard.data()[j].clone()
+            } else {
+                if current != s {
+                    current = s;
+                    shard = Some(self.shard(s - 1)?);
+                }
+                shard
+                    .as_ref()
+                    .unwrap_or_else(|| unreachable!("The shard was just loaded"))
+                    .data()[j]
+                    .clone()
+            };
+            instances.push((p, instance));
+        }
+
+        instances.sort_unstable_by_key(|&(p, _)| p);
+        Ok(instances.into_iter().map(|(_, instance)| instance).collect())
+    }
+
+    fn original_index(&self, index: usize) -> Result<usize, String> {
+        let cardinality = self.cardinality();
+        if index >= cardinality {
+            return Err(format!(
+                "Index {index} is out of range for a cardinality of {cardinality}."
+            ));
+        }
+
+        let s = self.offsets.partition_point(|&o| o <= index) - 1;
+        let local = index - self.offsets[s];
+        if s == 0 {
+            Ok(self.sample_shard.data().original_index(local))
+        } else {
+            Ok(self.shard(s - 1)?.data().original_index(local))
+        }
+    }
+
+    fn shard_cardinalities(&self) -> Vec<usize> {
+        core::iter::once(self.sample_shard.data().cardinality())
+            .chain(self.roots.iter().map(|r| r.cardinality))
+            .collect()
+    }
+
+    fn tuned_rnn_algorithm(&self) -> rnn::Algorithm {
+        self.sample_shard.tuned_rnn_algorithm()
+    }
+
+    fn try_budgeted_rnn_search(
+        &self,
+        query: &I,
+        radius: U,
+        algo: rnn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, U)>, String> {
+        let mut hits = self.sample_shard.budgeted_rnn_search(query, radius, algo, tracker);
+        if !tracker.spend(self.roots.len()) {
+            return Ok(hits);
+        }
+
+        for (i, o, d) in self.root_distances(query) {
+            if tracker.is_exhausted() {
+                break;
+            }
+            // Only shards whose root overlaps the query ball can have hits.
+            if self.roots[i].d_min(d) <= radius {
+                let new_hits = self.shard(i)?.budgeted_rnn_search(query, radius, algo, tracker);
+                hits.extend(new_hits.into_iter().map(|(j, d)| (j + o, d)));
+            }
+        }
+
+        Ok(hits)
+    }
+
+    fn tuned_knn_algorithm(&self) -> knn::Algorithm {
+        self.sample_shard.tuned_knn_algorithm()
+    }
+
+    fn try_budgeted_knn_search(
+        &self,
+        query: &I,
+        k: usize,
+        algo: knn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, U)>, String> {
+        let initial_hits = self.sample_shard.budgeted_knn_search(query, k, algo, tracker);
+        let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
+        if !tracker.spend(self.roots.len()) {
+            return Ok(hits_queue.extract());
+        }
+
+        // Search the shards in order of the minimum possible distance to their
+        // instances, so that the threshold tightens as quickly as possible.
+        let mut shards = self
+            .root_distances(query)
+            .into_iter()
+            .map(|(i, o, d)| (i, o, self.roots[i].d_min(d)))
+            .collect::<Vec<_>>();
+        shards.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+
+        for (i, o, d_min) in shards {
+            let new_hits = if tracker.is_exhausted() {
+                break;
+            } else if hits_queue.len() < k {
+                self.shard(i)?.budgeted_knn_search(query, k, algo, tracker)
+            } else if d_min > hits_queue.peek() {
+                // This, and every remaining, shard is beyond the threshold.
+                break;
+            } else {
+                self.shard(i)?
+                    .budgeted_rnn_search(query, hits_queue.peek(), rnn::Algorithm::Clustered, tracker)
+            };
+            hits_queue.push_batch(new_hits.into_iter().map(|(j, d)| (j + o, d)));
+        }
+
+        Ok(hits_queue.extract())
+    }
+
+    fn auto_tune_rnn_on(&mut self, radius: U, queries: &[&I]) {
+        self.sample_shard.auto_tune_rnn_on(radius, queries);
+    }
+
+    fn auto_tune_knn_on(&mut self, k: usize, queries: &[&I]) {
+        self.sample_shard.auto_tune_knn_on(k, queries);
+    }
+
+    fn tuning_profile(&self) -> &TuningProfile<U> {
+        self.sample_shard.tuning_profile()
+    }
+
+    fn tuning_profile_mut(&mut self) -> &mut TuningProfile<U> {
+        self.sample_shard.tuning_profile_mut()
+    }
+
+    fn try_dual_tree_knn_search(&self, query_tree: &QueryTree<I, U>, k: usize) -> Result<Vec<Vec<(usize, U)>>, String> {
+        let mut hits_queues = self
+            .sample_shard
+            .dual_tree_knn_search(query_tree, k)
+            .into_iter()
+            .map(|hits| knn::Hits::from_vec(k, hits))
+            .collect::<Vec<_>>();
+
+        for (i, &o) in self.offsets[1..].iter().enumerate() {
+            let new_hits = self.shard(i)?.dual_tree_knn_search(query_tree, k);
+            for (hits_queue, new_hits) in hits_queues.iter_mut().zip(new_hits) {
+                hits_queue.push_batch(new_hits.into_iter().map(|(j, d)| (j + o, d)));
+            }
+        }
+
+        Ok(hits_queues.iter().map(knn::Hits::extract).collect())
+    }
+
+    fn try_kfn_search(&self, query: &I, k: usize, algo: kfn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        let mut hits_queue = kfn::Hits::new(k);
+        hits_queue.push_batch(self.sample_shard.kfn_search(query, k, algo).into_iter());
+
+        // Search the shards in order of the maximum possible distance to their
+        // instances, skipping those which cannot improve the hits.
+        let mut shards = self
+            .root_distances(query)
+            .into_iter()
+            .map(|(i, o, d)| (i, o, d + self.roots[i].radius))
+            .collect::<Vec<_>>();
+        shards.sort_by(|(_, _, a), (_, _, b)| b.partial_cmp(a).unwrap_or(core::cmp::Ordering::Less));
+
+        for (i, o, d_max) in shards {
+            if hits_queue.is_full() && d_max <= hits_queue.peek() {
+                break;
+            }
+            let new_hits = self.shard(i)?.kfn_search(query, k, algo);
+            hits_queue.push_batch(new_hits.into_iter().map(|(j, d)| (j + o, d)));
+        }
+
+        Ok(hits_queue.extract())
+    }
+
+    // Each shard is loaded at most twice: once to find the candidates in it,
+    // and once to find the neighbors of all candidates in it.
+    fn try_rknn_search(&self, query: &I, k: usize, algo: rknn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        // Candidates are found in each shard, with their global indices.
+        let mut candidates = Self::rknn_candidates(&self.sample_shard, 0, query, k, algo);
+        for (i, &o) in self.offsets[1..].iter().enumerate() {
+            candidates.extend(Self::rknn_candidates(&*self.shard(i)?, o, query, k, algo));
+        }
+
+        // They must be verified against their k-nearest neighbors from all
+        // shards, which are searched one at a time.
+        let mut neighbors = candidates.iter().map(|_| knn::Hits::new(k + 1)).collect::<Vec<_>>();
+        Self::rknn_neighbors(&self.sample_shard, 0, k, algo, &candidates, &mut neighbors);
+        for (i, &o) in self.offsets[1..].iter().enumerate() {
+            Self::rknn_neighbors(&*self.shard(i)?, o, k, algo, &candidates, &mut neighbors);
+        }
+
+        Ok(candidates
+            .into_iter()
+            .zip(neighbors)
+            .filter(|((i, d, _), neighbors)| rknn::is_reverse_neighbor(*i, *d, k, &neighbors.extract()))
+            .map(|((i, d, _), _)| (i, d))
+            .collect())
+    }
+}
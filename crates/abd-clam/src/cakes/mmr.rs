@@ -0,0 +1,99 @@
+//! Diversity-aware search with Maximal Marginal Relevance (MMR).
+//!
+//! MMR greedily selects, one at a time, the candidate which minimizes
+//!
+//! `lambda * d(q, x) - (1 - lambda) * min_{s in S} d(x, s)`,
+//!
+//! where `q` is the query, `S` is the set of candidates selected so far and the
+//! minimum over an empty `S` is zero. With `lambda = 1` this selects the
+//! nearest neighbors in order of distance, and smaller values of `lambda`
+//! prefer candidates which are far from those already selected.
+
+use distances::Number;
+
+/// Greedily selects up to `k` candidates under MMR.
+///
+/// The candidates are the nearest neighbors of the query, sorted by increasing
+/// distance, and every instance not among them is at least `d_next` from the
+/// query.
+///
+/// # Arguments
+///
+/// * `candidates` - The indices of the candidates and their distances to the
+///   query, sorted by increasing distance.
+/// * `pairwise` - The distances between all pairs of candidates.
+/// * `k` - The number of candidates to select.
+/// * `lambda` - The trade-off between relevance and diversity, in `[0, 1]`.
+/// * `d_next` - A lower bound on the distance from the query to any instance
+///   which is not a candidate, or `None` if every instance is a candidate.
+///
+/// # Returns
+///
+/// The selected candidates, in the order in which they were selected, and
+/// whether the selection is certain to be the same as it would be with every
+/// instance as a candidate.
+pub fn select<U: Number>(
+    candidates: &[(usize, U)],
+    pairwise: &[Vec<U>],
+    k: usize,
+    lambda: f64,
+    d_next: Option<U>,
+) -> (Vec<(usize, U)>, bool) {
+    let mut is_exact = true;
+    let mut selected = Vec::with_capacity(k);
+
+    // The distance from each candidate to its closest selected candidate, or
+    // `None` for the candidates which have been selected.
+    let mut diversity = vec![Some(f64::INFINITY); candidates.len()];
+    // The smallest distance from the query to a selected candidate.
+    let mut nearest_selected = f64::INFINITY;
+
+    while selected.len() < k {
+        let best = diversity
+            .iter()
+            .enumerate()
+            .filter_map(|(j, div)| {
+                let div = if selected.is_empty() { Some(0.0) } else { *div };
+                div.map(|div| (j, lambda.mul_add(candidates[j].1.as_f64(), -(1.0 - lambda) * div)))
+            })
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Greater));
+
+        // By the triangle inequality, an instance `x` which is not a candidate
+        // has `min_{s in S} d(x, s) <= d(q, x) + min_{s in S} d(q, s)`, which
+        // bounds its score from below. For `lambda < 0.5`, this bound decreases
+        // with `d(q, x)`, so there is no bound for the instances which are not
+        // candidates.
+        let bound = d_next.map(|d| {
+            let d = d.as_f64();
+            if selected.is_empty() {
+                lambda * d
+            } else if lambda < 0.5 {
+                f64::NEG_INFINITY
+            } else {
+                2.0f64
+                    .mul_add(lambda, -1.0)
+                    .mul_add(d, -(1.0 - lambda) * nearest_selected)
+            }
+        });
+
+        let Some((j, score)) = best else {
+            // Every candidate was selected, but other instances may remain.
+            is_exact &= d_next.is_none();
+            break;
+        };
+        if bound.is_some_and(|bound| score > bound) {
+            is_exact = false;
+        }
+
+        selected.push(candidates[j]);
+        diversity[j] = None;
+        nearest_selected = nearest_selected.min(candidates[j].1.as_f64());
+        for (div, d) in diversity.iter_mut().zip(&pairwise[j]) {
+            if let Some(div) = div {
+                *div = div.min(d.as_f64());
+            }
+        }
+    }
+
+    (selected, is_exact)
+}
@@ -1,22 +1,35 @@
 //! CLAM-Accelerated K-nearest-neighbor Entropy-scaling Search.
 
-use core::ops::Index;
-
-use std::path::Path;
+use std::{borrow::Cow, path::Path};
 
+pub mod budget;
+pub mod dedup;
+pub mod kfn;
 pub mod knn;
+mod lazy;
+mod mmr;
+pub mod rerank;
+pub mod rknn;
 pub mod rnn;
-mod search;
+pub mod search;
 mod sharded;
 mod singular;
+pub mod tuning;
 
+pub use budget::{Budget, BudgetedHits};
+pub use dedup::Dedup;
 use distances::Number;
+pub use lazy::{LazySharded, DEFAULT_MAX_RESIDENT};
 use rayon::prelude::*;
-use search::Search;
-use sharded::RandomlySharded;
-use singular::SingleShard;
+pub use rerank::RerankReport;
+pub use search::Search;
+pub use sharded::{RandomlySharded, ShardStrategy};
+pub use singular::SingleShard;
+
+use mt_logger::{mt_log, Level};
 
-use crate::{Dataset, Instance, PartitionCriterion, Tree, UniBall};
+use crate::{validate_metric, Dataset, Instance, MetricReport, PartitionCriterion, Tree, UniBall, VecDataset};
+use tuning::TuningProfile;
 
 /// CAKES search.
 pub enum Cakes<I: Instance, U: Number, D: Dataset<I, U>> {
@@ -24,6 +37,10 @@ pub enum Cakes<I: Instance, U: Number, D: Dataset<I, U>> {
     SingleShard(SingleShard<I, U, D>),
     /// Search with multiple shards.
     RandomlySharded(RandomlySharded<I, U, D>),
+    /// Search with multiple shards which are loaded from disk on demand.
+    LazySharded(LazySharded<I, U, D>),
+    /// Search with a layout of the index which is defined outside this crate.
+    Custom(Box<dyn Search<I, U, D>>),
 }
 
 impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
@@ -38,6 +55,69 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         Self::SingleShard(SingleShard::new(data, seed, criteria))
     }
 
+    /// Creates a new CAKES instance with a single shard dataset, after checking
+    /// whether its distance function behaves like a metric.
+    ///
+    /// The check uses `validate_metric` on a sample of the instances, and a
+    /// warning is logged if it finds any violations, because the exact search
+    /// algorithms may then miss some hits.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset to search.
+    /// * `seed` - The seed to use for the random number generator.
+    /// * `criteria` - The criteria to use for partitioning the tree.
+    /// * `num_samples` - The number of instances to sample for the check.
+    ///
+    /// # Returns
+    ///
+    /// The CAKES instance and the report of the check.
+    pub fn new_validated<P: PartitionCriterion<U>>(
+        data: D,
+        seed: Option<u64>,
+        criteria: &P,
+        num_samples: usize,
+    ) -> (Self, MetricReport) {
+        let report = validate_metric(&data, num_samples, seed);
+        if !report.is_metric() {
+            mt_log!(
+                Level::Warning,
+                "The distance function of dataset {} is not a metric, so exact search may miss some hits: {report:?}",
+                data.name()
+            );
+        }
+        (Self::new(data, seed, criteria), report)
+    }
+
+    /// Creates a new CAKES instance with a custom layout of the index.
+    ///
+    /// # Arguments
+    ///
+    /// * `search` - The layout of the index.
+    pub fn from_search<S: Search<I, U, D> + 'static>(search: S) -> Self {
+        Self::Custom(Box::new(search))
+    }
+
+    /// Returns the layout of the index.
+    pub fn search(&self) -> &dyn Search<I, U, D> {
+        match self {
+            Self::SingleShard(ss) => ss,
+            Self::RandomlySharded(rs) => rs,
+            Self::LazySharded(ls) => ls,
+            Self::Custom(search) => search.as_ref(),
+        }
+    }
+
+    /// Returns the layout of the index, mutably.
+    fn search_mut(&mut self) -> &mut dyn Search<I, U, D> {
+        match self {
+            Self::SingleShard(ss) => ss,
+            Self::RandomlySharded(rs) => rs,
+            Self::LazySharded(ls) => ls,
+            Self::Custom(search) => search.as_mut(),
+        }
+    }
+
     /// Saves the Cakes structure to the given path.
     ///
     /// # Arguments
@@ -49,10 +129,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// * If the `path` does not exist.
     /// * If the `path` is not a valid directory.
     pub fn save(&self, path: &Path) -> Result<(), String> {
-        match self {
-            Self::SingleShard(ss) => ss.save(path),
-            Self::RandomlySharded(rs) => rs.save(path),
-        }
+        self.search().save(path)
     }
 
     /// Loads the Cakes structure from the given path.
@@ -92,20 +169,80 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         }
     }
 
+    /// Loads Cakes with a custom layout of the index from the given path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to load the Cakes structure from.
+    /// * `metric` - The metric to use for the search.
+    /// * `is_expensive` - Whether the metric is expensive to compute.
+    ///
+    /// # Errors
+    ///
+    /// * If the layout cannot be loaded from the `path`.
+    pub fn load_with<S: Search<I, U, D> + 'static>(
+        path: &Path,
+        metric: fn(&I, &I) -> U,
+        is_expensive: bool,
+    ) -> Result<Self, String> {
+        S::load(path, metric, is_expensive).map(Self::from_search)
+    }
+
+    /// Loads sharded Cakes from the given path, keeping only the sample shard
+    /// and the roots of the other shards in memory.
+    ///
+    /// The other shards are loaded from disk when a query may have hits in
+    /// them, and at most `max_resident` of them are kept in memory at a time.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to load the Cakes structure from.
+    /// * `metric` - The metric to use for the search.
+    /// * `is_expensive` - Whether the metric is expensive to compute.
+    /// * `max_resident` - The maximum number of shards, other than the sample
+    ///   shard, to keep in memory at a time.
+    ///
+    /// # Errors
+    ///
+    /// * If the `path` does not exist.
+    /// * If the `path` is not a valid directory.
+    /// * If the `path` does not contain a valid sharded Cakes structure.
+    pub fn load_lazy(
+        path: &Path,
+        metric: fn(&I, &I) -> U,
+        is_expensive: bool,
+        max_resident: usize,
+    ) -> Result<Self, String> {
+        LazySharded::open(path, metric, is_expensive, max_resident).map(Self::LazySharded)
+    }
+
+    /// Returns the instance at the given index.
+    ///
+    /// The instance is borrowed if its shard is in memory. With lazily loaded
+    /// shards, it is otherwise a copy read from its shard.
+    ///
+    /// # Errors
+    ///
+    /// * If the index is out of range.
+    /// * If the shard holding the instance cannot be loaded.
+    pub fn instance(&self, index: usize) -> Result<Cow<'_, I>, String> {
+        self.search().instance(index)
+    }
+
     /// Returns the references to the tree(s) of the dataset.
+    ///
+    /// With lazily loaded shards, only the tree of the sample shard is in
+    /// memory and is returned.
     pub fn trees(&self) -> Vec<&Tree<I, U, D, UniBall<U>>> {
-        match self {
-            Self::SingleShard(ss) => vec![ss.tree()],
-            Self::RandomlySharded(rs) => rs.shards().into_iter().map(SingleShard::tree).collect(),
-        }
+        self.search().trees()
     }
 
     /// Returns the references to the shard(s) of the dataset.
+    ///
+    /// With lazily loaded shards, only the sample shard is in memory and is
+    /// returned.
     pub fn shards(&self) -> Vec<&D> {
-        match self {
-            Self::SingleShard(ss) => vec![ss.data()],
-            Self::RandomlySharded(rs) => rs.shards().into_iter().map(SingleShard::data).collect(),
-        }
+        self.trees().into_iter().map(Tree::data).collect()
     }
 
     /// Creates a new CAKES instance with a randomly sharded dataset.
@@ -124,20 +261,41 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         Self::RandomlySharded(RandomlySharded::new(shards))
     }
 
+    /// Sets the strategy used for K-Nearest Neighbor search across the shards.
+    ///
+    /// This has no effect on a CAKES instance with a single shard, or with
+    /// lazily loaded shards, which are always searched one at a time to bound
+    /// the number of shards in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `strategy` - The strategy to use.
+    pub fn set_shard_strategy(&mut self, strategy: ShardStrategy) {
+        self.search_mut().set_shard_strategy(strategy);
+    }
+
+    /// Returns the index, in the dataset from which `Cakes` was built, of the
+    /// instance at the given index.
+    ///
+    /// The hits of every search are indices into the reordered and, possibly,
+    /// sharded dataset. This maps them back to the original dataset.
+    ///
+    /// # Errors
+    ///
+    /// * If the index is out of range.
+    /// * If the shard holding the instance cannot be loaded.
+    pub fn original_index(&self, index: usize) -> Result<usize, String> {
+        self.search().original_index(index)
+    }
+
     /// Returns the number of shards in the dataset.
     pub fn num_shards(&self) -> usize {
-        match self {
-            Self::SingleShard(_) => 1,
-            Self::RandomlySharded(rs) => rs.num_shards(),
-        }
+        self.search().num_shards()
     }
 
     /// Returns the cardinalities of the shards in the dataset.
     pub fn shard_cardinalities(&self) -> Vec<usize> {
-        match self {
-            Self::SingleShard(ss) => ss.shard_cardinalities(),
-            Self::RandomlySharded(rs) => rs.shard_cardinalities(),
-        }
+        self.search().shard_cardinalities()
     }
 
     /// Returns the total cardinality of the dataset.
@@ -147,10 +305,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
 
     /// Returns the tuned RNN algorithm.
     pub fn tuned_rnn_algorithm(&self) -> rnn::Algorithm {
-        match self {
-            Self::SingleShard(ss) => ss.tuned_rnn_algorithm(),
-            Self::RandomlySharded(rs) => rs.tuned_rnn_algorithm(),
-        }
+        self.search().tuned_rnn_algorithm()
     }
 
     /// Performs RNN search on a batch of queries with the given algorithm.
@@ -180,12 +335,54 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// # Returns
     ///
     /// A vector of tuples containing the index of the instance and the distance
-    /// to the query.
+    /// to the query. If the search fails, e.g. because a lazily loaded shard
+    /// cannot be loaded, the error is logged and the vector is empty.
     pub fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
-        match self {
-            Self::SingleShard(ss) => ss.rnn_search(query, radius, algo),
-            Self::RandomlySharded(rs) => rs.rnn_search(query, radius, algo),
-        }
+        self.search().rnn_search(query, radius, algo)
+    }
+
+    /// Performs an RNN search with the given algorithm, returning an error
+    /// instead of logging it if a lazily loaded shard cannot be loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Errors
+    ///
+    /// * If a shard which may have hits cannot be loaded from disk.
+    pub fn try_rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        self.search().try_rnn_search(query, radius, algo)
+    }
+
+    /// Performs an RNN search with the given algorithm under a budget.
+    ///
+    /// If the budget runs out, the search stops and returns the hits found so
+    /// far, flagged as possibly incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The search radius.
+    /// * `algo` - The algorithm to use.
+    /// * `budget` - The limits on the work done by the search.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as tuples of the index of the instance and the distance to
+    /// the query, along with whether the search finished within its budget.
+    pub fn rnn_search_with_budget(
+        &self,
+        query: &I,
+        radius: U,
+        algo: rnn::Algorithm,
+        budget: &Budget,
+    ) -> BudgetedHits<U> {
+        let tracker = budget.start();
+        let hits = self.search().budgeted_rnn_search(query, radius, algo, &tracker);
+        tracker.finish(hits)
     }
 
     /// Performs Linear RNN search on a batch of queries.
@@ -215,18 +412,12 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// A vector of tuples containing the index of the instance and the distance
     /// to the query.
     pub fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        match self {
-            Self::SingleShard(ss) => ss.linear_rnn_search(query, radius),
-            Self::RandomlySharded(rs) => rs.linear_rnn_search(query, radius),
-        }
+        self.search().linear_rnn_search(query, radius)
     }
 
     /// Returns the tuned KNN algorithm.
     pub fn tuned_knn_algorithm(&self) -> knn::Algorithm {
-        match self {
-            Self::SingleShard(ss) => ss.tuned_knn_algorithm(),
-            Self::RandomlySharded(rs) => rs.tuned_knn_algorithm(),
-        }
+        self.search().tuned_knn_algorithm()
     }
 
     /// Performs KNN search on a batch of queries with the given algorithm.
@@ -245,6 +436,43 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
         queries.par_iter().map(|q| self.knn_search(q, k, algo)).collect()
     }
 
+    /// Performs KNN search on a batch of queries by building a tree over the
+    /// queries and traversing it together with the tree over the data.
+    ///
+    /// Queries which are close to each other share the decisions about which
+    /// clusters may contain their neighbors, so this is faster than searching
+    /// for each query independently when the queries are themselves clustered.
+    /// It returns the same neighbors as `knn_search`, up to ties.
+    ///
+    /// The query tree is traversed with the tree of every shard and the hits
+    /// are merged across the shards. The query tree is built with the metric
+    /// of a shard in memory, so, if a custom layout has no shard in memory,
+    /// each query is searched for independently with the tuned algorithm.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `seed` - The seed for building the tree over the queries.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of tuples containing the index of the instance and
+    /// the distance to the query.
+    pub fn batch_dual_tree_knn_search(&self, queries: &[&I], k: usize, seed: Option<u64>) -> Vec<Vec<(usize, U)>> {
+        if queries.is_empty() {
+            return Vec::new();
+        }
+
+        self.shards().first().map_or_else(
+            || self.batch_tuned_knn_search(queries, k),
+            |data| {
+                let query_tree = knn::dual_tree::query_tree(queries, data.metric(), data.is_metric_expensive(), seed);
+                self.search().dual_tree_knn_search(&query_tree, k)
+            },
+        )
+    }
+
     /// Performs a KNN search with the given algorithm.
     ///
     /// # Arguments
@@ -256,11 +484,406 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// # Returns
     ///
     /// A vector of tuples containing the index of the instance and the distance to the query.
+    /// If the search fails, e.g. because a lazily loaded shard cannot be
+    /// loaded, the error is logged and the vector is empty.
     pub fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
-        match self {
-            Self::SingleShard(ss) => ss.knn_search(query, k, algo),
-            Self::RandomlySharded(rs) => rs.knn_search(query, k, algo),
+        self.search().knn_search(query, k, algo)
+    }
+
+    /// Performs a KNN search with the given algorithm, returning an error
+    /// instead of logging it if a lazily loaded shard cannot be loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Errors
+    ///
+    /// * If a shard which may have hits cannot be loaded from disk.
+    pub fn try_knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        self.search().try_knn_search(query, k, algo)
+    }
+
+    /// Performs a KNN search with the given algorithm under a budget.
+    ///
+    /// If the budget runs out, the search stops and returns the best hits
+    /// found so far, flagged as possibly incomplete.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    /// * `budget` - The limits on the work done by the search.
+    ///
+    /// # Returns
+    ///
+    /// The hits, as tuples of the index of the instance and the distance to
+    /// the query, along with whether the search finished within its budget.
+    pub fn knn_search_with_budget(&self, query: &I, k: usize, algo: knn::Algorithm, budget: &Budget) -> BudgetedHits<U> {
+        let tracker = budget.start();
+        let hits = self.search().budgeted_knn_search(query, k, algo, &tracker);
+        tracker.finish(hits)
+    }
+
+    /// Performs KNN search on a batch of queries, each under its own budget.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    /// * `budget` - The limits on the work done by the search for each query.
+    ///
+    /// # Returns
+    ///
+    /// The hits for each query, along with whether that search finished within
+    /// its budget.
+    pub fn batch_knn_search_with_budget(
+        &self,
+        queries: &[&I],
+        k: usize,
+        algo: knn::Algorithm,
+        budget: &Budget,
+    ) -> Vec<BudgetedHits<U>> {
+        queries
+            .par_iter()
+            .map(|q| self.knn_search_with_budget(q, k, algo, budget))
+            .collect()
+    }
+
+    /// Computes the distances from the query to the instances at the given
+    /// indices with a metric other than the one used by the dataset.
+    ///
+    /// # Errors
+    ///
+    /// * If a shard holding some of the instances cannot be loaded.
+    fn distances_with<V: Number>(
+        &self,
+        query: &I,
+        indices: &[usize],
+        metric: fn(&I, &I) -> V,
+    ) -> Result<Vec<V>, String> {
+        let instances = self.search().try_instances(indices)?;
+        Ok(instances.par_iter().map(|x| metric(query, x)).collect())
+    }
+
+    /// Returns the number of candidates to retrieve for two-stage KNN search,
+    /// which is at most the cardinality of the dataset.
+    fn num_rerank_candidates(&self, k: usize, expansion: usize) -> usize {
+        k.saturating_mul(expansion.max(1)).min(self.total_cardinality())
+    }
+
+    /// Performs a two-stage KNN search, retrieving candidates with the metric
+    /// of the dataset and re-ranking them with an exact metric.
+    ///
+    /// The `k * expansion` nearest neighbors under the metric of the dataset,
+    /// or all instances if there are fewer, are retrieved with the given
+    /// algorithm. The distances from the query to these candidates are
+    /// recomputed with the `exact` metric, and the `k` closest candidates are
+    /// returned. Use `rerank_report` to measure how
+    /// often the true nearest neighbors under the exact metric are missed.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `expansion` - The number of candidates to retrieve per neighbor.
+    /// * `algo` - The algorithm to use for retrieving the candidates.
+    /// * `exact` - The metric with which to re-rank the candidates.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the
+    /// distance to the query under the exact metric, sorted by increasing
+    /// distance. This is empty if the candidates could not be loaded.
+    pub fn rerank_knn_search<V: Number>(
+        &self,
+        query: &I,
+        k: usize,
+        expansion: usize,
+        algo: knn::Algorithm,
+        exact: fn(&I, &I) -> V,
+    ) -> Vec<(usize, V)> {
+        let candidates = self
+            .knn_search(query, self.num_rerank_candidates(k, expansion), algo)
+            .into_iter()
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        search::or_log(
+            self.distances_with(query, &candidates, exact)
+                .map(|distances| rerank::top_k(candidates, distances, k)),
+        )
+    }
+
+    /// Performs two-stage KNN search on a batch of queries.
+    ///
+    /// See `rerank_knn_search` for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of nearest neighbors to return.
+    /// * `expansion` - The number of candidates to retrieve per neighbor.
+    /// * `algo` - The algorithm to use for retrieving the candidates.
+    /// * `exact` - The metric with which to re-rank the candidates.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of tuples containing the index of the instance and
+    /// the distance to the query under the exact metric.
+    pub fn batch_rerank_knn_search<V: Number>(
+        &self,
+        queries: &[&I],
+        k: usize,
+        expansion: usize,
+        algo: knn::Algorithm,
+        exact: fn(&I, &I) -> V,
+    ) -> Vec<Vec<(usize, V)>> {
+        queries
+            .par_iter()
+            .map(|q| self.rerank_knn_search(q, k, expansion, algo, exact))
+            .collect()
+    }
+
+    /// Measures how often two-stage KNN search misses some of the true nearest
+    /// neighbors under the exact metric.
+    ///
+    /// The true nearest neighbors are found by computing the exact distance
+    /// from each query to every instance in the dataset, so this should be run
+    /// on a small sample of queries.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The sample of queries.
+    /// * `k` - The number of nearest neighbors to search for.
+    /// * `expansion` - The number of candidates to retrieve per neighbor.
+    /// * `algo` - The algorithm to use for retrieving the candidates.
+    /// * `exact` - The metric with which to re-rank the candidates.
+    pub fn rerank_report<V: Number>(
+        &self,
+        queries: &[&I],
+        k: usize,
+        expansion: usize,
+        algo: knn::Algorithm,
+        exact: fn(&I, &I) -> V,
+    ) -> RerankReport {
+        let all_indices = (0..self.total_cardinality()).collect::<Vec<_>>();
+        let misses = queries
+            .iter()
+            .map(|&q| {
+                let hits = self.rerank_knn_search(q, k, expansion, algo, exact);
+                let truth = search::or_log(
+                    self.distances_with(q, &all_indices, exact)
+                        .map(|distances| rerank::top_k(all_indices.clone(), distances, k)),
+                );
+                rerank::count_misses(&hits, &truth)
+            })
+            .collect::<Vec<_>>();
+
+        RerankReport {
+            k,
+            num_candidates: self.num_rerank_candidates(k, expansion),
+            num_queries: queries.len(),
+            num_queries_with_misses: misses.iter().filter(|&&m| m > 0).count(),
+            num_missed_neighbors: misses.iter().sum(),
+        }
+    }
+
+    /// Computes the distances between all pairs of instances at the given
+    /// indices.
+    ///
+    /// With a single shard, this uses `Dataset::pairwise`. Otherwise, the
+    /// instances may be in different shards, so they are gathered from every
+    /// shard and the distances are computed with the metric of a shard in
+    /// memory, which is shared by all the shards.
+    ///
+    /// # Errors
+    ///
+    /// * If no shard is in memory to supply the metric.
+    /// * If a shard holding some of the instances cannot be loaded.
+    fn pairwise(&self, indices: &[usize]) -> Result<Vec<Vec<U>>, String> {
+        if let Self::SingleShard(ss) = self {
+            return Ok(ss.data().pairwise(indices));
+        }
+
+        let metric = self
+            .shards()
+            .first()
+            .map(|data| data.metric())
+            .ok_or_else(|| "No shard is in memory to compute the distances between instances.".to_string())?;
+        let instances = self.search().try_instances(indices)?;
+        Ok(instances
+            .par_iter()
+            .map(|x| instances.iter().map(|y| metric(x, y)).collect())
+            .collect())
+    }
+
+    /// Performs a diversity-aware KNN search with Maximal Marginal Relevance.
+    ///
+    /// Results are selected greedily, each minimizing
+    /// `lambda * d(q, x) - (1 - lambda) * min_{s in S} d(x, s)` over the
+    /// candidates `x`, where `S` holds the results selected so far. With
+    /// `lambda = 1`, this returns the same results as `knn_search`, up to ties.
+    ///
+    /// Candidates are pulled, nearest first, with KNN search using the given
+    /// algorithm, doubling the number of candidates until the selection is
+    /// certain to be the same as it would be over the whole dataset, or until
+    /// there are `max_candidates` candidates. For `lambda < 0.5`, this is only
+    /// certain when every instance is a candidate, so `max_candidates` should
+    /// be set to bound the work done.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of results to return.
+    /// * `lambda` - The trade-off between relevance and diversity. It is
+    ///   clamped to `[0, 1]`, where `1` ignores diversity.
+    /// * `algo` - The algorithm to use for pulling candidates.
+    /// * `max_candidates` - The maximum number of candidates to pull.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the
+    /// distance to the query, in the order in which they were selected.
+    ///
+    /// # Errors
+    ///
+    /// * If a custom layout has no shard in memory to supply the metric for
+    ///   the distances between the candidates.
+    /// * If a shard holding some of the candidates cannot be loaded.
+    pub fn mmr_search(
+        &self,
+        query: &I,
+        k: usize,
+        lambda: f64,
+        algo: knn::Algorithm,
+        max_candidates: usize,
+    ) -> Result<Vec<(usize, U)>, String> {
+        let lambda = lambda.clamp(0.0, 1.0);
+        let cardinality = self.total_cardinality();
+        let max_candidates = max_candidates.max(k).min(cardinality);
+
+        let mut num_candidates = (2 * k).min(max_candidates);
+        loop {
+            // One more neighbor than the candidates bounds the distance to the
+            // instances which are not candidates.
+            let mut hits = self.knn_search(query, (num_candidates + 1).min(cardinality), algo);
+            hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Greater));
+            let d_next = hits.get(num_candidates).map(|&(_, d)| d);
+            hits.truncate(num_candidates);
+
+            let indices = hits.iter().map(|&(i, _)| i).collect::<Vec<_>>();
+            let (selected, is_exact) = mmr::select(&hits, &self.pairwise(&indices)?, k, lambda, d_next);
+            if is_exact || num_candidates == max_candidates {
+                return Ok(selected);
+            }
+            num_candidates = (2 * num_candidates).min(max_candidates);
+        }
+    }
+
+    /// Performs diversity-aware KNN search on a batch of queries.
+    ///
+    /// See `mmr_search` for details.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of results to return.
+    /// * `lambda` - The trade-off between relevance and diversity.
+    /// * `algo` - The algorithm to use for pulling candidates.
+    /// * `max_candidates` - The maximum number of candidates to pull.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of tuples containing the index of the instance and
+    /// the distance to the query.
+    ///
+    /// # Errors
+    ///
+    /// * If the search for any of the queries fails.
+    pub fn batch_mmr_search(
+        &self,
+        queries: &[&I],
+        k: usize,
+        lambda: f64,
+        algo: knn::Algorithm,
+        max_candidates: usize,
+    ) -> Result<Vec<Vec<(usize, U)>>, String> {
+        queries
+            .par_iter()
+            .map(|q| self.mmr_search(q, k, lambda, algo, max_candidates))
+            .collect()
+    }
+
+    /// Returns the indices of the instances within `threshold` of each instance
+    /// in the dataset, found with the tuned RNN algorithm.
+    fn neighbors_within(&self, threshold: U) -> Vec<Vec<usize>> {
+        let search = |query: &I| {
+            self.tuned_rnn_search(query, threshold)
+                .into_iter()
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+
+        // Only one shard at a time is copied into memory to be used as queries.
+        let mut offset = 0;
+        let mut neighbors = Vec::with_capacity(self.total_cardinality());
+        for cardinality in self.shard_cardinalities() {
+            let indices = (offset..(offset + cardinality)).collect::<Vec<_>>();
+            // If the shard cannot be loaded, its instances are left without
+            // neighbors.
+            let instances = search::or_log(self.search().try_instances(&indices));
+            if instances.is_empty() {
+                neighbors.extend(indices.iter().map(|_| Vec::new()));
+            } else {
+                neighbors.extend(instances.par_iter().map(search).collect::<Vec<_>>());
+            }
+            offset += cardinality;
         }
+        neighbors
+    }
+
+    /// Finds groups of near-duplicate instances in the dataset.
+    ///
+    /// Two instances are in the same group if there is a chain of instances
+    /// between them in which each step is within `threshold`, i.e. the groups
+    /// are the connected components of the graph which connects instances
+    /// within `threshold` of each other. The neighbors of each instance are
+    /// found with RNN search on the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - The distance within which instances are duplicates.
+    ///
+    /// # Returns
+    ///
+    /// The groups with at least two instances, each sorted by index, and
+    /// sorted by their smallest index.
+    pub fn near_duplicates(&self, threshold: U) -> Vec<Vec<usize>> {
+        dedup::groups(&self.neighbors_within(threshold))
+    }
+
+    /// Chooses a subset of representative instances so that every other
+    /// instance is within `threshold` of a representative.
+    ///
+    /// Representatives are chosen greedily, preferring instances with more
+    /// near-duplicates. Unlike the groups from `near_duplicates`, every removed
+    /// instance is within `threshold` of its own representative, so a group
+    /// may keep more than one representative.
+    ///
+    /// # Arguments
+    ///
+    /// * `threshold` - The distance within which instances are duplicates.
+    ///
+    /// # Returns
+    ///
+    /// The indices of the representatives and a mapping from each removed
+    /// instance to its representative.
+    pub fn dedup(&self, threshold: U) -> Dedup {
+        dedup::greedy(&self.neighbors_within(threshold))
     }
 
     /// Automatically finds the best RNN algorithm to use.
@@ -270,10 +893,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// * `radius` - The search radius.
     /// * `tuning_depth` - The number of instances to use for tuning.
     pub fn auto_tune_rnn(&mut self, radius: U, tuning_depth: usize) {
-        match self {
-            Self::SingleShard(ss) => ss.auto_tune_rnn(radius, tuning_depth),
-            Self::RandomlySharded(rs) => rs.auto_tune_rnn(radius, tuning_depth),
-        }
+        self.search_mut().auto_tune_rnn(radius, tuning_depth);
     }
 
     /// Automatically finds the best KNN algorithm to use.
@@ -283,10 +903,45 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     /// * `k` - The number of nearest neighbors to return.
     /// * `tuning_depth` - The number of instances to use for tuning.
     pub fn auto_tune_knn(&mut self, k: usize, tuning_depth: usize) {
-        match self {
-            Self::SingleShard(ss) => ss.auto_tune_knn(k, tuning_depth),
-            Self::RandomlySharded(rs) => rs.auto_tune_knn(k, tuning_depth),
-        }
+        self.search_mut().auto_tune_knn(k, tuning_depth);
+    }
+
+    /// Auto-tunes the RNN algorithm on the given queries.
+    ///
+    /// Tuning on queries from the expected workload may pick a better
+    /// algorithm than tuning on the cluster centers in the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - The search radius to tune for.
+    /// * `queries` - The queries to use for tuning.
+    pub fn auto_tune_rnn_on(&mut self, radius: U, queries: &[&I]) {
+        self.search_mut().auto_tune_rnn_on(radius, queries);
+    }
+
+    /// Auto-tunes the KNN algorithm on the given queries.
+    ///
+    /// Tuning on queries from the expected workload may pick a better
+    /// algorithm than tuning on the cluster centers in the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of nearest neighbors to tune for.
+    /// * `queries` - The queries to use for tuning.
+    pub fn auto_tune_knn_on(&mut self, k: usize, queries: &[&I]) {
+        self.search_mut().auto_tune_knn_on(k, queries);
+    }
+
+    /// Returns the profile of the best algorithms for the values of k and
+    /// radius that have been tuned.
+    ///
+    /// Each call to one of the auto-tuning methods adds an entry to the
+    /// profile. The profile is saved and loaded with the Cakes structure.
+    ///
+    /// For sharded datasets, tuning only runs on the sample shard, and the
+    /// algorithms it chooses are used to search every shard.
+    pub fn tuning_profile(&self) -> &TuningProfile<U> {
+        self.search().tuning_profile()
     }
 
     /// Performs Linear KNN search on a batch of queries.
@@ -315,10 +970,76 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     ///
     /// A vector of tuples containing the index of the instance and the distance to the query.
     pub fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        match self {
-            Self::SingleShard(ss) => ss.linear_knn_search(query, k),
-            Self::RandomlySharded(rs) => rs.linear_knn_search(query, k),
+        self.search().linear_knn_search(query, k)
+    }
+
+    /// Performs KFN search on a batch of queries with the given algorithm.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of farthest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of tuples containing the index of the instance and
+    /// the distance to the query.
+    pub fn batch_kfn_search(&self, queries: &[&I], k: usize, algo: kfn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        queries.par_iter().map(|q| self.kfn_search(q, k, algo)).collect()
+    }
+
+    /// Performs a K-Farthest Neighbor search with the given algorithm.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of farthest neighbors to return.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    pub fn kfn_search(&self, query: &I, k: usize, algo: kfn::Algorithm) -> Vec<(usize, U)> {
+        self.search().kfn_search(query, k, algo)
+    }
+
+    /// Performs Reverse KNN search on a batch of queries with the given algorithm.
+    ///
+    /// # Arguments
+    ///
+    /// * `queries` - The queries to search.
+    /// * `k` - The number of neighbors that defines the neighborhood of each instance.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of vectors of tuples containing the index of the instance and
+    /// the distance to the query.
+    pub fn batch_rknn_search(&self, queries: &[&I], k: usize, algo: rknn::Algorithm) -> Vec<Vec<(usize, U)>> {
+        queries.par_iter().map(|q| self.rknn_search(q, k, algo)).collect()
+    }
+
+    /// Performs a Reverse K-Nearest Neighbor search with the given algorithm.
+    ///
+    /// This finds all instances that would have the query among their `k`
+    /// nearest neighbors.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of neighbors that defines the neighborhood of each instance.
+    /// * `algo` - The algorithm to use.
+    ///
+    /// # Returns
+    ///
+    /// A vector of tuples containing the index of the instance and the distance to the query.
+    /// This is empty if `k` is zero.
+    pub fn rknn_search(&self, query: &I, k: usize, algo: rknn::Algorithm) -> Vec<(usize, U)> {
+        if k == 0 {
+            return Vec::new();
         }
+        self.search().rknn_search(query, k, algo)
     }
 
     /// Performs RNN search on a batch of queries with the tuned algorithm.
@@ -340,7 +1061,10 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
 
     /// Performs a RNN search with the tuned algorithm.
     ///
-    /// If the algorithm has not been tuned, this will use the default algorithm.
+    /// The algorithm is chosen from the tuning profile by the range in which
+    /// the `radius` falls. If the algorithm has not been tuned, this will use
+    /// the default algorithm. The dispatch is that of `Search::tuned_rnn_search`,
+    /// so sharded datasets use the algorithm tuned on their sample shard.
     ///
     /// # Arguments
     ///
@@ -351,8 +1075,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     ///
     /// A vector of tuples containing the index of the instance and the distance to the query.
     pub fn tuned_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        let algo = self.tuned_rnn_algorithm();
-        self.rnn_search(query, radius, algo)
+        self.search().tuned_rnn_search(query, radius)
     }
 
     /// Performs KNN search on a batch of queries with the tuned algorithm.
@@ -374,7 +1097,10 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
 
     /// Performs a KNN search with the tuned algorithm.
     ///
-    /// If the algorithm has not been tuned, this will use the default algorithm.
+    /// The algorithm is chosen from the tuning profile by the range in which
+    /// `k` falls. If the algorithm has not been tuned, this will use the
+    /// default algorithm. The dispatch is that of `Search::tuned_knn_search`,
+    /// so sharded datasets use the algorithm tuned on their sample shard.
     ///
     /// # Arguments
     ///
@@ -385,33 +1111,36 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Cakes<I, U, D> {
     ///
     /// A vector of tuples containing the index of the instance and the distance to the query.
     pub fn tuned_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        let algo = self.tuned_knn_algorithm();
-        self.knn_search(query, k, algo)
+        self.search().tuned_knn_search(query, k)
     }
 }
 
-impl<I, U, D> Index<usize> for Cakes<I, U, D>
-where
-    I: Instance,
-    U: Number,
-    D: Dataset<I, U>,
-{
-    type Output = I;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        match self {
-            Self::SingleShard(ss) => ss.data().index(index),
-            Self::RandomlySharded(rs) => {
-                let i = rs
-                    .offsets()
-                    .iter()
-                    .enumerate()
-                    .find(|(_, &o)| o > index)
-                    .map_or_else(|| rs.num_shards() - 1, |(i, _)| i - 1);
-
-                let index = index - rs.offsets()[i];
-                rs.shards()[i].data().index(index)
-            }
-        }
+impl<I: Instance, U: Number, M: Instance> Cakes<I, U, VecDataset<I, U, M>> {
+    /// Creates a new CAKES instance with a dataset sharded along the clusters
+    /// of a tree, as in `RandomlySharded::cluster_shards`.
+    ///
+    /// The first shard is a random sample of the dataset, which is used for
+    /// tuning. Unlike random shards, each of the other shards covers a compact
+    /// region of the metric space, so most queries will only need to search a
+    /// few of the shards.
+    ///
+    /// Sharding reorders the instances, so the indices in the hits of a search
+    /// are mapped back to indices in `data` with `original_index`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset to shard and search.
+    /// * `max_cardinality` - The maximum cardinality of each shard.
+    /// * `seed` - The seed to use for the random number generator.
+    /// * `criteria` - The criteria to use for partitioning the trees of the shards.
+    #[must_use]
+    pub fn new_cluster_sharded<P: PartitionCriterion<U>>(
+        data: VecDataset<I, U, M>,
+        max_cardinality: usize,
+        seed: Option<u64>,
+        criteria: &P,
+    ) -> Self {
+        let shards = RandomlySharded::cluster_shards(data, max_cardinality, seed);
+        Self::new_randomly_sharded(shards, seed, criteria)
     }
 }
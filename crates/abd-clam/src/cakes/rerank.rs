@@ -0,0 +1,82 @@
+//! Two-stage search which retrieves candidates with the indexed metric and
+//! re-ranks them with a second, exact metric.
+//!
+//! This is useful when the exact metric is too expensive to index, e.g.
+//! Needleman-Wunsch on long sequences, but a cheaper proxy metric, e.g. the
+//! Jaccard distance between k-mer sets, ranks the true neighbors highly.
+
+use distances::Number;
+
+/// How often re-ranking missed some of the true nearest neighbors under the
+/// exact metric, measured on a sample of queries.
+///
+/// A true neighbor is missed when it was not among the candidates retrieved
+/// with the indexed metric, so that the re-ranked hits are farther from the
+/// query than the true nearest neighbors. Ties in the exact metric are not
+/// counted as misses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RerankReport {
+    /// The number of nearest neighbors searched for.
+    pub k: usize,
+    /// The number of candidates retrieved with the indexed metric.
+    pub num_candidates: usize,
+    /// The number of queries measured.
+    pub num_queries: usize,
+    /// The number of queries for which at least one true neighbor was missed.
+    pub num_queries_with_misses: usize,
+    /// The total number of true neighbors missed, over all queries.
+    pub num_missed_neighbors: usize,
+}
+
+impl RerankReport {
+    /// Returns the fraction of queries for which at least one true neighbor was
+    /// missed.
+    #[must_use]
+    pub fn query_miss_rate(&self) -> f64 {
+        if self.num_queries == 0 {
+            0.0
+        } else {
+            self.num_queries_with_misses.as_f64() / self.num_queries.as_f64()
+        }
+    }
+
+    /// Returns the fraction of the true neighbors, over all queries, which were
+    /// missed.
+    #[must_use]
+    pub fn neighbor_miss_rate(&self) -> f64 {
+        let num_neighbors = self.num_queries * self.k;
+        if num_neighbors == 0 {
+            0.0
+        } else {
+            self.num_missed_neighbors.as_f64() / num_neighbors.as_f64()
+        }
+    }
+}
+
+/// Returns the `k` candidates closest to the query under the exact metric.
+///
+/// # Arguments
+///
+/// * `candidates` - The indices of the candidates.
+/// * `distances` - The distances from the query to the candidates under the
+///   exact metric.
+/// * `k` - The number of hits to keep.
+pub(crate) fn top_k<V: Number>(candidates: Vec<usize>, distances: Vec<V>, k: usize) -> Vec<(usize, V)> {
+    let mut hits = candidates.into_iter().zip(distances).collect::<Vec<_>>();
+    hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Greater));
+    hits.truncate(k);
+    hits
+}
+
+/// Counts the true nearest neighbors which are missing from the re-ranked hits.
+///
+/// Both arguments are sorted by increasing distance. The i-th true neighbor is
+/// missed if the i-th hit is farther from the query, or if there are fewer
+/// than i hits.
+pub(crate) fn count_misses<V: Number>(hits: &[(usize, V)], truth: &[(usize, V)]) -> usize {
+    truth
+        .iter()
+        .enumerate()
+        .filter(|&(i, &(_, t))| hits.get(i).map_or(true, |&(_, d)| d > t))
+        .count()
+}
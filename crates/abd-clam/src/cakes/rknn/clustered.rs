@@ -0,0 +1,115 @@
+//! Clustered search for the reverse k-nearest neighbors of a query.
+
+use distances::Number;
+use rayon::prelude::*;
+
+use crate::{cakes::knn, Cluster, Dataset, Instance, Tree};
+
+use super::is_reverse_neighbor;
+
+/// Clustered search for the reverse k-nearest neighbors of a query.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors that defines the neighborhood of each instance.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    tree_search(data, &tree.root, query, k)
+        .into_par_iter()
+        .filter(|&(i, d)| {
+            let neighbors = knn::Algorithm::default().search(tree, &data[i], k + 1);
+            is_reverse_neighbor(i, d, k, &neighbors)
+        })
+        .collect()
+}
+
+/// Finds the candidates for reverse k-nearest neighbors of the query.
+///
+/// Clusters are pruned using an upper bound on the k-nearest neighbor radii
+/// of their instances. Instances in the surviving leaves are returned if
+/// their distance to the query is within that bound. The candidates still
+/// need to be verified.
+///
+/// # Arguments
+///
+/// * `data` - The dataset to search.
+/// * `root` - The root of the tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors that defines the neighborhood of each instance.
+///
+/// # Returns
+///
+/// A vector of 2-tuples of the index of each candidate and its distance to the query.
+pub fn tree_search<I, U, D, C>(data: &D, root: &C, query: &I, k: usize) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let mut hits = Vec::new();
+    let mut candidates = vec![(root, None)];
+
+    while let Some((c, bound)) = candidates.pop() {
+        let bound = tighter_bound(knn_radius_bound(c, k), bound);
+        let d = c.distance_to_instance(data, query);
+
+        // The closest instance in the cluster is still outside the bound.
+        if bound.is_some_and(|b| d > c.radius() + b) {
+            continue;
+        }
+
+        if c.is_leaf() {
+            let distances = if c.is_singleton() {
+                vec![d; c.cardinality()]
+            } else {
+                data.query_to_many(query, &c.indices().collect::<Vec<_>>())
+            };
+            hits.extend(
+                c.indices()
+                    .zip(distances)
+                    .filter(|&(_, d)| bound.map_or(true, |b| d <= b)),
+            );
+        } else {
+            let children = c
+                .children()
+                .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
+            candidates.extend(children.into_iter().map(|child| (child, bound)));
+        }
+    }
+
+    hits
+}
+
+/// An upper bound on the k-nearest neighbor radius of every instance in the cluster.
+///
+/// This is `None` if the cluster does not have enough instances to provide a bound.
+fn knn_radius_bound<U: Number, C: Cluster<U>>(c: &C, k: usize) -> Option<U> {
+    if c.cardinality() > k {
+        Some(c.radius() + c.radius())
+    } else {
+        None
+    }
+}
+
+/// Returns the smaller of two optional bounds.
+fn tighter_bound<U: Number>(a: Option<U>, b: Option<U>) -> Option<U> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if a < b { a } else { b }),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
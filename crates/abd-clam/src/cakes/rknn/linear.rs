@@ -0,0 +1,42 @@
+//! Linear search for the reverse k-nearest neighbors of a query.
+
+use distances::Number;
+use rayon::prelude::*;
+
+use crate::{cakes::knn, Cluster, Dataset, Instance, Tree};
+
+use super::is_reverse_neighbor;
+
+/// Linear search for the reverse k-nearest neighbors of a query.
+///
+/// # Arguments
+///
+/// * `tree` - The tree to search.
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors that defines the neighborhood of each instance.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    C: Cluster<U>,
+{
+    let data = tree.data();
+    let indices = (0..tree.cardinality()).collect::<Vec<_>>();
+    let distances = data.query_to_many(query, &indices);
+
+    indices
+        .par_iter()
+        .copied()
+        .zip(distances.into_par_iter())
+        .filter(|&(i, d)| {
+            let neighbors = knn::linear::search(data, &data[i], k + 1, &indices);
+            is_reverse_neighbor(i, d, k, &neighbors)
+        })
+        .collect()
+}
@@ -0,0 +1,144 @@
+//! Algorithms for Reverse K-Nearest Neighbor search.
+//!
+//! An instance `x` is a reverse k-nearest neighbor of a query `q` if `q` would
+//! be among the `k` nearest neighbors of `x`, i.e. if `d(x, q)` is no greater
+//! than the distance from `x` to its `k`-th nearest neighbor in the dataset
+//! (excluding `x` itself). This is useful for influence queries.
+//!
+//! The stable algorithms are `Linear` and `Clustered`, with the default being `Clustered`.
+
+use core::cmp::Ordering;
+
+use distances::Number;
+
+use crate::{Cluster, Dataset, Instance, Tree};
+
+pub(crate) mod clustered;
+pub(crate) mod linear;
+
+/// The algorithm to use for Reverse K-Nearest Neighbor search.
+///
+/// The default is `Clustered`.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Algorithm {
+    /// Check every instance in the dataset, using linear KNN search to find
+    /// the distance to its `k`-th nearest neighbor.
+    ///
+    /// This is a stable algorithm.
+    Linear,
+
+    /// Use per-cluster bounds on the k-nearest neighbor radii of instances to
+    /// prune the tree, and verify the surviving candidates with KNN search.
+    ///
+    /// This is a stable algorithm.
+    ///
+    /// If a cluster with radius `r` has more than `k` instances, every instance
+    /// in that cluster has at least `k` other instances within a distance of
+    /// `2 * r`, so its k-nearest neighbor radius is at most `2 * r`. This bound
+    /// is inherited by the descendants of the cluster. A cluster can then be
+    /// pruned if its nearest possible instance, at `d(q, c) - r`, is farther
+    /// from the query than the bound.
+    #[default]
+    Clustered,
+}
+
+impl Algorithm {
+    /// Searches for the reverse k-nearest neighbors of a query.
+    ///
+    /// # Arguments
+    ///
+    /// * `tree` - The tree to search.
+    /// * `query` - The query to search around.
+    /// * `k` - The number of neighbors that defines the neighborhood of each instance.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the instance
+    /// and the second element is the distance from the query to the instance.
+    /// This is empty if `k` is zero.
+    pub fn search<I, U, D, C>(self, tree: &Tree<I, U, D, C>, query: &I, k: usize) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        if k == 0 {
+            return Vec::new();
+        }
+
+        match self {
+            Self::Linear => linear::search(tree, query, k),
+            Self::Clustered => clustered::search(tree, query, k),
+        }
+    }
+
+    /// Returns the name of the algorithm.
+    #[must_use]
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Linear => "Linear",
+            Self::Clustered => "Clustered",
+        }
+    }
+
+    /// Returns the algorithm from a string representation of the name.
+    ///
+    /// The string is case-insensitive.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - The string representation of the algorithm.
+    ///
+    /// # Returns
+    ///
+    /// The algorithm variant.
+    ///
+    /// # Errors
+    ///
+    /// If the string does not match any of the algorithms.
+    pub fn from_name(s: &str) -> Result<Self, String> {
+        match s.to_lowercase().as_str() {
+            "linear" => Ok(Self::Linear),
+            "clustered" => Ok(Self::Clustered),
+            _ => Err(format!("Unknown algorithm: {s}")),
+        }
+    }
+
+    /// Returns a list of all the algorithms, excluding Linear.
+    #[must_use]
+    pub const fn variants<'a>() -> &'a [Self] {
+        &[Self::Clustered]
+    }
+}
+
+/// Checks whether an instance is a reverse k-nearest neighbor of the query.
+///
+/// # Arguments
+///
+/// * `index` - The index of the instance.
+/// * `distance` - The distance from the query to the instance.
+/// * `k` - The number of neighbors that defines the neighborhood of the instance.
+/// * `neighbors` - The `k + 1` nearest neighbors of the instance. The instance
+///   itself is ignored if it is among them.
+///
+/// With `k` equal to zero the instance has no neighbors, so this is `false`.
+pub(crate) fn is_reverse_neighbor<U: Number>(index: usize, distance: U, k: usize, neighbors: &[(usize, U)]) -> bool {
+    if k == 0 {
+        return false;
+    }
+
+    let mut distances = neighbors
+        .iter()
+        .filter(|&&(i, _)| i != index)
+        .map(|&(_, d)| d)
+        .collect::<Vec<_>>();
+
+    if distances.len() < k {
+        // The instance has fewer than `k` other neighbors, so the query is always among them.
+        true
+    } else {
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+        distance <= distances[k - 1]
+    }
+}
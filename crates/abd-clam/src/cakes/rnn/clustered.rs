@@ -1,8 +1,10 @@
 //! Clustered search for the ranged nearest neighbors of a query.
 
+use std::collections::HashSet;
+
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, Tree};
 
 use super::linear;
 
@@ -13,20 +15,21 @@ use super::linear;
 /// * `tree` - The tree to search.
 /// * `query` - The query to search around.
 /// * `radius` - The radius to search within.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, radius: U) -> Vec<(usize, U)>
+pub fn search<I, U, D, C>(tree: &Tree<I, U, D, C>, query: &I, radius: U, tracker: &Tracker) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
     C: Cluster<U>,
 {
-    let [confirmed, straddlers] = tree_search(tree.data(), &tree.root, query, radius);
-    leaf_search(tree.data(), confirmed, straddlers, query, radius)
+    let [confirmed, straddlers] = tree_search(tree.data(), &tree.root, query, radius, tracker);
+    leaf_search(tree.data(), confirmed, straddlers, query, radius, tracker)
 }
 
 /// Perform coarse-grained tree search.
@@ -37,6 +40,7 @@ where
 /// * `root` - The root of the tree to search.
 /// * `query` - The query to search around.
 /// * `radius` - The radius to search within.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
@@ -45,7 +49,16 @@ where
 /// query ball, and the second element is the straddlers, i.e. those that
 /// overlap the query ball. The 2-tuples are the clusters and the distance
 /// from the query to the cluster center.
-pub fn tree_search<'a, I, U, D, C>(data: &D, root: &'a C, query: &I, radius: U) -> [Vec<(&'a C, U)>; 2]
+///
+/// If the budget runs out, the clusters whose centers were not yet compared to
+/// the query are dropped.
+pub fn tree_search<'a, I, U, D, C>(
+    data: &D,
+    root: &'a C,
+    query: &I,
+    radius: U,
+    tracker: &Tracker,
+) -> [Vec<(&'a C, U)>; 2]
 where
     I: Instance,
     U: Number,
@@ -60,6 +73,8 @@ where
     while !candidates.is_empty() {
         (terminal, non_terminal) = candidates
             .into_iter()
+            .filter(|c| data.may_be_within(query, c.arg_center(), c.radius() + radius))
+            .take_while(|_| tracker.spend(1))
             .map(|c| (c, c.distance_to_instance(data, query)))
             .filter(|&(c, d)| d <= (c.radius() + radius))
             .partition(|&(c, d)| (c.radius() + d) <= radius);
@@ -85,12 +100,16 @@ where
 }
 
 /// Perform fine-grained leaf search
+///
+/// If the budget runs out, the hits found so far are returned along with the
+/// centers, within the query ball, of the clusters from the tree search.
 pub fn leaf_search<I, U, D, C>(
     data: &D,
     confirmed: Vec<(&C, U)>,
     straddlers: Vec<(&C, U)>,
     query: &I,
     radius: U,
+    tracker: &Tracker,
 ) -> Vec<(usize, U)>
 where
     I: Instance,
@@ -98,19 +117,37 @@ where
     D: Dataset<I, U>,
     C: Cluster<U>,
 {
-    let hits = confirmed.into_iter().flat_map(|(c, d)| {
-        let distances = if c.is_singleton() {
-            vec![d; c.cardinality()]
+    let centers = if tracker.is_limited() {
+        confirmed
+            .iter()
+            .chain(straddlers.iter())
+            .filter(|&&(_, d)| d <= radius)
+            .map(|&(c, d)| (c.arg_center(), d))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut hits = Vec::new();
+    for (c, d) in confirmed {
+        if c.is_singleton() {
+            hits.extend(c.indices().map(|i| (i, d)));
         } else {
-            data.query_to_many(query, &c.indices().collect::<Vec<_>>())
-        };
-        c.indices().zip(distances)
-    });
+            let indices = c.indices().collect::<Vec<_>>();
+            hits.append(&mut tracker.query_to_many(data, query, &indices));
+        }
+    }
 
     let indices = straddlers
         .into_iter()
         .flat_map(|(c, _)| c.indices())
         .collect::<Vec<_>>();
+    hits.append(&mut linear::search(data, query, radius, &indices, tracker));
+
+    if tracker.is_exhausted() {
+        let found = hits.iter().map(|&(i, _)| i).collect::<HashSet<_>>();
+        hits.extend(centers.into_iter().filter(|(i, _)| !found.contains(i)));
+    }
 
-    hits.chain(linear::search(data, query, radius, &indices)).collect()
+    hits
 }
@@ -2,9 +2,13 @@
 
 use distances::Number;
 
-use crate::{Dataset, Instance};
+use crate::{cakes::budget::Tracker, Dataset, Instance};
 
-/// Linear search for the ranged nearest neighbors of a query.
+/// Linear search, under a budget, for the ranged nearest neighbors of a query.
+///
+/// Instances ruled out by the dataset's lower bound on the metric are skipped.
+/// The rest are scanned in chunks, checking the budget before each chunk. If
+/// the budget runs out, the hits among the scanned instances are returned.
 ///
 /// # Arguments
 ///
@@ -12,22 +16,22 @@ use crate::{Dataset, Instance};
 /// * `query` - The query to search around.
 /// * `radius` - The radius to search within.
 /// * `indices` - The indices to search.
+/// * `tracker` - Tracks the work done against the budget.
 ///
 /// # Returns
 ///
 /// A vector of 2-tuples, where the first element is the index of the instance
 /// and the second element is the distance from the query to the instance.
-pub fn search<I, U, D>(data: &D, query: &I, radius: U, indices: &[usize]) -> Vec<(usize, U)>
+pub fn search<I, U, D>(data: &D, query: &I, radius: U, indices: &[usize], tracker: &Tracker) -> Vec<(usize, U)>
 where
     I: Instance,
     U: Number,
     D: Dataset<I, U>,
 {
-    let distances = data.query_to_many(query, indices);
-    indices
-        .iter()
-        .copied()
-        .zip(distances)
+    let indices = data.prefilter(query, indices, radius);
+    tracker
+        .query_to_many(data, query, &indices)
+        .into_iter()
         .filter(|&(_, d)| d <= radius)
         .collect()
 }
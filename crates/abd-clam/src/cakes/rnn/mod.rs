@@ -8,7 +8,7 @@
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, Tree};
+use crate::{cakes::budget::Tracker, Cluster, Dataset, Instance, Tree};
 
 pub(crate) mod clustered;
 pub(crate) mod linear;
@@ -49,6 +49,37 @@ impl Algorithm {
     /// A vector of 2-tuples, where the first element is the index of the instance
     /// and the second element is the distance from the query to the instance.
     pub fn search<I, U, D, C>(self, query: &I, radius: U, tree: &Tree<I, U, D, C>) -> Vec<(usize, U)>
+    where
+        I: Instance,
+        U: Number,
+        D: Dataset<I, U>,
+        C: Cluster<U>,
+    {
+        self.budgeted_search(query, radius, tree, &Tracker::unlimited())
+    }
+
+    /// Searches for the nearest neighbors of a query under a budget.
+    ///
+    /// If the budget runs out, the hits found so far are returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query to search around.
+    /// * `radius` - The radius to search within.
+    /// * `tree` - The tree to search.
+    /// * `tracker` - Tracks the work done against the budget.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples, where the first element is the index of the instance
+    /// and the second element is the distance from the query to the instance.
+    pub(crate) fn budgeted_search<I, U, D, C>(
+        self,
+        query: &I,
+        radius: U,
+        tree: &Tree<I, U, D, C>,
+        tracker: &Tracker,
+    ) -> Vec<(usize, U)>
     where
         I: Instance,
         U: Number,
@@ -58,9 +89,9 @@ impl Algorithm {
         match self {
             Self::Linear => {
                 let indices = (0..tree.cardinality()).collect::<Vec<_>>();
-                linear::search(tree.data(), query, radius, &indices)
+                linear::search(tree.data(), query, radius, &indices, tracker)
             }
-            Self::Clustered => clustered::search(tree, query, radius),
+            Self::Clustered => clustered::search(tree, query, radius, tracker),
         }
     }
 
@@ -1,13 +1,44 @@
 //! Supplies the `Search` trait.
 
-use std::path::Path;
+use std::{borrow::Cow, path::Path};
 
 use distances::Number;
+use mt_logger::{mt_log, Level};
+use rayon::prelude::*;
 
-use crate::{cakes::knn, cakes::rnn, Dataset, Instance};
+use crate::{
+    cakes::budget::Tracker,
+    cakes::kfn,
+    cakes::knn,
+    cakes::knn::dual_tree::{self, QueryTree},
+    cakes::rknn,
+    cakes::rnn,
+    cakes::tuning::{self, TuningProfile},
+    cakes::ShardStrategy,
+    Dataset, Instance, Tree, UniBall,
+};
 
 /// A trait for performing RNN- and KNN-Search.
-#[allow(dead_code)]
+///
+/// This is implemented by each layout of the index, e.g. a single shard or
+/// several shards, and `Cakes` delegates to it. A custom layout, e.g. one with
+/// replicated shards, may implement this trait, usually by combining
+/// `SingleShard`s, and be wrapped with `Cakes::from_search` to get the batched
+/// and tuned searches of `Cakes`.
+///
+/// The indices of the instances are global to the layout, i.e. they run from
+/// zero to the sum of the `shard_cardinalities`, and every search returns
+/// hits with these indices.
+///
+/// The searches which may fail, e.g. because a shard cannot be loaded from
+/// disk, are the `try_` methods. The other searches call these, and log the
+/// error and return no hits if they fail.
+///
+/// A layout need only implement `save`, `load`, `trees`,
+/// `shard_cardinalities`, `tuning_profile` and `tuning_profile_mut`. The other
+/// methods search the `trees` as consecutive shards, and fail if some of the
+/// shards are not in memory. A layout which keeps shards elsewhere, or which
+/// searches them in its own way, overrides `instance` and the `try_` searches.
 pub trait Search<I: Instance, U: Number, D: Dataset<I, U>>: Send + Sync {
     /// Saves the search structure to a file.
     ///
@@ -40,83 +71,604 @@ pub trait Search<I: Instance, U: Number, D: Dataset<I, U>>: Send + Sync {
     where
         Self: Sized;
 
-    /// Returns the number of shards.
-    fn num_shards(&self) -> usize;
+    /// Returns the trees of the shards which are in memory.
+    ///
+    /// When every shard is in memory, the trees are in the order of the
+    /// shards.
+    fn trees(&self) -> Vec<&Tree<I, U, D, UniBall<U>>>;
 
     /// Returns the cardinalities of the shards.
     fn shard_cardinalities(&self) -> Vec<usize>;
 
+    /// Returns the profile of the best algorithms for the values of k and
+    /// radius that have been tuned.
+    ///
+    /// Sharded layouts tune on, and return the profile of, their sample shard.
+    fn tuning_profile(&self) -> &TuningProfile<U>;
+
+    /// Returns the profile of the best algorithms, mutably, to record the
+    /// results of tuning.
+    fn tuning_profile_mut(&mut self) -> &mut TuningProfile<U>;
+
+    /// Returns the number of shards.
+    fn num_shards(&self) -> usize {
+        self.shard_cardinalities().len()
+    }
+
+    /// Returns the instance at the given global index.
+    ///
+    /// The instance is borrowed if it is in memory, and is otherwise a copy
+    /// read from its shard. By default, this is a copy of the instance from
+    /// the `trees`, and layouts which own their trees override this to
+    /// borrow it.
+    ///
+    /// # Errors
+    ///
+    /// * If the index is out of range.
+    /// * If the layout loads shards on demand and the shard holding the
+    ///   instance cannot be loaded.
+    fn instance(&self, index: usize) -> Result<Cow<'_, I>, String> {
+        let (s, i) = locate(&self.shard_cardinalities(), index)?;
+        self.trees()
+            .get(s)
+            .map(|tree| Cow::Owned(tree.data()[i].clone()))
+            .ok_or_else(|| format!("Shard {s}, which holds index {index}, is not in memory."))
+    }
+
+    /// Returns copies of the instances at the given global indices.
+    ///
+    /// Layouts which load shards on demand should override this to load each
+    /// shard at most once.
+    ///
+    /// # Errors
+    ///
+    /// * If one of the indices is out of range.
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_instances(&self, indices: &[usize]) -> Result<Vec<I>, String> {
+        indices.iter().map(|&i| self.instance(i).map(Cow::into_owned)).collect()
+    }
+
+    /// Returns the index, in the dataset from which the layout was built, of
+    /// the instance at the given global index.
+    ///
+    /// Building the trees reorders the instances, and sharding may also
+    /// reorder them, so this maps the hits of a search back to the original
+    /// dataset.
+    ///
+    /// # Errors
+    ///
+    /// * If the index is out of range.
+    /// * If the shard holding the instance is not in memory and cannot be
+    ///   loaded.
+    fn original_index(&self, index: usize) -> Result<usize, String> {
+        let (s, i) = locate(&self.shard_cardinalities(), index)?;
+        self.trees()
+            .get(s)
+            .map(|tree| tree.data().original_index(i))
+            .ok_or_else(|| format!("Shard {s}, which holds index {index}, is not in memory."))
+    }
+
+    /// Sets the strategy used for KNN-Search across the shards.
+    ///
+    /// This does nothing by default, for layouts with a single way of
+    /// searching their shards.
+    fn set_shard_strategy(&mut self, _strategy: ShardStrategy) {}
+
     /// Returns the best RNN-Search algorithm.
     ///
     /// If the algorithm has not been tuned, this will return the default variant.
-    fn tuned_rnn_algorithm(&self) -> rnn::Algorithm;
+    fn tuned_rnn_algorithm(&self) -> rnn::Algorithm {
+        rnn::Algorithm::default()
+    }
 
-    /// Performs an RNN-Search.
+    /// Performs an RNN-Search under a budget.
+    ///
+    /// If the budget runs out, the hits found so far are returned.
     ///
     /// # Arguments
     ///
     /// * `query` - The query instance.
     /// * `radius` - The radius to use for the search.
     /// * `algo` - The algorithm to use for the search.
+    /// * `tracker` - Tracks the work done against the budget.
     ///
     /// # Returns
     ///
     /// A vector of 2-tuples containing the index of the instance and its
     /// distance to the query.
-    fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)>;
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_budgeted_rnn_search(
+        &self,
+        query: &I,
+        radius: U,
+        algo: rnn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, U)>, String> {
+        let shards = in_memory_shards(self)?;
+        Ok(shards
+            .into_par_iter()
+            .flat_map(|(tree, o)| {
+                algo.budgeted_search(query, radius, tree, tracker)
+                    .into_par_iter()
+                    .map(move |(i, d)| (i + o, d))
+            })
+            .collect())
+    }
+
+    /// Performs an RNN-Search.
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        self.try_budgeted_rnn_search(query, radius, algo, &Tracker::unlimited())
+    }
+
+    /// Performs an RNN-Search.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `radius` - The radius to use for the search.
+    /// * `algo` - The algorithm to use for the search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples containing the index of the instance and its
+    /// distance to the query, which is empty if the search failed.
+    fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
+        or_log(self.try_rnn_search(query, radius, algo))
+    }
+
+    /// Performs an RNN-Search under a budget, as in `try_budgeted_rnn_search`.
+    ///
+    /// The hits are empty if the search failed.
+    fn budgeted_rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm, tracker: &Tracker) -> Vec<(usize, U)> {
+        or_log(self.try_budgeted_rnn_search(query, radius, algo, tracker))
+    }
 
     /// Performs RNN-Search using the naive linear algorithm.
-    fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)>;
+    fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
+        self.rnn_search(query, radius, rnn::Algorithm::Linear)
+    }
 
     /// Returns the best KNN-Search algorithm.
     ///
     /// If the algorithm has not been tuned, this will return the default variant.
-    fn tuned_knn_algorithm(&self) -> knn::Algorithm;
+    fn tuned_knn_algorithm(&self) -> knn::Algorithm {
+        knn::Algorithm::default()
+    }
 
-    /// Performs a KNN-Search.
+    /// Performs a KNN-Search under a budget.
+    ///
+    /// If the budget runs out, the best hits found so far are returned. By
+    /// default, the shards are searched in order, as in
+    /// `ShardStrategy::Sequential`.
     ///
     /// # Arguments
     ///
     /// * `query` - The query instance.
     /// * `k` - The number of neighbors to search for.
     /// * `algo` - The algorithm to use for the search.
+    /// * `tracker` - Tracks the work done against the budget.
     ///
     /// # Returns
     ///
     /// A vector of 2-tuples containing the index of the instance and its
     /// distance to the query.
-    fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)>;
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_budgeted_knn_search(
+        &self,
+        query: &I,
+        k: usize,
+        algo: knn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, U)>, String> {
+        let shards = in_memory_shards(self)?;
+        Ok(sequential_knn_search(&shards, query, k, algo, tracker))
+    }
+
+    /// Performs a KNN-Search.
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        self.try_budgeted_knn_search(query, k, algo, &Tracker::unlimited())
+    }
+
+    /// Performs a KNN-Search.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of neighbors to search for.
+    /// * `algo` - The algorithm to use for the search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples containing the index of the instance and its
+    /// distance to the query, which is empty if the search failed.
+    fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
+        or_log(self.try_knn_search(query, k, algo))
+    }
+
+    /// Performs a KNN-Search under a budget, as in `try_budgeted_knn_search`.
+    ///
+    /// The hits are empty if the search failed.
+    fn budgeted_knn_search(&self, query: &I, k: usize, algo: knn::Algorithm, tracker: &Tracker) -> Vec<(usize, U)> {
+        or_log(self.try_budgeted_knn_search(query, k, algo, tracker))
+    }
+
+    /// Performs KNN-Search for a batch of queries by traversing a tree over
+    /// the queries together with the tree over each shard.
+    ///
+    /// If some of the shards are not in memory, this searches for each query
+    /// independently with the tuned algorithm instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `query_tree` - The tree over the queries.
+    /// * `k` - The number of neighbors to search for.
+    ///
+    /// # Returns
+    ///
+    /// For each query, in the order in which the queries were given to build
+    /// the query tree, a vector of 2-tuples containing the index of the
+    /// instance and its distance to the query.
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_dual_tree_knn_search(&self, query_tree: &QueryTree<I, U>, k: usize) -> Result<Vec<Vec<(usize, U)>>, String> {
+        let queries = query_tree.data();
+        let Ok(shards) = in_memory_shards(self) else {
+            let mut hits = vec![Vec::new(); queries.cardinality()];
+            for i in 0..queries.cardinality() {
+                let algo = self.tuned_knn_algorithm_for(k);
+                hits[queries.original_index(i)] = self.try_knn_search(&queries[i], k, algo)?;
+            }
+            return Ok(hits);
+        };
+
+        if let [(tree, _)] = shards.as_slice() {
+            return Ok(dual_tree::search(tree, query_tree, k));
+        }
+
+        let mut hits_queues = (0..queries.cardinality())
+            .map(|_| knn::Hits::new(k))
+            .collect::<Vec<_>>();
+        for (tree, o) in shards {
+            let new_hits = dual_tree::search(tree, query_tree, k);
+            for (hits_queue, new_hits) in hits_queues.iter_mut().zip(new_hits) {
+                hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
+            }
+        }
+        Ok(hits_queues.iter().map(knn::Hits::extract).collect())
+    }
+
+    /// Performs KNN-Search for a batch of queries, as in
+    /// `try_dual_tree_knn_search`.
+    ///
+    /// The hits of every query are empty if the search failed.
+    fn dual_tree_knn_search(&self, query_tree: &QueryTree<I, U>, k: usize) -> Vec<Vec<(usize, U)>> {
+        self.try_dual_tree_knn_search(query_tree, k).unwrap_or_else(|e| {
+            mt_log!(Level::Error, "{e}");
+            vec![Vec::new(); query_tree.data().cardinality()]
+        })
+    }
 
     /// Auto-tunes the RNN-Search algorithm and sets it as the best.
     ///
+    /// The queries are the centers of the clusters at `tuning_depth` in the
+    /// first tree in memory, e.g. that of the sample shard.
+    ///
     /// # Arguments
     ///
     /// * `radius` - The radius to tune for.
     /// * `tuning_depth` - The depth to use for tuning.
-    fn auto_tune_rnn(&mut self, radius: U, tuning_depth: usize);
+    fn auto_tune_rnn(&mut self, radius: U, tuning_depth: usize) {
+        let queries = self
+            .trees()
+            .first()
+            .map_or_else(Vec::new, |tree| tuning::sample_queries(tree, tuning_depth));
+        self.auto_tune_rnn_on(radius, &queries.iter().collect::<Vec<_>>());
+    }
 
     /// Auto-tunes the KNN-Search algorithm and sets it as the best.
     ///
+    /// The queries are chosen as in `auto_tune_rnn`.
+    ///
     /// # Arguments
     ///
     /// * `k` - The number of neighbors to tune for.
     /// * `tuning_depth` - The depth to use for tuning.
-    fn auto_tune_knn(&mut self, k: usize, tuning_depth: usize);
+    fn auto_tune_knn(&mut self, k: usize, tuning_depth: usize) {
+        let queries = self
+            .trees()
+            .first()
+            .map_or_else(Vec::new, |tree| tuning::sample_queries(tree, tuning_depth));
+        self.auto_tune_knn_on(k, &queries.iter().collect::<Vec<_>>());
+    }
+
+    /// Auto-tunes the RNN-Search algorithm on the given queries and sets it as
+    /// the best.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - The radius to tune for.
+    /// * `queries` - The queries to use for tuning.
+    fn auto_tune_rnn_on(&mut self, radius: U, queries: &[&I]) {
+        if let Some(entry) = tuning::time_rnn(&*self, radius, queries) {
+            self.tuning_profile_mut().insert_rnn(entry);
+        }
+    }
+
+    /// Auto-tunes the KNN-Search algorithm on the given queries and sets it as
+    /// the best.
+    ///
+    /// # Arguments
+    ///
+    /// * `k` - The number of neighbors to tune for.
+    /// * `queries` - The queries to use for tuning.
+    fn auto_tune_knn_on(&mut self, k: usize, queries: &[&I]) {
+        if let Some(entry) = tuning::time_knn(&*self, k, queries) {
+            self.tuning_profile_mut().insert_knn(entry);
+        }
+    }
 
     /// Performs KNN-Search using the naive linear algorithm.
-    fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)>;
+    fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
+        self.knn_search(query, k, knn::Algorithm::Linear)
+    }
+
+    /// Performs a KFN-Search.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of farthest neighbors to search for.
+    /// * `algo` - The algorithm to use for the search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples containing the index of the instance and its
+    /// distance to the query.
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_kfn_search(&self, query: &I, k: usize, algo: kfn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        let shards = in_memory_shards(self)?;
+        if let [(tree, _)] = shards.as_slice() {
+            return Ok(algo.search(tree, query, k));
+        }
+
+        let mut hits_queue = kfn::Hits::new(k);
+        for (tree, o) in shards {
+            let new_hits = algo.search(tree, query, k);
+            hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
+        }
+        Ok(hits_queue.extract())
+    }
+
+    /// Performs a KFN-Search, as in `try_kfn_search`.
+    ///
+    /// The hits are empty if the search failed.
+    fn kfn_search(&self, query: &I, k: usize, algo: kfn::Algorithm) -> Vec<(usize, U)> {
+        or_log(self.try_kfn_search(query, k, algo))
+    }
+
+    /// Performs a Reverse KNN-Search.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query instance.
+    /// * `k` - The number of neighbors that defines the neighborhood of each instance.
+    /// * `algo` - The algorithm to use for the search.
+    ///
+    /// # Returns
+    ///
+    /// A vector of 2-tuples containing the index of the instance and its
+    /// distance to the query.
+    ///
+    /// # Errors
+    ///
+    /// * If the layout loads shards on demand and one of them cannot be
+    ///   loaded.
+    fn try_rknn_search(&self, query: &I, k: usize, algo: rknn::Algorithm) -> Result<Vec<(usize, U)>, String> {
+        let shards = in_memory_shards(self)?;
+        if let [(tree, _)] = shards.as_slice() {
+            return Ok(algo.search(tree, query, k));
+        }
+        if k == 0 {
+            return Ok(Vec::new());
+        }
+
+        // Candidates are found in each shard, but they must be verified against
+        // the k-nearest neighbors from all shards.
+        let knn_algo = match algo {
+            rknn::Algorithm::Linear => knn::Algorithm::Linear,
+            rknn::Algorithm::Clustered => knn::Algorithm::default(),
+        };
+        let mut hits = Vec::new();
+        for (tree, o) in shards {
+            let data = tree.data();
+            let candidates = match algo {
+                rknn::Algorithm::Linear => {
+                    let indices = (0..data.cardinality()).collect::<Vec<_>>();
+                    let distances = data.query_to_many(query, &indices);
+                    indices.into_iter().zip(distances).collect::<Vec<_>>()
+                }
+                rknn::Algorithm::Clustered => rknn::clustered::tree_search(data, tree.root(), query, k),
+            };
+
+            let verified = candidates
+                .into_par_iter()
+                .map(|(i, d)| {
+                    let neighbors = self.try_knn_search(&data[i], k + 1, knn_algo)?;
+                    Ok(rknn::is_reverse_neighbor(i + o, d, k, &neighbors).then_some((i + o, d)))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            hits.extend(verified.into_iter().flatten());
+        }
+        Ok(hits)
+    }
+
+    /// Performs a Reverse KNN-Search, as in `try_rknn_search`.
+    ///
+    /// The hits are empty if the search failed.
+    fn rknn_search(&self, query: &I, k: usize, algo: rknn::Algorithm) -> Vec<(usize, U)> {
+        or_log(self.try_rknn_search(query, k, algo))
+    }
+
+    /// Returns the best RNN-Search algorithm for the given radius.
+    ///
+    /// This uses the tuning profile if RNN-Search has been tuned, and the
+    /// default variant otherwise.
+    fn tuned_rnn_algorithm_for(&self, radius: U) -> rnn::Algorithm {
+        self.tuning_profile()
+            .rnn_algorithm(radius)
+            .unwrap_or_else(|| self.tuned_rnn_algorithm())
+    }
+
+    /// Returns the best KNN-Search algorithm for the given k.
+    ///
+    /// This uses the tuning profile if KNN-Search has been tuned, and the
+    /// default variant otherwise.
+    fn tuned_knn_algorithm_for(&self, k: usize) -> knn::Algorithm {
+        self.tuning_profile()
+            .knn_algorithm(k)
+            .unwrap_or_else(|| self.tuned_knn_algorithm())
+    }
 
     /// Performs RNN-Search using the best algorithm.
-    #[allow(dead_code)]
     fn tuned_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        let algo = self.tuned_rnn_algorithm();
+        let algo = self.tuned_rnn_algorithm_for(radius);
         self.rnn_search(query, radius, algo)
     }
 
     /// Performs KNN-Search using the best algorithm.
-    #[allow(dead_code)]
     fn tuned_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        let algo = self.tuned_knn_algorithm();
+        let algo = self.tuned_knn_algorithm_for(k);
         self.knn_search(query, k, algo)
     }
 }
+
+/// Returns the shard holding the instance at the given global index, and the
+/// index of the instance in that shard.
+///
+/// # Errors
+///
+/// * If the index is out of range.
+fn locate(cardinalities: &[usize], index: usize) -> Result<(usize, usize), String> {
+    let mut offset = 0;
+    for (s, &cardinality) in cardinalities.iter().enumerate() {
+        if index < offset + cardinality {
+            return Ok((s, index - offset));
+        }
+        offset += cardinality;
+    }
+    Err(format!("Index {index} is out of range for a cardinality of {offset}."))
+}
+
+/// Returns the trees of all the shards of a layout, with the offsets of their
+/// indices.
+///
+/// # Errors
+///
+/// * If some of the shards are not in memory.
+#[allow(clippy::type_complexity)]
+fn in_memory_shards<I, U, D, S>(search: &S) -> Result<Vec<(&Tree<I, U, D, UniBall<U>>, usize)>, String>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    S: Search<I, U, D> + ?Sized,
+{
+    let trees = search.trees();
+    let cardinalities = search.shard_cardinalities();
+    if trees.len() != cardinalities.len() {
+        return Err(format!(
+            "Only {} of the {} shards are in memory.",
+            trees.len(),
+            cardinalities.len()
+        ));
+    }
+
+    let offsets = cardinalities.iter().scan(0, |o, &c| {
+        let offset = *o;
+        *o += c;
+        Some(offset)
+    });
+    Ok(trees.into_iter().zip(offsets).collect())
+}
+
+/// Performs a KNN-Search under a budget by searching the first shard and then
+/// the remaining shards one at a time, tightening the search radius after
+/// each shard.
+///
+/// # Arguments
+///
+/// * `shards` - The trees of the shards, with the offsets of their indices.
+/// * `query` - The query instance.
+/// * `k` - The number of neighbors to search for.
+/// * `algo` - The algorithm to use for the search.
+/// * `tracker` - Tracks the work done against the budget.
+#[allow(clippy::type_complexity)]
+pub(crate) fn sequential_knn_search<I: Instance, U: Number, D: Dataset<I, U>>(
+    shards: &[(&Tree<I, U, D, UniBall<U>>, usize)],
+    query: &I,
+    k: usize,
+    algo: knn::Algorithm,
+    tracker: &Tracker,
+) -> Vec<(usize, U)> {
+    let Some(((first, first_o), rest)) = shards.split_first() else {
+        return Vec::new();
+    };
+    let initial_hits = algo
+        .budgeted_search(first, query, k, tracker)
+        .into_iter()
+        .map(|(i, d)| (i + first_o, d))
+        .collect::<Vec<_>>();
+    if rest.is_empty() {
+        return initial_hits;
+    }
+
+    let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
+    for &(tree, o) in rest {
+        if tracker.is_exhausted() {
+            break;
+        }
+        let new_hits = if hits_queue.len() < k {
+            algo.budgeted_search(tree, query, k, tracker)
+        } else {
+            rnn::Algorithm::Clustered.budgeted_search(query, hits_queue.peek(), tree, tracker)
+        };
+        hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
+    }
+
+    hits_queue.extract()
+}
+
+/// Returns the hits of a search, or logs the error and returns no hits if the
+/// search failed.
+pub(crate) fn or_log<T: Default>(result: Result<T, String>) -> T {
+    result.unwrap_or_else(|e| {
+        mt_log!(Level::Error, "{e}");
+        T::default()
+    })
+}
@@ -2,11 +2,59 @@
 
 use core::ops::AddAssign;
 
+use std::{
+    borrow::Cow,
+    sync::{Mutex, PoisonError},
+};
+
 use distances::Number;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
 use rayon::prelude::*;
 
-use super::{Search, SingleShard};
-use crate::{cakes::knn, cakes::rnn, Dataset, Instance};
+use super::{lazy::ShardRoot, search, Search, SingleShard};
+use crate::{
+    cakes::budget::Tracker, cakes::knn, cakes::rnn, cakes::tuning::TuningProfile, Cluster, Dataset, Instance,
+    PartitionCriteria, Tree, UniBall, VecDataset,
+};
+
+/// The strategy used for K-Nearest Neighbor search across the shards.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ShardStrategy {
+    /// Search the sample shard for an initial set of hits, and then search
+    /// the remaining shards one at a time, tightening the search radius after
+    /// each shard.
+    #[default]
+    Sequential,
+    /// Search the shard whose root is closest to the query for an initial set
+    /// of hits, and then search the remaining shards concurrently. The shards
+    /// share a search radius which is tightened as hits are found, and shards
+    /// whose root lies entirely outside that radius are skipped.
+    Parallel,
+}
+
+impl ShardStrategy {
+    /// Returns the name of the strategy.
+    #[must_use]
+    pub const fn name(&self) -> &str {
+        match self {
+            Self::Sequential => "Sequential",
+            Self::Parallel => "Parallel",
+        }
+    }
+
+    /// Returns the strategy with the given name.
+    ///
+    /// # Errors
+    ///
+    /// * If the name is not a valid strategy name.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "sequential" => Ok(Self::Sequential),
+            "parallel" => Ok(Self::Parallel),
+            _ => Err(format!("Unknown shard strategy: {name}")),
+        }
+    }
+}
 
 /// Cakes search with sharded datasets.
 ///
@@ -24,8 +72,10 @@ pub struct RandomlySharded<I: Instance, U: Number, D: Dataset<I, U>> {
     sample_shard: SingleShard<I, U, D>,
     /// The full shards.
     shards: Vec<SingleShard<I, U, D>>,
-    /// The Dataset.
+    /// The offsets of the indices of every shard, starting with the sample shard.
     offsets: Vec<usize>,
+    /// The strategy used for K-Nearest Neighbor search across the shards.
+    strategy: ShardStrategy,
 }
 
 impl<I: Instance, U: Number, D: Dataset<I, U>> RandomlySharded<I, U, D> {
@@ -41,11 +91,12 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> RandomlySharded<I, U, D> {
             .pop()
             .unwrap_or_else(|| unreachable!("There should be at least one shard."));
 
-        let offsets = new_shards
-            .iter()
-            .scan(sample_shard.data().cardinality(), |o, d| {
+        let offsets = core::iter::once(&sample_shard)
+            .chain(new_shards.iter())
+            .scan(0, |o, d| {
+                let offset = *o;
                 o.add_assign(d.data().cardinality());
-                Some(*o)
+                Some(offset)
             })
             .collect::<Vec<_>>();
 
@@ -53,18 +104,200 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> RandomlySharded<I, U, D> {
             sample_shard,
             shards: new_shards,
             offsets,
+            strategy: ShardStrategy::default(),
         }
     }
 
+    /// Returns the strategy used for K-Nearest Neighbor search across the shards.
+    pub const fn strategy(&self) -> ShardStrategy {
+        self.strategy
+    }
+
+    /// Sets the strategy used for K-Nearest Neighbor search across the shards.
+    pub fn set_strategy(&mut self, strategy: ShardStrategy) {
+        self.strategy = strategy;
+    }
+
     /// Returns the shards.
     pub fn shards(&self) -> Vec<&SingleShard<I, U, D>> {
         core::iter::once(&self.sample_shard).chain(self.shards.iter()).collect()
     }
 
-    /// Returns the offsets of the shard indices.
-    pub fn offsets(&self) -> &[usize] {
+    /// Returns the offset of the indices of each shard, starting with the
+    /// sample shard, i.e. the global index of the first instance of the shard.
+    pub fn shard_offsets(&self) -> &[usize] {
         &self.offsets
     }
+
+    /// Returns the shards paired with the offsets of their indices.
+    fn shards_with_offsets(&self) -> Vec<(&SingleShard<I, U, D>, usize)> {
+        self.shards().into_iter().zip(self.offsets.iter().copied()).collect()
+    }
+
+    /// Returns the shards, with the offsets of their indices, sorted by the
+    /// minimum possible distance from the query to an instance in the shard.
+    fn shards_by_distance(&self, query: &I) -> Vec<(&SingleShard<I, U, D>, usize, U)> {
+        let mut shards = self
+            .shards_with_offsets()
+            .into_par_iter()
+            .map(|(shard, o)| {
+                let root = shard.tree().root();
+                let d = shard.data().query_to_one(query, root.arg_center());
+                let d_min = if d > root.radius() {
+                    d - root.radius()
+                } else {
+                    U::zero()
+                };
+                (shard, o, d_min)
+            })
+            .collect::<Vec<_>>();
+        shards.sort_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Less));
+        shards
+    }
+
+    /// Performs K-Nearest Neighbor search by starting with the closest shard
+    /// and then searching the remaining shards concurrently with a shared,
+    /// tightening, search radius.
+    fn parallel_knn_search(&self, query: &I, k: usize, algo: knn::Algorithm, tracker: &Tracker) -> Vec<(usize, U)> {
+        if !tracker.spend(self.num_shards()) {
+            return Vec::new();
+        }
+        let shards = self.shards_by_distance(query);
+        let ((first, first_o, _), rest) = shards
+            .split_first()
+            .unwrap_or_else(|| unreachable!("There should be at least one shard."));
+
+        let initial_hits = first.budgeted_knn_search(query, k, algo, tracker);
+        let hits_queue = Mutex::new(knn::Hits::from_vec(
+            k,
+            initial_hits.into_iter().map(|(i, d)| (i + first_o, d)).collect(),
+        ));
+
+        rest.par_iter().for_each(|&(shard, o, d_min)| {
+            // The radius is only an upper bound on the distance to the k-th
+            // nearest neighbor once the queue is full.
+            let radius = {
+                let hits_queue = hits_queue.lock().unwrap_or_else(PoisonError::into_inner);
+                (hits_queue.len() == k).then(|| hits_queue.peek())
+            };
+
+            let new_hits = match radius {
+                _ if tracker.is_exhausted() => return,
+                Some(radius) if d_min > radius => return,
+                Some(radius) => shard.budgeted_rnn_search(query, radius, rnn::Algorithm::Clustered, tracker),
+                None => shard.budgeted_knn_search(query, k, algo, tracker),
+            };
+
+            hits_queue
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
+        });
+
+        hits_queue
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .extract()
+    }
+}
+
+impl<I: Instance, U: Number, M: Instance> RandomlySharded<I, U, VecDataset<I, U, M>> {
+    /// Partitions a dataset into a random sample shard followed by shards which
+    /// follow the clusters of a tree built on the rest of the dataset.
+    ///
+    /// The first shard, which is used for tuning, is a random sample of
+    /// `max_cardinality` instances so that it is representative of the whole
+    /// dataset. The tree on the remaining instances is walked down from the
+    /// root, keeping each cluster with at most `max_cardinality` instances and
+    /// splitting the larger ones, and neighboring clusters are then merged
+    /// while they fit in a shard. Each of the other shards therefore covers a
+    /// compact region of the metric space, so most queries will only need to
+    /// search a few of the shards.
+    ///
+    /// Every shard has at most `max_cardinality` instances, and keeps the
+    /// original indices of its instances, so the hits of a search over these
+    /// shards can be mapped back to indices in `data` with
+    /// `Search::original_index`.
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The dataset to shard.
+    /// * `max_cardinality` - The maximum cardinality of each shard.
+    /// * `seed` - The seed to use for the random number generator.
+    #[must_use]
+    pub fn cluster_shards(
+        mut data: VecDataset<I, U, M>,
+        max_cardinality: usize,
+        seed: Option<u64>,
+    ) -> Vec<VecDataset<I, U, M>> {
+        let max_cardinality = max_cardinality.max(1);
+        let cardinality = data.cardinality();
+        if cardinality <= max_cardinality {
+            return vec![data];
+        }
+
+        // Move a random sample to the front of the dataset for the sample shard.
+        let mut permutation = (0..cardinality).collect::<Vec<_>>();
+        match seed {
+            Some(seed) => permutation.shuffle(&mut StdRng::seed_from_u64(seed)),
+            None => permutation.shuffle(&mut rand::thread_rng()),
+        }
+        data.permute_instances(&permutation)
+            .unwrap_or_else(|e| unreachable!("The permutation covers the dataset: {e}"));
+        let mut split = data
+            .split_into_shards(&[max_cardinality, cardinality - max_cardinality])
+            .unwrap_or_else(|e| unreachable!("The cardinalities sum to that of the dataset: {e}"));
+        let rest = split.pop().unwrap_or_else(|| unreachable!("There are two shards."));
+        let sample = split.pop().unwrap_or_else(|| unreachable!("There are two shards."));
+
+        let criteria = PartitionCriteria::new(true).with_min_cardinality(max_cardinality);
+        let tree = Tree::<I, U, _, UniBall<U>>::new(rest, seed).partition(&criteria, seed);
+        let cardinalities = Self::cluster_cardinalities(tree.root(), max_cardinality);
+
+        let shards = tree
+            .data
+            .split_into_shards(&cardinalities)
+            .unwrap_or_else(|e| unreachable!("The clusters cover the dataset: {e}"));
+        core::iter::once(sample).chain(shards).collect()
+    }
+
+    /// Returns the cardinalities of consecutive shards, of at most
+    /// `max_cardinality` instances each, which follow the clusters of a tree.
+    ///
+    /// A leaf with more than `max_cardinality` instances, e.g. one of
+    /// duplicates, is cut into pieces which fit in a shard.
+    fn cluster_cardinalities(root: &UniBall<U>, max_cardinality: usize) -> Vec<usize> {
+        let mut pieces = Vec::new();
+        let mut stack = vec![root];
+        while let Some(c) = stack.pop() {
+            if c.cardinality() <= max_cardinality {
+                pieces.push(c.cardinality());
+            } else if let Some([left, right]) = c.children() {
+                // The left child is popped first, so the pieces are in order
+                // of their offsets.
+                stack.push(right);
+                stack.push(left);
+            } else {
+                let mut remaining = c.cardinality();
+                while remaining > 0 {
+                    let piece = remaining.min(max_cardinality);
+                    pieces.push(piece);
+                    remaining -= piece;
+                }
+            }
+        }
+
+        // Neighboring clusters are close together in the tree, so they are
+        // merged into one shard rather than each making a tiny shard.
+        let mut cardinalities: Vec<usize> = Vec::new();
+        for piece in pieces {
+            match cardinalities.last_mut() {
+                Some(last) if *last + piece <= max_cardinality => *last += piece,
+                _ => cardinalities.push(piece),
+            }
+        }
+        cardinalities
+    }
 }
 
 impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlySharded<I, U, D> {
@@ -96,7 +329,9 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlyShard
             shard.save(&shard_dir)?;
         }
 
-        Ok(())
+        // The roots allow the shards to be loaded lazily.
+        let roots = self.shards.iter().map(ShardRoot::of).collect::<Vec<_>>();
+        ShardRoot::save_all(&roots, &shards_dir)
     }
 
     #[allow(clippy::similar_names)]
@@ -128,8 +363,22 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlyShard
         Ok(Self::new(shards))
     }
 
-    fn num_shards(&self) -> usize {
-        1 + self.shards.len()
+    fn trees(&self) -> Vec<&Tree<I, U, D, UniBall<U>>> {
+        self.shards().into_iter().map(SingleShard::tree).collect()
+    }
+
+    fn instance(&self, index: usize) -> Result<Cow<'_, I>, String> {
+        let i = self.offsets.partition_point(|&o| o <= index) - 1;
+        let shard = if i == 0 {
+            &self.sample_shard
+        } else {
+            &self.shards[i - 1]
+        };
+        shard.instance(index - self.offsets[i])
+    }
+
+    fn set_shard_strategy(&mut self, strategy: ShardStrategy) {
+        self.set_strategy(strategy);
     }
 
     fn shard_cardinalities(&self) -> Vec<usize> {
@@ -142,64 +391,44 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for RandomlyShard
         self.sample_shard.tuned_rnn_algorithm()
     }
 
-    fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
-        self.sample_shard
-            .rnn_search(query, radius, algo)
-            .into_par_iter()
-            .chain(
-                self.shards
-                    .par_iter()
-                    .zip(self.offsets.par_iter())
-                    .map(|(shard, &o)| {
-                        shard
-                            .rnn_search(query, radius, algo)
-                            .into_par_iter()
-                            .map(move |(i, d)| (i + o, d))
-                    })
-                    .flatten(),
-            )
-            .collect()
-    }
-
-    fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        self.rnn_search(query, radius, rnn::Algorithm::Linear)
-    }
-
     fn tuned_knn_algorithm(&self) -> knn::Algorithm {
         self.sample_shard.tuned_knn_algorithm()
     }
 
-    fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
-        let initial_hits = self.sample_shard.knn_search(query, k, algo);
-        let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
-
-        for (shard, &o) in self.shards.iter().zip(self.offsets.iter()) {
-            let radius = hits_queue.peek();
-            let new_hits = shard.rnn_search(query, radius, rnn::Algorithm::Clustered);
-            hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
-        }
-
-        hits_queue.extract()
+    fn try_budgeted_knn_search(
+        &self,
+        query: &I,
+        k: usize,
+        algo: knn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, U)>, String> {
+        Ok(match self.strategy {
+            ShardStrategy::Sequential => {
+                let shards = self
+                    .shards_with_offsets()
+                    .into_iter()
+                    .map(|(shard, o)| (shard.tree(), o))
+                    .collect::<Vec<_>>();
+                search::sequential_knn_search(&shards, query, k, algo, tracker)
+            }
+            ShardStrategy::Parallel => self.parallel_knn_search(query, k, algo, tracker),
+        })
     }
 
-    fn auto_tune_rnn(&mut self, radius: U, tuning_depth: usize) {
-        self.sample_shard.auto_tune_rnn(radius, tuning_depth);
+    fn auto_tune_rnn_on(&mut self, radius: U, queries: &[&I]) {
+        self.sample_shard.auto_tune_rnn_on(radius, queries);
     }
 
-    fn auto_tune_knn(&mut self, k: usize, tuning_depth: usize) {
-        self.sample_shard.auto_tune_knn(k, tuning_depth);
+    fn auto_tune_knn_on(&mut self, k: usize, queries: &[&I]) {
+        self.sample_shard.auto_tune_knn_on(k, queries);
     }
 
-    fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        let initial_hits = self.sample_shard.knn_search(query, k, knn::Algorithm::Linear);
-        let mut hits_queue = knn::Hits::from_vec(k, initial_hits);
-
-        for (shard, &o) in self.shards.iter().zip(self.offsets.iter()) {
-            let new_hits = shard.knn_search(query, k, knn::Algorithm::Linear);
-            hits_queue.push_batch(new_hits.into_iter().map(|(i, d)| (i + o, d)));
-        }
+    fn tuning_profile(&self) -> &TuningProfile<U> {
+        self.sample_shard.tuning_profile()
+    }
 
-        hits_queue.extract()
+    fn tuning_profile_mut(&mut self) -> &mut TuningProfile<U> {
+        self.sample_shard.tuning_profile_mut()
     }
 }
 
@@ -211,11 +440,11 @@ mod tests {
     use symagen::random_data;
 
     use crate::{
-        cakes::{knn, rnn, Search, SingleShard},
+        cakes::{kfn, knn, rknn, rnn, Search, SingleShard},
         Dataset, PartitionCriteria, VecDataset,
     };
 
-    use super::RandomlySharded;
+    use super::{RandomlySharded, ShardStrategy};
 
     #[allow(clippy::ptr_arg)]
     fn metric(a: &Vec<f32>, b: &Vec<f32>) -> f32 {
@@ -339,4 +568,157 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn farthest_and_reverse() {
+        let seed = 42;
+        let (cardinality, dimensionality) = (2_000, 5);
+
+        let data_vec = random_data::random_tabular(
+            cardinality,
+            dimensionality,
+            -1.,
+            1.,
+            &mut rand::rngs::StdRng::seed_from_u64(seed),
+        );
+        let queries = random_data::random_tabular(
+            10,
+            dimensionality,
+            -1.,
+            1.,
+            &mut rand::rngs::StdRng::seed_from_u64(seed + 1),
+        );
+
+        let criteria = PartitionCriteria::default();
+        let data = VecDataset::new("test-full".to_string(), data_vec.clone(), metric, false);
+        let cakes = SingleShard::new(data, Some(seed), &criteria);
+
+        let shards = VecDataset::new("test-sharded".to_string(), data_vec, metric, false)
+            .make_shards(cardinality / 4)
+            .into_iter()
+            .map(|d| SingleShard::new(d, Some(seed), &criteria))
+            .collect::<Vec<_>>();
+        let sharded_cakes = RandomlySharded::new(shards);
+
+        let sorted_distances = |hits: Vec<(usize, f32)>| {
+            let mut distances = hits.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+            distances
+        };
+
+        for k in [1, 10] {
+            for (i, query) in queries.iter().enumerate() {
+                let expected = sorted_distances(cakes.kfn_search(query, k, kfn::Algorithm::Linear));
+                let actual = sorted_distances(sharded_cakes.kfn_search(query, k, kfn::Algorithm::Clustered));
+                assert_eq!(expected, actual, "Failed KFN search: query: {i}, k: {k}");
+
+                let expected = sorted_distances(cakes.rknn_search(query, k, rknn::Algorithm::Linear));
+                for algo in [rknn::Algorithm::Linear, rknn::Algorithm::Clustered] {
+                    let actual = sorted_distances(sharded_cakes.rknn_search(query, k, algo));
+                    assert_eq!(
+                        expected,
+                        actual,
+                        "Failed {} RKNN search: query: {i}, k: {k}",
+                        algo.name()
+                    );
+                }
+            }
+        }
+
+        for algo in [rknn::Algorithm::Linear, rknn::Algorithm::Clustered] {
+            assert!(sharded_cakes.rknn_search(&queries[0], 0, algo).is_empty());
+        }
+    }
+
+    #[test]
+    fn strategies_and_cluster_shards() {
+        let seed = 42;
+        let (cardinality, dimensionality) = (5_000, 5);
+
+        let data_vec = random_data::random_tabular(
+            cardinality,
+            dimensionality,
+            -1.,
+            1.,
+            &mut rand::rngs::StdRng::seed_from_u64(seed),
+        );
+        let queries = random_data::random_tabular(
+            20,
+            dimensionality,
+            -1.,
+            1.,
+            &mut rand::rngs::StdRng::seed_from_u64(seed + 1),
+        );
+
+        let criteria = PartitionCriteria::default();
+        let data = VecDataset::new("test-full".to_string(), data_vec.clone(), metric, false);
+        let cakes = SingleShard::new(data, Some(seed), &criteria);
+
+        let max_cardinality = cardinality / 8;
+        let random_shards =
+            VecDataset::new("test-random".to_string(), data_vec.clone(), metric, false).make_shards(max_cardinality);
+        let cluster_shards = RandomlySharded::cluster_shards(
+            VecDataset::new("test-cluster".to_string(), data_vec, metric, false),
+            max_cardinality,
+            Some(seed),
+        );
+        for s in ["sequential", "Sequential", "PARALLEL"] {
+            assert!(ShardStrategy::from_name(s).is_ok());
+        }
+        assert_eq!(
+            cluster_shards.iter().map(Dataset::cardinality).sum::<usize>(),
+            cardinality
+        );
+        assert!(cluster_shards.iter().all(|d| d.cardinality() <= max_cardinality));
+
+        let sorted_distances = |hits: Vec<(usize, f32)>| {
+            let mut distances = hits.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+            distances.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+            distances
+        };
+
+        for data_shards in [random_shards, cluster_shards] {
+            let shards = data_shards
+                .into_iter()
+                .map(|d| SingleShard::new(d, Some(seed), &criteria))
+                .collect::<Vec<_>>();
+            let mut sharded_cakes = RandomlySharded::new(shards);
+
+            // Each shard's indices must start where the previous shard's end.
+            let offsets = sharded_cakes.shard_offsets();
+            assert_eq!(offsets[0], 0);
+            for (o, card) in offsets.windows(2).zip(sharded_cakes.shard_cardinalities()) {
+                assert_eq!(o[1], o[0] + card);
+            }
+
+            for strategy in [ShardStrategy::Sequential, ShardStrategy::Parallel] {
+                sharded_cakes.set_strategy(strategy);
+                for k in [1, 10, 100] {
+                    for (i, query) in queries.iter().enumerate() {
+                        let expected = sorted_distances(cakes.knn_search(query, k, knn::Algorithm::Linear));
+                        let hits = sharded_cakes.knn_search(query, k, knn::Algorithm::default());
+
+                        // The global indices must point to instances at the reported distances.
+                        for &(index, d) in &hits {
+                            let (shard, o) = sharded_cakes
+                                .shards_with_offsets()
+                                .into_iter()
+                                .rev()
+                                .find(|&(_, o)| o <= index)
+                                .unwrap_or_else(|| unreachable!("The first offset is zero."));
+                            let actual = metric(query, &shard.data()[index - o]);
+                            assert!((actual - d).abs() <= f32::EPSILON);
+                        }
+
+                        assert_eq!(
+                            expected,
+                            sorted_distances(hits),
+                            "Failed {} KNN search: query: {i}, k: {k}",
+                            strategy.name()
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
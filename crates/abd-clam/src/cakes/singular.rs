@@ -1,13 +1,15 @@
 //! CAKES search with a single shard.
 
-use core::cmp::Ordering;
-
-use std::path::Path;
+use std::{borrow::Cow, path::Path};
 
 use distances::Number;
-use rayon::prelude::*;
 
-use crate::{cakes::knn, cakes::rnn, Cluster, Dataset, Instance, PartitionCriterion, Tree, UniBall};
+use crate::{
+    cakes::knn,
+    cakes::rnn,
+    cakes::tuning::{self, TuningEntry, TuningProfile},
+    Dataset, Instance, PartitionCriterion, Tree, UniBall,
+};
 
 use super::Search;
 
@@ -28,6 +30,8 @@ pub struct SingleShard<I: Instance, U: Number, D: Dataset<I, U>> {
     best_rnn: Option<rnn::Algorithm>,
     /// Best knn-search algorithm.
     best_knn: Option<knn::Algorithm>,
+    /// Best algorithms for different values of k and radius.
+    profile: TuningProfile<U>,
 }
 
 impl<I: Instance, U: Number, D: Dataset<I, U>> SingleShard<I, U, D> {
@@ -43,6 +47,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> SingleShard<I, U, D> {
             tree: Tree::new(data, seed).partition(criteria, seed),
             best_rnn: None,
             best_knn: None,
+            profile: TuningProfile::default(),
         }
     }
 
@@ -56,23 +61,20 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> SingleShard<I, U, D> {
         &self.tree
     }
 
-    /// A helper function for sampling query indices for tuning.
-    ///
-    /// # Arguments
-    ///
-    /// * `depth` - The depth in the tree to sample at.
-    ///
-    /// # Returns
-    ///
-    /// A vector of indices of cluster centers at the given depth.
-    fn sample_query_indices(&self, depth: usize) -> Vec<usize> {
-        self.tree
-            .root()
-            .subtree()
-            .into_iter()
-            .filter(|&c| c.depth() == depth || c.is_leaf() && c.depth() < depth)
-            .map(Cluster::arg_center)
-            .collect()
+    /// Records the result of tuning RNN-Search and sets it as the best.
+    fn record_rnn(&mut self, entry: Option<TuningEntry<U, rnn::Algorithm>>) {
+        if let Some(entry) = entry {
+            self.best_rnn = Some(entry.best);
+            self.profile.insert_rnn(entry);
+        }
+    }
+
+    /// Records the result of tuning KNN-Search and sets it as the best.
+    fn record_knn(&mut self, entry: Option<TuningEntry<usize, knn::Algorithm>>) {
+        if let Some(entry) = entry {
+            self.best_knn = Some(entry.best);
+            self.profile.insert_knn(entry);
+        }
     }
 }
 
@@ -103,7 +105,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         let best_algo_file = path.join("best-algo.txt");
         std::fs::write(best_algo_file, format!("{best_rnn}\n{best_knn}")).map_err(|e| e.to_string())?;
 
-        Ok(())
+        self.profile.save(path)
     }
 
     #[allow(clippy::similar_names)]
@@ -148,86 +150,59 @@ impl<I: Instance, U: Number, D: Dataset<I, U>> Search<I, U, D> for SingleShard<I
         let tree_dir = path.join("tree");
         let tree = Tree::<I, U, D, UniBall<_>>::load(&tree_dir, metric, is_expensive)?;
 
+        let profile = TuningProfile::load(path)?;
+
         Ok(Self {
             tree,
             best_rnn,
             best_knn,
+            profile,
         })
     }
 
-    fn num_shards(&self) -> usize {
-        1
-    }
-
-    fn shard_cardinalities(&self) -> Vec<usize> {
-        vec![self.tree.data().cardinality()]
+    fn trees(&self) -> Vec<&Tree<I, U, D, UniBall<U>>> {
+        vec![&self.tree]
     }
 
-    fn auto_tune_rnn(&mut self, radius: U, tuning_depth: usize) {
-        let queries = self
-            .sample_query_indices(tuning_depth)
-            .into_iter()
-            .map(|i| &self.data()[i])
-            .collect::<Vec<_>>();
-
-        (self.best_rnn, _, _) = rnn::Algorithm::variants()
-            .iter()
-            .map(|&algo| {
-                let start = std::time::Instant::now();
-                let hits = queries
-                    .par_iter()
-                    .map(|query| self.rnn_search(query, radius, algo))
-                    .collect::<Vec<_>>();
-                let elapsed = start.elapsed().as_secs_f32();
-                (Some(algo), hits, elapsed)
-            })
-            .min_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater))
-            .unwrap_or_else(|| unreachable!("There are several variants of rnn-search."));
+    fn instance(&self, index: usize) -> Result<Cow<'_, I>, String> {
+        let data = self.tree.data();
+        if index < data.cardinality() {
+            Ok(Cow::Borrowed(&data[index]))
+        } else {
+            Err(format!(
+                "Index {index} is out of range for a cardinality of {}.",
+                data.cardinality()
+            ))
+        }
     }
 
-    fn tuned_rnn_algorithm(&self) -> rnn::Algorithm {
-        self.best_rnn.unwrap_or_default()
+    fn shard_cardinalities(&self) -> Vec<usize> {
+        vec![self.tree.data().cardinality()]
     }
 
-    fn rnn_search(&self, query: &I, radius: U, algo: rnn::Algorithm) -> Vec<(usize, U)> {
-        algo.search(query, radius, &self.tree)
+    fn tuning_profile(&self) -> &TuningProfile<U> {
+        &self.profile
     }
 
-    fn linear_rnn_search(&self, query: &I, radius: U) -> Vec<(usize, U)> {
-        self.rnn_search(query, radius, rnn::Algorithm::Linear)
+    fn tuning_profile_mut(&mut self) -> &mut TuningProfile<U> {
+        &mut self.profile
     }
 
-    fn auto_tune_knn(&mut self, k: usize, tuning_depth: usize) {
-        let queries = self
-            .sample_query_indices(tuning_depth)
-            .into_iter()
-            .map(|i| &self.data()[i])
-            .collect::<Vec<_>>();
-
-        (self.best_knn, _, _) = knn::Algorithm::variants()
-            .iter()
-            .map(|&algo| {
-                let start = std::time::Instant::now();
-                let hits = queries
-                    .par_iter()
-                    .map(|query| self.knn_search(query, k, algo))
-                    .collect::<Vec<_>>();
-                let elapsed = start.elapsed().as_secs_f32();
-                (Some(algo), hits, elapsed)
-            })
-            .min_by(|(_, _, a), (_, _, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater))
-            .unwrap_or_else(|| unreachable!("There are several variants of knn-search."));
+    fn tuned_rnn_algorithm(&self) -> rnn::Algorithm {
+        self.best_rnn.unwrap_or_default()
     }
 
     fn tuned_knn_algorithm(&self) -> knn::Algorithm {
         self.best_knn.unwrap_or_default()
     }
 
-    fn knn_search(&self, query: &I, k: usize, algo: knn::Algorithm) -> Vec<(usize, U)> {
-        algo.search(&self.tree, query, k)
+    fn auto_tune_rnn_on(&mut self, radius: U, queries: &[&I]) {
+        let entry = tuning::time_rnn(&*self, radius, queries);
+        self.record_rnn(entry);
     }
 
-    fn linear_knn_search(&self, query: &I, k: usize) -> Vec<(usize, U)> {
-        self.knn_search(query, k, knn::Algorithm::Linear)
+    fn auto_tune_knn_on(&mut self, k: usize, queries: &[&I]) {
+        let entry = tuning::time_knn(&*self, k, queries);
+        self.record_knn(entry);
     }
 }
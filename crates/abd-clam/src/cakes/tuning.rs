@@ -0,0 +1,324 @@
+//! Profiles of the fastest search algorithms for different values of k and radius.
+
+use core::cmp::Ordering;
+
+use std::path::Path;
+
+use distances::Number;
+use rayon::prelude::*;
+
+use crate::{
+    cakes::{knn, rnn, Search},
+    Cluster, Dataset, Instance, Tree, UniBall,
+};
+
+/// The name of the file in which a `TuningProfile` is saved.
+pub const PROFILE_FILE: &str = "tuning-profile.txt";
+
+/// The timings of the search algorithms at one value of k or radius.
+///
+/// # Type Parameters
+///
+/// * `P` - The type of the search parameter, i.e. k or radius.
+/// * `A` - The type of the search algorithm.
+#[derive(Clone, Debug)]
+pub struct TuningEntry<P, A> {
+    /// The value of k or radius at which the algorithms were timed.
+    pub param: P,
+    /// The fastest algorithm.
+    pub best: A,
+    /// The mean time, in seconds per query, taken by each algorithm.
+    pub timings: Vec<(A, f32)>,
+    /// The number of queries used for the timings.
+    pub num_queries: usize,
+}
+
+impl<P: Copy, A: Copy> TuningEntry<P, A> {
+    /// Creates a new entry from the timings of the algorithms.
+    ///
+    /// # Arguments
+    ///
+    /// * `param` - The value of k or radius at which the algorithms were timed.
+    /// * `timings` - The mean time, in seconds per query, taken by each algorithm.
+    /// * `num_queries` - The number of queries used for the timings.
+    ///
+    /// # Returns
+    ///
+    /// The entry, or `None` if there are no timings.
+    pub fn new(param: P, timings: Vec<(A, f32)>, num_queries: usize) -> Option<Self> {
+        let (best, _) = *timings
+            .iter()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater))?;
+        Some(Self {
+            param,
+            best,
+            timings,
+            num_queries,
+        })
+    }
+}
+
+/// A profile of the fastest search algorithms for different values of k and
+/// radius.
+///
+/// The values of k, and of radius, at which the algorithms were tuned split
+/// the range of possible values into contiguous ranges. The boundary between
+/// two neighboring tuned values of k is their geometric mean, and that between
+/// two neighboring tuned radii is their arithmetic mean. A search uses the
+/// fastest algorithm for the range in which its k or radius falls.
+#[derive(Clone, Debug)]
+pub struct TuningProfile<U: Number> {
+    /// The entries for KNN-Search, sorted by k.
+    knn: Vec<TuningEntry<usize, knn::Algorithm>>,
+    /// The entries for RNN-Search, sorted by radius.
+    rnn: Vec<TuningEntry<U, rnn::Algorithm>>,
+}
+
+impl<U: Number> Default for TuningProfile<U> {
+    fn default() -> Self {
+        Self {
+            knn: Vec::new(),
+            rnn: Vec::new(),
+        }
+    }
+}
+
+impl<U: Number> TuningProfile<U> {
+    /// Returns the entries for KNN-Search, sorted by k.
+    #[must_use]
+    pub fn knn_entries(&self) -> &[TuningEntry<usize, knn::Algorithm>] {
+        &self.knn
+    }
+
+    /// Returns the entries for RNN-Search, sorted by radius.
+    #[must_use]
+    pub fn rnn_entries(&self) -> &[TuningEntry<U, rnn::Algorithm>] {
+        &self.rnn
+    }
+
+    /// Whether the profile has no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.knn.is_empty() && self.rnn.is_empty()
+    }
+
+    /// Returns the fastest KNN-Search algorithm for the range in which `k`
+    /// falls, or `None` if KNN-Search has not been tuned.
+    #[must_use]
+    pub fn knn_algorithm(&self, k: usize) -> Option<knn::Algorithm> {
+        // Comparing on a log scale puts the boundaries at the geometric means.
+        let log_k = k.max(1).as_f64().ln();
+        Self::nearest(&self.knn, |e| (e.param.max(1).as_f64().ln() - log_k).abs())
+    }
+
+    /// Returns the fastest RNN-Search algorithm for the range in which `radius`
+    /// falls, or `None` if RNN-Search has not been tuned.
+    #[must_use]
+    pub fn rnn_algorithm(&self, radius: U) -> Option<rnn::Algorithm> {
+        let radius = radius.as_f64();
+        Self::nearest(&self.rnn, |e| (e.param.as_f64() - radius).abs())
+    }
+
+    /// Returns the best algorithm of the entry nearest to the search parameter.
+    fn nearest<P, A: Copy, F: Fn(&TuningEntry<P, A>) -> f64>(entries: &[TuningEntry<P, A>], distance: F) -> Option<A> {
+        entries
+            .iter()
+            .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap_or(Ordering::Greater))
+            .map(|e| e.best)
+    }
+
+    /// Adds an entry for KNN-Search, replacing any entry for the same k.
+    pub fn insert_knn(&mut self, entry: TuningEntry<usize, knn::Algorithm>) {
+        self.knn.retain(|e| e.param != entry.param);
+        self.knn.push(entry);
+        self.knn.sort_by_key(|e| e.param);
+    }
+
+    /// Adds an entry for RNN-Search, replacing any entry for the same radius.
+    pub fn insert_rnn(&mut self, entry: TuningEntry<U, rnn::Algorithm>) {
+        self.rnn.retain(|e| e.param != entry.param);
+        self.rnn.push(entry);
+        self.rnn
+            .sort_by(|a, b| a.param.partial_cmp(&b.param).unwrap_or(Ordering::Greater));
+    }
+
+    /// Saves the profile to the given directory.
+    ///
+    /// Each entry is saved on its own line as the kind of search, the value of
+    /// k or radius, the number of queries, the best algorithm, and the timing
+    /// of each algorithm.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be written.
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let knn_lines = self.knn.iter().map(|e| {
+            Self::entry_line(
+                "knn",
+                &e.param.to_string(),
+                e.num_queries,
+                e.best.name(),
+                &Self::timings_of(e, knn::Algorithm::name),
+            )
+        });
+        let rnn_lines = self.rnn.iter().map(|e| {
+            Self::entry_line(
+                "rnn",
+                &e.param.as_f64().to_string(),
+                e.num_queries,
+                e.best.name(),
+                &Self::timings_of(e, rnn::Algorithm::name),
+            )
+        });
+        let contents = knn_lines.chain(rnn_lines).collect::<Vec<_>>().join("\n");
+        std::fs::write(dir.join(PROFILE_FILE), contents).map_err(|e| e.to_string())
+    }
+
+    /// Loads the profile from the given directory.
+    ///
+    /// If there is no profile in the directory, an empty profile is returned.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be read.
+    /// * If the file is not a valid profile.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(PROFILE_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+        let mut profile = Self::default();
+        for line in contents.lines().filter(|l| !l.is_empty()) {
+            let mut fields = line.split_whitespace();
+            let kind = fields.next().ok_or_else(|| format!("Empty line in profile: {line}"))?;
+            let param = fields
+                .next()
+                .ok_or_else(|| format!("Missing parameter in profile: {line}"))?;
+            let num_queries = fields
+                .next()
+                .ok_or_else(|| format!("Missing number of queries in profile: {line}"))?
+                .parse::<usize>()
+                .map_err(|e| e.to_string())?;
+            let best = fields
+                .next()
+                .ok_or_else(|| format!("Missing best algorithm in profile: {line}"))?;
+            let timings = fields
+                .map(|t| {
+                    let (name, time) = t
+                        .split_once('=')
+                        .ok_or_else(|| format!("Invalid timing in profile: {t}"))?;
+                    let time = time.parse::<f32>().map_err(|e| e.to_string())?;
+                    Ok((name, time))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+
+            match kind {
+                "knn" => profile.knn.push(TuningEntry {
+                    param: param.parse::<usize>().map_err(|e| e.to_string())?,
+                    best: knn::Algorithm::from_name(best)?,
+                    timings: timings
+                        .into_iter()
+                        .map(|(n, t)| knn::Algorithm::from_name(n).map(|a| (a, t)))
+                        .collect::<Result<_, _>>()?,
+                    num_queries,
+                }),
+                "rnn" => profile.rnn.push(TuningEntry {
+                    param: U::from(param.parse::<f64>().map_err(|e| e.to_string())?),
+                    best: rnn::Algorithm::from_name(best)?,
+                    timings: timings
+                        .into_iter()
+                        .map(|(n, t)| rnn::Algorithm::from_name(n).map(|a| (a, t)))
+                        .collect::<Result<_, _>>()?,
+                    num_queries,
+                }),
+                _ => return Err(format!("Unknown kind of search in profile: {kind}")),
+            }
+        }
+
+        Ok(profile)
+    }
+
+    /// Formats the timings of an entry as `name=seconds` pairs.
+    fn timings_of<P, A: Copy>(entry: &TuningEntry<P, A>, name: fn(&A) -> &str) -> String {
+        entry
+            .timings
+            .iter()
+            .map(|(a, t)| format!("{}={t}", name(a)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Formats an entry as a line of the profile file.
+    fn entry_line(kind: &str, param: &str, num_queries: usize, best: &str, timings: &str) -> String {
+        format!("{kind} {param} {num_queries} {best} {timings}")
+    }
+}
+
+/// Returns copies of the centers of the clusters at the given depth in the
+/// tree, and of the leaves above that depth, to use as queries for tuning.
+pub(crate) fn sample_queries<I: Instance, U: Number, D: Dataset<I, U>>(
+    tree: &Tree<I, U, D, UniBall<U>>,
+    depth: usize,
+) -> Vec<I> {
+    tree.root()
+        .subtree()
+        .into_iter()
+        .filter(|&c| c.depth() == depth || c.is_leaf() && c.depth() < depth)
+        .map(|c| tree.data()[c.arg_center()].clone())
+        .collect()
+}
+
+/// Times the RNN-Search algorithms of a layout on the given queries.
+pub(crate) fn time_rnn<I, U, D, S>(search: &S, radius: U, queries: &[&I]) -> Option<TuningEntry<U, rnn::Algorithm>>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    S: Search<I, U, D> + ?Sized,
+{
+    let timings = time_queries(queries, rnn::Algorithm::variants(), |query, algo| {
+        search.rnn_search(query, radius, algo)
+    });
+    TuningEntry::new(radius, timings, queries.len())
+}
+
+/// Times the KNN-Search algorithms of a layout on the given queries.
+pub(crate) fn time_knn<I, U, D, S>(search: &S, k: usize, queries: &[&I]) -> Option<TuningEntry<usize, knn::Algorithm>>
+where
+    I: Instance,
+    U: Number,
+    D: Dataset<I, U>,
+    S: Search<I, U, D> + ?Sized,
+{
+    let timings = time_queries(queries, knn::Algorithm::variants(), |query, algo| {
+        search.knn_search(query, k, algo)
+    });
+    TuningEntry::new(k, timings, queries.len())
+}
+
+/// Times the given queries with each of the given algorithms.
+///
+/// # Returns
+///
+/// The mean time, in seconds per query, taken by each algorithm.
+fn time_queries<I, U, A, F>(queries: &[&I], algorithms: &[A], search: F) -> Vec<(A, f32)>
+where
+    I: Instance,
+    U: Number,
+    A: Copy + Send + Sync,
+    F: Fn(&I, A) -> Vec<(usize, U)> + Send + Sync,
+{
+    let num_queries = queries.len().max(1).as_f32();
+    algorithms
+        .iter()
+        .map(|&algo| {
+            let start = std::time::Instant::now();
+            let hits = queries.par_iter().map(|query| search(query, algo)).collect::<Vec<_>>();
+            let elapsed = start.elapsed().as_secs_f32();
+            drop(hits);
+            (algo, elapsed / num_queries)
+        })
+        .collect()
+}
@@ -14,7 +14,7 @@ use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{core::cluster::Children, utils, Cluster, Dataset, Instance, PartitionCriterion, UniBall};
+use crate::{core::cluster::Children, utils, Cluster, Dataset, Instance, LfdEstimator, PartitionCriterion, UniBall};
 
 /// The ratios used for anomaly detection.
 pub type Ratios = [f64; 6];
@@ -176,7 +176,15 @@ impl<U: Number> Vertex<U> {
 
 impl<U: Number> Cluster<U> for Vertex<U> {
     fn new_root<I: Instance, D: Dataset<I, U>>(data: &D, seed: Option<u64>) -> Self {
-        let uni_ball = UniBall::new_root(data, seed);
+        Self::new_root_with_lfd(data, seed, LfdEstimator::default())
+    }
+
+    fn new_root_with_lfd<I: Instance, D: Dataset<I, U>>(
+        data: &D,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self {
+        let uni_ball = UniBall::new_root_with_lfd(data, seed, lfd_estimator);
         let ratios = [0.0; 6];
         Self::new(uni_ball, ratios, None)
     }
@@ -187,7 +195,22 @@ impl<U: Number> Cluster<U> for Vertex<U> {
         D: Dataset<I, U>,
         P: PartitionCriterion<U>,
     {
-        let uni_ball = self.uni_ball.partition(data, criteria, seed);
+        self.partition_with_lfd(data, criteria, seed, LfdEstimator::default())
+    }
+
+    fn partition_with_lfd<I, D, P>(
+        self,
+        data: &mut D,
+        criteria: &P,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        let uni_ball = self.uni_ball.partition_with_lfd(data, criteria, seed, lfd_estimator);
         Self::from_base_tree(uni_ball)
     }
 
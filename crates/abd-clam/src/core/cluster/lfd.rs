@@ -0,0 +1,135 @@
+//! Estimators of the local fractal dimension of a `Cluster`.
+
+use distances::Number;
+use serde::{Deserialize, Serialize};
+
+use crate::utils;
+
+/// The minimum number of instances within a radius for `MultiScale` to use it.
+const MIN_SCALE_COUNT: usize = 10;
+
+/// How to estimate the local fractal dimension (LFD) of a `Cluster` from the
+/// distances between its center and its instances.
+///
+/// The LFD is used by `RepeatedRnn` to grow its search radius and by CHAODA in
+/// the `Ratios` of a `Vertex`. Every estimator returns `1.0` in degenerate
+/// cases, e.g. when all instances are at the center.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum LfdEstimator {
+    /// The log2 of the ratio of the number of instances within the radius to
+    /// the number within half the radius.
+    ///
+    /// This is cheap, but it relies on the instances within half the radius
+    /// alone, so it is noisy for small clusters and in high dimensions.
+    #[default]
+    HalfRadius,
+    /// The slope of a least-squares fit of the log of the number of instances
+    /// within `r` against the log of `r`, for up to `num_scales` radii, each
+    /// a factor of `sqrt(2)` smaller than the last, starting from the radius
+    /// divided by `sqrt(2)`.
+    ///
+    /// Radii with fewer than ten instances are ignored, so small clusters use
+    /// fewer scales. This uses the counts at many radii rather than only at
+    /// half the radius, so it is less noisy than `HalfRadius`, and it measures
+    /// the growth closer to the center.
+    MultiScale {
+        /// The maximum number of radii to use.
+        num_scales: usize,
+    },
+    /// The maximum-likelihood estimator of Levina and Bickel, with the
+    /// `m - 2` correction for bias, using the `k` instances nearest to the
+    /// center:
+    ///
+    /// `(m - 2) / sum(ln(d_m / d_j))`
+    ///
+    /// where `m` is the number of those instances which are not at the center,
+    /// `d_m` is the largest of their distances to the center and the sum is
+    /// over their distances `d_j`.
+    ///
+    /// This is the most accurate of the estimators when the instances are
+    /// spread uniformly over a manifold. A `k` of around 50 is a good
+    /// compromise between noise, for smaller `k`, and the effects of the
+    /// boundary of the cluster, for larger `k`.
+    MaximumLikelihood {
+        /// The number of instances nearest to the center to use.
+        k: usize,
+    },
+}
+
+impl LfdEstimator {
+    /// Estimates the local fractal dimension of a cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `radius` - The radius of the cluster.
+    /// * `distances` - The distances from the center of the cluster to each of
+    ///   its instances, including the center itself.
+    #[must_use]
+    pub fn estimate<T: Number>(&self, radius: T, distances: &[T]) -> f64 {
+        if radius == T::zero() {
+            return 1.;
+        }
+
+        match *self {
+            Self::HalfRadius => utils::compute_lfd(radius, distances),
+            Self::MultiScale { num_scales } => multi_scale(radius.as_f64(), distances, num_scales),
+            Self::MaximumLikelihood { k } => maximum_likelihood(distances, k),
+        }
+    }
+}
+
+/// Estimates the LFD as the slope of `log(count(d <= r))` against `log(r)`.
+fn multi_scale<T: Number>(radius: f64, distances: &[T], num_scales: usize) -> f64 {
+    let points = (1..=num_scales)
+        .map(|i| radius * (-(i.as_f64()) / 2.).exp2())
+        .filter_map(|r| {
+            let count = distances.iter().filter(|&&d| d.as_f64() <= r).count();
+            (count >= MIN_SCALE_COUNT).then(|| (r.log2(), count.as_f64().log2()))
+        })
+        .collect::<Vec<_>>();
+    if points.len() < 2 {
+        return 1.;
+    }
+
+    let n = points.len().as_f64();
+    let mean_x = points.iter().map(|&(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|&(_, y)| y).sum::<f64>() / n;
+    let (sxy, sxx) = points.iter().fold((0., 0.), |(sxy, sxx), &(x, y)| {
+        let dx = x - mean_x;
+        (dx.mul_add(y - mean_y, sxy), dx.mul_add(dx, sxx))
+    });
+
+    let slope = sxy / sxx;
+    if slope > 0. {
+        slope
+    } else {
+        1.
+    }
+}
+
+/// Estimates the LFD by maximum likelihood from the distances to the center.
+fn maximum_likelihood<T: Number>(distances: &[T], k: usize) -> f64 {
+    let mut distances = distances
+        .iter()
+        .map(|d| d.as_f64())
+        .filter(|&d| d > 0.)
+        .collect::<Vec<_>>();
+    if distances.len() > k {
+        if k > 0 {
+            distances.select_nth_unstable_by(k - 1, f64::total_cmp);
+        }
+        distances.truncate(k);
+    }
+    let m = distances.len();
+    if m < 3 {
+        return 1.;
+    }
+
+    let farthest = distances.iter().copied().fold(0., f64::max);
+    let sum = distances.iter().map(|&d| (farthest / d).ln()).sum::<f64>();
+    if sum > 0. {
+        (m - 2).as_f64() / sum
+    } else {
+        1.
+    }
+}
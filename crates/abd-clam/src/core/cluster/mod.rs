@@ -3,14 +3,17 @@
 //!
 //! It also provides the `PartitionCriterion` trait, and implementations for
 //! `PartitionCriterion` for `MaxDepth` and `MinCardinality` which are used to
-//! determine when to stop partitioning the tree.
+//! determine when to stop partitioning the tree, and the `LfdEstimator` used
+//! to estimate the local fractal dimension of each cluster.
 
 mod children;
 mod criteria;
+mod lfd;
 mod uni;
 
 pub use children::Children;
 pub use criteria::{MaxDepth, MinCardinality, PartitionCriteria, PartitionCriterion};
+pub use lfd::LfdEstimator;
 #[allow(clippy::module_name_repetitions)]
 pub use uni::UniBall;
 
@@ -38,6 +41,17 @@ pub trait Cluster<U: Number>:
     /// Creates a new `Cluster` from a given dataset.
     fn new_root<I: Instance, D: Dataset<I, U>>(data: &D, seed: Option<u64>) -> Self;
 
+    /// Creates a new `Cluster` from a given dataset, using the given
+    /// `LfdEstimator` for its local fractal dimension.
+    ///
+    /// This is required, rather than defaulting to `new_root`, because a `Tree`
+    /// records the estimator it was built with.
+    fn new_root_with_lfd<I: Instance, D: Dataset<I, U>>(
+        data: &D,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self;
+
     /// Recursively partitions the `Cluster` until the `PartitionCriteria` are met.
     #[must_use]
     fn partition<I, D, P>(self, data: &mut D, criteria: &P, seed: Option<u64>) -> Self
@@ -46,6 +60,24 @@ pub trait Cluster<U: Number>:
         D: Dataset<I, U>,
         P: PartitionCriterion<U>;
 
+    /// Recursively partitions the `Cluster` until the `PartitionCriteria` are
+    /// met, using the given `LfdEstimator` for the children.
+    ///
+    /// Like `new_root_with_lfd`, this is required so that a `Tree` is never
+    /// recorded with an estimator which was not used.
+    #[must_use]
+    fn partition_with_lfd<I, D, P>(
+        self,
+        data: &mut D,
+        criteria: &P,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>;
+
     /// The offset of the indices of the `Cluster`'s instances in the dataset.
     fn offset(&self) -> usize;
 
@@ -66,7 +98,7 @@ pub trait Cluster<U: Number>:
     /// The index of the instance with the maximum distance from the `center`
     fn arg_radial(&self) -> usize;
 
-    /// The local fractal dimension of the `å`.
+    /// The local fractal dimension of the `Cluster`.
     fn lfd(&self) -> f64;
 
     /// The two child clusters.
@@ -168,6 +200,9 @@ pub trait Cluster<U: Number>:
 
     /// Assuming the `Cluster` overlaps with the query ball, we return only
     /// those children that also overlap with the query ball.
+    ///
+    /// Children ruled out by the dataset's lower bound on the metric are
+    /// dropped before computing any distances.
     fn overlapping_children<I: Instance, D: Dataset<I, U>>(&self, data: &D, query: &I, radius: U) -> Vec<&Self> {
         if self.is_leaf() {
             Vec::new()
@@ -175,6 +210,13 @@ pub trait Cluster<U: Number>:
             let [left, right] = self
                 .children()
                 .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
+
+            match [left, right].map(|c| data.may_be_within(query, c.arg_center(), c.radius() + radius)) {
+                [false, false] => return Vec::new(),
+                [true, false] => return vec![left],
+                [false, true] => return vec![right],
+                [true, true] => (),
+            }
             let [arg_l, arg_r] = self
                 .arg_poles()
                 .unwrap_or_else(|| unreachable!("We checked that the cluster is not a leaf."));
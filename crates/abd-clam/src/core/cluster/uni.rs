@@ -18,7 +18,7 @@ use serde::{
 
 use crate::{utils, Cluster, Dataset, Instance, PartitionCriterion};
 
-use super::Children;
+use super::{Children, LfdEstimator};
 
 /// A `UniBall` is a cluster that behaves as clusters used to before the introduction
 /// of the `Cluster` trait.
@@ -88,6 +88,7 @@ impl<U: Number> UniBall<U> {
         offset: usize,
         indices: &[usize],
         depth: usize,
+        lfd_estimator: LfdEstimator,
     ) -> Self {
         let cardinality = indices.len();
 
@@ -114,7 +115,7 @@ impl<U: Number> UniBall<U> {
             unreachable!("The UniBall has at least one instance.")
         };
 
-        let lfd = utils::compute_lfd(radius, &center_distances);
+        let lfd = lfd_estimator.estimate(radius, &center_distances);
 
         let end = start.elapsed().as_secs_f32();
         mt_log!(
@@ -177,6 +178,7 @@ impl<U: Number> UniBall<U> {
         criteria: &P,
         mut indices: Vec<usize>,
         seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
     ) -> (Self, Vec<usize>) {
         if criteria.check(&self) {
             let ([(arg_l, l_indices), (arg_r, r_indices)], polar_distance) = self.partition_once(data, indices.clone());
@@ -187,12 +189,22 @@ impl<U: Number> UniBall<U> {
 
                 let ((left, l_indices), (right, r_indices)) = rayon::join(
                     || {
-                        Self::new(data, seed, self.offset, &l_indices, self.depth + 1)
-                            ._partition(data, criteria, l_indices, seed)
+                        Self::new(data, seed, self.offset, &l_indices, self.depth + 1, lfd_estimator)._partition(
+                            data,
+                            criteria,
+                            l_indices,
+                            seed,
+                            lfd_estimator,
+                        )
                     },
                     || {
-                        Self::new(data, seed, r_offset, &r_indices, self.depth + 1)
-                            ._partition(data, criteria, r_indices, seed)
+                        Self::new(data, seed, r_offset, &r_indices, self.depth + 1, lfd_estimator)._partition(
+                            data,
+                            criteria,
+                            r_indices,
+                            seed,
+                            lfd_estimator,
+                        )
                     },
                 );
                 self._check_partition(&l_indices, &r_indices);
@@ -268,22 +280,46 @@ impl<U: Number> UniBall<U> {
     fn drop_distances(indices: Vec<((usize, U), U)>) -> Vec<usize> {
         indices.into_iter().map(|((i, _), _)| i).collect()
     }
+
+    /// Sets the radius of the `UniBall`, e.g. to grow it around an instance
+    /// added after the tree was built.
+    pub(crate) fn set_radius(&mut self, radius: U) {
+        self.radius = radius;
+    }
 }
 
 impl<U: Number> Cluster<U> for UniBall<U> {
     fn new_root<I: Instance, D: Dataset<I, U>>(data: &D, seed: Option<u64>) -> Self {
+        Self::new_root_with_lfd(data, seed, LfdEstimator::default())
+    }
+
+    fn new_root_with_lfd<I: Instance, D: Dataset<I, U>>(
+        data: &D,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self {
         let indices = (0..data.cardinality()).collect::<Vec<usize>>();
-        Self::new(data, seed, 0, &indices, 0)
+        Self::new(data, seed, 0, &indices, 0, lfd_estimator)
     }
 
     fn partition<I: Instance, D: Dataset<I, U>, P: PartitionCriterion<U>>(
+        self,
+        data: &mut D,
+        criteria: &P,
+        seed: Option<u64>,
+    ) -> Self {
+        self.partition_with_lfd(data, criteria, seed, LfdEstimator::default())
+    }
+
+    fn partition_with_lfd<I: Instance, D: Dataset<I, U>, P: PartitionCriterion<U>>(
         mut self,
         data: &mut D,
         criteria: &P,
         seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
     ) -> Self {
         let mut indices = (0..self.cardinality).collect::<Vec<_>>();
-        (self, indices) = self._partition(data, criteria, indices, seed);
+        (self, indices) = self._partition(data, criteria, indices, seed, lfd_estimator);
 
         mt_log!(Level::Debug, "Finished building tree. Starting data permutation.");
         data.permute_instances(&indices).unwrap_or_else(|e| unreachable!("{e}"));
@@ -9,9 +9,11 @@ use rand::prelude::*;
 use rayon::prelude::*;
 
 mod instance;
+mod validation;
 mod vec2d;
 
 pub use instance::Instance;
+pub use validation::{validate_metric, MetricReport};
 #[allow(clippy::module_name_repetitions)]
 pub use vec2d::VecDataset;
 
@@ -43,8 +45,57 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
     ///
     /// If the metric also obeys the triangle inequality, `d(x, z) <= d(x, y) + d(y, z)`,
     /// then CLAM can make certain guarantees about the exactness of search results.
+    /// Use `validate_metric` to check these properties on a sample of the
+    /// instances.
     fn metric(&self) -> fn(&I, &I) -> U;
 
+    /// Returns a cheap lower bound on the metric, if the dataset has one.
+    ///
+    /// Search uses the bound to rule out candidates without calculating the
+    /// metric, so the bound must never exceed the metric. See
+    /// `distances::strings::lower_bounds` for bounds on the string metrics.
+    fn lower_bound(&self) -> Option<fn(&I, &I) -> U> {
+        None
+    }
+
+    /// Whether the indexed instance may be within `radius` of the query, going
+    /// only by the lower bound on the metric.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    /// * `index` - An index in the dataset.
+    /// * `radius` - The radius around the query.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the lower bound exceeds `radius`, `true` otherwise.
+    fn may_be_within(&self, query: &I, index: usize, radius: U) -> bool {
+        self.lower_bound()
+            .map_or(true, |bound| bound(query, &self[index]) <= radius)
+    }
+
+    /// Returns those indices whose instances may be within `radius` of the
+    /// query, going only by the lower bound on the metric.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The query.
+    /// * `indices` - A slice of indices in the dataset.
+    /// * `radius` - The radius around the query.
+    fn prefilter(&self, query: &I, indices: &[usize], radius: U) -> Vec<usize> {
+        self.lower_bound().map_or_else(
+            || indices.to_vec(),
+            |bound| {
+                indices
+                    .iter()
+                    .copied()
+                    .filter(|&i| bound(query, &self[i]) <= radius)
+                    .collect()
+            },
+        )
+    }
+
     /// Sets the permutation of indices that was used to reorder the dataset.
     ///
     /// This is primarily used when permuting the dataset to reorder it after
@@ -76,6 +127,9 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
 
     /// Returns the permutation of indices that was used to reorder the dataset.
     ///
+    /// If the dataset was reordered more than once, this is the composition of
+    /// the permutations, i.e. the original index of each instance.
+    ///
     /// # Returns
     ///
     /// * Some if the dataset was permuted.
@@ -84,6 +138,9 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
 
     /// Reorders the internal order of instances by a given permutation of indices.
     ///
+    /// The permutation is composed with any earlier one, so `original_index`
+    /// keeps referring to the order in which the dataset was created.
+    ///
     /// # Arguments
     ///
     /// * `permutation` - A permutation of indices in the dataset.
@@ -128,8 +185,12 @@ pub trait Dataset<I: Instance, U: Number>: Debug + Send + Sync + Index<usize, Ou
             }
         }
 
-        // Inverse mapping
-        self.set_permuted_indices(Some(permutation));
+        // Inverse mapping, composed with any earlier permutation.
+        let permuted_indices = self.permuted_indices().map_or_else(
+            || permutation.to_vec(),
+            |earlier| permutation.iter().map(|&index| earlier[index]).collect::<Vec<_>>(),
+        );
+        self.set_permuted_indices(Some(&permuted_indices));
 
         Ok(())
     }
@@ -0,0 +1,158 @@
+//! Empirical checks of whether a distance function behaves like a metric.
+
+use distances::Number;
+use rayon::prelude::*;
+
+use super::{Dataset, Instance};
+
+/// The violations of the properties of a metric which were found among a
+/// sample of instances.
+///
+/// Distances are compared with a tolerance of a few ULPs of the largest
+/// sampled distance, so that rounding errors in floating-point distances are
+/// not reported as violations. Integer distances are compared exactly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricReport {
+    /// The number of instances which were sampled.
+    pub num_samples: usize,
+    /// The number of ordered triples of distinct instances which were checked
+    /// for the triangle inequality.
+    pub num_triples: usize,
+    /// The tolerance used when comparing distances.
+    pub tolerance: f64,
+    /// The number of instances whose distance to themselves is not zero.
+    pub identity_violations: usize,
+    /// The number of ordered pairs of instances with a negative distance.
+    pub non_negativity_violations: usize,
+    /// The number of unordered pairs of instances `{x, y}` for which
+    /// `d(x, y) != d(y, x)`.
+    pub symmetry_violations: usize,
+    /// The number of ordered triples of instances `(x, y, z)` for which
+    /// `d(x, z) > d(x, y) + d(y, z)`.
+    pub triangle_violations: usize,
+    /// The largest observed `|d(x, y) - d(y, x)|`.
+    pub worst_asymmetry: f64,
+    /// The smallest observed slack, `d(x, y) + d(y, z) - d(x, z)`, in the
+    /// triangle inequality. This is negative if the inequality was violated,
+    /// and infinite if no triples were checked.
+    pub worst_triangle_slack: f64,
+    /// The indices of the triple `[x, y, z]` with the smallest slack.
+    pub worst_triangle: Option<[usize; 3]>,
+}
+
+impl MetricReport {
+    /// Whether no violations were found.
+    #[must_use]
+    pub const fn is_metric(&self) -> bool {
+        self.identity_violations == 0
+            && self.non_negativity_violations == 0
+            && self.symmetry_violations == 0
+            && self.triangle_violations == 0
+    }
+}
+
+/// Empirically checks whether the metric of a dataset obeys identity,
+/// non-negativity, symmetry and the triangle inequality.
+///
+/// The instances are sampled with `Dataset::choose_unique`, which treats
+/// instances at a distance of zero from each other as duplicates, so identity
+/// is checked as `d(x, x) = 0`. Every ordered triple of distinct sampled
+/// instances is checked for the triangle inequality, so this computes the
+/// distances between `num_samples^2` pairs of instances and checks
+/// `num_samples^3` triples.
+///
+/// Finding no violations does not prove that the distance function is a
+/// metric, but any violation means that exact search may miss some hits.
+///
+/// # Arguments
+///
+/// * `data` - The dataset whose metric to check.
+/// * `num_samples` - The number of instances to sample.
+/// * `seed` - An optional seed for the random number generator.
+///
+/// # Returns
+///
+/// A report of the violations found.
+pub fn validate_metric<I: Instance, U: Number, D: Dataset<I, U>>(
+    data: &D,
+    num_samples: usize,
+    seed: Option<u64>,
+) -> MetricReport {
+    let indices = (0..data.cardinality()).collect::<Vec<_>>();
+    let samples = data.choose_unique(num_samples, &indices, seed);
+    let n = samples.len();
+
+    // The distances between all ordered pairs, including each instance and
+    // itself.
+    let row = |&i: &usize| {
+        samples
+            .iter()
+            .map(|&j| data.one_to_one(i, j).as_f64())
+            .collect::<Vec<_>>()
+    };
+    let distances = if data.is_metric_expensive() {
+        samples.par_iter().map(row).collect::<Vec<_>>()
+    } else {
+        samples.iter().map(row).collect::<Vec<_>>()
+    };
+
+    let max_distance = distances.iter().flatten().fold(0_f64, |max, d| max.max(d.abs()));
+    let tolerance = 4.0 * U::epsilon().as_f64() * max_distance;
+
+    let identity_violations = (0..n).filter(|&i| distances[i][i].abs() > tolerance).count();
+    let non_negativity_violations = distances.iter().flatten().filter(|&&d| d < -tolerance).count();
+
+    // The asymmetry of each unordered pair.
+    let asymmetries = distances
+        .iter()
+        .enumerate()
+        .flat_map(|(i, row)| {
+            row.iter()
+                .zip(&distances)
+                .skip(i + 1)
+                .map(move |(&d, other)| (d - other[i]).abs())
+        })
+        .collect::<Vec<_>>();
+    let symmetry_violations = asymmetries.iter().filter(|&&a| a > tolerance).count();
+    let worst_asymmetry = asymmetries.into_iter().fold(0_f64, f64::max);
+
+    // For each `x`, the number of violations and the triple with the
+    // smallest slack.
+    let triangles = (0..n)
+        .into_par_iter()
+        .map(|x| {
+            let (mut violations, mut worst) = (0, (f64::INFINITY, None));
+            for y in (0..n).filter(|&y| y != x) {
+                for z in (0..n).filter(|&z| z != x && z != y) {
+                    let slack = distances[x][y] + distances[y][z] - distances[x][z];
+                    if slack < -tolerance {
+                        violations += 1;
+                    }
+                    if slack < worst.0 {
+                        worst = (slack, Some([samples[x], samples[y], samples[z]]));
+                    }
+                }
+            }
+            (violations, worst)
+        })
+        .collect::<Vec<_>>();
+
+    let triangle_violations = triangles.iter().map(|(v, _)| v).sum();
+    let (worst_triangle_slack, worst_triangle) = triangles
+        .into_iter()
+        .map(|(_, worst)| worst)
+        .fold((f64::INFINITY, None), |a, b| if b.0 < a.0 { b } else { a });
+
+    MetricReport {
+        num_samples: n,
+        num_triples: n * n.saturating_sub(1) * n.saturating_sub(2),
+        tolerance,
+        identity_violations,
+        non_negativity_violations,
+        symmetry_violations,
+        triangle_violations,
+        worst_asymmetry,
+        worst_triangle_slack,
+        worst_triangle,
+    }
+}
@@ -34,6 +34,8 @@ pub struct VecDataset<I: Instance, U: Number, M: Instance> {
     pub(crate) metric: fn(&I, &I) -> U,
     /// Whether the metric is expensive to compute.
     pub(crate) is_expensive: bool,
+    /// A cheap lower bound on the metric.
+    pub(crate) lower_bound: Option<fn(&I, &I) -> U>,
     /// The reordering of the dataset after building the tree.
     pub(crate) permuted_indices: Option<Vec<usize>>,
     /// Metadata about the dataset.
@@ -56,6 +58,7 @@ impl<I: Instance, U: Number> VecDataset<I, U, usize> {
             data,
             metric,
             is_expensive,
+            lower_bound: None,
             permuted_indices: None,
             metadata,
         }
@@ -90,6 +93,7 @@ impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
                 data: self.data,
                 metric: self.metric,
                 is_expensive: self.is_expensive,
+                lower_bound: self.lower_bound,
                 permuted_indices: self.permuted_indices,
                 metadata,
             })
@@ -102,6 +106,21 @@ impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
         }
     }
 
+    /// Attaches a cheap lower bound on the metric to the dataset.
+    ///
+    /// Search uses the bound to skip candidates which cannot be within the
+    /// search radius. The bound is not saved with the dataset, and so it must
+    /// be attached again after loading.
+    ///
+    /// # Arguments
+    ///
+    /// * `lower_bound`: The lower bound, which must never exceed the metric.
+    #[must_use]
+    pub const fn with_lower_bound(mut self, lower_bound: Option<fn(&I, &I) -> U>) -> Self {
+        self.lower_bound = lower_bound;
+        self
+    }
+
     /// A reference to the underlying data.
     #[must_use]
     pub fn data(&self) -> &[I] {
@@ -131,6 +150,61 @@ impl<I: Instance, U: Number, M: Instance> VecDataset<I, U, M> {
     pub fn metadata_of(&self, index: usize) -> &M {
         &self.metadata[index]
     }
+
+    /// Splits the dataset into shards of contiguous instances.
+    ///
+    /// The first shard holds the first `cardinalities[0]` instances, the second
+    /// shard holds the next `cardinalities[1]` instances, and so on. Each shard
+    /// keeps the metadata and the original indices of its instances, so that
+    /// `original_index` on a shard, even after building a tree on it, gives the
+    /// index of the instance before this dataset was permuted.
+    ///
+    /// # Arguments
+    ///
+    /// * `cardinalities` - The cardinality of each shard, in order.
+    ///
+    /// # Errors
+    ///
+    /// * If the `cardinalities` do not sum to the cardinality of the dataset.
+    pub fn split_into_shards(mut self, cardinalities: &[usize]) -> Result<Vec<Self>, String> {
+        let total = cardinalities.iter().sum::<usize>();
+        if total != self.data.len() {
+            return Err(format!(
+                "Invalid shard cardinalities. Expected a sum of {}, got a sum of {total}",
+                self.data.len()
+            ));
+        }
+
+        let mut shards = Vec::with_capacity(cardinalities.len());
+
+        // Split off the shards from the back so that the remaining data is
+        // always a prefix of the original.
+        for (i, &cardinality) in cardinalities.iter().enumerate().skip(1).rev() {
+            let name = format!("{}-shard-{i}", self.name);
+            let at = self.data.len() - cardinality;
+            shards.push(self.split_off_tail(at, name));
+        }
+
+        self.name = format!("{}-shard-0", self.name);
+        shards.push(self);
+        shards.reverse();
+
+        Ok(shards)
+    }
+
+    /// Splits off the instances from index `at` onwards into a new dataset
+    /// with the given name, which keeps their metadata and original indices.
+    fn split_off_tail(&mut self, at: usize, name: String) -> Self {
+        Self {
+            name,
+            data: self.data.split_off(at),
+            metric: self.metric,
+            is_expensive: self.is_expensive,
+            lower_bound: self.lower_bound,
+            permuted_indices: self.permuted_indices.as_mut().map(|indices| indices.split_off(at)),
+            metadata: self.metadata.split_off(at),
+        }
+    }
 }
 
 impl<I: Instance, U: Number, M: Instance> Index<usize> for VecDataset<I, U, M> {
@@ -162,6 +236,10 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
         self.metric
     }
 
+    fn lower_bound(&self) -> Option<fn(&I, &I) -> U> {
+        self.lower_bound
+    }
+
     fn set_permuted_indices(&mut self, indices: Option<&[usize]>) {
         self.permuted_indices = indices.map(<[usize]>::to_vec);
     }
@@ -191,29 +269,25 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
             .map(|&index| self.metadata[index].clone())
             .collect();
 
-        self.set_permuted_indices(Some(permutation));
+        // Compose with any earlier permutation, so that the original indices
+        // still refer to the order in which the dataset was created.
+        let permuted_indices = self.permuted_indices.as_ref().map_or_else(
+            || permutation.to_vec(),
+            |earlier| permutation.iter().map(|&index| earlier[index]).collect(),
+        );
+        self.permuted_indices = Some(permuted_indices);
 
         Ok(())
     }
 
     fn make_shards(mut self, max_cardinality: usize) -> Vec<Self> {
         let mut shards = Vec::new();
-        let mut metadata = self.metadata.clone();
 
         while self.data.len() > max_cardinality {
-            // Create a new name for the shard.
+            // Split off the last `max_cardinality` instances into a new shard.
             let name = format!("{}-shard-{}", self.name, shards.len());
-
-            // Split the data.
             let at = self.data.len() - max_cardinality;
-            let data = self.data.split_off(at);
-
-            // Create the shard, assign the metadata, and add it to the list of shards.
-            shards.push(
-                VecDataset::new(name, data, self.metric, self.is_expensive)
-                    .assign_metadata(metadata.split_off(at))
-                    .unwrap_or_else(|_| unreachable!("We just split this dataset at the same indices.")),
-            );
+            shards.push(self.split_off_tail(at, name));
         }
 
         self.name = format!("{}-shard-{}", self.name, shards.len());
@@ -351,6 +425,7 @@ impl<I: Instance, U: Number, M: Instance> Dataset<I, U> for VecDataset<I, U, M>
             data,
             metric,
             is_expensive,
+            lower_bound: None,
             permuted_indices: permutation,
             metadata,
         })
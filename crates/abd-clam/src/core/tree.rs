@@ -2,11 +2,15 @@
 
 use core::marker::PhantomData;
 
-use std::path::Path;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+};
 
 use distances::Number;
 
-use crate::{Cluster, Dataset, Instance, PartitionCriterion};
+use crate::{Cluster, Dataset, Instance, LfdEstimator, PartitionCriterion};
 
 /// A `Tree` represents a hierarchy of `Cluster`s, i.e. "similar" instances
 /// from a metric-`Space`.
@@ -27,6 +31,8 @@ pub struct Tree<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> {
     pub(crate) root: C,
     /// The depth of the tree.
     pub(crate) depth: usize,
+    /// The estimator used for the local fractal dimension of each `Cluster`.
+    pub(crate) lfd_estimator: LfdEstimator,
     /// To satisfy the `Instance` trait bound.
     _i: PhantomData<I>,
     /// To satisfy the `Number` trait bound.
@@ -40,12 +46,27 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// # Arguments
     /// dataset: The dataset from which the tree will be built
     pub fn new(data: D, seed: Option<u64>) -> Self {
-        let root = C::new_root(&data, seed);
+        Self::new_with_lfd(data, seed, LfdEstimator::default())
+    }
+
+    /// Constructs a new `Tree` for a given dataset, using the given estimator
+    /// for the local fractal dimension of each `Cluster`. Importantly, this
+    /// does not partition the tree.
+    ///
+    /// # Arguments
+    ///
+    /// * `data`: The dataset from which the tree will be built.
+    /// * `seed`: The seed for the random number generator.
+    /// * `lfd_estimator`: The estimator for the local fractal dimension, which
+    ///   is also used when the tree is partitioned.
+    pub fn new_with_lfd(data: D, seed: Option<u64>, lfd_estimator: LfdEstimator) -> Self {
+        let root = C::new_root_with_lfd(&data, seed, lfd_estimator);
         let depth = root.max_leaf_depth();
         Self {
             data,
             root,
             depth,
+            lfd_estimator,
             _i: PhantomData,
             _u: PhantomData,
         }
@@ -62,7 +83,9 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// The `Tree` after partitioning.
     #[must_use]
     pub fn partition<P: PartitionCriterion<U>>(mut self, criteria: &P, seed: Option<u64>) -> Self {
-        self.root = self.root.partition(&mut self.data, criteria, seed);
+        self.root = self
+            .root
+            .partition_with_lfd(&mut self.data, criteria, seed, self.lfd_estimator);
         self.depth = self.root.max_leaf_depth();
         self
     }
@@ -107,6 +130,11 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
         self.depth
     }
 
+    /// The estimator used for the local fractal dimension of each `Cluster`.
+    pub const fn lfd_estimator(&self) -> LfdEstimator {
+        self.lfd_estimator
+    }
+
     /// Saves a tree to a given location
     ///
     /// The path given will point to a newly created folder which will
@@ -116,8 +144,9 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     ///
     /// ```text
     /// /user/given/path/
-    ///    |- dataset      <-- The serialized dataset.
-    ///    |- clusters     <-- Clusters are serialized to a single file.
+    ///    |- dataset        <-- The serialized dataset.
+    ///    |- clusters       <-- Clusters are serialized to a single file.
+    ///    |- lfd_estimator  <-- The estimator for the local fractal dimension.
     /// ```
     ///
     /// # Arguments
@@ -129,6 +158,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// * If `path` does not exist.
     /// * If `path` cannot be written to.
     /// * If there are any serialization errors with the dataset.
+    /// * If there are any serialization errors with the `LfdEstimator`.
     pub fn save(&self, path: &Path) -> Result<(), String> {
         if !path.exists() {
             return Err("Given path does not exist".to_string());
@@ -140,6 +170,10 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
         let cluster_path = path.join("clusters");
         self.root.save(&cluster_path)?;
 
+        let lfd_path = path.join("lfd_estimator");
+        let mut writer = BufWriter::new(File::create(lfd_path).map_err(|e| e.to_string())?);
+        bincode::serialize_into(&mut writer, &self.lfd_estimator).map_err(|e| e.to_string())?;
+
         Ok(())
     }
 
@@ -164,6 +198,7 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
     /// * If the `path` cannot be read from.
     /// * If there are any deserialization errors with the dataset.
     /// * If there are any deserialization errors with the clusters.
+    /// * If there are any deserialization errors with the `LfdEstimator`.
     pub fn load(path: &Path, metric: fn(&I, &I) -> U, is_expensive: bool) -> Result<Self, String> {
         if !path.exists() {
             return Err("Given path does not exist".to_string());
@@ -180,10 +215,20 @@ impl<I: Instance, U: Number, D: Dataset<I, U>, C: Cluster<U>> Tree<I, U, D, C> {
         let data = D::load(&dataset_path, metric, is_expensive)?;
         let root = C::load(&cluster_path)?;
 
+        // Trees saved before the estimator was recorded used the default.
+        let lfd_path = path.join("lfd_estimator");
+        let lfd_estimator = if lfd_path.exists() {
+            let reader = BufReader::new(File::open(lfd_path).map_err(|e| e.to_string())?);
+            bincode::deserialize_from(reader).map_err(|e| e.to_string())?
+        } else {
+            LfdEstimator::default()
+        };
+
         Ok(Self {
             data,
             depth: root.max_leaf_depth(),
             root,
+            lfd_estimator,
             _i: PhantomData,
             _u: PhantomData,
         })
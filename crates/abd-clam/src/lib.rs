@@ -26,8 +26,8 @@ pub use crate::{
     cakes::Cakes,
     chaoda::graph,
     core::{
-        cluster::{Cluster, MaxDepth, MinCardinality, PartitionCriteria, PartitionCriterion, UniBall},
-        dataset::{Dataset, Instance, VecDataset},
+        cluster::{Cluster, LfdEstimator, MaxDepth, MinCardinality, PartitionCriteria, PartitionCriterion, UniBall},
+        dataset::{validate_metric, Dataset, Instance, MetricReport, VecDataset},
         tree::Tree,
     },
 };
@@ -0,0 +1,324 @@
+//! A builder for `CodecData` which encodes the squished clusters in parallel.
+
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use distances::number::UInt;
+use rayon::prelude::*;
+
+use crate::{Cluster, Dataset, Instance};
+
+use super::{
+    dataset::{prepare_dir, LeafBytes, LeafData, LEAF_DATA_FILE},
+    format::BlockSizes,
+    CodecData, DecoderFn, EncoderFn, LeafCost, LeafFormat, SquishyBall,
+};
+
+/// Builds a `CodecData`, encoding the squished clusters in parallel.
+///
+/// The squished clusters are encoded in batches of `batch_size` clusters. Each
+/// batch is encoded in parallel and then written, in order, to the leaf data.
+/// With `build_to_disk`, the leaf data are streamed to a file, so that at most
+/// one batch of encodings is held in memory, and the file is then memory-mapped.
+#[derive(Debug, Clone)]
+pub struct CodecBuilder<I: Instance, M: Instance> {
+    /// The encoding function.
+    encoder: EncoderFn<I>,
+    /// The decoding function.
+    decoder: DecoderFn<I>,
+    /// Metadata for the dataset.
+    metadata: Vec<M>,
+    /// The number of squished clusters to encode in parallel before writing.
+    batch_size: usize,
+    /// The layout of the compressed data for each squished cluster.
+    format: LeafFormat,
+}
+
+impl<I: Instance, M: Instance> CodecBuilder<I, M> {
+    /// The default number of squished clusters to encode in parallel before
+    /// writing.
+    pub const DEFAULT_BATCH_SIZE: usize = 1024;
+
+    /// Creates a new `CodecBuilder`.
+    ///
+    /// # Arguments
+    ///
+    /// * `encoder`: The encoding function.
+    /// * `decoder`: The decoding function.
+    /// * `metadata`: Metadata for the dataset.
+    pub const fn new(encoder: EncoderFn<I>, decoder: DecoderFn<I>, metadata: Vec<M>) -> Self {
+        Self {
+            encoder,
+            decoder,
+            metadata,
+            batch_size: Self::DEFAULT_BATCH_SIZE,
+            format: LeafFormat::Fixed,
+        }
+    }
+
+    /// Sets the number of squished clusters to encode in parallel before
+    /// writing.
+    #[must_use]
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Sets the layout of the compressed data for each squished cluster.
+    ///
+    /// The `CostModel`s predict the size of the `Fixed` layout, so the
+    /// predictions in the `CostReport` overestimate the sizes of the other
+    /// layouts.
+    #[must_use]
+    pub const fn with_leaf_format(mut self, format: LeafFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Builds the `CodecData` with the leaf data held in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `root`: The root of the squishy ball tree.
+    /// * `data`: The dataset to compress.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any leaf data could not be encoded.
+    pub fn build<U: UInt, D: Dataset<I, U>>(
+        self,
+        mut root: SquishyBall<U>,
+        data: &D,
+    ) -> Result<CodecData<I, U, M>, String> {
+        root.trim();
+
+        let mut bytes = Vec::new();
+        let leaf_costs = write_leaves(&mut root, data, self.encoder, self.format, self.batch_size, &mut bytes)?;
+
+        let leaf_data = LeafData::new(LeafBytes::InMemory(bytes.into_boxed_slice()), self.format, self.decoder);
+        CodecData::from_parts(root, data, self.encoder, leaf_data, self.metadata, leaf_costs)
+    }
+
+    /// Builds the `CodecData`, streaming the leaf data to disk, and saves it to
+    /// the given directory.
+    ///
+    /// The returned `CodecData` memory-maps the leaf data, and it is the same
+    /// as the one returned by `CodecData::load_mapped` for the directory.
+    ///
+    /// # Arguments
+    ///
+    /// * `root`: The root of the squishy ball tree.
+    /// * `data`: The dataset to compress.
+    /// * `path`: The directory to save the `CodecData`.
+    ///
+    /// # Errors
+    ///
+    /// * If any leaf data could not be encoded.
+    /// * See `CodecData::save`.
+    pub fn build_to_disk<U: UInt, D: Dataset<I, U>>(
+        self,
+        mut root: SquishyBall<U>,
+        data: &D,
+        path: &Path,
+    ) -> Result<CodecData<I, U, M>, String> {
+        prepare_dir(path)?;
+        root.trim();
+
+        let leaf_data_path = path.join(LEAF_DATA_FILE);
+        let file = std::fs::File::create(&leaf_data_path).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(file);
+        let leaf_costs = write_leaves(&mut root, data, self.encoder, self.format, self.batch_size, &mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.to_string())?
+            .sync_all()
+            .map_err(|e| e.to_string())?;
+
+        let leaf_data = LeafData::new(LeafBytes::map(&leaf_data_path)?, self.format, self.decoder);
+        let codec = CodecData::from_parts(root, data, self.encoder, leaf_data, self.metadata, leaf_costs)?;
+        codec.save_index(path)?;
+
+        Ok(codec)
+    }
+}
+
+/// Encodes the squished clusters in batches and writes them, in order, to
+/// `writer`, setting the offset of each cluster in the leaf data.
+///
+/// Each squished cluster is written with the given `LeafFormat`.
+///
+/// # Returns
+///
+/// The predicted and actual sizes of each squished cluster.
+fn write_leaves<I, U, D, W>(
+    root: &mut SquishyBall<U>,
+    data: &D,
+    encoder: EncoderFn<I>,
+    format: LeafFormat,
+    batch_size: usize,
+    writer: &mut W,
+) -> Result<Vec<LeafCost>, String>
+where
+    I: Instance,
+    U: UInt,
+    D: Dataset<I, U>,
+    W: Write,
+{
+    let mut leaves = root
+        .compressible_leaves_mut()
+        .into_iter()
+        .filter(|c| c.squish())
+        .collect::<Vec<_>>();
+
+    let mut offset = 0;
+    let mut leaf_costs = Vec::with_capacity(leaves.len());
+    for batch in leaves.chunks_mut(batch_size) {
+        let blocks = batch
+            .par_iter()
+            .map(|leaf| encode_leaf(leaf, data, encoder, format))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (leaf, (block, sizes)) in batch.iter_mut().zip(blocks) {
+            leaf.set_codec_offset(offset);
+            writer.write_all(&block).map_err(|e| e.to_string())?;
+            leaf_costs.push(LeafCost {
+                codec_offset: offset,
+                cardinality: leaf.cardinality(),
+                predicted: leaf.unitary_cost(),
+                header: sizes.header,
+                encodings: sizes.encodings,
+                actual: sizes.stored,
+            });
+            offset += block.len();
+        }
+    }
+
+    Ok(leaf_costs)
+}
+
+/// Encodes the instances in a squished cluster in terms of its center.
+fn encode_leaf<I: Instance, U: UInt, D: Dataset<I, U>>(
+    leaf: &SquishyBall<U>,
+    data: &D,
+    encoder: EncoderFn<I>,
+    format: LeafFormat,
+) -> Result<(Vec<u8>, BlockSizes), String> {
+    let center = &data[leaf.arg_center()];
+    let encodings = leaf
+        .indices()
+        .map(|i| encoder(center, &data[i]))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(format.write_block(&encodings))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pancakes::{
+        codec::fixture::{fixture, lev_metric, Fixture},
+        decode_general, encode_general, rnn,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_build_to_disk() -> Result<(), String> {
+        let fixture = fixture()?;
+        let (strings, dataset, root) = (&fixture.strings, &fixture.dataset, fixture.squishy());
+        let queries = symagen::random_data::random_string(5, 20, 40, "ACGT", 43);
+        let metadata = dataset.metadata().to_vec();
+
+        let in_memory = CodecBuilder::new(encode_general::<u16>, decode_general, metadata.clone())
+            .with_batch_size(1)
+            .build(root.clone(), dataset)?;
+
+        let path = fixture.tmp_dir.path().join("codec");
+        let on_disk = CodecBuilder::new(encode_general::<u16>, decode_general, metadata)
+            .with_batch_size(3)
+            .build_to_disk(root, dataset, &path)?;
+
+        // The batch size does not change the leaf data.
+        let saved = fixture.tmp_dir.path().join("saved");
+        in_memory.save(&saved)?;
+        let read = |p: &Path| std::fs::read(p.join(LEAF_DATA_FILE)).map_err(|e| e.to_string());
+        assert_eq!(read(&saved)?, read(&path)?);
+
+        let mapped = CodecData::<String, u16, usize>::load_mapped(
+            &path,
+            lev_metric,
+            true,
+            encode_general::<u16>,
+            decode_general,
+        )?;
+        for codec in [&on_disk, &mapped] {
+            for (i, s) in strings.iter().enumerate() {
+                assert_eq!(&codec.get(i)?, s, "index {i}");
+            }
+            for query in &queries {
+                let mut expected = in_memory.rnn_search(query, 15, &rnn::Algorithm::Linear);
+                let mut actual = codec.rnn_search(query, 15, &rnn::Algorithm::Clustered);
+                expected.sort_unstable();
+                actual.sort_unstable();
+                assert!(!expected.is_empty());
+                assert_eq!(actual, expected);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leaf_formats() -> Result<(), String> {
+        let fixture = fixture()?;
+        let (strings, dataset, root) = (&fixture.strings, &fixture.dataset, fixture.squishy());
+        let queries = symagen::random_data::random_string(5, 20, 40, "ACGT", 43);
+        let metadata = dataset.metadata().to_vec();
+
+        let build = |format: LeafFormat| {
+            CodecBuilder::new(encode_general::<u16>, decode_general, metadata.clone())
+                .with_leaf_format(format)
+                .build(root.clone(), dataset)
+        };
+        let fixed = build(LeafFormat::Fixed)?;
+        let fixed_report = fixed.cost_report().ok_or("Missing cost report")?;
+        assert_eq!(
+            fixed_report.stored_total(),
+            fixed_report.header_total() + fixed_report.encodings_total()
+        );
+
+        for format in [
+            LeafFormat::Varint,
+            LeafFormat::Deflate(LeafFormat::DEFAULT_DEFLATE_LEVEL),
+        ] {
+            let codec = build(format)?;
+            assert_eq!(codec.leaf_format(), format);
+
+            // The varint headers are smaller, and so is the entropy-coded data.
+            let report = codec.cost_report().ok_or("Missing cost report")?;
+            assert_eq!(report.encodings_total(), fixed_report.encodings_total());
+            assert!(report.header_total() < fixed_report.header_total(), "{}", format.name());
+            assert!(report.stored_total() < fixed_report.stored_total(), "{}", format.name());
+
+            let path = fixture.tmp_dir.path().join(format.name());
+            codec.save(&path)?;
+            let loaded = Fixture::load(&path)?;
+            assert_eq!(loaded.leaf_format(), format);
+
+            for c in [&codec, &loaded] {
+                for (i, s) in strings.iter().enumerate() {
+                    assert_eq!(&c.get(i)?, s, "{} at index {i}", format.name());
+                }
+                for query in &queries {
+                    let mut expected = fixed.rnn_search(query, 15, &rnn::Algorithm::Linear);
+                    let mut actual = c.rnn_search(query, 15, &rnn::Algorithm::Clustered);
+                    expected.sort_unstable();
+                    actual.sort_unstable();
+                    assert_eq!(actual, expected, "{}", format.name());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
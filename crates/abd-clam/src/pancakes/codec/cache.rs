@@ -0,0 +1,243 @@
+//! A least-recently-used cache of decoded leaves, bounded by memory.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+};
+
+use crate::Instance;
+
+/// The hit and miss counters and the current size of a leaf cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LeafCacheStats {
+    /// The number of times a leaf was found in the cache.
+    pub hits: usize,
+    /// The number of times a leaf had to be decoded.
+    pub misses: usize,
+    /// The number of leaves in the cache.
+    pub num_leaves: usize,
+    /// The estimated number of bytes used by the leaves in the cache.
+    pub num_bytes: usize,
+    /// The maximum number of bytes to use for the leaves in the cache.
+    pub capacity: usize,
+}
+
+impl LeafCacheStats {
+    /// The fraction of lookups that were found in the cache.
+    #[must_use]
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            #[allow(clippy::cast_precision_loss)]
+            let rate = self.hits as f64 / lookups as f64;
+            rate
+        }
+    }
+}
+
+/// A decoded leaf in the cache.
+#[derive(Debug)]
+struct Entry<I: Instance> {
+    /// The decoded instances.
+    instances: Arc<[I]>,
+    /// The estimated number of bytes used by the instances.
+    num_bytes: usize,
+    /// The tick at which the leaf was last used.
+    last_used: u64,
+}
+
+/// The leaves in the cache, keyed by their offsets in the compressed data.
+#[derive(Debug)]
+struct Resident<I: Instance> {
+    /// The decoded leaves.
+    entries: HashMap<usize, Entry<I>>,
+    /// The estimated number of bytes used by the decoded leaves.
+    num_bytes: usize,
+    /// Incremented on every use of a leaf, to order the leaves by recency.
+    tick: u64,
+}
+
+/// A thread-safe least-recently-used cache of decoded leaves.
+///
+/// The memory used by a leaf is estimated as the size of each instance plus
+/// the length of its serialized bytes. Leaves are evicted, least recently
+/// used first, until the estimate is no more than the capacity. A leaf which
+/// alone exceeds the capacity is never cached.
+#[derive(Debug)]
+pub struct LeafCache<I: Instance> {
+    /// The maximum number of bytes to use for the decoded leaves.
+    capacity: usize,
+    /// The decoded leaves.
+    resident: Mutex<Resident<I>>,
+    /// The number of times a leaf was found in the cache.
+    hits: AtomicUsize,
+    /// The number of times a leaf had to be decoded.
+    misses: AtomicUsize,
+}
+
+impl<I: Instance> LeafCache<I> {
+    /// Creates an empty cache which uses at most `capacity` bytes.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            resident: Mutex::new(Resident {
+                entries: HashMap::new(),
+                num_bytes: 0,
+                tick: 0,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the leaf at the given offset, decoding and caching it if it is
+    /// not already in the cache.
+    ///
+    /// # Errors
+    ///
+    /// * If the leaf could not be decoded.
+    pub fn get_or_decode<F>(&self, offset: usize, decode: F) -> Result<Arc<[I]>, String>
+    where
+        F: FnOnce() -> Result<Vec<I>, String>,
+    {
+        let resident = self.lock().get(offset);
+        if let Some(instances) = resident {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(instances);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        // The leaf is decoded without holding the lock so that other threads
+        // may use the cached leaves in the meantime.
+        let instances: Arc<[I]> = decode()?.into();
+        let num_bytes = instances
+            .iter()
+            .map(|i| core::mem::size_of::<I>() + i.to_bytes().len())
+            .sum();
+        if num_bytes <= self.capacity {
+            self.lock()
+                .insert(offset, Arc::clone(&instances), num_bytes, self.capacity);
+        }
+        Ok(instances)
+    }
+
+    /// Removes the leaf at the given offset from the cache, e.g. because
+    /// instances were appended to it.
+    pub fn remove(&self, offset: usize) {
+        let mut resident = self.lock();
+        if let Some(entry) = resident.entries.remove(&offset) {
+            resident.num_bytes -= entry.num_bytes;
+        }
+    }
+
+    /// Removes all leaves from the cache and resets the counters.
+    pub fn clear(&self) {
+        {
+            let mut resident = self.lock();
+            resident.entries.clear();
+            resident.num_bytes = 0;
+        }
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the hit and miss counters and the current size of the cache.
+    pub fn stats(&self) -> LeafCacheStats {
+        let resident = self.lock();
+        LeafCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            num_leaves: resident.entries.len(),
+            num_bytes: resident.num_bytes,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Locks the decoded leaves.
+    fn lock(&self) -> MutexGuard<'_, Resident<I>> {
+        self.resident.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<I: Instance> Resident<I> {
+    /// Returns the leaf at the given offset, if it is resident, and marks it
+    /// as the most recently used.
+    fn get(&mut self, offset: usize) -> Option<Arc<[I]>> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(&offset).map(|entry| {
+            entry.last_used = tick;
+            Arc::clone(&entry.instances)
+        })
+    }
+
+    /// Inserts a newly decoded leaf, evicting the least recently used leaves
+    /// until the cache is within its capacity.
+    fn insert(&mut self, offset: usize, instances: Arc<[I]>, num_bytes: usize, capacity: usize) {
+        // Another thread may have decoded the same leaf in the meantime.
+        if self.get(offset).is_some() {
+            return;
+        }
+
+        while self.num_bytes + num_bytes > capacity {
+            let Some((&lru, _)) = self.entries.iter().min_by_key(|(_, e)| e.last_used) else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&lru) {
+                self.num_bytes -= entry.num_bytes;
+            }
+        }
+
+        self.tick += 1;
+        self.num_bytes += num_bytes;
+        self.entries.insert(
+            offset,
+            Entry {
+                instances,
+                num_bytes,
+                last_used: self.tick,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lru() -> Result<(), String> {
+        // Each leaf of two `u8` vectors of length 3 uses `2 * (24 + 3)` bytes.
+        let leaf = |x: u8| vec![vec![x; 3], vec![x; 3]];
+        let size = 2 * (core::mem::size_of::<Vec<u8>>() + 3);
+        let cache = LeafCache::<Vec<u8>>::new(2 * size);
+
+        assert_eq!(cache.get_or_decode(0, || Ok(leaf(0)))?[0], vec![0; 3]);
+        cache.get_or_decode(1, || Ok(leaf(1)))?;
+        // Offset 0 is now the most recently used, so offset 1 is evicted.
+        cache.get_or_decode(0, || Err("Should be cached".to_string()))?;
+        cache.get_or_decode(2, || Ok(leaf(2)))?;
+        assert!(cache.get_or_decode(1, || Err("Evicted".to_string())).is_err());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 4));
+        assert_eq!(stats.num_leaves, 2);
+        assert_eq!(stats.num_bytes, 2 * size);
+        assert!((stats.hit_rate() - 0.2).abs() < f64::EPSILON);
+
+        // A leaf larger than the capacity is decoded but not cached.
+        let cache = LeafCache::<Vec<u8>>::new(size - 1);
+        cache.get_or_decode(0, || Ok(leaf(0)))?;
+        assert_eq!(cache.stats().num_leaves, 0);
+
+        cache.clear();
+        assert_eq!(cache.stats().misses, 0);
+
+        Ok(())
+    }
+}
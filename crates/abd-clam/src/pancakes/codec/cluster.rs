@@ -7,17 +7,22 @@ use core::{
     marker::PhantomData,
 };
 
-use distances::{number::UInt, Number};
-use rayon::prelude::*;
+use distances::number::UInt;
 use serde::{
     de::{MapAccess, SeqAccess, Visitor},
     ser::SerializeStruct,
     Deserialize, Deserializer, Serialize, Serializer,
 };
 
-use crate::{core::cluster::Children, Cluster, Dataset, Instance, PartitionCriterion, UniBall};
+use crate::{core::cluster::Children, Cluster, Dataset, Instance, LfdEstimator, PartitionCriterion, UniBall};
+
+use super::{CostFn, CostModel, FnCost, MetricCost};
 
 /// A `SquishyBall` is a `Cluster` that supports compression.
+///
+/// The distance type `U` must be an unsigned integer, so the metric of the
+/// dataset must have unsigned integer values even for instances such as float
+/// vectors.
 #[derive(Debug, Clone)]
 pub struct SquishyBall<U: UInt> {
     /// The `UniBall` for the underlying `Cluster`.
@@ -38,31 +43,61 @@ pub struct SquishyBall<U: UInt> {
 
 impl<U: UInt> SquishyBall<U> {
     /// Creates a new `SquishyBall` tree.
+    ///
+    /// The costs of compression are estimated with a `MetricCost`, i.e. from
+    /// the distances between instances.
     pub fn from_base_tree<I: Instance, D: Dataset<I, U>>(root: UniBall<U>, data: &D) -> Self {
-        Self::from_uni_ball(root, data)
+        Self::from_base_tree_with_model(root, data, &MetricCost::new(data.metric()))
+    }
+
+    /// Creates a new `SquishyBall` tree, estimating the costs of compression
+    /// with the given `CostFn` instead of the distances between instances.
+    ///
+    /// The `CostFn` should estimate the number of bytes that the encoder will
+    /// use, e.g. `float_vector_cost` for `encode_float_vector`.
+    pub fn from_base_tree_with_cost<I: Instance, D: Dataset<I, U>>(root: UniBall<U>, data: &D, cost: CostFn<I>) -> Self {
+        Self::from_base_tree_with_model(root, data, &FnCost::new(cost))
+    }
+
+    /// Creates a new `SquishyBall` tree, estimating the costs of compression
+    /// with the given `CostModel`.
+    ///
+    /// Use a `SampledEncodingCost` to estimate the costs by encoding a sample
+    /// of the instances with the actual `EncoderFn`.
+    pub fn from_base_tree_with_model<I, D, C>(root: UniBall<U>, data: &D, model: &C) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        C: CostModel<I>,
+    {
+        Self::from_uni_ball(root, data, model)
     }
 
     /// Recursively creates a new `SquishyBall` tree.
-    fn from_uni_ball<I: Instance, D: Dataset<I, U>>(mut uni_ball: UniBall<U>, data: &D) -> Self {
-        let unitary_cost = Self::calculate_unitary_cost(&uni_ball, data);
+    fn from_uni_ball<I, D, C>(mut uni_ball: UniBall<U>, data: &D, model: &C) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        C: CostModel<I>,
+    {
+        let unitary_cost = Self::calculate_unitary_cost(&uni_ball, data, model);
         match uni_ball.children {
             Some(children) => {
                 uni_ball.children = None;
                 let (left, right) = rayon::join(
-                    || Box::new(Self::from_uni_ball(*children.left, data)),
-                    || Box::new(Self::from_uni_ball(*children.right, data)),
+                    || Box::new(Self::from_uni_ball(*children.left, data, model)),
+                    || Box::new(Self::from_uni_ball(*children.right, data, model)),
                 );
 
                 let recursive_cost = {
-                    // TODO: Incorporate the `bytes_per_unit_distance` into the cost calculation.
                     let [l_center, r_center, c_center] = [
                         &data[left.arg_center()],
                         &data[right.arg_center()],
                         &data[uni_ball.arg_center()],
                     ];
                     let (l_cost, r_cost) = rayon::join(
-                        || Number::as_u64(data.metric()(c_center, l_center)),
-                        || Number::as_u64(data.metric()(c_center, r_center)),
+                        || model.pair_cost(c_center, l_center),
+                        || model.pair_cost(c_center, r_center),
                     );
                     l_cost + left.min_cost + r_cost + right.min_cost
                 };
@@ -139,6 +174,39 @@ impl<U: UInt> SquishyBall<U> {
         self.codec_offset = Some(offset);
     }
 
+    /// Grows the radii of the clusters on the path from this cluster to the
+    /// squished cluster at `leaf_offset`, so that they contain an instance
+    /// appended to that squished cluster.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_offset`: The offset of the squished cluster.
+    /// * `distance_to`: The distance from the appended instance to the
+    ///   instance at the given index, which is always the center of a cluster.
+    ///
+    /// # Returns
+    ///
+    /// The offset and cardinality of each cluster on the path.
+    pub(super) fn grow_path<F: Fn(usize) -> U>(&mut self, leaf_offset: usize, distance_to: &F) -> Vec<(usize, usize)> {
+        let distance = distance_to(self.arg_center());
+        if distance > self.radius() {
+            self.uni_ball.set_radius(distance);
+        }
+
+        let mut path = vec![(self.offset(), self.cardinality())];
+        if !self.squish {
+            if let Some(children) = self.children.as_mut() {
+                let child = if children.left.indices().contains(&leaf_offset) {
+                    &mut children.left
+                } else {
+                    &mut children.right
+                };
+                path.append(&mut child.grow_path(leaf_offset, distance_to));
+            }
+        }
+        path
+    }
+
     /// Returns the compressible subtree.
     pub fn compressible_subtree(&self) -> Vec<&Self> {
         let mut clusters = vec![self];
@@ -184,13 +252,17 @@ impl<U: UInt> SquishyBall<U> {
 
     /// Estimates the memory cost of unitary compression.
     ///
-    /// The cost is estimated as the sum of distances from the center to all instances in the cluster.
-    fn calculate_unitary_cost<I: Instance, D: Dataset<I, U>>(c: &UniBall<U>, data: &D) -> u64 {
-        // TODO: Incorporate the `bytes_per_unit_distance` into the cost calculation.
+    /// The cost is estimated as the `CostModel::leaf_cost` of storing all instances in the cluster in terms of the
+    /// center.
+    fn calculate_unitary_cost<I, D, C>(c: &UniBall<U>, data: &D, model: &C) -> u64
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        C: CostModel<I>,
+    {
         let center = &data[c.arg_center()];
-        let instances = c.indices().into_par_iter().map(|i| &data[i]);
-        let distances = instances.map(|i| data.metric()(center, i)).map(Number::as_u64);
-        distances.sum()
+        let members = c.indices().map(|i| &data[i]).collect::<Vec<_>>();
+        model.leaf_cost(center, &members)
     }
 
     /// Trim the tree by removing the children of those clusters that are marked for squishing.
@@ -205,7 +277,15 @@ impl<U: UInt> SquishyBall<U> {
 
 impl<U: UInt> Cluster<U> for SquishyBall<U> {
     fn new_root<I: Instance, D: Dataset<I, U>>(data: &D, seed: Option<u64>) -> Self {
-        let uni_ball = UniBall::new_root(data, seed);
+        Self::new_root_with_lfd(data, seed, LfdEstimator::default())
+    }
+
+    fn new_root_with_lfd<I: Instance, D: Dataset<I, U>>(
+        data: &D,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self {
+        let uni_ball = UniBall::new_root_with_lfd(data, seed, lfd_estimator);
         Self {
             uni_ball,
             recursive_cost: 0,
@@ -223,7 +303,22 @@ impl<U: UInt> Cluster<U> for SquishyBall<U> {
         D: Dataset<I, U>,
         P: PartitionCriterion<U>,
     {
-        let uni_ball = self.uni_ball.partition(data, criteria, seed);
+        self.partition_with_lfd(data, criteria, seed, LfdEstimator::default())
+    }
+
+    fn partition_with_lfd<I, D, P>(
+        self,
+        data: &mut D,
+        criteria: &P,
+        seed: Option<u64>,
+        lfd_estimator: LfdEstimator,
+    ) -> Self
+    where
+        I: Instance,
+        D: Dataset<I, U>,
+        P: PartitionCriterion<U>,
+    {
+        let uni_ball = self.uni_ball.partition_with_lfd(data, criteria, seed, lfd_estimator);
         Self::from_base_tree(uni_ball, data)
     }
 
@@ -473,19 +568,16 @@ impl<'de, U: UInt> Deserialize<'de> for SquishyBall<U> {
 
 #[cfg(test)]
 mod tests {
-    use distances::strings::levenshtein;
-
     use crate::{
-        pancakes::{decode_general, encode_general, CodecData},
+        pancakes::{
+            codec::fixture::{fixture, lev_metric},
+            decode_general, encode_general, CodecData, SampledEncodingCost,
+        },
         PartitionCriteria, VecDataset,
     };
 
     use super::*;
 
-    fn lev_metric(x: &String, y: &String) -> u16 {
-        levenshtein(x, y)
-    }
-
     #[test]
     fn test_squishy() -> Result<(), String> {
         let strings = vec![
@@ -529,4 +621,40 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_sampled_cost() -> Result<(), String> {
+        let fixture = fixture()?;
+        let dataset = &fixture.dataset;
+        let build = |model: &SampledEncodingCost<String>| {
+            let squishy = SquishyBall::from_base_tree_with_model(fixture.root.clone(), dataset, model);
+            let metadata = dataset.metadata().to_vec();
+            CodecData::new(squishy, dataset, encode_general::<u16>, decode_general, metadata)
+        };
+
+        // Encoding every member predicts the sizes exactly.
+        let codec = build(&SampledEncodingCost::new(encode_general::<u16>).with_sample_size(usize::MAX))?;
+
+        let report = codec.cost_report().ok_or("Missing cost report")?;
+        assert!(!report.leaves.is_empty());
+        for leaf in &report.leaves {
+            assert_eq!(leaf.predicted, leaf.actual, "leaf at {}", leaf.codec_offset);
+        }
+        assert_eq!(report.predicted_centers, report.actual_centers);
+        assert_eq!(report.predicted_total(), report.actual_total());
+        assert!(report.relative_error().abs() < f64::EPSILON);
+
+        // Encoding a small sample extrapolates the sizes.
+        let codec = build(&SampledEncodingCost::new(encode_general::<u16>).with_sample_size(4))?;
+
+        let report = codec.cost_report().ok_or("Missing cost report")?;
+        assert!(
+            report.relative_error().abs() < 0.25,
+            "predicted: {}, actual: {}",
+            report.predicted_total(),
+            report.actual_total()
+        );
+
+        Ok(())
+    }
 }
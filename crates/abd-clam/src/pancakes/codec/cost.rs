@@ -0,0 +1,236 @@
+//! Cost models used by `SquishyBall` to decide which clusters to squish.
+
+use distances::{number::UInt, Number};
+use rayon::prelude::*;
+
+use crate::Instance;
+
+use super::{CostFn, EncoderFn};
+
+/// Estimates the memory cost of compressing instances in terms of each other.
+///
+/// A `SquishyBall` squishes a cluster when storing all of its instances in
+/// terms of its center is expected to cost no more than storing the centers of
+/// its children in terms of its center and recursing into the children.
+pub trait CostModel<I: Instance>: Send + Sync {
+    /// Estimates the cost of storing `target` in terms of `reference`, as is
+    /// done for the centers of clusters.
+    fn pair_cost(&self, reference: &I, target: &I) -> u64;
+
+    /// Estimates the cost of storing all `members` of a squished cluster in
+    /// terms of its `center`.
+    ///
+    /// By default, this is the sum of the `pair_cost`s.
+    fn leaf_cost(&self, center: &I, members: &[&I]) -> u64 {
+        members.par_iter().map(|t| self.pair_cost(center, t)).sum()
+    }
+}
+
+/// Uses the distance between instances as the cost.
+///
+/// This is the default cost model. It is only a rough proxy for the number of
+/// bytes, e.g. for Levenshtein distance and edit scripts, and it is meaningless
+/// for metrics whose values are not related to the size of the encodings.
+#[derive(Debug, Clone)]
+pub struct MetricCost<I: Instance, U: UInt> {
+    /// The distance function.
+    metric: fn(&I, &I) -> U,
+}
+
+impl<I: Instance, U: UInt> MetricCost<I, U> {
+    /// Creates a new `MetricCost` with the given distance function.
+    pub const fn new(metric: fn(&I, &I) -> U) -> Self {
+        Self { metric }
+    }
+}
+
+impl<I: Instance, U: UInt> CostModel<I> for MetricCost<I, U> {
+    fn pair_cost(&self, reference: &I, target: &I) -> u64 {
+        Number::as_u64((self.metric)(reference, target))
+    }
+}
+
+/// Uses a `CostFn` as the cost, e.g. `float_vector_cost` for
+/// `encode_float_vector`.
+#[derive(Debug, Clone)]
+pub struct FnCost<I: Instance> {
+    /// The cost function.
+    cost: CostFn<I>,
+}
+
+impl<I: Instance> FnCost<I> {
+    /// Creates a new `FnCost` with the given cost function.
+    pub const fn new(cost: CostFn<I>) -> Self {
+        Self { cost }
+    }
+}
+
+impl<I: Instance> CostModel<I> for FnCost<I> {
+    fn pair_cost(&self, reference: &I, target: &I) -> u64 {
+        (self.cost)(reference, target)
+    }
+}
+
+/// Encodes a sample of the instances with the actual `EncoderFn` and
+/// extrapolates the number of bytes that `CodecData` will use.
+///
+/// The costs include the headers that `CodecData` writes along with each
+/// encoding: the cardinality and offset table of each squished cluster, and
+/// the indices and length of each pair of centers.
+///
+/// If an instance cannot be encoded, the size of its raw bytes is used instead.
+#[derive(Debug, Clone)]
+pub struct SampledEncodingCost<I: Instance> {
+    /// The encoding function.
+    encoder: EncoderFn<I>,
+    /// The maximum number of members of a cluster to encode.
+    sample_size: usize,
+}
+
+impl<I: Instance> SampledEncodingCost<I> {
+    /// The default maximum number of members of a cluster to encode.
+    pub const DEFAULT_SAMPLE_SIZE: usize = 32;
+
+    /// Creates a new `SampledEncodingCost` with the given encoding function,
+    /// which encodes up to `DEFAULT_SAMPLE_SIZE` members of each cluster.
+    pub const fn new(encoder: EncoderFn<I>) -> Self {
+        Self {
+            encoder,
+            sample_size: Self::DEFAULT_SAMPLE_SIZE,
+        }
+    }
+
+    /// Sets the maximum number of members of a cluster to encode.
+    ///
+    /// Clusters with at most this many members are encoded in full, so their
+    /// costs are exact.
+    #[must_use]
+    pub fn with_sample_size(mut self, sample_size: usize) -> Self {
+        self.sample_size = sample_size.max(1);
+        self
+    }
+
+    /// Returns the maximum number of members of a cluster to encode.
+    #[must_use]
+    pub const fn sample_size(&self) -> usize {
+        self.sample_size
+    }
+
+    /// The number of bytes used to encode `target` in terms of `reference`.
+    fn encoded_len(&self, reference: &I, target: &I) -> u64 {
+        (self.encoder)(reference, target).map_or_else(|_| target.to_bytes().len(), |e| e.len()) as u64
+    }
+}
+
+impl<I: Instance> CostModel<I> for SampledEncodingCost<I> {
+    fn pair_cost(&self, reference: &I, target: &I) -> u64 {
+        // The indices of the pair and the length of the encoding.
+        let header = 3 * usize::num_bytes() as u64;
+        header + self.encoded_len(reference, target)
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn leaf_cost(&self, center: &I, members: &[&I]) -> u64 {
+        let cardinality = members.len();
+        let sample = if cardinality <= self.sample_size {
+            members.to_vec()
+        } else {
+            // An evenly spaced sample, so that the estimate is deterministic.
+            let stride = cardinality / self.sample_size;
+            members.iter().step_by(stride).take(self.sample_size).copied().collect()
+        };
+
+        let sampled = sample.par_iter().map(|t| self.encoded_len(center, t)).sum::<u64>();
+        let body = if sample.len() == cardinality {
+            sampled
+        } else {
+            (sampled.as_f64() * cardinality.as_f64() / sample.len().as_f64()).round() as u64
+        };
+
+        // The cardinality of the cluster and the offset table of the encodings.
+        let header = (2 + cardinality as u64) * usize::num_bytes() as u64;
+        header + body
+    }
+}
+
+/// The predicted and actual sizes of the compressed data, recorded by
+/// `CodecData::new`.
+///
+/// The predictions are the costs from the `CostModel` used to build the
+/// `SquishyBall` tree, so they are only comparable to the actual sizes when the
+/// model predicts bytes, e.g. `SampledEncodingCost`.
+#[derive(Debug, Clone, Default)]
+pub struct CostReport {
+    /// The predicted and actual sizes of each squished cluster.
+    pub leaves: Vec<LeafCost>,
+    /// The predicted number of bytes for the centers, encoded in terms of the
+    /// centers of their parents.
+    pub predicted_centers: u64,
+    /// The actual number of bytes for the centers, encoded in terms of the
+    /// centers of their parents.
+    pub actual_centers: u64,
+}
+
+/// The predicted and actual sizes of a squished cluster.
+#[derive(Debug, Clone)]
+pub struct LeafCost {
+    /// The offset of the cluster in the compressed data.
+    pub codec_offset: usize,
+    /// The number of instances in the cluster.
+    pub cardinality: usize,
+    /// The predicted number of bytes.
+    pub predicted: u64,
+    /// The number of bytes for the cardinality and the offsets or lengths of
+    /// the encodings, before any entropy coding.
+    pub header: u64,
+    /// The number of bytes for the encodings, before any entropy coding.
+    pub encodings: u64,
+    /// The actual number of bytes, after any entropy coding.
+    pub actual: u64,
+}
+
+impl CostReport {
+    /// The predicted number of bytes for the squished clusters and the centers.
+    #[must_use]
+    pub fn predicted_total(&self) -> u64 {
+        self.predicted_centers + self.leaves.iter().map(|l| l.predicted).sum::<u64>()
+    }
+
+    /// The actual number of bytes for the squished clusters and the centers.
+    #[must_use]
+    pub fn actual_total(&self) -> u64 {
+        self.actual_centers + self.leaves.iter().map(|l| l.actual).sum::<u64>()
+    }
+
+    /// The number of bytes for the headers of the squished clusters, before
+    /// any entropy coding.
+    #[must_use]
+    pub fn header_total(&self) -> u64 {
+        self.leaves.iter().map(|l| l.header).sum()
+    }
+
+    /// The number of bytes for the encodings in the squished clusters, before
+    /// any entropy coding.
+    #[must_use]
+    pub fn encodings_total(&self) -> u64 {
+        self.leaves.iter().map(|l| l.encodings).sum()
+    }
+
+    /// The number of bytes stored for the squished clusters, after any
+    /// entropy coding.
+    #[must_use]
+    pub fn stored_total(&self) -> u64 {
+        self.leaves.iter().map(|l| l.actual).sum()
+    }
+
+    /// The relative error of the predicted total, i.e. `(predicted - actual) / actual`.
+    #[must_use]
+    pub fn relative_error(&self) -> f64 {
+        let actual = self.actual_total().as_f64();
+        if actual == 0.0 {
+            0.0
+        } else {
+            (self.predicted_total().as_f64() - actual) / actual
+        }
+    }
+}
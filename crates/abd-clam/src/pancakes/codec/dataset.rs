@@ -2,22 +2,33 @@
 
 use std::{
     collections::{HashMap, HashSet},
+    ops::Deref,
     path::Path,
+    sync::Arc,
 };
 
 use distances::{number::UInt, Number};
+use memmap2::Mmap;
+use rayon::prelude::*;
 
-use crate::{Cluster, Dataset, Instance};
+use crate::{Cluster, Dataset, Instance, PartitionCriterion, UniBall, VecDataset};
 
-use super::{DecoderFn, EncoderFn, SquishyBall};
+use super::{
+    cache::LeafCache, overflow::Overflow, CodecBuilder, CodecStats, CostReport, DecoderFn, EncoderFn, LeafCacheStats,
+    LeafCost, LeafFormat, Mismatch, SquishyBall, VerifyReport,
+};
 
 /// A `Dataset` that allows for searching in a compressed space.
+///
+/// As with `SquishyBall`, the distance type `U` must be an unsigned integer.
 #[derive(Debug)]
 pub struct CodecData<I: Instance, U: UInt, M: Instance> {
     /// The root of the squishy ball tree.
     root: SquishyBall<U>,
     /// The subset of the dataset that contains the centers of the clusters in the tree.
     centers: HashMap<usize, I>,
+    /// The encoded centers, as they are saved to disk.
+    center_bytes: Box<[u8]>,
     /// The encoding function.
     encoder: EncoderFn<I>,
     /// The compressed data for the squished clusters.
@@ -30,6 +41,19 @@ pub struct CodecData<I: Instance, U: UInt, M: Instance> {
     metadata: Vec<M>,
     /// The reordering of the dataset after building the tree.
     permuted_indices: Vec<usize>,
+    /// The inverse of `permuted_indices`, i.e. the position in the tree of
+    /// each original index.
+    positions: Vec<usize>,
+    /// The predicted and actual sizes of the compressed data, if it was built
+    /// rather than loaded.
+    cost_report: Option<CostReport>,
+    /// The total size of the instances by `Instance::to_bytes`, if it was
+    /// recorded when the `CodecData` was built.
+    raw_bytes: Option<u64>,
+    /// The optional cache of decoded leaves used by the searches.
+    leaf_cache: Option<LeafCache<I>>,
+    /// The instances appended after the `CodecData` was built.
+    overflow: Overflow,
 }
 
 impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
@@ -51,19 +75,33 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
     ///
     /// Returns an error if any leaf data could not be encoded.
     pub fn new<D: Dataset<I, U>>(
-        mut root: SquishyBall<U>,
+        root: SquishyBall<U>,
         data: &D,
         encoder: EncoderFn<I>,
         decoder: DecoderFn<I>,
         metadata: Vec<M>,
+    ) -> Result<Self, String> {
+        CodecBuilder::new(encoder, decoder, metadata).build(root, data)
+    }
+
+    /// Assembles a `CodecData` from a trimmed tree whose squished clusters
+    /// have already been encoded into `leaf_data`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the centers could not be encoded.
+    pub(super) fn from_parts<D: Dataset<I, U>>(
+        root: SquishyBall<U>,
+        data: &D,
+        encoder: EncoderFn<I>,
+        leaf_data: LeafData<I>,
+        metadata: Vec<M>,
+        leaf_costs: Vec<LeafCost>,
     ) -> Result<Self, String> {
         let permuted_indices = data
             .permuted_indices()
             .map_or_else(|| (0..data.cardinality()).collect(), <[usize]>::to_vec);
 
-        // Trim the tree.
-        root.trim();
-
         // Build the centers
         let subtree = root.compressible_subtree();
         let centers = subtree.iter().map(|c| c.arg_center()).collect::<HashSet<_>>();
@@ -72,47 +110,63 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
             .map(|i| (i, data[i].clone()))
             .collect::<HashMap<_, _>>();
 
-        // Build the leaves' data
-        let mut bytes = Vec::new();
-        for leaf in root.compressible_leaves_mut().into_iter().filter(|c| c.squish()) {
-            // Set the offset in the compressed data where the leaf data starts.
-            leaf.set_codec_offset(bytes.len());
-
-            // Encode the points in the leaf in terms of the center.
-            let center = &data[leaf.arg_center()];
-            let encodings = leaf
-                .indices()
-                .map(|i| encoder(center, &data[i]))
-                .collect::<Result<Vec<_>, _>>()?;
-
-            // Write the number of encodings.
-            bytes.extend_from_slice(&leaf.cardinality().to_le_bytes());
-            for encoding in encodings {
-                // Write the length of the encoding.
-                let len = encoding.len();
-                bytes.extend_from_slice(&len.to_le_bytes());
-                // Write the encoding.
-                bytes.extend_from_slice(&encoding);
-            }
-        }
+        // Compare the predicted and actual sizes of the centers, excluding the
+        // root center which is stored as raw bytes.
+        let predicted_centers = root
+            .min_cost()
+            .saturating_sub(leaf_costs.iter().map(|l| l.predicted).sum::<u64>());
+        let center_bytes = encode_centers(&root, &centers, encoder)?;
+        let actual_centers = {
+            let root_center = centers[&root.arg_center()].to_bytes().len() + usize::num_bytes();
+            (center_bytes.len() - root_center) as u64
+        };
+        let cost_report = CostReport {
+            leaves: leaf_costs,
+            predicted_centers,
+            actual_centers,
+        };
 
-        let bytes = bytes.into_boxed_slice();
-        let leaf_data = LeafData { bytes, decoder };
+        let raw_bytes = (0..data.cardinality())
+            .into_par_iter()
+            .map(|i| data[i].to_bytes().len() as u64)
+            .sum();
 
         Ok(Self {
             root,
             centers,
+            center_bytes,
             encoder,
             leaf_data,
             metric: data.metric(),
             is_expensive: data.is_metric_expensive(),
             metadata,
+            positions: invert(&permuted_indices),
+            overflow: Overflow::new(permuted_indices.len()),
             permuted_indices,
+            cost_report: Some(cost_report),
+            raw_bytes: Some(raw_bytes),
+            leaf_cache: None,
         })
     }
 
+    /// Returns the layout of the compressed data for each squished cluster.
+    pub const fn leaf_format(&self) -> LeafFormat {
+        self.leaf_data.format
+    }
+
+    /// Returns the predicted and actual sizes of the compressed data.
+    ///
+    /// This is recorded by `new`, and is `None` if the `CodecData` was loaded
+    /// from disk or if instances were appended after it was built.
+    pub const fn cost_report(&self) -> Option<&CostReport> {
+        self.cost_report.as_ref()
+    }
+
     /// Loads the data for a leaf.
     ///
+    /// The instances are in the order of `leaf_indices`, i.e. those appended
+    /// to the leaf come last.
+    ///
     /// # Arguments
     ///
     /// * `leaf`: The leaf to load.
@@ -127,7 +181,345 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
     pub fn load_leaf_data(&self, leaf: &SquishyBall<U>) -> Result<Vec<I>, String> {
         let offset = leaf.codec_offset().ok_or("Leaf has no codec offset")?;
         let center = &self.centers[&leaf.arg_center()];
-        self.leaf_data.load_leaf(center, offset)
+        let mut instances = self.leaf_data.load_leaf(center, offset)?;
+        for &position in self.overflow.members(leaf.offset()) {
+            instances.push(self.decode_appended(position)?);
+        }
+        Ok(instances)
+    }
+
+    /// Returns the positions of the instances in a leaf, including those
+    /// appended to it.
+    pub fn leaf_indices(&self, leaf: &SquishyBall<U>) -> Vec<usize> {
+        leaf.indices()
+            .chain(self.overflow.members(leaf.offset()).iter().copied())
+            .collect()
+    }
+
+    /// Returns the number of instances in a cluster, including those appended
+    /// to it.
+    pub fn cardinality_of(&self, c: &SquishyBall<U>) -> usize {
+        c.cardinality() + self.overflow.count(c.offset(), c.cardinality())
+    }
+
+    /// Returns the number of instances, including those appended.
+    pub fn cardinality(&self) -> usize {
+        self.permuted_indices.len()
+    }
+
+    /// Returns the number of instances appended since the tree was built.
+    ///
+    /// Searches get slower as the appended instances loosen the clusters, so
+    /// `repack` should be used once this is a large fraction of the
+    /// `cardinality`.
+    pub fn num_appended(&self) -> usize {
+        self.overflow.len()
+    }
+
+    /// Enables a least-recently-used cache of decoded leaves for the searches,
+    /// using at most `max_bytes` bytes.
+    ///
+    /// The memory used by a leaf is estimated as the size of each instance
+    /// plus the length of its serialized bytes. This replaces any existing
+    /// cache.
+    #[must_use]
+    pub fn with_leaf_cache(mut self, max_bytes: usize) -> Self {
+        self.leaf_cache = Some(LeafCache::new(max_bytes));
+        self
+    }
+
+    /// Returns the hit and miss counters and the current size of the cache of
+    /// decoded leaves, if it is enabled.
+    pub fn leaf_cache_stats(&self) -> Option<LeafCacheStats> {
+        self.leaf_cache.as_ref().map(LeafCache::stats)
+    }
+
+    /// Removes all leaves from the cache of decoded leaves, if it is enabled,
+    /// and resets its counters.
+    pub fn clear_leaf_cache(&self) {
+        if let Some(cache) = &self.leaf_cache {
+            cache.clear();
+        }
+    }
+
+    /// Returns the decoded instances of a leaf, using the cache of decoded
+    /// leaves if it is enabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf`: The leaf to load.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any leaf data could not be decoded.
+    pub fn decoded_leaf(&self, leaf: &SquishyBall<U>) -> Result<Arc<[I]>, String> {
+        match &self.leaf_cache {
+            Some(cache) => {
+                let offset = leaf.codec_offset().ok_or("Leaf has no codec offset")?;
+                cache.get_or_decode(offset, || self.load_leaf_data(leaf))
+            }
+            None => self.load_leaf_data(leaf).map(Into::into),
+        }
+    }
+
+    /// Decodes a single instance by its index in the original dataset.
+    ///
+    /// Only the encoding of the requested instance is decoded. A search hit,
+    /// which is a position in the tree, has the original index
+    /// `permuted_indices()[hit]`.
+    ///
+    /// # Arguments
+    ///
+    /// * `index`: The index of the instance in the original dataset.
+    ///
+    /// # Errors
+    ///
+    /// * If `index` is out of bounds.
+    /// * If the instance could not be decoded.
+    pub fn get(&self, index: usize) -> Result<I, String> {
+        let &position = self
+            .positions
+            .get(index)
+            .ok_or_else(|| format!("Index {index} is out of bounds for {} instances.", self.positions.len()))?;
+
+        if let Some(center) = self.centers.get(&position) {
+            return Ok(center.clone());
+        }
+        if self.overflow.get(position).is_some() {
+            return self.decode_appended(position);
+        }
+
+        let leaf = self
+            .squished_leaf_of(position)
+            .ok_or_else(|| format!("No squished cluster contains position {position}."))?;
+        let offset = leaf.codec_offset().ok_or("Leaf has no codec offset")?;
+        let center = &self.centers[&leaf.arg_center()];
+        self.leaf_data.load_one(center, offset, position - leaf.offset())
+    }
+
+    /// Decodes several instances by their indices in the original dataset.
+    ///
+    /// # Arguments
+    ///
+    /// * `indices`: The indices of the instances in the original dataset.
+    ///
+    /// # Errors
+    ///
+    /// * See `get`.
+    pub fn get_many(&self, indices: &[usize]) -> Result<Vec<I>, String> {
+        indices.par_iter().map(|&i| self.get(i)).collect()
+    }
+
+    /// Appends instances to the compressed data, without rebuilding the tree.
+    ///
+    /// Each instance is routed to the squished cluster with the nearest
+    /// center, and encoded in terms of that center. The radii of that cluster
+    /// and its ancestors are grown to contain the instance, so that the
+    /// searches stay exact. The appended instances are given the indices,
+    /// and positions, after those of the existing instances.
+    ///
+    /// The cost report no longer describes the compressed data, so it is
+    /// dropped. `repack` records a new one.
+    ///
+    /// # Arguments
+    ///
+    /// * `instances`: The instances to append, with their metadata.
+    ///
+    /// # Returns
+    ///
+    /// The indices of the appended instances.
+    ///
+    /// # Errors
+    ///
+    /// * If an instance could not be encoded.
+    pub fn append(&mut self, instances: Vec<(I, M)>) -> Result<Vec<usize>, String> {
+        let leaves = self
+            .root
+            .compressible_leaves()
+            .into_iter()
+            .map(|leaf| (leaf.offset(), leaf.arg_center(), leaf.codec_offset()))
+            .collect::<Vec<_>>();
+
+        let mut indices = Vec::with_capacity(instances.len());
+        for (instance, metadata) in instances {
+            let distance_to = |i: usize| (self.metric)(&self.centers[&i], &instance);
+            let &(leaf_offset, arg_center, codec_offset) = leaves
+                .par_iter()
+                .map(|leaf| (distance_to(leaf.1), leaf))
+                .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(core::cmp::Ordering::Equal))
+                .map(|(_, leaf)| leaf)
+                .ok_or("The tree has no squished clusters.")?;
+
+            let encoding = (self.encoder)(&self.centers[&arg_center], &instance)?;
+            self.raw_bytes = self.raw_bytes.map(|n| n + instance.to_bytes().len() as u64);
+            let path = self.root.grow_path(leaf_offset, &distance_to);
+            let position = self.overflow.push(leaf_offset, &path, encoding);
+
+            self.permuted_indices.push(position);
+            self.positions.push(position);
+            self.metadata.push(metadata);
+            if let (Some(cache), Some(codec_offset)) = (&self.leaf_cache, codec_offset) {
+                cache.remove(codec_offset);
+            }
+            indices.push(position);
+        }
+
+        if !indices.is_empty() {
+            self.cost_report = None;
+        }
+
+        Ok(indices)
+    }
+
+    /// Rebuilds the tree and recompresses all instances, including those
+    /// appended, with the same layout and leaf cache capacity.
+    ///
+    /// The costs of compression are estimated with a `MetricCost`, as in
+    /// `SquishyBall::from_base_tree`. The indices of the instances are kept.
+    ///
+    /// # Arguments
+    ///
+    /// * `criteria`: The criteria used to partition the new tree.
+    /// * `seed`: The seed for the random number generator.
+    ///
+    /// # Errors
+    ///
+    /// * If an instance could not be decoded or encoded.
+    pub fn repack<P: PartitionCriterion<U>>(&self, criteria: &P, seed: Option<u64>) -> Result<Self, String> {
+        let instances = (0..self.cardinality())
+            .into_par_iter()
+            .map(|i| self.get(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut metadata = self.metadata.clone();
+        for (&index, m) in self.permuted_indices.iter().zip(&self.metadata) {
+            metadata[index] = m.clone();
+        }
+
+        let mut data = VecDataset::new("repacked".to_string(), instances, self.metric, self.is_expensive)
+            .assign_metadata(metadata)?;
+        let root = UniBall::new_root(&data, seed).partition(&mut data, criteria, seed);
+        let root = SquishyBall::from_base_tree(root, &data);
+
+        let codec = CodecBuilder::new(self.encoder, self.leaf_data.decoder, data.metadata().to_vec())
+            .with_leaf_format(self.leaf_data.format)
+            .build(root, &data)?;
+        Ok(match &self.leaf_cache {
+            Some(cache) => codec.with_leaf_cache(cache.stats().capacity),
+            None => codec,
+        })
+    }
+
+    /// Computes the sizes and shape of the compressed data.
+    ///
+    /// This only reads the tree and the recorded sizes, without decoding any
+    /// instances. Use `verify` to check that the instances can be decoded.
+    pub fn stats(&self) -> CodecStats {
+        let leaves = self.root.compressible_leaves();
+
+        let mut leaf_depths = Vec::new();
+        for leaf in &leaves {
+            let depth = leaf.depth() - self.root.depth();
+            if leaf_depths.len() <= depth {
+                leaf_depths.resize(depth + 1, 0);
+            }
+            leaf_depths[depth] += 1;
+        }
+
+        CodecStats {
+            cardinality: self.cardinality(),
+            num_appended: self.num_appended(),
+            num_centers: self.centers.len(),
+            num_squished: leaves.len(),
+            leaf_depths,
+            raw_bytes: self.raw_bytes,
+            center_bytes: self.center_bytes.len() as u64,
+            leaf_bytes: self.leaf_data.bytes.len() as u64,
+            overflow_bytes: self.overflow.num_bytes() as u64,
+        }
+    }
+
+    /// Decodes every squished cluster and checks that each instance is
+    /// byte-for-byte the same, by `Instance::to_bytes`, as in the dataset
+    /// from which the `CodecData` was built.
+    ///
+    /// # Arguments
+    ///
+    /// * `original`: The dataset from which the `CodecData` was built, before
+    ///   or after it was permuted by partitioning, including any appended
+    ///   instances.
+    ///
+    /// # Errors
+    ///
+    /// * If `original` does not have the same cardinality.
+    pub fn verify<D: Dataset<I, U>>(&self, original: &D) -> Result<VerifyReport, String> {
+        if original.cardinality() != self.cardinality() {
+            return Err(format!(
+                "Expected a dataset of {} instances, got {}.",
+                self.cardinality(),
+                original.cardinality()
+            ));
+        }
+
+        // The position, in `original`, of the instance at each original index.
+        let original_positions = original.permuted_indices().map(invert);
+        let original_of = |index: usize| &original[original_positions.as_ref().map_or(index, |p| p[index])];
+
+        let mut mismatches = self
+            .root
+            .compressible_leaves()
+            .par_iter()
+            .flat_map_iter(|leaf| {
+                let indices = self.leaf_indices(leaf).into_iter().map(|p| self.permuted_indices[p]);
+                match self.load_leaf_data(leaf) {
+                    Ok(instances) => indices
+                        .zip(instances)
+                        .filter(|(index, instance)| instance.to_bytes() != original_of(*index).to_bytes())
+                        .map(|(index, _)| Mismatch {
+                            index,
+                            reason: "The decoded instance differs from the original.".to_string(),
+                        })
+                        .collect::<Vec<_>>(),
+                    Err(reason) => indices
+                        .map(|index| Mismatch {
+                            index,
+                            reason: reason.clone(),
+                        })
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        mismatches.sort_by_key(|m| m.index);
+
+        Ok(VerifyReport {
+            num_checked: self.cardinality(),
+            mismatches,
+        })
+    }
+
+    /// Decodes an appended instance.
+    fn decode_appended(&self, position: usize) -> Result<I, String> {
+        let (leaf_offset, encoding) = self
+            .overflow
+            .get(position)
+            .ok_or_else(|| format!("Position {position} was not appended."))?;
+        let leaf = self
+            .squished_leaf_of(leaf_offset)
+            .ok_or_else(|| format!("No squished cluster contains position {leaf_offset}."))?;
+        (self.leaf_data.decoder)(&self.centers[&leaf.arg_center()], encoding)
+    }
+
+    /// Finds the squished cluster which contains the given position.
+    fn squished_leaf_of(&self, position: usize) -> Option<&SquishyBall<U>> {
+        let mut c = &self.root;
+        while !c.squish() {
+            let [left, right] = c.children()?;
+            c = if left.indices().contains(&position) {
+                left
+            } else {
+                right
+            };
+        }
+        c.indices().contains(&position).then_some(c)
     }
 
     /// Returns the root of the squishy ball tree.
@@ -171,35 +563,32 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
     /// * If the `path`s parent directory does not exist.
     /// * If lacking permissions to write to the `path`.
     pub fn save(&self, path: &Path) -> Result<(), String> {
-        // Check if the parent directory exists.
-        if let Some(parent) = path.parent() {
-            if !parent.exists() {
-                return Err(format!("Parent directory does not exist: {parent:?}"));
-            }
-        } else {
-            return Err("Path has no parent directory".to_string());
-        }
+        prepare_dir(path)?;
 
-        // If the directory already exists, delete it.
-        if path.exists() {
-            std::fs::remove_dir_all(path).map_err(|e| e.to_string())?;
-        }
+        // Save the leaf data.
+        let leaf_data_path = path.join(LEAF_DATA_FILE);
+        std::fs::write(leaf_data_path, &self.leaf_data.bytes[..]).map_err(|e| e.to_string())?;
 
-        // Create the directory.
-        std::fs::create_dir(path).map_err(|e| e.to_string())?;
+        self.save_index(path)
+    }
 
+    /// Saves everything but the leaf data to an existing directory.
+    ///
+    /// # Errors
+    ///
+    /// * If lacking permissions to write to the `path`.
+    pub(super) fn save_index(&self, path: &Path) -> Result<(), String> {
         // Save the root.
         let root_path = path.join("root.bin");
         self.root.save(&root_path)?;
 
         // Save the centers.
         let centers_path = path.join("centers.bin");
-        let centers = encode_centers(&self.root, &self.centers, self.encoder)?;
-        std::fs::write(centers_path, &centers).map_err(|e| e.to_string())?;
+        std::fs::write(centers_path, &self.center_bytes).map_err(|e| e.to_string())?;
 
-        // Save the leaf data.
-        let leaf_data_path = path.join("leaf_data.bin");
-        std::fs::write(leaf_data_path, &self.leaf_data.bytes).map_err(|e| e.to_string())?;
+        // Save the layout of the leaf data.
+        let leaf_format_path = path.join(LEAF_FORMAT_FILE);
+        std::fs::write(leaf_format_path, self.leaf_data.format.to_bytes()).map_err(|e| e.to_string())?;
 
         // Save the metadata.
         let metadata_path = path.join("metadata.bin");
@@ -212,6 +601,16 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
         let permuted_indices = bincode::serialize(&self.permuted_indices).map_err(|e| e.to_string())?;
         std::fs::write(permuted_indices_path, permuted_indices).map_err(|e| e.to_string())?;
 
+        // Save the appended instances, if there are any.
+        if self.overflow.len() > 0 {
+            self.overflow.save(&path.join(OVERFLOW_FILE))?;
+        }
+
+        // Save the raw size of the instances, if it is known.
+        if let Some(raw_bytes) = self.raw_bytes {
+            std::fs::write(path.join(RAW_BYTES_FILE), raw_bytes.to_le_bytes()).map_err(|e| e.to_string())?;
+        }
+
         Ok(())
     }
 
@@ -242,6 +641,42 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
         is_expensive: bool,
         encoder: EncoderFn<I>,
         decoder: DecoderFn<I>,
+    ) -> Result<Self, String> {
+        Self::load_with(path, metric, is_expensive, encoder, decoder, false)
+    }
+
+    /// Loads the `CodecData` from disk, memory-mapping the leaf data instead of
+    /// reading them into memory.
+    ///
+    /// The leaf data must not be modified while the `CodecData` is in use.
+    ///
+    /// # Arguments
+    ///
+    /// * See `load`.
+    ///
+    /// # Errors
+    ///
+    /// * See `load`.
+    /// * If the leaf data cannot be memory-mapped.
+    pub fn load_mapped(
+        path: &Path,
+        metric: fn(&I, &I) -> U,
+        is_expensive: bool,
+        encoder: EncoderFn<I>,
+        decoder: DecoderFn<I>,
+    ) -> Result<Self, String> {
+        Self::load_with(path, metric, is_expensive, encoder, decoder, true)
+    }
+
+    /// Loads the `CodecData` from disk, either reading or memory-mapping the
+    /// leaf data.
+    fn load_with(
+        path: &Path,
+        metric: fn(&I, &I) -> U,
+        is_expensive: bool,
+        encoder: EncoderFn<I>,
+        decoder: DecoderFn<I>,
+        mapped: bool,
     ) -> Result<Self, String> {
         // Check if the directory exists.
         if !path.exists() {
@@ -256,7 +691,8 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
         // Check if all the files exist.
         let root_path = path.join("root.bin");
         let centers_path = path.join("centers.bin");
-        let leaf_data_path = path.join("leaf_data.bin");
+        let leaf_data_path = path.join(LEAF_DATA_FILE);
+        let leaf_format_path = path.join(LEAF_FORMAT_FILE);
         let metadata_path = path.join("metadata.bin");
         let permuted_indices_path = path.join("permuted_indices.bin");
 
@@ -276,15 +712,26 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
         let root = SquishyBall::load(&root_path)?;
 
         // Load the centers.
-        let centers = std::fs::read(&centers_path).map_err(|e| e.to_string())?;
-        let centers = decode_centers(&root, &centers, decoder)?;
+        let center_bytes = std::fs::read(&centers_path)
+            .map_err(|e| e.to_string())?
+            .into_boxed_slice();
+        let centers = decode_centers(&root, &center_bytes, decoder)?;
 
         // Load the leaf data.
-        let leaf_data = std::fs::read(&leaf_data_path).map_err(|e| e.to_string())?;
-        let leaf_data = LeafData {
-            bytes: leaf_data.into_boxed_slice(),
-            decoder,
+        let format = if leaf_format_path.exists() {
+            let format = std::fs::read(&leaf_format_path).map_err(|e| e.to_string())?;
+            LeafFormat::from_bytes(&format)?
+        } else {
+            // Older saves do not record the layout, which was always `Fixed`.
+            LeafFormat::Fixed
         };
+        let leaf_data = if mapped {
+            LeafBytes::map(&leaf_data_path)?
+        } else {
+            let bytes = std::fs::read(&leaf_data_path).map_err(|e| e.to_string())?;
+            LeafBytes::InMemory(bytes.into_boxed_slice())
+        };
+        let leaf_data = LeafData::new(leaf_data, format, decoder);
 
         // Load the metadata.
         let metadata = std::fs::read(&metadata_path).map_err(|e| e.to_string())?;
@@ -296,21 +743,98 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
 
         // Load the permuted indices.
         let permuted_indices = std::fs::read(&permuted_indices_path).map_err(|e| e.to_string())?;
-        let permuted_indices = bincode::deserialize(&permuted_indices).map_err(|e| e.to_string())?;
+        let permuted_indices: Vec<usize> = bincode::deserialize(&permuted_indices).map_err(|e| e.to_string())?;
+
+        // Load the appended instances, if there are any.
+        let overflow_path = path.join(OVERFLOW_FILE);
+        let overflow = if overflow_path.exists() {
+            Overflow::load(&overflow_path)?
+        } else {
+            Overflow::new(permuted_indices.len())
+        };
+
+        // Load the raw size of the instances, which older saves do not record.
+        let raw_bytes_path = path.join(RAW_BYTES_FILE);
+        let raw_bytes = if raw_bytes_path.exists() {
+            let raw_bytes = std::fs::read(&raw_bytes_path).map_err(|e| e.to_string())?;
+            if raw_bytes.len() != u64::num_bytes() {
+                return Err(format!("Invalid raw size in {}", raw_bytes_path.display()));
+            }
+            Some(<u64 as Number>::from_le_bytes(&raw_bytes))
+        } else {
+            None
+        };
 
         Ok(Self {
             root,
             centers,
+            center_bytes,
             encoder,
             leaf_data,
             metric,
             is_expensive,
             metadata,
+            positions: invert(&permuted_indices),
             permuted_indices,
+            cost_report: None,
+            raw_bytes,
+            leaf_cache: None,
+            overflow,
         })
     }
 }
 
+/// The name of the file, in the directory of a saved `CodecData`, holding the
+/// leaf data.
+pub(super) const LEAF_DATA_FILE: &str = "leaf_data.bin";
+
+/// The name of the file, in the directory of a saved `CodecData`, holding the
+/// layout of the leaf data.
+const LEAF_FORMAT_FILE: &str = "leaf_format.bin";
+
+/// The name of the file, in the directory of a saved `CodecData`, holding the
+/// instances appended after it was built.
+const OVERFLOW_FILE: &str = "overflow.bin";
+
+/// The name of the file, in the directory of a saved `CodecData`, holding the
+/// total size of the instances by `Instance::to_bytes`.
+const RAW_BYTES_FILE: &str = "raw_bytes.bin";
+
+/// Creates an empty directory to save a `CodecData` to, deleting the directory
+/// if it already exists.
+///
+/// # Errors
+///
+/// * If the `path`s parent directory does not exist.
+/// * If lacking permissions to write to the `path`.
+pub(super) fn prepare_dir(path: &Path) -> Result<(), String> {
+    // Check if the parent directory exists.
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            return Err(format!("Parent directory does not exist: {parent:?}"));
+        }
+    } else {
+        return Err("Path has no parent directory".to_string());
+    }
+
+    // If the directory already exists, delete it.
+    if path.exists() {
+        std::fs::remove_dir_all(path).map_err(|e| e.to_string())?;
+    }
+
+    // Create the directory.
+    std::fs::create_dir(path).map_err(|e| e.to_string())
+}
+
+/// Inverts a permutation.
+fn invert(permutation: &[usize]) -> Vec<usize> {
+    let mut inverse = vec![0; permutation.len()];
+    for (position, &index) in permutation.iter().enumerate() {
+        inverse[index] = position;
+    }
+    inverse
+}
+
 /// Recursively encodes the `centers`.
 fn encode_centers<I: Instance, U: UInt>(
     root: &SquishyBall<U>,
@@ -396,16 +920,63 @@ fn decode_centers<I: Instance, U: UInt>(
     Ok(centers)
 }
 
+/// The bytes of the compressed data for the squished clusters.
+#[derive(Debug)]
+pub(super) enum LeafBytes {
+    /// The bytes are held in memory.
+    InMemory(Box<[u8]>),
+    /// The bytes are memory-mapped from a file.
+    Mapped(Mmap),
+}
+
+impl LeafBytes {
+    /// Memory-maps the file at the given path.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be opened or memory-mapped.
+    pub(super) fn map(path: &Path) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+        if file.metadata().map_err(|e| e.to_string())?.len() == 0 {
+            // Empty files cannot be memory-mapped on all platforms.
+            return Ok(Self::InMemory(Box::default()));
+        }
+        // SAFETY: The file is only read, and `CodecData::load_mapped`
+        // documents that it must not be modified while it is mapped.
+        let mmap = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+        Ok(Self::Mapped(mmap))
+    }
+}
+
+impl Deref for LeafBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::InMemory(bytes) => bytes,
+            Self::Mapped(mmap) => mmap,
+        }
+    }
+}
+
 /// The compressed data for the squished clusters.
 #[derive(Debug)]
-struct LeafData<I: Instance> {
+pub(super) struct LeafData<I: Instance> {
     /// The compressed data for the squished clusters.
-    pub bytes: Box<[u8]>,
+    bytes: LeafBytes,
+    /// The layout of the compressed data for each squished cluster.
+    format: LeafFormat,
     /// The decoding function.
-    pub decoder: DecoderFn<I>,
+    decoder: DecoderFn<I>,
 }
 
 impl<I: Instance> LeafData<I> {
+    /// Creates a new `LeafData` with the given bytes, layout and decoding
+    /// function.
+    pub(super) const fn new(bytes: LeafBytes, format: LeafFormat, decoder: DecoderFn<I>) -> Self {
+        Self { bytes, format, decoder }
+    }
+
     /// Loads the data for a leaf.
     ///
     /// # Arguments
@@ -421,32 +992,238 @@ impl<I: Instance> LeafData<I> {
     ///
     /// Returns an error if any leaf data could not be decoded.
     fn load_leaf(&self, center: &I, offset: usize) -> Result<Vec<I>, String> {
-        // Read the number of encodings.
-        let cardinality = {
-            let bytes = &self.bytes[offset..(offset + usize::num_bytes())];
-            <usize as Number>::from_le_bytes(bytes)
+        let block = self.format.read_block(&self.bytes, offset)?;
+        (0..block.cardinality())
+            .map(|j| (self.decoder)(center, block.encoding(j)?))
+            .collect()
+    }
+
+    /// Loads a single instance from a leaf.
+    ///
+    /// # Arguments
+    ///
+    /// * `center`: The center of the leaf.
+    /// * `offset`: The offset in the compressed data where the leaf data starts.
+    /// * `j`: The position of the instance in the leaf.
+    ///
+    /// # Errors
+    ///
+    /// * If `j` is out of bounds for the leaf.
+    /// * If the instance could not be decoded.
+    fn load_one(&self, center: &I, offset: usize, j: usize) -> Result<I, String> {
+        let encoding = self.format.read_encoding(&self.bytes, offset, j)?;
+        (self.decoder)(center, &encoding)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        pancakes::codec::fixture::{fixture, lev_metric, Fixture},
+        VecDataset,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_get() -> Result<(), String> {
+        let fixture = fixture()?;
+        let strings = &fixture.strings;
+        let codec = fixture.codec()?;
+
+        for (i, s) in strings.iter().enumerate() {
+            assert_eq!(&codec.get(i)?, s, "index {i}");
+        }
+        let indices = [7, 0, 299, 7, 42];
+        let expected = indices.iter().map(|&i| strings[i].clone()).collect::<Vec<_>>();
+        assert_eq!(codec.get_many(&indices)?, expected);
+        assert!(codec.get(strings.len()).is_err());
+        assert!(codec.get_many(&[0, strings.len()]).is_err());
+
+        // Search hits are positions in the tree.
+        let hits = codec.rnn_search(&strings[3], 0, &crate::pancakes::rnn::Algorithm::Clustered);
+        assert!(hits.iter().any(|&(hit, _)| codec.permuted_indices()[hit] == 3));
+
+        let path = fixture.tmp_dir.path().join("codec");
+        codec.save(&path)?;
+        let loaded = Fixture::load(&path)?;
+        for (i, s) in strings.iter().enumerate() {
+            assert_eq!(&loaded.get(i)?, s, "index {i} after loading");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_append() -> Result<(), String> {
+        let fixture = fixture()?;
+        let strings = &fixture.strings;
+        let appended = symagen::random_data::random_string(50, 20, 40, "ACGT", 7);
+        let mut codec = fixture.codec()?.with_leaf_cache(1 << 20);
+
+        // Search once so that the cache holds leaves which are then appended to.
+        let _ = codec.rnn_search(&appended[0], 15, &crate::pancakes::rnn::Algorithm::Clustered);
+
+        // Appending nothing keeps the cost report.
+        assert!(codec.append(Vec::new())?.is_empty());
+        assert!(codec.cost_report().is_some());
+
+        let instances = appended
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), strings.len() + i))
+            .collect();
+        let indices = codec.append(instances)?;
+        assert_eq!(
+            indices,
+            (strings.len()..strings.len() + appended.len()).collect::<Vec<_>>()
+        );
+        assert_eq!(codec.num_appended(), appended.len());
+        assert_eq!(codec.cardinality(), strings.len() + appended.len());
+        assert!(codec.cost_report().is_none());
+
+        let all = strings.iter().chain(&appended).cloned().collect::<Vec<_>>();
+        for (i, s) in all.iter().enumerate() {
+            assert_eq!(&codec.get(i)?, s, "index {i}");
+        }
+
+        // The searches stay exact, and report the original indices of the hits.
+        let original_hits = |codec: &CodecData<String, u16, usize>, query: &String, radius: u16| {
+            let mut hits = codec
+                .rnn_search(query, radius, &crate::pancakes::rnn::Algorithm::Clustered)
+                .into_iter()
+                .map(|(hit, d)| (codec.permuted_indices()[hit], d))
+                .collect::<Vec<_>>();
+            hits.sort_unstable();
+            hits
         };
+        for query in appended.iter().step_by(5).chain(strings.iter().step_by(50)) {
+            let mut expected = all
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (i, lev_metric(query, s)))
+                .filter(|&(_, d)| d <= 15)
+                .collect::<Vec<_>>();
+            expected.sort_unstable();
+            assert_eq!(original_hits(&codec, query, 15), expected);
 
-        let mut data = Vec::with_capacity(cardinality);
-        let mut offset = offset + usize::num_bytes();
-        for _ in 0..cardinality {
-            // Read the length of the encoding.
-            let len = {
-                let bytes = &self.bytes[offset..(offset + usize::num_bytes())];
-                <usize as Number>::from_le_bytes(bytes)
-            };
-            offset += usize::num_bytes();
+            let mut linear = codec
+                .rnn_search(query, 15, &crate::pancakes::rnn::Algorithm::Linear)
+                .into_iter()
+                .map(|(hit, d)| (codec.permuted_indices()[hit], d))
+                .collect::<Vec<_>>();
+            linear.sort_unstable();
+            assert_eq!(linear, expected);
 
-            // Read the encoding.
-            let target = {
-                let bytes = &self.bytes[offset..(offset + len)];
-                offset += len;
-                (self.decoder)(center, bytes)?
-            };
+            let mut distances = all.iter().map(|s| lev_metric(query, s)).collect::<Vec<_>>();
+            distances.sort_unstable();
+            for algorithm in crate::pancakes::knn::Algorithm::variants().iter() {
+                let mut hits = codec
+                    .knn_search(query, 5, algorithm)?
+                    .into_iter()
+                    .map(|(_, d)| d)
+                    .collect::<Vec<_>>();
+                hits.sort_unstable();
+                assert_eq!(hits, distances[..5], "{}", algorithm.name());
+            }
+        }
+
+        // The appended instances are saved and loaded.
+        let path = fixture.tmp_dir.path().join("codec");
+        codec.save(&path)?;
+        let loaded = Fixture::load(&path)?;
+        assert_eq!(loaded.num_appended(), appended.len());
+        for (i, s) in all.iter().enumerate() {
+            assert_eq!(&loaded.get(i)?, s, "index {i} after loading");
+        }
+        assert_eq!(
+            original_hits(&loaded, &appended[0], 15),
+            original_hits(&codec, &appended[0], 15)
+        );
+
+        // Repacking keeps the indices and the search results.
+        let repacked = codec.repack(&fixture.criteria, fixture.seed)?;
+        assert_eq!(repacked.num_appended(), 0);
+        assert!(repacked.cost_report().is_some());
+        assert_eq!(repacked.cardinality(), all.len());
+        for (i, s) in all.iter().enumerate() {
+            assert_eq!(&repacked.get(i)?, s, "index {i} after repacking");
+        }
+        for query in appended.iter().step_by(10) {
+            assert_eq!(original_hits(&repacked, query, 15), original_hits(&codec, query, 15));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_and_verify() -> Result<(), String> {
+        let fixture = fixture()?;
+        let strings = &fixture.strings;
+        let mut codec = fixture.codec()?;
+
+        let stats = codec.stats();
+        assert_eq!(stats.cardinality, strings.len());
+        assert_eq!(stats.num_appended, 0);
+        assert_eq!(stats.num_centers, codec.centers().len());
+        assert_eq!(stats.num_squished, codec.root().compressible_leaves().len());
+        assert_eq!(stats.leaf_depths.iter().sum::<usize>(), stats.num_squished);
+        assert!(stats.leaf_depths.last().is_some_and(|&n| n > 0));
+        let raw = strings.iter().map(|s| s.to_bytes().len() as u64).sum::<u64>();
+        assert_eq!(stats.raw_bytes, Some(raw));
+        assert_eq!(stats.compressed_bytes(), stats.center_bytes + stats.leaf_bytes);
+        assert!(stats.mean_leaf_depth() <= stats.max_leaf_depth().as_f64());
 
-            data.push(target);
+        // The original dataset may be given before or after it was permuted.
+        let unpermuted = VecDataset::new("test-genomic".to_string(), strings.clone(), lev_metric, true);
+        for report in [codec.verify(&fixture.dataset)?, codec.verify(&unpermuted)?] {
+            assert_eq!(report.num_checked, strings.len());
+            assert!(report.is_exact(), "{:?}", report.mismatches);
         }
 
-        Ok(data)
+        let mut changed = strings.clone();
+        changed[17].push('A');
+        changed[3] = "ACGT".to_string();
+        let changed = VecDataset::new("test-genomic".to_string(), changed, lev_metric, true);
+        let report = codec.verify(&changed)?;
+        assert_eq!(
+            report.mismatches.iter().map(|m| m.index).collect::<Vec<_>>(),
+            vec![3, 17]
+        );
+        assert!(codec
+            .verify(&VecDataset::new(
+                "short".to_string(),
+                strings[1..].to_vec(),
+                lev_metric,
+                true
+            ))
+            .is_err());
+
+        // Appended instances are included.
+        let appended = symagen::random_data::random_string(20, 20, 40, "ACGT", 7);
+        codec.append(appended.iter().cloned().zip(strings.len()..).collect())?;
+        let stats = codec.stats();
+        assert_eq!(stats.num_appended, appended.len());
+        assert_eq!(
+            stats.raw_bytes,
+            Some(raw + appended.iter().map(|s| s.to_bytes().len() as u64).sum::<u64>())
+        );
+        assert!(stats.overflow_bytes > 0);
+
+        // The stats do not change when the `CodecData` is saved and loaded.
+        let path = fixture.tmp_dir.path().join("codec");
+        codec.save(&path)?;
+        let loaded = Fixture::load(&path)?;
+        assert_eq!(loaded.stats(), stats);
+        std::fs::remove_file(path.join(RAW_BYTES_FILE)).map_err(|e| e.to_string())?;
+        let loaded = Fixture::load(&path)?;
+        assert_eq!(loaded.stats().raw_bytes, None);
+        assert!(loaded.stats().compression_factor().is_none());
+        let all = strings.iter().cloned().chain(appended).collect::<Vec<_>>();
+        assert!(codec
+            .verify(&VecDataset::new("all".to_string(), all, lev_metric, true))?
+            .is_exact());
+
+        Ok(())
     }
 }
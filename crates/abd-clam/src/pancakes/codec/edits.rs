@@ -0,0 +1,567 @@
+//! Edit-script codecs for strings.
+//!
+//! A target string is encoded as the edits, from a Needleman-Wunsch alignment,
+//! which turn the reference into the target. The encoding starts with a header
+//! of two bytes, the format version and the symbol mode, followed by a sequence
+//! of records. Each record is:
+//!
+//! * a varint tag, holding the kind of edit in the low 2 bits and the number of
+//!   edits in the run, minus one, in the remaining bits,
+//! * the zigzag varint of the position of the first edit in the run, relative
+//!   to the position of the last edit in the previous run, and
+//! * for insertions and substitutions, one varint symbol per edit in the run.
+//!
+//! A run is a sequence of edits of the same kind at consecutive positions. For
+//! deletions, these are all at the same position because each deletion shifts
+//! the rest of the string.
+//!
+//! Symbols are either Unicode scalar values or indices into the alphabet of an
+//! `EditFormat`.
+
+use distances::{
+    number::UInt,
+    strings::{
+        needleman_wunsch::{apply_edits, compute_table, Direction, Edit},
+        Penalties,
+    },
+};
+
+use super::varint::{read_varint, unzigzag, write_varint, zigzag};
+
+/// The version of the encoding, written as the first byte of the header.
+const VERSION: u8 = 1;
+
+/// The symbol mode for Unicode scalar values.
+const MODE_UNICODE: u8 = 0;
+
+/// The symbol mode for indices into the alphabet of an `EditFormat`.
+const MODE_ALPHABET: u8 = 1;
+
+/// The tag bits for a deletion.
+const OP_DEL: u64 = 0b00;
+
+/// The tag bits for an insertion.
+const OP_INS: u64 = 0b01;
+
+/// The tag bits for a substitution.
+const OP_SUB: u64 = 0b10;
+
+/// Configures how the edit-script codec stores symbols and runs of edits.
+pub trait EditFormat {
+    /// The symbols which may appear in the target strings, or `None` to allow
+    /// any Unicode scalar value.
+    ///
+    /// With an alphabet of at most 128 symbols, each symbol takes one byte.
+    const ALPHABET: Option<&'static str>;
+
+    /// Whether to merge consecutive edits of the same kind into runs.
+    const RUN_LENGTH: bool;
+}
+
+/// Any Unicode scalar value, with runs of edits merged.
+pub struct Unicode;
+
+impl EditFormat for Unicode {
+    const ALPHABET: Option<&'static str> = None;
+    const RUN_LENGTH: bool = true;
+}
+
+/// The IUPAC nucleotide codes and the gap symbol, with runs of edits merged.
+pub struct Nucleotides;
+
+impl EditFormat for Nucleotides {
+    const ALPHABET: Option<&'static str> = Some("ACGTUNRYKMSWBDHV-acgtunrykmswbdhv");
+    const RUN_LENGTH: bool = true;
+}
+
+/// The IUPAC amino-acid codes, the stop and gap symbols, with runs of edits
+/// merged.
+pub struct AminoAcids;
+
+impl EditFormat for AminoAcids {
+    const ALPHABET: Option<&'static str> = Some("ACDEFGHIKLMNPQRSTVWYBZJUOX*-");
+    const RUN_LENGTH: bool = true;
+}
+
+/// Encodes a reference and target string into a byte array.
+///
+/// This uses the `Unicode` format.
+///
+/// # Arguments
+///
+/// * `reference`: The reference string.
+/// * `target`: The target string.
+///
+/// # Errors
+///
+/// * See `encode_edits`.
+///
+/// # Returns
+///
+/// A byte array encoding the reference and target strings.
+#[allow(clippy::ptr_arg)]
+pub fn encode_general<U: UInt>(reference: &String, target: &String) -> Result<Box<[u8]>, String> {
+    encode_edits::<U, Unicode>(reference, target)
+}
+
+/// Decodes a target string from a reference string and a byte array.
+///
+/// This uses the `Unicode` format.
+///
+/// # Arguments
+///
+/// * `reference`: The reference string.
+/// * `encoding`: The byte array encoding the target string.
+///
+/// # Errors
+///
+/// * See `decode_edits`.
+///
+/// # Returns
+///
+/// The target string.
+#[allow(clippy::ptr_arg)]
+pub fn decode_general(reference: &String, encoding: &[u8]) -> Result<String, String> {
+    decode_edits::<Unicode>(reference, encoding)
+}
+
+/// Encodes a reference and target string into a byte array, using the given
+/// `EditFormat`.
+///
+/// # Arguments
+///
+/// * `reference`: The reference string.
+/// * `target`: The target string.
+///
+/// # Errors
+///
+/// * If the format has an alphabet and an edit uses a symbol outside of it.
+///
+/// # Returns
+///
+/// A byte array encoding the reference and target strings.
+#[allow(clippy::ptr_arg)]
+pub fn encode_edits<U: UInt, F: EditFormat>(reference: &String, target: &String) -> Result<Box<[u8]>, String> {
+    let table = compute_table::<U>(reference, target, Penalties::default());
+    serialize_edits::<F>(&trace_back_edits(&table, reference, target))
+}
+
+/// Decodes a target string from a reference string and a byte array, using the
+/// given `EditFormat`.
+///
+/// # Arguments
+///
+/// * `reference`: The reference string.
+/// * `encoding`: The byte array encoding the target string.
+///
+/// # Errors
+///
+/// * If the byte array is not a valid encoding of edits in this format.
+/// * If the edits do not fit the reference string.
+///
+/// # Returns
+///
+/// The target string.
+#[allow(clippy::ptr_arg)]
+pub fn decode_edits<F: EditFormat>(reference: &String, encoding: &[u8]) -> Result<String, String> {
+    let len = reference.chars().count();
+    let edits = deserialize_edits::<F>(encoding, len)?;
+    check_edits(len, &edits)?;
+    Ok(apply_edits(reference, &edits))
+}
+
+/// Traces back through the Needleman-Wunsch table to get the edits which turn
+/// the reference into the target.
+///
+/// The gaps in the alignment are `None`, rather than a gap character, so that
+/// every character, including '-', may appear in the strings.
+fn trace_back_edits<U: UInt>(table: &[Vec<(U, Direction)>], reference: &str, target: &str) -> Vec<Edit> {
+    let (x, y) = (
+        reference.chars().collect::<Vec<_>>(),
+        target.chars().collect::<Vec<_>>(),
+    );
+
+    let mut aligned = Vec::with_capacity(x.len().max(y.len()));
+    let (mut row, mut col) = (y.len(), x.len());
+    while row > 0 || col > 0 {
+        match table[row][col].1 {
+            Direction::Diagonal => {
+                aligned.push((Some(x[col - 1]), Some(y[row - 1])));
+                row -= 1;
+                col -= 1;
+            }
+            Direction::Left => {
+                aligned.push((Some(x[col - 1]), None));
+                col -= 1;
+            }
+            Direction::Up => {
+                aligned.push((None, Some(y[row - 1])));
+                row -= 1;
+            }
+        }
+    }
+    aligned.reverse();
+
+    let mut edits = Vec::new();
+    let mut deleted = 0;
+    for (index, pair) in aligned.into_iter().enumerate() {
+        let i = index - deleted;
+        match pair {
+            (Some(c_x), Some(c_y)) if c_x != c_y => edits.push(Edit::Sub(i, c_y)),
+            (Some(_), None) => {
+                edits.push(Edit::Del(i));
+                deleted += 1;
+            }
+            (None, Some(c_y)) => edits.push(Edit::Ins(i, c_y)),
+            _ => (),
+        }
+    }
+    edits
+}
+
+/// The kind of an edit, as its tag bits, its position and its symbol.
+const fn parts(edit: &Edit) -> (u64, usize, Option<char>) {
+    match *edit {
+        Edit::Del(i) => (OP_DEL, i, None),
+        Edit::Ins(i, c) => (OP_INS, i, Some(c)),
+        Edit::Sub(i, c) => (OP_SUB, i, Some(c)),
+    }
+}
+
+/// How far apart the positions of consecutive edits in a run of this kind are.
+const fn step(op: u64) -> usize {
+    if op == OP_DEL {
+        0
+    } else {
+        1
+    }
+}
+
+/// Serializes a sequence of edit operations into a byte array.
+#[allow(clippy::cast_possible_wrap)]
+fn serialize_edits<F: EditFormat>(edits: &[Edit]) -> Result<Box<[u8]>, String> {
+    let mode = if F::ALPHABET.is_some() {
+        MODE_ALPHABET
+    } else {
+        MODE_UNICODE
+    };
+    let mut bytes = vec![VERSION, mode];
+
+    let mut previous = 0;
+    let mut start = 0;
+    while start < edits.len() {
+        let (op, position, _) = parts(&edits[start]);
+
+        let mut run = 1;
+        if F::RUN_LENGTH {
+            while edits
+                .get(start + run)
+                .map(parts)
+                .is_some_and(|(o, p, _)| o == op && p == position + run * step(op))
+            {
+                run += 1;
+            }
+        }
+
+        write_varint(((run as u64 - 1) << 2) | op, &mut bytes);
+        write_varint(zigzag(position as i64 - previous as i64), &mut bytes);
+        for edit in &edits[start..start + run] {
+            if let (_, _, Some(c)) = parts(edit) {
+                write_varint(symbol_to_code::<F>(c)?, &mut bytes);
+            }
+        }
+
+        previous = position + (run - 1) * step(op);
+        start += run;
+    }
+
+    Ok(bytes.into_boxed_slice())
+}
+
+/// Deserializes a byte array into a sequence of edit operations.
+///
+/// # Arguments
+///
+/// * `bytes`: The byte array encoding the edit operations.
+/// * `len`: The number of characters in the reference string.
+///
+/// # Errors
+///
+/// * If the header is missing or does not match this format.
+/// * If the byte array ends in the middle of a record.
+/// * If the edit type is not recognized.
+/// * If a position or symbol is out of range.
+/// * If a run of deletions is longer than the rest of the string.
+///
+/// # Returns
+///
+/// A vector of edit operations.
+fn deserialize_edits<F: EditFormat>(bytes: &[u8], mut len: usize) -> Result<Vec<Edit>, String> {
+    let (version, mode) = match bytes {
+        [version, mode, ..] => (*version, *mode),
+        _ => return Err(format!("Expected a header of 2 bytes, got {} bytes.", bytes.len())),
+    };
+    if version != VERSION {
+        return Err(format!("Unsupported edit-script version: {version}."));
+    }
+    let expected_mode = if F::ALPHABET.is_some() {
+        MODE_ALPHABET
+    } else {
+        MODE_UNICODE
+    };
+    if mode != expected_mode {
+        return Err(format!("Expected symbol mode {expected_mode}, got {mode}."));
+    }
+
+    let mut edits = Vec::new();
+    let mut previous = 0_usize;
+    let mut i = 2;
+    while i < bytes.len() {
+        let tag = read_varint(bytes, &mut i)?;
+        let op = tag & 0b11;
+        let run = usize::try_from(tag >> 2)
+            .ok()
+            .and_then(|r| r.checked_add(1))
+            .ok_or_else(|| format!("Run of edits is too long: {}.", tag >> 2))?;
+
+        // Insertions and substitutions need at least one byte per symbol.
+        if op != OP_DEL && run > bytes.len() - i {
+            return Err(format!(
+                "Run of {run} edits exceeds the {} remaining bytes.",
+                bytes.len() - i
+            ));
+        }
+
+        let delta = unzigzag(read_varint(bytes, &mut i)?);
+        let position = isize::try_from(delta)
+            .ok()
+            .and_then(|d| previous.checked_add_signed(d))
+            .ok_or_else(|| format!("Invalid edit position: {previous} + {delta}."))?;
+
+        // Deletions need a character each, so the run is bounded by the length
+        // of the string rather than by the number of bytes.
+        if op == OP_DEL {
+            if run > len.saturating_sub(position) {
+                return Err(format!(
+                    "Run of {run} deletions at {position} exceeds a string of length {len}."
+                ));
+            }
+            len -= run;
+        } else if op == OP_INS {
+            len += run;
+        }
+
+        for k in 0..run {
+            let p = position + k * step(op);
+            let edit = match op {
+                OP_DEL => Edit::Del(p),
+                OP_INS => Edit::Ins(p, code_to_symbol::<F>(read_varint(bytes, &mut i)?)?),
+                OP_SUB => Edit::Sub(p, code_to_symbol::<F>(read_varint(bytes, &mut i)?)?),
+                _ => return Err(format!("Invalid edit type: {op:b}.")),
+            };
+            edits.push(edit);
+        }
+
+        previous = position + (run - 1) * step(op);
+    }
+
+    Ok(edits)
+}
+
+/// Checks that each edit fits the string that it will be applied to.
+fn check_edits(mut len: usize, edits: &[Edit]) -> Result<(), String> {
+    for edit in edits {
+        match *edit {
+            Edit::Del(i) | Edit::Sub(i, _) if i >= len => {
+                return Err(format!("Edit {edit:?} is out of bounds for a string of length {len}."));
+            }
+            Edit::Ins(i, _) if i > len => {
+                return Err(format!("Edit {edit:?} is out of bounds for a string of length {len}."));
+            }
+            Edit::Del(_) => len -= 1,
+            Edit::Ins(_, _) => len += 1,
+            Edit::Sub(_, _) => (),
+        }
+    }
+    Ok(())
+}
+
+/// Converts a symbol to its code in the format.
+fn symbol_to_code<F: EditFormat>(c: char) -> Result<u64, String> {
+    F::ALPHABET.map_or_else(
+        || Ok(u64::from(u32::from(c))),
+        |alphabet| {
+            alphabet
+                .chars()
+                .position(|a| a == c)
+                .map(|i| i as u64)
+                .ok_or_else(|| format!("Symbol {c:?} is not in the alphabet {alphabet:?}."))
+        },
+    )
+}
+
+/// Converts a code in the format to its symbol.
+fn code_to_symbol<F: EditFormat>(code: u64) -> Result<char, String> {
+    F::ALPHABET
+        .map_or_else(
+            || u32::try_from(code).ok().and_then(char::from_u32),
+            |alphabet| usize::try_from(code).ok().and_then(|i| alphabet.chars().nth(i)),
+        )
+        .ok_or_else(|| format!("Invalid symbol code: {code}."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_round_trip<F: EditFormat>(reference: &str, target: &str) -> Result<usize, String> {
+        let (reference, target) = (reference.to_string(), target.to_string());
+        let encoding = encode_edits::<u32, F>(&reference, &target)?;
+        assert_eq!(decode_edits::<F>(&reference, &encoding)?, target);
+        Ok(encoding.len())
+    }
+
+    #[test]
+    fn test_round_trip() -> Result<(), String> {
+        let pairs = [
+            ("NAJIBPEPPERSEATS", "NAJIBEATSPEPPERS"),
+            ("TOMEATSWHATFOODEATS", "FOODEATSWHATTOMEATS"),
+            ("", "ACGT"),
+            ("ACGT", ""),
+            ("GATTACA", "GCATGCT"),
+        ];
+        for (reference, target) in pairs {
+            check_round_trip::<Unicode>(reference, target)?;
+        }
+
+        let pairs = [
+            ("ACGTTGCA", "ACTTGGCAN"),
+            ("", "ACGT"),
+            ("ACGT", ""),
+            ("GATTACA", "GCATGCT"),
+        ];
+        for (reference, target) in pairs {
+            check_round_trip::<Nucleotides>(reference, target)?;
+        }
+
+        // The gap symbol is an ordinary character in both strings.
+        let pairs = [
+            ("ACG-T--A", "AC-GTA-"),
+            ("---", "-A-"),
+            ("A-C", "A--C"),
+            ("--", ""),
+            ("", "--"),
+        ];
+        for (reference, target) in pairs {
+            check_round_trip::<Nucleotides>(reference, target)?;
+            check_round_trip::<Unicode>(reference, target)?;
+        }
+        check_round_trip::<AminoAcids>("MK-TAYIA--KQR*", "MKT-AYI-AKQR-*")?;
+
+        // Identical strings only need the header.
+        assert_eq!(check_round_trip::<Unicode>("ACGT", "ACGT")?, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unicode() -> Result<(), String> {
+        check_round_trip::<Unicode>("naïve café", "naive cafe")?;
+        check_round_trip::<Unicode>("hello", "héllo 🦀 wörld")?;
+        check_round_trip::<Unicode>("日本語のテキスト", "日本のテキスト")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_strings() -> Result<(), String> {
+        // Positions beyond 14 bits used to be truncated. Aligning strings this
+        // long is slow, so this checks the serialization of the edits directly.
+        let reference = "ACGT".repeat(25_000);
+        let edits = vec![
+            Edit::Sub(16_384, 'T'),
+            Edit::Del(70_000),
+            Edit::Del(70_000),
+            Edit::Ins(99_998, 'G'),
+            Edit::Sub(5, 'A'),
+        ];
+
+        let bytes = serialize_edits::<Nucleotides>(&edits)?;
+        let decoded = deserialize_edits::<Nucleotides>(&bytes, reference.len())?;
+        assert_eq!(decoded, edits);
+        check_edits(reference.len(), &decoded)?;
+        assert_eq!(apply_edits(&reference, &decoded).len(), reference.len() - 1);
+
+        Ok(())
+    }
+
+    /// The `Nucleotides` format without runs.
+    struct NoRuns;
+
+    impl EditFormat for NoRuns {
+        const ALPHABET: Option<&'static str> = Nucleotides::ALPHABET;
+        const RUN_LENGTH: bool = false;
+    }
+
+    #[test]
+    fn test_run_length() -> Result<(), String> {
+        let reference = "ACGTACGTACGTACGTACGT";
+        for target in ["ACGTACGTACGTACGTACGTTTTTTTTT", "ACGTACGT", "ACGTAAAAAAAAACGTACGT"] {
+            let with_runs = check_round_trip::<Nucleotides>(reference, target)?;
+            let without_runs = check_round_trip::<NoRuns>(reference, target)?;
+            assert!(with_runs < without_runs, "{with_runs} >= {without_runs} for {target}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_alphabet() -> Result<(), String> {
+        let reference = "MKTAYIAKQR".to_string();
+        let target = "MKTAYIAKQRQISFVKSHFSRQ*".to_string();
+        check_round_trip::<AminoAcids>(&reference, &target)?;
+
+        // Symbols outside of the alphabet are rejected when encoding.
+        assert!(encode_edits::<u32, Nucleotides>(&reference, &target).is_err());
+
+        // Encodings in another symbol mode are rejected when decoding.
+        let encoding = encode_edits::<u32, AminoAcids>(&reference, &target)?;
+        assert!(decode_edits::<Unicode>(&reference, &encoding).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_encodings() -> Result<(), String> {
+        let reference = "NAJIBPEPPERSEATS".to_string();
+        let target = "NAJIBEATSPEPPERS".to_string();
+        let encoding = encode_general::<u32>(&reference, &target)?;
+
+        // Every truncation is rejected instead of panicking.
+        for len in 0..encoding.len() {
+            let truncated = &encoding[..len];
+            if let Ok(decoded) = decode_general(&reference, truncated) {
+                // Truncating at a record boundary leaves a valid, shorter script.
+                assert_ne!(decoded, target);
+            }
+        }
+
+        // Unknown version.
+        assert!(decode_general(&reference, &[0, MODE_UNICODE]).is_err());
+        // Unknown edit type.
+        assert!(decode_general(&reference, &[VERSION, MODE_UNICODE, 0b11, 0]).is_err());
+        // Deletion beyond the end of the reference.
+        assert!(decode_general(&reference, &[VERSION, MODE_UNICODE, 0, 40]).is_err());
+        // Huge run of deletions.
+        let mut huge = vec![VERSION, MODE_UNICODE];
+        write_varint(!0b11, &mut huge);
+        huge.push(0);
+        assert!(decode_general(&reference, &huge).is_err());
+        // More deletions than characters.
+        assert!(decode_general(&reference, &[VERSION, MODE_UNICODE, 16 << 2, 0]).is_err());
+        // Huge run of insertions.
+        assert!(decode_general(&reference, &[VERSION, MODE_UNICODE, 0xfd, 0xff, 0x03, 0, 65]).is_err());
+        // Invalid Unicode scalar value.
+        assert!(decode_general(&reference, &[VERSION, MODE_UNICODE, 0b01, 0, 0x80, 0xb0, 0x03]).is_err());
+
+        Ok(())
+    }
+}
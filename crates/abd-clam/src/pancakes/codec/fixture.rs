@@ -0,0 +1,79 @@
+//! A fixture shared by the tests of the codec.
+
+use std::path::Path;
+
+use distances::strings::levenshtein;
+use tempdir::TempDir;
+
+use crate::{Cluster, PartitionCriteria, UniBall, VecDataset};
+
+use super::{decode_general, encode_general, CodecData, SquishyBall};
+
+/// The number of strings in the fixture.
+const CARDINALITY: usize = 300;
+
+/// The Levenshtein distance, with the signature of a metric for a `VecDataset`.
+#[allow(clippy::ptr_arg)]
+pub fn lev_metric(x: &String, y: &String) -> u16 {
+    levenshtein(x, y)
+}
+
+/// Random genomic strings partitioned into a tree, with a temporary directory
+/// in which to save codecs.
+pub struct Fixture {
+    /// The strings, in their original order.
+    pub strings: Vec<String>,
+    /// The strings, permuted by the partition, with their original indices as
+    /// metadata.
+    pub dataset: VecDataset<String, u16, usize>,
+    /// The root of the tree.
+    pub root: UniBall<u16>,
+    /// The criteria with which the tree was partitioned.
+    pub criteria: PartitionCriteria<u16>,
+    /// The seed with which the tree was partitioned.
+    pub seed: Option<u64>,
+    /// A temporary directory, which is removed with the fixture.
+    pub tmp_dir: TempDir,
+}
+
+impl Fixture {
+    /// Returns a tree of `SquishyBall`s with the default cost model.
+    pub fn squishy(&self) -> SquishyBall<u16> {
+        SquishyBall::from_base_tree(self.root.clone(), &self.dataset)
+    }
+
+    /// Builds a `CodecData` with the default options.
+    pub fn codec(&self) -> Result<CodecData<String, u16, usize>, String> {
+        let metadata = self.dataset.metadata().to_vec();
+        CodecData::new(
+            self.squishy(),
+            &self.dataset,
+            encode_general::<u16>,
+            decode_general,
+            metadata,
+        )
+    }
+
+    /// Loads a `CodecData` which was saved at the given path.
+    pub fn load(path: &Path) -> Result<CodecData<String, u16, usize>, String> {
+        CodecData::load(path, lev_metric, true, encode_general::<u16>, decode_general)
+    }
+}
+
+/// Builds the fixture.
+pub fn fixture() -> Result<Fixture, String> {
+    let strings = symagen::random_data::random_string(CARDINALITY, 20, 40, "ACGT", 42);
+    let mut dataset = VecDataset::new("test-genomic".to_string(), strings.clone(), lev_metric, true);
+    let criteria = PartitionCriteria::default();
+    let seed = Some(42);
+    let root = UniBall::new_root(&dataset, seed).partition(&mut dataset, &criteria, seed);
+    let tmp_dir = TempDir::new("codec").map_err(|e| e.to_string())?;
+    Ok(Fixture {
+        strings,
+        dataset,
+        root,
+        criteria,
+        seed,
+        tmp_dir,
+    })
+}
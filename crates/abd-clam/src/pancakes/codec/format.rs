@@ -0,0 +1,395 @@
+//! The layouts of the compressed data for a squished cluster.
+
+use std::{borrow::Cow, ops::Range};
+
+use distances::Number;
+
+use super::varint::{read_varint, write_varint};
+
+/// How the encodings of the instances in a squished cluster are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeafFormat {
+    /// The cardinality, followed by an offset table of `cardinality + 1`
+    /// `usize`s, followed by the encodings.
+    ///
+    /// Single instances can be decoded without reading the rest of the
+    /// cluster. This is the default.
+    #[default]
+    Fixed,
+    /// The cardinality and the length of each encoding as varints, followed by
+    /// the encodings.
+    Varint,
+    /// The `Varint` layout compressed with DEFLATE, at a level from 0 (no
+    /// compression) to 10 (best compression), and prefixed with its lengths
+    /// before and after compression as varints.
+    ///
+    /// The whole cluster is decompressed to decode any of its instances, and
+    /// never to more than its recorded length.
+    Deflate(u8),
+}
+
+/// The sizes of the compressed data for a squished cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockSizes {
+    /// The number of bytes for the cardinality and the offsets or lengths of
+    /// the encodings, before any entropy coding.
+    pub header: u64,
+    /// The number of bytes for the encodings, before any entropy coding.
+    pub encodings: u64,
+    /// The number of bytes that are stored.
+    pub stored: u64,
+}
+
+impl LeafFormat {
+    /// The default level for `Deflate`.
+    pub const DEFAULT_DEFLATE_LEVEL: u8 = 6;
+
+    /// Returns the name of the format.
+    #[must_use]
+    pub fn name(&self) -> String {
+        match self {
+            Self::Fixed => "fixed".to_string(),
+            Self::Varint => "varint".to_string(),
+            Self::Deflate(level) => format!("deflate-{level}"),
+        }
+    }
+
+    /// Returns the format with the given name.
+    ///
+    /// `deflate` without a level uses `DEFAULT_DEFLATE_LEVEL`.
+    ///
+    /// # Errors
+    ///
+    /// If the name is not recognized or the level is greater than 10.
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "fixed" => Ok(Self::Fixed),
+            "varint" => Ok(Self::Varint),
+            "deflate" => Ok(Self::Deflate(Self::DEFAULT_DEFLATE_LEVEL)),
+            other => other
+                .strip_prefix("deflate-")
+                .and_then(|level| level.parse::<u8>().ok())
+                .filter(|&level| level <= 10)
+                .map(Self::Deflate)
+                .ok_or_else(|| format!("Unknown leaf format: {name}")),
+        }
+    }
+
+    /// Returns the bytes with which the format is saved.
+    pub(super) const fn to_bytes(self) -> [u8; 2] {
+        match self {
+            Self::Fixed => [0, 0],
+            Self::Varint => [1, 0],
+            Self::Deflate(level) => [2, level],
+        }
+    }
+
+    /// Reads the format from the bytes written by `to_bytes`.
+    pub(super) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        match bytes {
+            [0, 0] => Ok(Self::Fixed),
+            [1, 0] => Ok(Self::Varint),
+            &[2, level] if level <= 10 => Ok(Self::Deflate(level)),
+            _ => Err(format!("Invalid leaf format: {bytes:?}")),
+        }
+    }
+
+    /// Writes the encodings of the instances in a squished cluster.
+    ///
+    /// # Returns
+    ///
+    /// The bytes to store and their sizes.
+    pub(super) fn write_block(self, encodings: &[Box<[u8]>]) -> (Vec<u8>, BlockSizes) {
+        let encodings_len = encodings.iter().map(|e| e.len() as u64).sum::<u64>();
+        let block = match self {
+            Self::Fixed => {
+                let mut bytes = Vec::new();
+                // Write the number of encodings.
+                bytes.extend_from_slice(&encodings.len().to_le_bytes());
+                // Write the offset table, with the start of each encoding and
+                // the end of the last one, relative to the end of the table.
+                let mut end = 0_usize;
+                bytes.extend_from_slice(&end.to_le_bytes());
+                for encoding in encodings {
+                    end += encoding.len();
+                    bytes.extend_from_slice(&end.to_le_bytes());
+                }
+                bytes
+            }
+            Self::Varint | Self::Deflate(_) => {
+                let mut bytes = Vec::new();
+                write_varint(encodings.len() as u64, &mut bytes);
+                for encoding in encodings {
+                    write_varint(encoding.len() as u64, &mut bytes);
+                }
+                bytes
+            }
+        };
+        let header = block.len() as u64;
+
+        let mut block = block;
+        for encoding in encodings {
+            block.extend_from_slice(encoding);
+        }
+        if let Self::Deflate(level) = self {
+            let compressed = miniz_oxide::deflate::compress_to_vec(&block, level);
+            let inflated_len = block.len() as u64;
+            block = Vec::with_capacity(compressed.len() + 20);
+            write_varint(inflated_len, &mut block);
+            write_varint(compressed.len() as u64, &mut block);
+            block.extend_from_slice(&compressed);
+        }
+
+        let sizes = BlockSizes {
+            header,
+            encodings: encodings_len,
+            stored: block.len() as u64,
+        };
+        (block, sizes)
+    }
+
+    /// Reads the stored bytes of the squished cluster starting at `offset`.
+    ///
+    /// # Returns
+    ///
+    /// The bytes of the encodings and the range of each encoding in them.
+    ///
+    /// # Errors
+    ///
+    /// If the stored bytes are truncated or malformed.
+    pub(super) fn read_block(self, bytes: &[u8], offset: usize) -> Result<LeafBlock<'_>, String> {
+        let bytes = bytes
+            .get(offset..)
+            .ok_or_else(|| format!("Leaf offset {offset} is out of bounds."))?;
+        match self {
+            Self::Fixed => {
+                let ranges = fixed_ranges(bytes)?;
+                LeafBlock::new(Cow::Borrowed(bytes), ranges)
+            }
+            Self::Varint => {
+                let ranges = varint_ranges(bytes)?;
+                LeafBlock::new(Cow::Borrowed(bytes), ranges)
+            }
+            Self::Deflate(_) => {
+                let mut i = 0;
+                let inflated_len = read_varint(bytes, &mut i)?;
+                let inflated_len = usize::try_from(inflated_len)
+                    .map_err(|_| format!("Invalid inflated leaf length {inflated_len}."))?;
+                let len = read_varint(bytes, &mut i)?;
+                let compressed = usize::try_from(len)
+                    .ok()
+                    .and_then(|len| i.checked_add(len))
+                    .and_then(|end| bytes.get(i..end))
+                    .ok_or_else(|| format!("Compressed leaf of {len} bytes is truncated."))?;
+                let inflated = miniz_oxide::inflate::decompress_to_vec_with_limit(compressed, inflated_len)
+                    .map_err(|e| format!("Failed to inflate leaf: {e:?}"))?;
+                if inflated.len() != inflated_len {
+                    return Err(format!(
+                        "Inflated leaf has {} bytes instead of {inflated_len}.",
+                        inflated.len()
+                    ));
+                }
+                let ranges = varint_ranges(&inflated)?;
+                LeafBlock::new(Cow::Owned(inflated), ranges)
+            }
+        }
+    }
+
+    /// Reads the `j`th encoding of the squished cluster starting at `offset`.
+    ///
+    /// With the `Fixed` layout, the other encodings are not read.
+    ///
+    /// # Errors
+    ///
+    /// * If `j` is out of bounds.
+    /// * If the stored bytes are truncated or malformed.
+    pub(super) fn read_encoding(self, bytes: &[u8], offset: usize, j: usize) -> Result<Cow<'_, [u8]>, String> {
+        if self == Self::Fixed {
+            let bytes = bytes
+                .get(offset..)
+                .ok_or_else(|| format!("Leaf offset {offset} is out of bounds."))?;
+            let (cardinality, body) = fixed_header(bytes)?;
+            if j >= cardinality {
+                return Err(format!(
+                    "Position {j} is out of bounds for a leaf of {cardinality} instances."
+                ));
+            }
+            let range = fixed_range(bytes, body, j)?;
+            bytes
+                .get(range.clone())
+                .map(Cow::Borrowed)
+                .ok_or_else(|| format!("Invalid encoding bounds {}..{} in a leaf.", range.start, range.end))
+        } else {
+            let block = self.read_block(bytes, offset)?;
+            block.encoding(j).map(|e| Cow::Owned(e.to_vec()))
+        }
+    }
+}
+
+/// Reads a `usize` from the `Fixed` layout.
+fn read_usize(bytes: &[u8], at: usize) -> Result<usize, String> {
+    bytes
+        .get(at..(at + usize::num_bytes()))
+        .map(<usize as Number>::from_le_bytes)
+        .ok_or_else(|| format!("Leaf data ends before byte {}.", at + usize::num_bytes()))
+}
+
+/// Reads the cardinality of a squished cluster in the `Fixed` layout and the
+/// start of its encodings.
+fn fixed_header(bytes: &[u8]) -> Result<(usize, usize), String> {
+    let cardinality = read_usize(bytes, 0)?;
+    let body = cardinality
+        .checked_add(2)
+        .and_then(|n| n.checked_mul(usize::num_bytes()))
+        .filter(|&b| b <= bytes.len())
+        .ok_or_else(|| format!("Invalid leaf cardinality {cardinality}."))?;
+    Ok((cardinality, body))
+}
+
+/// Reads the range of the `j`th encoding in the `Fixed` layout, whose
+/// encodings start at `body`.
+fn fixed_range(bytes: &[u8], body: usize, j: usize) -> Result<Range<usize>, String> {
+    let start = read_usize(bytes, (j + 1) * usize::num_bytes())?;
+    let end = read_usize(bytes, (j + 2) * usize::num_bytes())?;
+    body.checked_add(start)
+        .zip(body.checked_add(end))
+        .map(|(start, end)| start..end)
+        .ok_or_else(|| format!("Invalid encoding bounds {start}..{end} in a leaf."))
+}
+
+/// Reads the ranges of the encodings in the `Fixed` layout.
+fn fixed_ranges(bytes: &[u8]) -> Result<Vec<Range<usize>>, String> {
+    let (cardinality, body) = fixed_header(bytes)?;
+    (0..cardinality).map(|j| fixed_range(bytes, body, j)).collect()
+}
+
+/// Reads the ranges of the encodings in the `Varint` layout.
+fn varint_ranges(bytes: &[u8]) -> Result<Vec<Range<usize>>, String> {
+    let mut i = 0;
+    let cardinality = read_varint(bytes, &mut i)?;
+    if cardinality > bytes.len() as u64 {
+        return Err(format!("Invalid leaf cardinality {cardinality}."));
+    }
+    let lens = (0..cardinality)
+        .map(|_| read_varint(bytes, &mut i))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut ranges = Vec::with_capacity(lens.len());
+    for len in lens {
+        let end = usize::try_from(len)
+            .ok()
+            .and_then(|len| i.checked_add(len))
+            .ok_or_else(|| format!("Invalid encoding length {len}."))?;
+        ranges.push(i..end);
+        i = end;
+    }
+    Ok(ranges)
+}
+
+/// The encodings of the instances in a squished cluster.
+#[derive(Debug)]
+pub(super) struct LeafBlock<'a> {
+    /// The bytes containing the encodings.
+    bytes: Cow<'a, [u8]>,
+    /// The range of each encoding in `bytes`.
+    ranges: Vec<Range<usize>>,
+}
+
+impl<'a> LeafBlock<'a> {
+    /// Creates a `LeafBlock`, checking that the ranges are within the bytes.
+    fn new(bytes: Cow<'a, [u8]>, ranges: Vec<Range<usize>>) -> Result<Self, String> {
+        if let Some(r) = ranges.iter().find(|r| r.start > r.end || r.end > bytes.len()) {
+            return Err(format!(
+                "Invalid encoding bounds {}..{} in a leaf of {} bytes.",
+                r.start,
+                r.end,
+                bytes.len()
+            ));
+        }
+        Ok(Self { bytes, ranges })
+    }
+
+    /// The number of encodings.
+    pub fn cardinality(&self) -> usize {
+        self.ranges.len()
+    }
+
+    /// Returns the `j`th encoding.
+    ///
+    /// # Errors
+    ///
+    /// If `j` is out of bounds.
+    pub fn encoding(&self, j: usize) -> Result<&[u8], String> {
+        self.ranges.get(j).map(|r| &self.bytes[r.clone()]).ok_or_else(|| {
+            format!(
+                "Position {j} is out of bounds for a leaf of {} instances.",
+                self.cardinality()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() -> Result<(), String> {
+        let encodings: Vec<Box<[u8]>> = vec![
+            vec![1, 2, 3].into_boxed_slice(),
+            Box::default(),
+            vec![7; 300].into_boxed_slice(),
+        ];
+
+        for format in [
+            LeafFormat::Fixed,
+            LeafFormat::Varint,
+            LeafFormat::Deflate(0),
+            LeafFormat::Deflate(LeafFormat::DEFAULT_DEFLATE_LEVEL),
+        ] {
+            let (block, sizes) = format.write_block(&encodings);
+            assert_eq!(sizes.stored, block.len() as u64, "{}", format.name());
+            assert_eq!(sizes.encodings, 303);
+
+            // Leaves are read from the middle of the leaf data.
+            let bytes = [vec![42; 5], block.clone()].concat();
+            let leaf = format.read_block(&bytes, 5)?;
+            assert_eq!(leaf.cardinality(), 3);
+            for (j, encoding) in encodings.iter().enumerate() {
+                assert_eq!(leaf.encoding(j)?, &encoding[..], "{} at {j}", format.name());
+            }
+            assert!(leaf.encoding(3).is_err());
+            for (j, encoding) in encodings.iter().enumerate() {
+                assert_eq!(&format.read_encoding(&bytes, 5, j)?[..], &encoding[..]);
+            }
+            assert!(format.read_encoding(&bytes, 5, 3).is_err());
+
+            // Truncated blocks are rejected.
+            assert!(format.read_block(&block[..(block.len() - 1)], 0).is_err());
+
+            assert_eq!(LeafFormat::from_bytes(&format.to_bytes())?, format);
+            assert_eq!(LeafFormat::from_name(&format.name())?, format);
+        }
+
+        let header = |format: LeafFormat| format.write_block(&encodings).1.header;
+        assert_eq!(header(LeafFormat::Fixed), 5 * usize::num_bytes() as u64);
+        assert_eq!(header(LeafFormat::Varint), 5);
+        assert_eq!(header(LeafFormat::Deflate(6)), 5);
+        assert!(LeafFormat::Deflate(6).write_block(&encodings).1.stored < 300);
+
+        // A leaf which inflates to more than its recorded length is rejected.
+        let (block, _) = LeafFormat::Deflate(6).write_block(&encodings);
+        let mut i = 0;
+        let inflated_len = read_varint(&block, &mut i)?;
+        let mut short = Vec::new();
+        write_varint(inflated_len - 1, &mut short);
+        short.extend_from_slice(&block[i..]);
+        assert!(LeafFormat::Deflate(6).read_block(&short, 0).is_err());
+
+        assert_eq!(LeafFormat::default(), LeafFormat::Fixed);
+        assert!(LeafFormat::from_name("deflate-11").is_err());
+        assert!(LeafFormat::from_bytes(&[2, 11]).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Instances appended to a `CodecData` after it was built.
+
+use std::{collections::HashMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+/// Instances appended to a `CodecData` after it was built, each encoded in
+/// terms of the center of the squished cluster to which it was routed.
+///
+/// Appended instances are given the positions after those of the instances in
+/// the tree, in the order in which they were appended.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Overflow {
+    /// The position of the first appended instance.
+    start: usize,
+    /// For each appended instance, the offset of its squished cluster and its
+    /// encoding.
+    entries: Vec<(usize, Box<[u8]>)>,
+    /// For each squished cluster with appended instances, by offset, the
+    /// positions of those instances.
+    members: HashMap<usize, Vec<usize>>,
+    /// For each cluster with appended instances, by offset and cardinality,
+    /// the number of those instances.
+    counts: HashMap<(usize, usize), usize>,
+}
+
+impl Overflow {
+    /// Creates an empty `Overflow` for a tree of `cardinality` instances.
+    pub fn new(cardinality: usize) -> Self {
+        Self {
+            start: cardinality,
+            entries: Vec::new(),
+            members: HashMap::new(),
+            counts: HashMap::new(),
+        }
+    }
+
+    /// The number of appended instances.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The number of bytes of the encodings of the appended instances.
+    pub fn num_bytes(&self) -> usize {
+        self.entries.iter().map(|(_, encoding)| encoding.len()).sum()
+    }
+
+    /// Adds an instance to the squished cluster at `leaf_offset`.
+    ///
+    /// # Arguments
+    ///
+    /// * `leaf_offset`: The offset of the squished cluster.
+    /// * `path`: The offset and cardinality of each cluster from the root to
+    ///   the squished cluster.
+    /// * `encoding`: The instance, encoded in terms of the center of the
+    ///   squished cluster.
+    ///
+    /// # Returns
+    ///
+    /// The position of the instance.
+    pub fn push(&mut self, leaf_offset: usize, path: &[(usize, usize)], encoding: Box<[u8]>) -> usize {
+        let position = self.start + self.entries.len();
+        self.entries.push((leaf_offset, encoding));
+        self.members.entry(leaf_offset).or_default().push(position);
+        for &key in path {
+            *self.counts.entry(key).or_default() += 1;
+        }
+        position
+    }
+
+    /// Returns the offset of the squished cluster and the encoding of the
+    /// instance at `position`, if it was appended.
+    pub fn get(&self, position: usize) -> Option<(usize, &[u8])> {
+        position
+            .checked_sub(self.start)
+            .and_then(|i| self.entries.get(i))
+            .map(|(leaf_offset, encoding)| (*leaf_offset, encoding.as_ref()))
+    }
+
+    /// Returns the positions of the instances appended to the squished
+    /// cluster at `leaf_offset`.
+    pub fn members(&self, leaf_offset: usize) -> &[usize] {
+        self.members.get(&leaf_offset).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the number of instances appended to the cluster with the given
+    /// offset and cardinality.
+    pub fn count(&self, offset: usize, cardinality: usize) -> usize {
+        self.counts.get(&(offset, cardinality)).copied().unwrap_or_default()
+    }
+
+    /// Saves the `Overflow` to the given file.
+    ///
+    /// # Errors
+    ///
+    /// * If the `Overflow` cannot be serialized or the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let bytes = bincode::serialize(self).map_err(|e| e.to_string())?;
+        std::fs::write(path, bytes).map_err(|e| e.to_string())
+    }
+
+    /// Loads the `Overflow` from the given file.
+    ///
+    /// # Errors
+    ///
+    /// * If the file cannot be read or the `Overflow` cannot be deserialized.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        bincode::deserialize(&bytes).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,95 @@
+//! Statistics about, and verification of, a `CodecData`.
+
+use distances::Number;
+
+/// The sizes and shape of a `CodecData`, from `CodecData::stats`.
+///
+/// The sizes are those of the compressed data in memory, i.e. without the
+/// tree, the metadata and the permutation which are saved along with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodecStats {
+    /// The number of instances, including those appended.
+    pub cardinality: usize,
+    /// The number of instances appended since the tree was built.
+    pub num_appended: usize,
+    /// The number of centers of the compressible clusters.
+    pub num_centers: usize,
+    /// The number of squished clusters.
+    pub num_squished: usize,
+    /// The number of squished clusters at each depth, starting at the root.
+    pub leaf_depths: Vec<usize>,
+    /// The total number of bytes of `Instance::to_bytes` for all instances,
+    /// if it was recorded when the `CodecData` was built.
+    pub raw_bytes: Option<u64>,
+    /// The number of bytes for the centers, as saved in `centers.bin`.
+    pub center_bytes: u64,
+    /// The number of bytes for the squished clusters, as saved in
+    /// `leaf_data.bin`.
+    pub leaf_bytes: u64,
+    /// The number of bytes for the encodings of the appended instances.
+    pub overflow_bytes: u64,
+}
+
+impl CodecStats {
+    /// The total number of bytes for the compressed instances.
+    #[must_use]
+    pub const fn compressed_bytes(&self) -> u64 {
+        self.center_bytes + self.leaf_bytes + self.overflow_bytes
+    }
+
+    /// The ratio of the compressed size to the raw size, i.e. lower is better,
+    /// if the raw size is known.
+    #[must_use]
+    pub fn compression_factor(&self) -> Option<f64> {
+        self.raw_bytes.map(|raw_bytes| {
+            if raw_bytes == 0 {
+                0.0
+            } else {
+                self.compressed_bytes().as_f64() / raw_bytes.as_f64()
+            }
+        })
+    }
+
+    /// The greatest depth of a squished cluster.
+    #[must_use]
+    pub fn max_leaf_depth(&self) -> usize {
+        self.leaf_depths.len().saturating_sub(1)
+    }
+
+    /// The mean depth of the squished clusters.
+    #[must_use]
+    pub fn mean_leaf_depth(&self) -> f64 {
+        if self.num_squished == 0 {
+            0.0
+        } else {
+            let total = self.leaf_depths.iter().enumerate().map(|(d, &n)| d * n).sum::<usize>();
+            total.as_f64() / self.num_squished.as_f64()
+        }
+    }
+}
+
+/// An instance which did not round-trip through a `CodecData`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The index of the instance in the original dataset.
+    pub index: usize,
+    /// Why the instance did not round-trip, e.g. a decoding error.
+    pub reason: String,
+}
+
+/// The result of `CodecData::verify`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// The number of instances which were decoded and compared.
+    pub num_checked: usize,
+    /// The instances which did not round-trip, by increasing index.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    /// Whether every instance round-tripped exactly.
+    #[must_use]
+    pub fn is_exact(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
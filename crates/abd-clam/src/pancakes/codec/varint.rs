@@ -0,0 +1,85 @@
+//! Variable-length encoding of integers, with 7 bits per byte.
+
+/// Maps signed integers to unsigned integers so that small magnitudes stay small.
+#[allow(clippy::cast_sign_loss)]
+pub const fn zigzag(x: i64) -> u64 {
+    ((x << 1) ^ (x >> 63)) as u64
+}
+
+/// Inverts `zigzag`.
+#[allow(clippy::cast_possible_wrap)]
+pub const fn unzigzag(x: u64) -> i64 {
+    ((x >> 1) as i64) ^ -((x & 1) as i64)
+}
+
+/// Appends an unsigned integer to the bytes, using 7 bits per byte.
+#[allow(clippy::cast_possible_truncation)]
+pub fn write_varint(mut x: u64, bytes: &mut Vec<u8>) {
+    while x >= 0x80 {
+        bytes.push((x as u8 & 0x7f) | 0x80);
+        x >>= 7;
+    }
+    bytes.push(x as u8);
+}
+
+/// Reads an unsigned integer, starting at `i`, and advances `i` past it.
+pub fn read_varint(bytes: &[u8], i: &mut usize) -> Result<u64, String> {
+    let mut x = 0_u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes
+            .get(*i)
+            .ok_or_else(|| format!("Unexpected end of encoding at byte {i}."))?;
+        *i += 1;
+        // Only the lowest bit of the tenth byte fits in 64 bits.
+        if shift == 63 && byte & 0x7f > 1 {
+            return Err(format!("Varint ending at byte {i} overflows 64 bits."));
+        }
+        x |= <u64 as From<u8>>::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(x);
+        }
+    }
+    Err(format!("Varint ending at byte {i} is longer than 64 bits."))
+}
+
+/// The number of bytes that `write_varint` uses for an unsigned integer.
+pub const fn varint_len(x: u64) -> u64 {
+    1 + (63 - (x | 1).leading_zeros() as u64) / 7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_varints(values: &[u64]) {
+        for &x in values {
+            let mut bytes = Vec::new();
+            write_varint(x, &mut bytes);
+            assert_eq!(bytes.len() as u64, varint_len(x), "varint_len({x})");
+
+            let mut i = 0;
+            assert_eq!(read_varint(&bytes, &mut i), Ok(x));
+            assert_eq!(i, bytes.len());
+        }
+    }
+
+    #[test]
+    fn test_varint() {
+        check_varints(&[0, 1, 127, 128, 255, 16_383, 16_384, u64::MAX >> 32, u64::MAX]);
+
+        for x in [0, 1, -1, 63, -64, 64, i64::MAX, i64::MIN] {
+            assert_eq!(unzigzag(zigzag(x)), x);
+        }
+        assert_eq!(zigzag(-1), 1);
+        assert_eq!(zigzag(1), 2);
+
+        let mut i = 0;
+        assert!(read_varint(&[0x80], &mut i).is_err());
+
+        // The tenth byte of `u64::MAX` is 1, so anything larger overflows.
+        let mut bytes = vec![0xff; 9];
+        bytes.push(0x02);
+        let mut i = 0;
+        assert!(read_varint(&bytes, &mut i).is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Delta codecs for vectors of numbers.
+//!
+//! Each encoding starts with the length of the target as a varint, followed by
+//! one varint per element of the target. Each element is encoded as a
+//! difference from the element at the same position in the reference, where
+//! missing elements of the reference are treated as zero.
+//!
+//! * Integers are encoded as the zigzag of the exact difference.
+//! * Floats are encoded as the XOR of the bits, so decoding is bit-exact.
+//! * Quantized floats are rounded to a fixed number of decimal places and then
+//!   encoded as the zigzag of the difference of the quantized values.
+//!
+//! Varints use 7 bits per byte, so elements which are close to the reference
+//! take fewer bytes. Numbers wider than 64 bits are not supported.
+//!
+//! `SquishyBall` and `CodecData` require distances of an unsigned integer type
+//! (`U: UInt`), whatever the type of the elements. Float vectors therefore need
+//! a metric with unsigned integer values, e.g. the Hamming distance, or a
+//! Manhattan distance on vectors of whole numbers.
+
+use distances::{
+    number::{Float, Int},
+    Number,
+};
+
+use super::varint::{read_varint, unzigzag, varint_len, write_varint, zigzag};
+
+/// Encodes a target vector of integers as the differences from a reference.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `target`: The target vector.
+///
+/// # Errors
+///
+/// * If the integers are wider than 64 bits.
+///
+/// # Returns
+///
+/// A byte array encoding the target vector.
+#[allow(clippy::ptr_arg)]
+pub fn encode_int_vector<T: Int>(reference: &Vec<T>, target: &Vec<T>) -> Result<Box<[u8]>, String> {
+    check_width::<T>()?;
+    Ok(encode_with(reference, target, |r, t| {
+        zigzag(t.as_i64().wrapping_sub(r.as_i64()))
+    }))
+}
+
+/// Decodes a target vector of integers from a reference and an encoding.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `encoding`: The byte array encoding the target vector.
+///
+/// # Errors
+///
+/// * If the integers are wider than 64 bits.
+/// * If the byte array is not a valid encoding.
+///
+/// # Returns
+///
+/// The target vector.
+#[allow(clippy::ptr_arg)]
+pub fn decode_int_vector<T: Int>(reference: &Vec<T>, encoding: &[u8]) -> Result<Vec<T>, String> {
+    check_width::<T>()?;
+    decode_with(reference, encoding, |r, v| {
+        T::from(r.as_i64().wrapping_add(unzigzag(v)))
+    })
+}
+
+/// The number of bytes that `encode_int_vector` uses for a target.
+#[must_use]
+#[allow(clippy::ptr_arg)]
+pub fn int_vector_cost<T: Int>(reference: &Vec<T>, target: &Vec<T>) -> u64 {
+    cost_with(reference, target, |r, t| zigzag(t.as_i64().wrapping_sub(r.as_i64())))
+}
+
+/// Encodes a target vector of floats as the XOR of its bits with a reference.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `target`: The target vector.
+///
+/// # Errors
+///
+/// * If the floats are wider than 64 bits.
+///
+/// # Returns
+///
+/// A byte array encoding the target vector.
+#[allow(clippy::ptr_arg)]
+pub fn encode_float_vector<T: Float>(reference: &Vec<T>, target: &Vec<T>) -> Result<Box<[u8]>, String> {
+    check_width::<T>()?;
+    Ok(encode_with(reference, target, |r, t| to_bits(t) ^ to_bits(r)))
+}
+
+/// Decodes a target vector of floats from a reference and an encoding.
+///
+/// The decoded vector is bit-for-bit identical to the encoded target.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `encoding`: The byte array encoding the target vector.
+///
+/// # Errors
+///
+/// * If the floats are wider than 64 bits.
+/// * If the byte array is not a valid encoding.
+///
+/// # Returns
+///
+/// The target vector.
+#[allow(clippy::ptr_arg)]
+pub fn decode_float_vector<T: Float>(reference: &Vec<T>, encoding: &[u8]) -> Result<Vec<T>, String> {
+    check_width::<T>()?;
+    decode_with(reference, encoding, |r, v| from_bits(to_bits(r) ^ v))
+}
+
+/// The number of bytes that `encode_float_vector` uses for a target.
+#[must_use]
+#[allow(clippy::ptr_arg)]
+pub fn float_vector_cost<T: Float>(reference: &Vec<T>, target: &Vec<T>) -> u64 {
+    cost_with(reference, target, |r, t| to_bits(t) ^ to_bits(r))
+}
+
+/// Encodes a target vector of floats, rounded to `DECIMALS` decimal places, as
+/// the differences from the rounded reference.
+///
+/// This is lossy: decoding recovers the target only up to the rounding.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `target`: The target vector.
+///
+/// # Errors
+///
+/// * If the floats are wider than 64 bits.
+///
+/// # Returns
+///
+/// A byte array encoding the target vector.
+#[allow(clippy::ptr_arg)]
+pub fn encode_quantized_vector<T: Float, const DECIMALS: i32>(
+    reference: &Vec<T>,
+    target: &Vec<T>,
+) -> Result<Box<[u8]>, String> {
+    check_width::<T>()?;
+    Ok(encode_with(reference, target, |r, t| {
+        zigzag(quantize::<T, DECIMALS>(t).wrapping_sub(quantize::<T, DECIMALS>(r)))
+    }))
+}
+
+/// Decodes a target vector of floats, rounded to `DECIMALS` decimal places,
+/// from a reference and an encoding.
+///
+/// # Arguments
+///
+/// * `reference`: The reference vector.
+/// * `encoding`: The byte array encoding the target vector.
+///
+/// # Errors
+///
+/// * If the floats are wider than 64 bits.
+/// * If the byte array is not a valid encoding.
+///
+/// # Returns
+///
+/// The rounded target vector.
+#[allow(clippy::ptr_arg)]
+pub fn decode_quantized_vector<T: Float, const DECIMALS: i32>(
+    reference: &Vec<T>,
+    encoding: &[u8],
+) -> Result<Vec<T>, String> {
+    check_width::<T>()?;
+    decode_with(reference, encoding, |r, v| {
+        let q = quantize::<T, DECIMALS>(r).wrapping_add(unzigzag(v));
+        T::from(q.as_f64() / 10_f64.powi(DECIMALS))
+    })
+}
+
+/// The number of bytes that `encode_quantized_vector` uses for a target.
+#[must_use]
+#[allow(clippy::ptr_arg)]
+pub fn quantized_vector_cost<T: Float, const DECIMALS: i32>(reference: &Vec<T>, target: &Vec<T>) -> u64 {
+    cost_with(reference, target, |r, t| {
+        zigzag(quantize::<T, DECIMALS>(t).wrapping_sub(quantize::<T, DECIMALS>(r)))
+    })
+}
+
+/// Returns an error if `T` is wider than 64 bits.
+fn check_width<T: Number>() -> Result<(), String> {
+    if T::num_bytes() > 8 {
+        Err(format!("Cannot encode {} with more than 64 bits.", T::type_name()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Pairs each element of the target with the element at the same position in
+/// the reference, or with zero if the reference is too short.
+fn paired<'a, T: Number>(reference: &'a [T], target: &'a [T]) -> impl Iterator<Item = (T, T)> + 'a {
+    target
+        .iter()
+        .enumerate()
+        .map(|(i, &t)| (reference.get(i).copied().unwrap_or_else(T::zero), t))
+}
+
+/// Encodes the length of the target and the `delta` of each element.
+fn encode_with<T: Number>(reference: &[T], target: &[T], delta: impl Fn(T, T) -> u64) -> Box<[u8]> {
+    let mut bytes = Vec::with_capacity(target.len() + 1);
+    write_varint(target.len() as u64, &mut bytes);
+    for (r, t) in paired(reference, target) {
+        write_varint(delta(r, t), &mut bytes);
+    }
+    bytes.into_boxed_slice()
+}
+
+/// Decodes the length of the target and then each element from its `delta`.
+fn decode_with<T: Number>(reference: &[T], encoding: &[u8], undo: impl Fn(T, u64) -> T) -> Result<Vec<T>, String> {
+    let mut i = 0;
+    let len = usize::try_from(read_varint(encoding, &mut i)?).map_err(|e| e.to_string())?;
+    if len > encoding.len() {
+        return Err(format!(
+            "Encoded length {len} exceeds the {} available bytes.",
+            encoding.len()
+        ));
+    }
+
+    let mut target = Vec::with_capacity(len);
+    for j in 0..len {
+        let r = reference.get(j).copied().unwrap_or_else(T::zero);
+        target.push(undo(r, read_varint(encoding, &mut i)?));
+    }
+
+    if i == encoding.len() {
+        Ok(target)
+    } else {
+        Err(format!("Found {} trailing bytes after decoding.", encoding.len() - i))
+    }
+}
+
+/// Computes the number of bytes that `encode_with` would use.
+fn cost_with<T: Number>(reference: &[T], target: &[T], delta: impl Fn(T, T) -> u64) -> u64 {
+    varint_len(target.len() as u64)
+        + paired(reference, target)
+            .map(|(r, t)| varint_len(delta(r, t)))
+            .sum::<u64>()
+}
+
+/// Returns the bits of a number, zero-extended to 64 bits.
+fn to_bits<T: Number>(x: T) -> u64 {
+    let mut bytes = [0; 8];
+    let le_bytes = x.to_le_bytes();
+    bytes[..le_bytes.len()].copy_from_slice(&le_bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Returns the number with the given bits, ignoring bits beyond its width.
+fn from_bits<T: Number>(bits: u64) -> T {
+    T::from_le_bytes(&bits.to_le_bytes()[..T::num_bytes()])
+}
+
+/// Rounds a float to `DECIMALS` decimal places and returns it as an integer.
+#[allow(clippy::cast_possible_truncation)]
+fn quantize<T: Float, const DECIMALS: i32>(x: T) -> i64 {
+    (x.as_f64() * 10_f64.powi(DECIMALS)).round() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_int_vector() -> Result<(), String> {
+        let reference = vec![10_u8, 200, 0, 255, 7];
+        let targets = [
+            vec![10, 201, 3, 250, 7],
+            vec![0, 255, 255, 0],
+            vec![10, 200, 0, 255, 7, 42, 99],
+            vec![],
+        ];
+        for target in &targets {
+            let encoding = encode_int_vector(&reference, target)?;
+            assert_eq!(encoding.len() as u64, int_vector_cost(&reference, target));
+            assert_eq!(&decode_int_vector(&reference, &encoding)?, target);
+        }
+        // Small differences take a single byte each.
+        assert_eq!(int_vector_cost(&reference, &targets[0]), 6);
+
+        let reference = vec![i64::MIN, -5, 0, i64::MAX];
+        let target = vec![i64::MAX, 5, -1, i64::MIN];
+        let encoding = encode_int_vector(&reference, &target)?;
+        assert_eq!(decode_int_vector(&reference, &encoding)?, target);
+
+        let reference = vec![u64::MAX, 0];
+        let target = vec![0, u64::MAX];
+        let encoding = encode_int_vector(&reference, &target)?;
+        assert_eq!(decode_int_vector(&reference, &encoding)?, target);
+
+        assert!(encode_int_vector(&vec![1_u128], &vec![2_u128]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_float_vector() -> Result<(), String> {
+        let reference = vec![1.0_f32, -2.5, 0.0, 1e-30, f32::MAX];
+        let targets = [
+            vec![1.000_001, -2.5, -0.0, 1e30, f32::NAN],
+            vec![f32::INFINITY, f32::NEG_INFINITY, f32::MIN_POSITIVE],
+            vec![1.0, -2.5, 0.0, 1e-30, f32::MAX, 3.25],
+            vec![],
+        ];
+        for target in &targets {
+            let encoding = encode_float_vector(&reference, target)?;
+            assert_eq!(encoding.len() as u64, float_vector_cost(&reference, target));
+            let decoded = decode_float_vector(&reference, &encoding)?;
+            assert_eq!(
+                decoded.iter().map(|x| x.to_bits()).collect::<Vec<_>>(),
+                target.iter().map(|x| x.to_bits()).collect::<Vec<_>>()
+            );
+        }
+        // Identical elements take a single byte each.
+        assert_eq!(float_vector_cost(&reference, &reference), 6);
+
+        let reference = vec![core::f64::consts::PI, -1.0];
+        let target = vec![core::f64::consts::E, -1.000_000_000_1];
+        let encoding = encode_float_vector(&reference, &target)?;
+        assert_eq!(decode_float_vector(&reference, &encoding)?, target);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantized_vector() -> Result<(), String> {
+        let reference = vec![1.0_f32, -2.5, 0.0, 100.0];
+        let target = vec![1.004_f32, -2.512, 0.333, 99.999, -7.5];
+
+        let encoding = encode_quantized_vector::<_, 2>(&reference, &target)?;
+        assert_eq!(
+            encoding.len() as u64,
+            quantized_vector_cost::<_, 2>(&reference, &target)
+        );
+        let decoded = decode_quantized_vector::<_, 2>(&reference, &encoding)?;
+        assert_eq!(decoded.len(), target.len());
+        for (d, t) in decoded.iter().zip(target.iter()) {
+            assert!((d - t).abs() <= 0.005 + f32::EPSILON, "{d} vs {t}");
+        }
+
+        // Decoding an encoding of the reference recovers the rounded reference.
+        let encoding = encode_quantized_vector::<_, 0>(&reference, &reference)?;
+        assert_eq!(
+            decode_quantized_vector::<_, 0>(&reference, &encoding)?,
+            vec![1.0, -3.0, 0.0, 100.0]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_encodings() {
+        let reference = vec![1_u16, 2, 3];
+        // Truncated.
+        assert!(decode_int_vector(&reference, &[3, 0, 0]).is_err());
+        // Trailing bytes.
+        assert!(decode_int_vector(&reference, &[1, 0, 0]).is_err());
+        // Impossible length.
+        assert!(decode_float_vector(&vec![1.0_f32], &[0xff, 0xff, 0x03]).is_err());
+        // Empty.
+        assert!(decode_int_vector(&reference, &[]).is_err());
+    }
+}
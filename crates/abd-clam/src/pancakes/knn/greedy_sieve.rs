@@ -0,0 +1,110 @@
+//! Greedy K-NN search, with an expanding threshold, in a compressed space.
+
+use distances::number::UInt;
+use priority_queue::PriorityQueue;
+use rayon::prelude::*;
+
+use crate::{
+    cakes::knn::{greedy_sieve::d_min, Hits, RevNumber},
+    pancakes::{CodecData, SquishyBall},
+    Cluster, Instance,
+};
+
+/// K-Nearest Neighbor search in a compressed space, which decompresses the
+/// squished leaves in order of `d_min`, the closest that any instance in a
+/// cluster could be to the query.
+///
+/// Search stops once there are `k` hits and the farthest hit is no farther
+/// than the closest remaining candidate, so only the leaves which can still
+/// contain hits are decompressed.
+///
+/// # Arguments
+///
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `data` - The compressed data to search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+///
+/// # Errors
+///
+/// * If a squished leaf could not be decoded.
+pub fn search<I, U, M>(query: &I, k: usize, data: &CodecData<I, U, M>) -> Result<Vec<(usize, U)>, String>
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    let mut candidates = PriorityQueue::<&SquishyBall<U>, RevNumber<U>>::new();
+    let mut hits = Hits::new(k);
+
+    let root = data.root();
+    candidates.push(root, RevNumber(d_min(root, distance_to_center(query, root, data))));
+
+    while let Some((_, &RevNumber(closest))) = candidates.peek() {
+        if hits.len() >= k && hits.peek() <= closest {
+            break;
+        }
+
+        let (c, _) = candidates
+            .pop()
+            .unwrap_or_else(|| unreachable!("`candidates` is non-empty."));
+        if c.squish() {
+            leaf_into_hits(query, c, data, &mut hits)?;
+        } else {
+            let children = c
+                .children()
+                .unwrap_or_else(|| unreachable!("Non-leaf node without children"));
+            for child in children {
+                let d = distance_to_center(query, child, data);
+                candidates.push(child, RevNumber(d_min(child, d)));
+            }
+        }
+    }
+
+    Ok(hits.extract())
+}
+
+/// The distance from the query to the center of a cluster.
+fn distance_to_center<I, U, M>(query: &I, c: &SquishyBall<U>, data: &CodecData<I, U, M>) -> U
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    data.metric()(&data.centers()[&c.arg_center()], query)
+}
+
+/// Decompresses a squished leaf and adds its instances to `hits`.
+///
+/// # Errors
+///
+/// * If the leaf could not be decoded.
+fn leaf_into_hits<I, U, M>(
+    query: &I,
+    leaf: &SquishyBall<U>,
+    data: &CodecData<I, U, M>,
+    hits: &mut Hits<usize, U>,
+) -> Result<(), String>
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    let points = data
+        .decoded_leaf(leaf)
+        .map_err(|e| format!("Could not decode leaf {}: {e}", leaf.name()))?;
+    let distances = if data.is_expensive() {
+        points.par_iter().map(|p| data.metric()(query, p)).collect::<Vec<_>>()
+    } else {
+        points.iter().map(|p| data.metric()(query, p)).collect()
+    };
+    data.leaf_indices(leaf)
+        .into_iter()
+        .zip(distances)
+        .for_each(|(i, d)| hits.push(i, d));
+    Ok(())
+}
@@ -6,7 +6,11 @@ use distances::number::UInt;
 use crate::cakes::knn::Hits;
 
 /// Perform a linear search in a compressed space.
-pub fn search<I, U, M>(query: &I, k: usize, data: &CodecData<I, U, M>) -> Vec<(usize, U)>
+///
+/// # Errors
+///
+/// * If a squished leaf could not be decoded.
+pub fn search<I, U, M>(query: &I, k: usize, data: &CodecData<I, U, M>) -> Result<Vec<(usize, U)>, String>
 where
     I: Instance,
     U: UInt,
@@ -18,13 +22,13 @@ where
 
     for leaf in leaves {
         let points = data
-            .load_leaf_data(leaf)
-            .unwrap_or_else(|e| unreachable!("Impossible by construction.: {e}"));
-        points.into_iter().zip(leaf.indices()).for_each(|(point, index)| {
-            let distance = data.metric()(query, &point);
+            .decoded_leaf(leaf)
+            .map_err(|e| format!("Could not decode leaf {}: {e}", leaf.name()))?;
+        points.iter().zip(data.leaf_indices(leaf)).for_each(|(point, index)| {
+            let distance = data.metric()(query, point);
             hits.push(index, distance);
         });
     }
 
-    hits.extract()
+    Ok(hits.extract())
 }
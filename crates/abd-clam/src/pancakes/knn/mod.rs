@@ -1,6 +1,8 @@
 //! K-Nearest Neighbors search in a compressed space.
 
+mod greedy_sieve;
 mod linear;
+mod repeated_rnn;
 
 use distances::number::UInt;
 
@@ -11,12 +13,28 @@ use super::CodecData;
 /// The algorithm to use for K-Nearest Neighbors search.
 pub enum Algorithm {
     /// Use linear search on the dataset.
+    ///
+    /// This decompresses every squished leaf for every query.
     Linear,
+    /// Use a repeated clustered RNN search, increasing the radius until enough
+    /// neighbors are found, as in `cakes::knn::Algorithm::RepeatedRnn`.
+    ///
+    /// Only the squished leaves which overlap the final query ball are
+    /// decompressed.
+    RepeatedRnn,
+    /// Decompress the squished leaves in order of `d_min`, the closest that any
+    /// instance in a leaf could be to the query, as in
+    /// `cakes::knn::Algorithm::GreedySieve`.
+    ///
+    /// Search stops once the farthest of the `k` hits is no farther than the
+    /// closest remaining leaf, so only the leaves which can still contain hits
+    /// are decompressed.
+    GreedySieve,
 }
 
 impl Default for Algorithm {
     fn default() -> Self {
-        Self::Linear
+        Self::GreedySieve
     }
 }
 
@@ -33,7 +51,11 @@ impl Algorithm {
     ///
     /// A vector of 2-tuples, where the first element is the index of the instance
     /// and the second element is the distance from the query to the instance.
-    pub fn search<I, U, M>(&self, query: &I, k: usize, data: &CodecData<I, U, M>) -> Vec<(usize, U)>
+    ///
+    /// # Errors
+    ///
+    /// * If a squished leaf could not be decoded.
+    pub fn search<I, U, M>(&self, query: &I, k: usize, data: &CodecData<I, U, M>) -> Result<Vec<(usize, U)>, String>
     where
         I: Instance,
         U: UInt,
@@ -41,6 +63,8 @@ impl Algorithm {
     {
         match self {
             Self::Linear => linear::search(query, k, data),
+            Self::RepeatedRnn => Ok(repeated_rnn::search(query, k, data)),
+            Self::GreedySieve => greedy_sieve::search(query, k, data),
         }
     }
 
@@ -49,6 +73,8 @@ impl Algorithm {
     pub const fn name(&self) -> &str {
         match self {
             Self::Linear => "Linear",
+            Self::RepeatedRnn => "RepeatedRnn",
+            Self::GreedySieve => "GreedySieve",
         }
     }
 
@@ -70,10 +96,18 @@ impl Algorithm {
     pub fn from_name(s: &str) -> Result<Self, String> {
         match s.to_lowercase().as_str() {
             "linear" => Ok(Self::Linear),
+            "repeatedrnn" => Ok(Self::RepeatedRnn),
+            "greedysieve" => Ok(Self::GreedySieve),
             _ => Err(format!("Unknown algorithm: {s}")),
         }
     }
 
+    /// Returns a list of all the algorithms, excluding Linear.
+    #[must_use]
+    pub fn variants() -> Box<[Self]> {
+        vec![Self::RepeatedRnn, Self::GreedySieve].into_boxed_slice()
+    }
+
     /// Returns the baseline algorithm, which is Linear
     #[must_use]
     pub const fn baseline() -> Self {
@@ -0,0 +1,84 @@
+//! Repeated RNN search, with increasing radii, for k-nearest neighbors in a
+//! compressed space.
+
+use distances::{number::UInt, Number};
+
+use crate::{
+    cakes::knn::Hits,
+    pancakes::{rnn::clustered, CodecData, SquishyBall},
+    utils, Cluster, Instance,
+};
+
+/// The multiplier to use for increasing the radius in the repeated RNN algorithm.
+const MULTIPLIER: f64 = 2.0;
+
+/// K-Nearest Neighbor search using a repeated clustered RNN search.
+///
+/// Only the squished leaves which overlap the final query ball are
+/// decompressed.
+///
+/// # Arguments
+///
+/// * `query` - The query to search around.
+/// * `k` - The number of neighbors to search for.
+/// * `data` - The compressed data to search.
+///
+/// # Returns
+///
+/// A vector of 2-tuples, where the first element is the index of the instance
+/// and the second element is the distance from the query to the instance.
+pub fn search<I, U, M>(query: &I, k: usize, data: &CodecData<I, U, M>) -> Vec<(usize, U)>
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    let root = data.root();
+    // Without this, the radius would grow forever when `k` exceeds the cardinality.
+    let k = k.min(data.cardinality());
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut radius = f64::EPSILON + root.radius().as_f64() / root.cardinality().as_f64();
+    let [mut confirmed, mut straddlers] = clustered::tree_search(query, U::from(radius), data);
+
+    let mut num_confirmed = count_hits(&confirmed, data);
+
+    while num_confirmed == 0 {
+        radius *= MULTIPLIER;
+        [confirmed, straddlers] = clustered::tree_search(query, U::from(radius), data);
+        num_confirmed = count_hits(&confirmed, data);
+    }
+
+    while num_confirmed < k {
+        let lfd = utils::mean(
+            &confirmed
+                .iter()
+                .chain(straddlers.iter())
+                .map(|&(c, _)| c.lfd())
+                .collect::<Vec<_>>(),
+        );
+        let factor = (k.as_f64() / num_confirmed.as_f64()).powf(1. / (lfd + f64::EPSILON));
+
+        radius *= if factor < MULTIPLIER { factor } else { MULTIPLIER };
+        [confirmed, straddlers] = clustered::tree_search(query, U::from(radius), data);
+        num_confirmed = count_hits(&confirmed, data);
+    }
+
+    Hits::from_vec(
+        k,
+        clustered::leaf_search(query, U::from(radius), data, confirmed, straddlers),
+    )
+    .extract()
+}
+
+/// Count the total cardinality of the clusters, including appended instances.
+fn count_hits<I, U, M>(clusters: &[(&SquishyBall<U>, U)], data: &CodecData<I, U, M>) -> usize
+where
+    I: Instance,
+    U: UInt,
+    M: Instance,
+{
+    clusters.iter().map(|(c, _)| data.cardinality_of(c)).sum()
+}
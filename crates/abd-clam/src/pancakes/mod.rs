@@ -5,4 +5,10 @@ pub mod knn;
 pub mod rnn;
 mod search;
 
-pub use codec::{decode_general, encode_general, CodecData, DecoderFn, EncoderFn, SquishyBall};
+pub use codec::{
+    decode_edits, decode_float_vector, decode_general, decode_int_vector, decode_quantized_vector, encode_edits,
+    encode_float_vector, encode_general, encode_int_vector, encode_quantized_vector, float_vector_cost, int_vector_cost,
+    quantized_vector_cost, AminoAcids, CodecBuilder, CodecData, CodecStats, CostFn, CostModel, CostReport, DecoderFn,
+    EditFormat, EncoderFn, FnCost, LeafCacheStats, LeafCost, LeafFormat, MetricCost, Mismatch, Nucleotides,
+    SampledEncodingCost, SquishyBall, Unicode, VerifyReport,
+};
@@ -33,7 +33,7 @@ where
 /// those that are contained within the query ball, and the second element is the
 /// straddlers, i.e. those that overlap the query ball. The 2-tuples are the clusters
 /// and the distance from the query to the cluster center.
-fn tree_search<'a, I, U, M>(query: &I, radius: U, data: &'a CodecData<I, U, M>) -> [Vec<(&'a SquishyBall<U>, U)>; 2]
+pub fn tree_search<'a, I, U, M>(query: &I, radius: U, data: &'a CodecData<I, U, M>) -> [Vec<(&'a SquishyBall<U>, U)>; 2]
 where
     I: Instance,
     U: UInt,
@@ -77,7 +77,7 @@ where
 }
 
 /// Perform fine-grained leaf search.
-fn leaf_search<I, U, M>(
+pub fn leaf_search<I, U, M>(
     query: &I,
     radius: U,
     data: &CodecData<I, U, M>,
@@ -96,9 +96,10 @@ where
             .into_iter()
             .flat_map(|leaf| {
                 let points = data
-                    .load_leaf_data(leaf)
+                    .decoded_leaf(leaf)
                     .unwrap_or_else(|e| unreachable!("Leaf data not found: {e}"));
-                points.into_iter().map(|p| data.metric()(query, &p)).zip(leaf.indices())
+                let distances = points.iter().map(|p| data.metric()(query, p)).collect::<Vec<_>>();
+                distances.into_iter().zip(data.leaf_indices(leaf))
             })
             .map(|(d, i)| (i, d))
     });
@@ -110,9 +111,10 @@ where
                 .into_iter()
                 .flat_map(|leaf| {
                     let points = data
-                        .load_leaf_data(leaf)
+                        .decoded_leaf(leaf)
                         .unwrap_or_else(|e| unreachable!("Leaf data not found: {e}"));
-                    points.into_iter().map(|p| data.metric()(query, &p)).zip(leaf.indices())
+                    let distances = points.iter().map(|p| data.metric()(query, p)).collect::<Vec<_>>();
+                    distances.into_iter().zip(data.leaf_indices(leaf))
                 })
                 .filter(|(d, _)| *d <= radius)
                 .map(|(d, i)| (i, d))
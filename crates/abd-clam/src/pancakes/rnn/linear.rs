@@ -3,7 +3,7 @@
 use distances::number::UInt;
 use rayon::prelude::*;
 
-use crate::{pancakes::CodecData, Cluster, Instance};
+use crate::{pancakes::CodecData, Instance};
 
 /// Perform a linear search in a compressed space.
 pub fn search<I, U, M>(query: &I, radius: U, data: &CodecData<I, U, M>) -> Vec<(usize, U)>
@@ -18,19 +18,20 @@ where
         .into_par_iter()
         .flat_map(|leaf| {
             let points = data
-                .load_leaf_data(leaf)
+                .decoded_leaf(leaf)
                 .unwrap_or_else(|e| unreachable!("Impossible by construction.: {e}"));
             points
-                .into_par_iter()
-                .zip(leaf.indices().into_par_iter())
+                .par_iter()
+                .zip(data.leaf_indices(leaf))
                 .filter_map(|(point, index)| {
-                    let distance = data.metric()(query, &point);
+                    let distance = data.metric()(query, point);
                     if distance <= radius {
                         Some((index, distance))
                     } else {
                         None
                     }
                 })
+                .collect::<Vec<_>>()
         })
         .collect()
 }
@@ -1,6 +1,6 @@
 //! Algorithms for Ranged Nearest Neighbor search in a compressed space.
 
-mod clustered;
+pub(crate) mod clustered;
 mod linear;
 
 use distances::number::UInt;
@@ -35,7 +35,11 @@ impl<I: Instance, U: UInt, M: Instance> CodecData<I, U, M> {
     ///
     /// A vector of 2-tuples, where the first element is the index of the instance
     /// and the second element is the distance from the query to the instance.
-    pub fn knn_search(&self, query: &I, k: usize, algo: &knn::Algorithm) -> Vec<(usize, U)> {
+    ///
+    /// # Errors
+    ///
+    /// * If a squished leaf could not be decoded.
+    pub fn knn_search(&self, query: &I, k: usize, algo: &knn::Algorithm) -> Result<Vec<(usize, U)>, String> {
         algo.search(query, k, self)
     }
 }
@@ -47,8 +51,11 @@ mod tests {
     use super::*;
 
     use crate::{
-        pancakes::{decode_general, encode_general, CodecData, SquishyBall},
-        Cluster, PartitionCriteria, VecDataset,
+        pancakes::{
+            decode_float_vector, decode_general, decode_int_vector, encode_float_vector, encode_general,
+            encode_int_vector, float_vector_cost, int_vector_cost, CodecData, SquishyBall,
+        },
+        Cluster, Dataset, PartitionCriteria, UniBall, VecDataset,
     };
 
     fn lev_metric(x: &String, y: &String) -> u16 {
@@ -113,15 +120,197 @@ mod tests {
         let query = "NAJIBEATSPEPPERS".to_string();
         let k = 2;
 
-        for algo in [knn::Algorithm::Linear] {
-            let result = codec_dataset.knn_search(&query, k, &algo);
+        for algo in [
+            knn::Algorithm::Linear,
+            knn::Algorithm::RepeatedRnn,
+            knn::Algorithm::GreedySieve,
+        ] {
+            let result = codec_dataset.knn_search(&query, k, &algo)?;
 
             println!("{}: {result:?}", algo.name());
+            // Both hits are at a distance of 1, so they may be in either order.
+            let mut hits = [dataset[result[0].0].clone(), dataset[result[1].0].clone()];
+            hits.sort();
+            assert_eq!(hits, ["NAJIB-EATSPEPPERS", "NAJIBEATS-PEPPERS"]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_knn_variants() -> Result<(), String> {
+        let strings = symagen::random_data::random_string(500, 20, 40, "ACGT", 42);
+        let queries = symagen::random_data::random_string(10, 20, 40, "ACGT", 43);
+
+        let mut dataset = VecDataset::new("test-codec".to_string(), strings, lev_metric, true);
+        let criteria = PartitionCriteria::default();
+        let seed = Some(42);
+        let root = SquishyBall::new_root(&dataset, seed).partition(&mut dataset, &criteria, seed);
+
+        let metadata = dataset.metadata().to_vec();
+        let codec_dataset = CodecData::new(root, &dataset, encode_general::<u16>, decode_general, metadata)?;
+
+        let sorted_distances = |hits: Vec<(usize, u16)>| {
+            let mut distances = hits.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+            distances.sort_unstable();
+            distances
+        };
+
+        for query in &queries {
+            for k in [0, 1, 10, 100, 500, 1000] {
+                let expected = sorted_distances(codec_dataset.knn_search(query, k, &knn::Algorithm::Linear)?);
+                assert_eq!(expected.len(), k.min(500));
+
+                for algo in knn::Algorithm::variants().iter() {
+                    let actual = sorted_distances(codec_dataset.knn_search(query, k, algo)?);
+                    assert_eq!(actual, expected, "{} with k = {k}", algo.name());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_leaf_cache() -> Result<(), String> {
+        let strings = symagen::random_data::random_string(500, 20, 40, "ACGT", 42);
+        let queries = symagen::random_data::random_string(5, 20, 40, "ACGT", 43);
+
+        let mut dataset = VecDataset::new("test-codec".to_string(), strings, lev_metric, true);
+        let criteria = PartitionCriteria::default();
+        let seed = Some(42);
+        let root = SquishyBall::new_root(&dataset, seed).partition(&mut dataset, &criteria, seed);
+
+        let metadata = dataset.metadata().to_vec();
+        let uncached = CodecData::new(root.clone(), &dataset, encode_general::<u16>, decode_general, metadata)?;
+        assert!(uncached.leaf_cache_stats().is_none());
+
+        let metadata = dataset.metadata().to_vec();
+        let cached =
+            CodecData::new(root, &dataset, encode_general::<u16>, decode_general, metadata)?.with_leaf_cache(1 << 20);
+
+        let sorted = |mut hits: Vec<(usize, u16)>| {
+            hits.sort_unstable();
+            hits
+        };
+
+        for _ in 0..2 {
+            for query in &queries {
+                for algo in knn::Algorithm::variants().iter() {
+                    let expected = sorted(uncached.knn_search(query, 10, algo)?);
+                    assert_eq!(sorted(cached.knn_search(query, 10, algo)?), expected);
+                }
+                for algo in [rnn::Algorithm::Linear, rnn::Algorithm::Clustered] {
+                    let expected = sorted(uncached.rnn_search(query, 10, &algo));
+                    assert_eq!(sorted(cached.rnn_search(query, 10, &algo)), expected);
+                }
+            }
+        }
+
+        // Every leaf fits in the cache, so each one is decoded only once.
+        let stats = cached.leaf_cache_stats().ok_or("Missing cache stats")?;
+        assert!(stats.hits > 0);
+        assert_eq!(stats.misses, stats.num_leaves);
+        assert!(stats.num_bytes <= stats.capacity);
+
+        cached.clear_leaf_cache();
+        let stats = cached.leaf_cache_stats().ok_or("Missing cache stats")?;
+        assert_eq!(
+            (stats.hits, stats.misses, stats.num_leaves, stats.num_bytes),
+            (0, 0, 0, 0)
+        );
+
+        // A small cache evicts leaves but gives the same results.
+        let cached = cached.with_leaf_cache(1 << 10);
+        for query in &queries {
+            let expected = sorted(uncached.knn_search(query, 10, &knn::Algorithm::GreedySieve)?);
             assert_eq!(
-                [dataset[result[0].0].clone(), dataset[result[1].0].clone()],
-                ["NAJIBEATS-PEPPERS", "NAJIB-EATSPEPPERS"]
+                sorted(cached.knn_search(query, 10, &knn::Algorithm::GreedySieve)?),
+                expected
             );
         }
+        let stats = cached.leaf_cache_stats().ok_or("Missing cache stats")?;
+        assert!(stats.num_bytes <= stats.capacity);
+
+        Ok(())
+    }
+
+    #[allow(clippy::ptr_arg)]
+    fn manhattan_u8(x: &Vec<u8>, y: &Vec<u8>) -> u32 {
+        x.iter().zip(y.iter()).map(|(&a, &b)| u32::from(a.abs_diff(b))).sum()
+    }
+
+    /// The Manhattan distance between vectors of whole numbers stored as floats,
+    /// which is exact in `u32` for these small vectors.
+    #[allow(clippy::ptr_arg, clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn manhattan_whole_f32(x: &Vec<f32>, y: &Vec<f32>) -> u32 {
+        distances::vectors::manhattan(x, y) as u32
+    }
+
+    /// Checks that KNN-Search on the compressed data finds the same distances
+    /// as a brute-force search on the original data.
+    fn check_vector_knn<I: Instance>(
+        dataset: &VecDataset<I, u32, usize>,
+        codec_dataset: &CodecData<I, u32, usize>,
+        queries: &[I],
+    ) -> Result<(), String> {
+        for query in queries {
+            let mut all = (0..dataset.cardinality())
+                .map(|i| dataset.metric()(query, &dataset[i]))
+                .collect::<Vec<_>>();
+            all.sort_unstable();
+
+            for k in [1, 10, 100] {
+                for algo in [knn::Algorithm::Linear, knn::Algorithm::GreedySieve] {
+                    let mut actual = codec_dataset
+                        .knn_search(query, k, &algo)?
+                        .into_iter()
+                        .map(|(_, d)| d)
+                        .collect::<Vec<_>>();
+                    actual.sort_unstable();
+                    assert_eq!(actual, all[..k], "{} with k = {k}", algo.name());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_codecs() -> Result<(), String> {
+        let criteria = PartitionCriteria::default();
+        let seed = Some(42);
+
+        let data = symagen::random_data::random_tabular_seedable::<u8>(500, 10, 0, 255, 42);
+        let queries = symagen::random_data::random_tabular_seedable::<u8>(5, 10, 0, 255, 43);
+        let mut dataset = VecDataset::new("test-u8".to_string(), data, manhattan_u8, false);
+        let root = UniBall::new_root(&dataset, seed).partition(&mut dataset, &criteria, seed);
+        let root = SquishyBall::from_base_tree_with_cost(root, &dataset, int_vector_cost::<u8>);
+        assert_eq!(
+            root.unitary_cost(),
+            (0..dataset.cardinality())
+                .map(|i| int_vector_cost(&dataset[root.arg_center()], &dataset[i]))
+                .sum::<u64>()
+        );
+
+        let metadata = dataset.metadata().to_vec();
+        let codec_dataset = CodecData::new(root, &dataset, encode_int_vector, decode_int_vector, metadata)?;
+        check_vector_knn(&dataset, &codec_dataset, &queries)?;
+
+        let whole = |data: Vec<Vec<u8>>| {
+            data.into_iter()
+                .map(|x| x.into_iter().map(f32::from).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        let data = whole(symagen::random_data::random_tabular_seedable::<u8>(500, 10, 0, 255, 42));
+        let queries = whole(symagen::random_data::random_tabular_seedable::<u8>(5, 10, 0, 255, 43));
+        let mut dataset = VecDataset::new("test-f32".to_string(), data, manhattan_whole_f32, false);
+        let root = UniBall::new_root(&dataset, seed).partition(&mut dataset, &criteria, seed);
+        let root = SquishyBall::from_base_tree_with_cost(root, &dataset, float_vector_cost::<f32>);
+
+        let metadata = dataset.metadata().to_vec();
+        let codec_dataset = CodecData::new(root, &dataset, encode_float_vector, decode_float_vector, metadata)?;
+        check_vector_knn(&dataset, &codec_dataset, &queries)?;
 
         Ok(())
     }
@@ -1,6 +1,19 @@
 //! Tests for Cakes.
 
-use abd_clam::{cakes::knn, cakes::rnn, Cakes, Instance, PartitionCriteria, VecDataset};
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use std::{borrow::Cow, path::Path};
+
+use abd_clam::{
+    cakes::{
+        budget::Tracker, kfn, knn, rknn, rnn, tuning::TuningProfile, Budget, LazySharded, Search, ShardStrategy,
+        SingleShard, DEFAULT_MAX_RESIDENT,
+    },
+    Cakes, Dataset, Instance, PartitionCriteria, Tree, UniBall, VecDataset,
+};
 use distances::Number;
 use float_cmp::approx_eq;
 use test_case::test_case;
@@ -24,9 +37,12 @@ fn tiny() {
         .unzip();
     assert_eq!(results.len(), 2);
 
-    let result_points = results.iter().map(|&i| &cakes[i]).collect::<Vec<_>>();
-    assert!(result_points.contains(&&vec![0., 0.]));
-    assert!(result_points.contains(&&vec![1., 1.]));
+    let result_points = results
+        .iter()
+        .map(|&i| cakes.instance(i).unwrap().into_owned())
+        .collect::<Vec<_>>();
+    assert!(result_points.contains(&vec![0., 0.]));
+    assert!(result_points.contains(&vec![1., 1.]));
 
     let query = vec![1., 1.];
     let (results, _): (Vec<_>, Vec<_>) = cakes
@@ -35,7 +51,14 @@ fn tiny() {
         .unzip();
     assert_eq!(results.len(), 1);
 
-    assert!(results.iter().map(|&i| &cakes[i]).any(|x| x == [1., 1.].as_slice()));
+    assert!(results
+        .iter()
+        .map(|&i| cakes.instance(i).unwrap())
+        .any(|x| x.as_slice() == [1., 1.].as_slice()));
+
+    // Asking for more neighbors than there are instances gets all of them.
+    let hits = cakes.knn_search(&query, 10, knn::Algorithm::RepeatedRnn);
+    assert_eq!(hits.len(), 4);
 }
 
 #[test]
@@ -246,3 +269,842 @@ fn save_load_sharded(num_shards: u64) {
     let trees = cakes.trees();
     assert_eq!(trees.len(), num_shards as usize);
 }
+
+#[test]
+fn load_lazy_sharded() {
+    let seed = 42;
+    let cardinality = 4_000;
+    let num_shards = 8;
+
+    let data = utils::gen_dataset(cardinality, 5, seed, utils::euclidean);
+    let queries = utils::gen_dataset(10, 5, seed + 1, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let cakes = Cakes::new_cluster_sharded(data, cardinality / num_shards, Some(seed), &criteria);
+
+    let tmp_dir = tempdir::TempDir::new("lazy-cakes-test").unwrap();
+    cakes.save(tmp_dir.path()).unwrap();
+
+    let max_resident = 2;
+    let lazy_cakes = Cakes::<Vec<f32>, f32, VecDataset<_, _, usize>>::load_lazy(
+        tmp_dir.path(),
+        utils::euclidean,
+        false,
+        max_resident,
+    )
+    .unwrap();
+    assert_eq!(lazy_cakes.num_shards(), cakes.num_shards());
+    assert_eq!(lazy_cakes.shard_cardinalities(), cakes.shard_cardinalities());
+    assert_eq!(lazy_cakes.shards().len(), 1);
+
+    let Cakes::LazySharded(ls) = &lazy_cakes else {
+        unreachable!("We loaded lazily sharded Cakes.")
+    };
+    assert_eq!(ls.num_resident(), 0);
+
+    // A query at an instance only needs the shards near that instance.
+    let instance = lazy_cakes.instance(0).unwrap().into_owned();
+    let hits = lazy_cakes.knn_search(&instance, 1, knn::Algorithm::default());
+    assert_eq!(hits.len(), 1);
+    assert!(hits[0].1 <= f32::EPSILON);
+    assert!(ls.num_loads() < cakes.num_shards() - 1);
+
+    let sorted = |mut hits: Vec<(usize, f32)>| {
+        hits.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap().then(i.cmp(j)));
+        hits
+    };
+
+    for &query in &queries {
+        for k in [1, 10, 100] {
+            let expected = sorted(cakes.knn_search(query, k, knn::Algorithm::default()));
+            let actual = sorted(lazy_cakes.knn_search(query, k, knn::Algorithm::default()));
+            assert_eq!(expected, actual);
+            assert!(ls.num_resident() <= max_resident);
+        }
+
+        for radius in [0.05, 0.25] {
+            let expected = sorted(cakes.rnn_search(query, radius, rnn::Algorithm::default()));
+            let actual = sorted(lazy_cakes.rnn_search(query, radius, rnn::Algorithm::default()));
+            assert_eq!(expected, actual);
+            assert!(ls.num_resident() <= max_resident);
+        }
+    }
+
+    // Instances outside the sample shard are loaded by index.
+    let last = cakes.total_cardinality() - 1;
+    assert_eq!(lazy_cakes.instance(last).unwrap(), cakes.instance(last).unwrap());
+    assert!(lazy_cakes.instance(cakes.total_cardinality()).is_err());
+
+    for &query in &queries[..2] {
+        let algo = knn::Algorithm::Linear;
+        assert_eq!(
+            lazy_cakes.mmr_search(query, 10, 0.5, algo, 100).unwrap(),
+            cakes.mmr_search(query, 10, 0.5, algo, 100).unwrap()
+        );
+        for algo in [rknn::Algorithm::Linear, rknn::Algorithm::Clustered] {
+            let expected = sorted(cakes.rknn_search(query, 5, algo));
+            assert_eq!(sorted(lazy_cakes.rknn_search(query, 5, algo)), expected);
+        }
+        assert!(ls.num_resident() <= max_resident);
+    }
+
+    // Loaded as a layout, more than one shard is kept in memory.
+    let layout =
+        LazySharded::<Vec<f32>, f32, VecDataset<_, _, usize>>::load(tmp_dir.path(), utils::euclidean, false).unwrap();
+    for &query in &queries {
+        layout.linear_knn_search(query, cardinality);
+    }
+    assert_eq!(layout.num_resident(), DEFAULT_MAX_RESIDENT.min(cakes.num_shards() - 1));
+
+    // A shard which cannot be loaded is an error from the fallible searches.
+    std::fs::remove_dir_all(tmp_dir.path().join("shards").join("shard_0")).unwrap();
+    let lazy_cakes = Cakes::<Vec<f32>, f32, VecDataset<_, _, usize>>::load_lazy(
+        tmp_dir.path(),
+        utils::euclidean,
+        false,
+        max_resident,
+    )
+    .unwrap();
+    let result = lazy_cakes.try_knn_search(queries[0], cardinality, knn::Algorithm::Linear);
+    assert!(result.unwrap_err().contains("shard 0"));
+    assert!(lazy_cakes.search().try_instances(&[0, last]).is_ok());
+    assert!(lazy_cakes.search().try_instances(&[0, ls.shard_offsets()[1]]).is_err());
+}
+
+#[test]
+fn cluster_sharded_original_indices() {
+    let seed = 42;
+    let cardinality = 2_000;
+    let max_cardinality = cardinality / 8;
+
+    let data = utils::gen_dataset(cardinality, 5, seed, utils::euclidean);
+    let original = (0..cardinality).map(|i| data[i].clone()).collect::<Vec<_>>();
+    let queries = utils::gen_dataset(10, 5, seed + 1, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let cakes = Cakes::new_cluster_sharded(data, max_cardinality, Some(seed), &criteria);
+    assert!(cakes.num_shards() > 1);
+    assert!(cakes.shard_cardinalities().iter().all(|&c| c <= max_cardinality));
+    assert!(cakes.original_index(cardinality).is_err());
+
+    let tmp_dir = tempdir::TempDir::new("cluster-sharded-test").unwrap();
+    cakes.save(tmp_dir.path()).unwrap();
+    let lazy_cakes = Cakes::<Vec<f32>, f32, VecDataset<_, _, usize>>::load_lazy(
+        tmp_dir.path(),
+        utils::euclidean,
+        false,
+        DEFAULT_MAX_RESIDENT,
+    )
+    .unwrap();
+
+    // The hits, mapped back to the original data, are those of a linear
+    // search over the original data.
+    let linear = |query: &Vec<f32>, within: &dyn Fn(f32) -> bool| {
+        let mut hits = original
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (i, utils::euclidean::<_, f32>(query, x)))
+            .filter(|&(_, d)| within(d))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap().then(i.cmp(j)));
+        hits
+    };
+    let mapped = |cakes: &Cakes<Vec<f32>, f32, VecDataset<_, _, usize>>, hits: Vec<(usize, f32)>| {
+        let mut hits = hits
+            .into_iter()
+            .map(|(i, d)| (cakes.original_index(i).unwrap(), d))
+            .collect::<Vec<_>>();
+        hits.sort_by(|(i, a), (j, b)| a.partial_cmp(b).unwrap().then(i.cmp(j)));
+        hits
+    };
+
+    for &query in &queries {
+        for radius in [0.05, 0.25] {
+            let expected = linear(query, &|d| d <= radius);
+            for c in [&cakes, &lazy_cakes] {
+                let hits = c.rnn_search(query, radius, rnn::Algorithm::default());
+                assert_eq!(mapped(c, hits), expected);
+            }
+        }
+
+        for k in [1, 10] {
+            let expected = linear(query, &|_| true);
+            let kth = expected[k - 1].1;
+            for c in [&cakes, &lazy_cakes] {
+                let hits = mapped(c, c.knn_search(query, k, knn::Algorithm::default()));
+                assert_eq!(hits.len(), k);
+                for (i, d) in hits {
+                    assert!(d <= kth);
+                    assert_eq!(utils::euclidean::<_, f32>(query, &original[i]), d);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn tuning_profile() {
+    let seed = 42;
+    let data = utils::gen_dataset(2_000, 10, seed, utils::euclidean);
+    let queries = utils::gen_dataset(20, 10, seed + 1, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let mut cakes = Cakes::new(data, Some(seed), &criteria);
+    assert!(cakes.tuning_profile().is_empty());
+
+    cakes.auto_tune_knn(1, 7);
+    for k in [100, 10] {
+        cakes.auto_tune_knn_on(k, &queries);
+    }
+    cakes.auto_tune_rnn_on(0.1, &queries);
+
+    let profile = cakes.tuning_profile();
+    let ks = profile.knn_entries().iter().map(|e| e.param).collect::<Vec<_>>();
+    assert_eq!(ks, vec![1, 10, 100]);
+    for entry in profile.knn_entries() {
+        assert_eq!(entry.timings.len(), knn::Algorithm::variants().len());
+    }
+    assert_eq!(profile.knn_entries()[1].num_queries, queries.len());
+    assert_eq!(profile.knn_entries()[2].num_queries, queries.len());
+    assert_eq!(profile.rnn_entries().len(), 1);
+    assert_eq!(profile.rnn_entries()[0].num_queries, queries.len());
+
+    // Each k uses the algorithm tuned for the range in which it falls.
+    let best_for = |i: usize| profile.knn_entries()[i].best.name().to_string();
+    assert_eq!(profile.knn_algorithm(2).unwrap().name(), best_for(0));
+    assert_eq!(profile.knn_algorithm(5).unwrap().name(), best_for(1));
+    assert_eq!(profile.knn_algorithm(40).unwrap().name(), best_for(2));
+    assert_eq!(profile.knn_algorithm(1_000).unwrap().name(), best_for(2));
+
+    for &query in &queries {
+        for k in [1, 5, 50] {
+            let mut expected = cakes
+                .linear_knn_search(query, k)
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect::<Vec<_>>();
+            let mut actual = cakes
+                .tuned_knn_search(query, k)
+                .into_iter()
+                .map(|(_, d)| d)
+                .collect::<Vec<_>>();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            actual.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            assert_eq!(expected, actual);
+        }
+    }
+
+    let tmp_dir = tempdir::TempDir::new("tuning-profile-test").unwrap();
+    cakes.save(tmp_dir.path()).unwrap();
+    let loaded = Cakes::<Vec<f32>, f32, VecDataset<_, _, usize>>::load(tmp_dir.path(), utils::euclidean, false).unwrap();
+
+    let loaded_profile = loaded.tuning_profile();
+    assert_eq!(loaded_profile.knn_entries().len(), profile.knn_entries().len());
+    for (a, b) in profile.knn_entries().iter().zip(loaded_profile.knn_entries()) {
+        assert_eq!(a.param, b.param);
+        assert_eq!(a.best.name(), b.best.name());
+        assert_eq!(a.num_queries, b.num_queries);
+        let a = a.timings.iter().map(|(algo, t)| (algo.name(), *t)).collect::<Vec<_>>();
+        let b = b.timings.iter().map(|(algo, t)| (algo.name(), *t)).collect::<Vec<_>>();
+        assert_eq!(a, b);
+    }
+    assert_eq!(loaded_profile.rnn_entries().len(), 1);
+    assert!((loaded_profile.rnn_entries()[0].param - 0.1).abs() <= f32::EPSILON);
+}
+
+#[test]
+fn search_budgets() {
+    let seed = 42;
+    let cardinality = 2_000;
+    let data = utils::gen_dataset(cardinality, 10, seed, utils::euclidean);
+    let queries = utils::gen_dataset(10, 10, seed + 1, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let single = Cakes::new(data.clone(), Some(seed), &criteria);
+    let mut sequential = Cakes::new_cluster_sharded(data.clone(), cardinality / 4, Some(seed), &criteria);
+    sequential.set_shard_strategy(ShardStrategy::Sequential);
+    let mut parallel = Cakes::new_cluster_sharded(data, cardinality / 4, Some(seed), &criteria);
+    parallel.set_shard_strategy(ShardStrategy::Parallel);
+
+    let distances = |hits: &[(usize, f32)]| {
+        let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        distances
+    };
+
+    let (k, radius) = (10, 0.25);
+    let unlimited = Budget::unlimited();
+    let tiny = Budget::unlimited().with_max_distances(50);
+    let late = Budget::unlimited().with_deadline(Duration::ZERO);
+
+    for cakes in [&single, &sequential, &parallel] {
+        for &query in &queries {
+            let algorithms = knn::Algorithm::variants().iter().chain([&knn::Algorithm::Linear]);
+            for &algo in algorithms {
+                let expected = cakes.knn_search(query, k, algo);
+                let actual = cakes.knn_search_with_budget(query, k, algo, &unlimited);
+                assert!(actual.is_complete);
+                assert_eq!(distances(&expected), distances(&actual.hits), "{}", algo.name());
+
+                let actual = cakes.knn_search_with_budget(query, k, algo, &tiny);
+                assert!(!actual.is_complete, "{}", algo.name());
+                assert!(actual.num_distances <= 50, "{}", algo.name());
+                assert!(actual.hits.len() <= k, "{}", algo.name());
+                for &(i, d) in &actual.hits {
+                    assert!((utils::euclidean::<_, f32>(query, &cakes.instance(i).unwrap()) - d).abs() <= f32::EPSILON);
+                }
+
+                let actual = cakes.knn_search_with_budget(query, k, algo, &late);
+                assert!(!actual.is_complete, "{}", algo.name());
+            }
+
+            for algo in [rnn::Algorithm::Clustered, rnn::Algorithm::Linear] {
+                let expected = cakes.rnn_search(query, radius, algo);
+                let actual = cakes.rnn_search_with_budget(query, radius, algo, &unlimited);
+                assert!(actual.is_complete);
+                assert_eq!(distances(&expected), distances(&actual.hits), "{}", algo.name());
+
+                let actual = cakes.rnn_search_with_budget(query, radius, algo, &tiny);
+                assert!(!actual.is_complete, "{}", algo.name());
+                assert!(actual.num_distances <= 50, "{}", algo.name());
+                assert!(actual.hits.iter().all(|&(_, d)| d <= radius));
+            }
+        }
+    }
+
+    // Linear search needs exactly one distance per instance.
+    let exact = Budget::unlimited().with_max_distances(cardinality);
+    let hits = single.knn_search_with_budget(queries[0], k, knn::Algorithm::Linear, &exact);
+    assert!(hits.is_complete);
+    assert_eq!(hits.num_distances, cardinality);
+    assert_eq!(hits.hits.len(), k);
+
+    // Searches without a budget do not count their distance computations, but
+    // an unlimited budget does.
+    let tracker = Tracker::unlimited();
+    assert!(tracker.spend(cardinality) && tracker.reserve(cardinality) == cardinality);
+    assert_eq!(tracker.finish(Vec::<(usize, f32)>::new()).num_distances, 0);
+    let hits = single.knn_search_with_budget(queries[0], k, knn::Algorithm::Linear, &unlimited);
+    assert_eq!(hits.num_distances, cardinality);
+
+    // A tree search that runs out of budget still returns the nearest cluster
+    // centers it has seen.
+    let hits = single.knn_search_with_budget(queries[0], k, knn::Algorithm::GreedySieve, &tiny);
+    assert!(!hits.hits.is_empty());
+}
+
+#[test]
+fn dual_tree_batch_search() {
+    let seed = 42;
+    let cardinality = 2_000;
+    let data = utils::gen_dataset(cardinality, 10, seed, utils::euclidean);
+
+    // Half of the queries are instances from the dataset, so that some of the
+    // queries are close to each other and to the data.
+    let random_queries = utils::gen_dataset(50, 10, seed + 1, utils::euclidean);
+    let queries = (0..50)
+        .map(|i| &data[i * 7])
+        .chain((0..random_queries.cardinality()).map(|i| &random_queries[i]))
+        .collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let single = Cakes::new(data.clone(), Some(seed), &criteria);
+    let sharded = Cakes::new_cluster_sharded(data.clone(), cardinality / 4, Some(seed), &criteria);
+
+    let distances = |hits: &[(usize, f32)]| {
+        let mut distances = hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+        distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        distances
+    };
+
+    for cakes in [&single, &sharded] {
+        for k in [1, 10, 100] {
+            let expected = cakes.batch_knn_search(&queries, k, knn::Algorithm::Linear);
+            let actual = cakes.batch_dual_tree_knn_search(&queries, k, Some(seed));
+            assert_eq!(expected.len(), actual.len());
+            for (i, (e, a)) in expected.iter().zip(actual.iter()).enumerate() {
+                assert_eq!(distances(e), distances(a), "query {i}, k {k}");
+                for &(j, d) in a {
+                    assert!(
+                        (utils::euclidean::<_, f32>(queries[i], &cakes.instance(j).unwrap()) - d).abs() <= f32::EPSILON
+                    );
+                }
+            }
+        }
+    }
+
+    assert!(single.batch_dual_tree_knn_search(&[], 10, None).is_empty());
+}
+
+/// Checks that searches on a dataset with a lower bound on its string metric
+/// find the same hits as without the bound, with fewer distance computations.
+fn check_lower_bound(alphabet: &str, metric: fn(&String, &String) -> u16, radii: [u16; 2]) {
+    let seed = 42;
+    let (cardinality, k) = (500, 10);
+
+    let data = symagen::random_data::random_string(cardinality, 50, 150, alphabet, seed);
+    let queries = symagen::random_data::random_string(5, 50, 150, alphabet, seed + 1);
+    let queries = queries.iter().chain(data.iter().take(5)).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let plain = VecDataset::new("plain".to_string(), data.clone(), metric, true);
+    let plain = Cakes::new(plain, Some(seed), &criteria);
+    let bounded = VecDataset::new("bounded".to_string(), data.clone(), metric, true)
+        .with_lower_bound(Some(utils::char_histogram::<u16>));
+    let bounded = Cakes::new(bounded, Some(seed), &criteria);
+
+    let sorted = |mut hits: Vec<(usize, u16)>| {
+        hits.sort_unstable();
+        hits
+    };
+    let distances = |hits: Vec<(usize, u16)>| {
+        let mut distances = hits.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+        distances.sort_unstable();
+        distances
+    };
+
+    let budget = Budget::unlimited();
+    let (mut plain_distances, mut bounded_distances) = (0, 0);
+    for &query in &queries {
+        for radius in radii {
+            for algo in [rnn::Algorithm::Linear, rnn::Algorithm::Clustered] {
+                let expected = plain.rnn_search_with_budget(query, radius, algo, &budget);
+                let actual = bounded.rnn_search_with_budget(query, radius, algo, &budget);
+                assert_eq!(sorted(expected.hits), sorted(actual.hits));
+                assert!(actual.num_distances <= expected.num_distances);
+                plain_distances += expected.num_distances;
+                bounded_distances += actual.num_distances;
+            }
+        }
+
+        let algorithms = knn::Algorithm::variants().iter().chain([&knn::Algorithm::Linear]);
+        for &algo in algorithms {
+            let expected = plain.knn_search_with_budget(query, k, algo, &budget);
+            let actual = bounded.knn_search_with_budget(query, k, algo, &budget);
+            assert_eq!(distances(expected.hits), distances(actual.hits), "{}", algo.name());
+            assert!(actual.num_distances <= expected.num_distances, "{}", algo.name());
+            plain_distances += expected.num_distances;
+            bounded_distances += actual.num_distances;
+        }
+        let expected = plain.batch_dual_tree_knn_search(&[query], k, Some(seed));
+        let actual = bounded.batch_dual_tree_knn_search(&[query], k, Some(seed));
+        assert_eq!(distances(expected[0].clone()), distances(actual[0].clone()));
+    }
+    assert!(bounded_distances < plain_distances);
+}
+
+#[test]
+fn string_lower_bounds() {
+    check_lower_bound("ACTG", utils::levenshtein::<u16>, [10, 40]);
+}
+
+#[test]
+fn string_lower_bounds_non_ascii() {
+    // `levenshtein` counts bytes rather than characters, so the bound is only
+    // checked against `nw_distance` for non-ASCII strings.
+    check_lower_bound("ACTGÅ∂ƒé", utils::needleman_wunsch::<u16>, [20, 60]);
+}
+
+#[test]
+fn rerank_search() {
+    let seed = 42;
+    let (cardinality, alphabet, k) = (400, "ACTG", 5);
+
+    let data = symagen::random_data::random_string(cardinality, 60, 60, alphabet, seed);
+    let queries = symagen::random_data::random_string(5, 60, 60, alphabet, seed + 1);
+    let queries = queries.iter().collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let single = VecDataset::new("proxy".to_string(), data.clone(), utils::hamming::<u16>, false);
+    let single = Cakes::new(single, Some(seed), &criteria);
+
+    let shards =
+        VecDataset::new("proxy".to_string(), data.clone(), utils::hamming::<u16>, false).make_shards(cardinality / 4);
+    let sharded = Cakes::new_randomly_sharded(shards, Some(seed), &criteria);
+
+    let exact = utils::levenshtein::<u16>;
+    for cakes in [&single, &sharded] {
+        let algo = knn::Algorithm::default();
+        for (&query, hits) in queries
+            .iter()
+            .zip(cakes.batch_rerank_knn_search(&queries, k, 4, algo, exact))
+        {
+            assert_eq!(hits.len(), k);
+            assert!(hits.windows(2).all(|w| w[0].1 <= w[1].1));
+            for &(i, d) in &hits {
+                assert_eq!(d, exact(query, &cakes.instance(i).unwrap()));
+            }
+        }
+
+        // With every instance as a candidate, no true neighbor can be missed.
+        let report = cakes.rerank_report(&queries, k, cardinality, algo, exact);
+        assert_eq!(report.num_queries, queries.len());
+        assert_eq!(report.num_candidates, cardinality);
+        assert_eq!(report.num_missed_neighbors, 0);
+        assert!(report.query_miss_rate().abs() < f64::EPSILON);
+
+        // An expansion for which `k * expansion` overflows also takes every instance.
+        let report = cakes.rerank_report(&queries, k, usize::MAX, algo, exact);
+        assert_eq!(report.num_candidates, cardinality);
+        assert_eq!(report.num_missed_neighbors, 0);
+
+        let report = cakes.rerank_report(&queries, k, 1, algo, exact);
+        assert_eq!(report.num_candidates, k);
+        assert!(report.num_queries_with_misses <= report.num_queries);
+        assert!(report.num_missed_neighbors <= k * report.num_queries);
+        assert!((0.0..=1.0).contains(&report.neighbor_miss_rate()));
+    }
+
+    // Lazily loaded shards fetch the candidates for re-ranking from disk.
+    let tmp_dir = tempdir::TempDir::new("rerank-cakes-test").unwrap();
+    sharded.save(tmp_dir.path()).unwrap();
+    let lazy =
+        Cakes::<String, u16, VecDataset<_, _, usize>>::load_lazy(tmp_dir.path(), utils::hamming, false, 1).unwrap();
+
+    let algo = knn::Algorithm::Linear;
+    for &query in &queries {
+        let expected = sharded.rerank_knn_search(query, k, cardinality, algo, exact);
+        let actual = lazy.rerank_knn_search(query, k, cardinality, algo, exact);
+        let distances = |hits: Vec<(usize, u16)>| hits.into_iter().map(|(_, d)| d).collect::<Vec<_>>();
+        assert_eq!(distances(expected), distances(actual));
+    }
+    assert_eq!(
+        lazy.rerank_report(&queries, k, cardinality, algo, exact)
+            .num_missed_neighbors,
+        0
+    );
+}
+
+#[test]
+fn near_duplicates() {
+    let seed = 42;
+    let (cardinality, num_copies, threshold) = (200, 50, 2);
+
+    // Each copy differs from its original in one position, and random strings
+    // are much farther apart than the threshold.
+    let mut data = symagen::random_data::random_string(cardinality, 50, 50, "ACTG", seed);
+    for i in 0..num_copies {
+        let mut copy = data[i].clone().into_bytes();
+        copy[i % 50] = if copy[i % 50] == b'A' { b'C' } else { b'A' };
+        data.push(String::from_utf8(copy).unwrap());
+    }
+    // The last copy is a chain of two near-duplicates.
+    let mut copy = data[cardinality].clone().into_bytes();
+    copy[49] = if copy[49] == b'G' { b'T' } else { b'G' };
+    data.push(String::from_utf8(copy).unwrap());
+
+    let criteria = PartitionCriteria::default();
+    let single = VecDataset::new("dups".to_string(), data.clone(), utils::hamming::<u16>, false);
+    let single = Cakes::new(single, Some(seed), &criteria);
+    let shards = VecDataset::new("dups".to_string(), data.clone(), utils::hamming::<u16>, false).make_shards(100);
+    let sharded = Cakes::new_randomly_sharded(shards, Some(seed), &criteria);
+
+    // The trees reorder the instances, so the groups are compared by content.
+    let as_strings = |groups: Vec<Vec<usize>>, data: &dyn Fn(usize) -> String| {
+        let mut groups = groups
+            .into_iter()
+            .map(|g| {
+                let mut g = g.into_iter().map(data).collect::<Vec<_>>();
+                g.sort();
+                g
+            })
+            .collect::<Vec<_>>();
+        groups.sort();
+        groups
+    };
+    let mut expected = (0..num_copies).map(|i| vec![i, cardinality + i]).collect::<Vec<_>>();
+    expected[0].push(cardinality + num_copies);
+    let expected = as_strings(expected, &|i| data[i].clone());
+
+    for cakes in [&single, &sharded] {
+        let groups = cakes.near_duplicates(threshold);
+        assert!(groups.iter().all(|g| g.windows(2).all(|w| w[0] < w[1])));
+        assert!(groups.windows(2).all(|w| w[0][0] < w[1][0]));
+        assert_eq!(
+            as_strings(groups, &|i| cakes.instance(i).unwrap().into_owned()),
+            expected
+        );
+
+        let dedup = cakes.dedup(threshold);
+        assert_eq!(dedup.representatives.len() + dedup.removed.len(), data.len());
+        assert_eq!(dedup.removed.len(), num_copies + 1);
+        for (&i, &r) in &dedup.removed {
+            assert!(dedup.representatives.binary_search(&r).is_ok());
+            assert!(utils::hamming::<u16>(&cakes.instance(i).unwrap(), &cakes.instance(r).unwrap()) <= threshold);
+            assert_eq!(dedup.representative_of(i), r);
+        }
+        assert!(dedup.representatives.iter().all(|&r| dedup.representative_of(r) == r));
+
+        // No two representatives are near-duplicates of each other.
+        for (a, &i) in dedup.representatives.iter().enumerate() {
+            for &j in &dedup.representatives[(a + 1)..] {
+                assert!(utils::hamming::<u16>(&cakes.instance(i).unwrap(), &cakes.instance(j).unwrap()) > threshold);
+            }
+        }
+    }
+
+    let tmp_dir = tempdir::TempDir::new("dedup-cakes-test").unwrap();
+    sharded.save(tmp_dir.path()).unwrap();
+    let lazy =
+        Cakes::<String, u16, VecDataset<_, _, usize>>::load_lazy(tmp_dir.path(), utils::hamming, false, 1).unwrap();
+    assert_eq!(lazy.near_duplicates(threshold), sharded.near_duplicates(threshold));
+    assert_eq!(lazy.dedup(threshold), sharded.dedup(threshold));
+}
+
+#[test]
+fn mmr_search() {
+    let seed = 42;
+    let (cardinality, k) = (2_000, 10);
+
+    let data = utils::gen_dataset(cardinality, 3, seed, utils::euclidean);
+    let queries = utils::gen_dataset(5, 3, seed + 1, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let single = Cakes::new(data.clone(), Some(seed), &criteria);
+    let sharded = Cakes::new_randomly_sharded(data.make_shards(cardinality / 4), Some(seed), &criteria);
+
+    // Greedy MMR over the whole dataset.
+    let brute_force = |cakes: &Cakes<Vec<f32>, f32, VecDataset<_, _, usize>>, query: &Vec<f32>, lambda: f32| {
+        let mut selected: Vec<usize> = Vec::new();
+        while selected.len() < k {
+            let score = |i: usize| {
+                let div = selected
+                    .iter()
+                    .map(|&s| utils::euclidean::<_, f32>(&cakes.instance(i).unwrap(), &cakes.instance(s).unwrap()))
+                    .fold(f32::INFINITY, f32::min);
+                let div = if selected.is_empty() { 0.0 } else { div };
+                lambda.mul_add(
+                    utils::euclidean(query, &cakes.instance(i).unwrap()),
+                    -(1.0 - lambda) * div,
+                )
+            };
+            let best = (0..cakes.total_cardinality())
+                .filter(|i| !selected.contains(i))
+                .min_by(|&a, &b| score(a).partial_cmp(&score(b)).unwrap())
+                .unwrap();
+            selected.push(best);
+        }
+        selected
+    };
+
+    let min_spread = |cakes: &Cakes<Vec<f32>, f32, VecDataset<_, _, usize>>, hits: &[(usize, f32)]| {
+        hits.iter()
+            .enumerate()
+            .flat_map(|(a, &(i, _))| {
+                hits[(a + 1)..].iter().map(move |&(j, _)| {
+                    utils::euclidean::<_, f32>(&cakes.instance(i).unwrap(), &cakes.instance(j).unwrap())
+                })
+            })
+            .fold(f32::INFINITY, f32::min)
+    };
+
+    for cakes in [&single, &sharded] {
+        let algo = knn::Algorithm::GreedySieve;
+        for &query in &queries {
+            let mut knn_hits = cakes.linear_knn_search(query, k);
+            knn_hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+            let mmr_hits = cakes.mmr_search(query, k, 1.0, algo, cardinality).unwrap();
+            let distances = |hits: &[(usize, f32)]| hits.iter().map(|&(_, d)| d).collect::<Vec<_>>();
+            assert_eq!(distances(&mmr_hits), distances(&knn_hits));
+
+            for lambda in [0.7, 0.3] {
+                let hits = cakes.mmr_search(query, k, lambda, algo, cardinality).unwrap();
+                let expected = brute_force(cakes, query, lambda.as_f32());
+                assert_eq!(hits.iter().map(|&(i, _)| i).collect::<Vec<_>>(), expected);
+                assert!(min_spread(cakes, &hits) >= min_spread(cakes, &knn_hits));
+            }
+
+            // A small pool of candidates still returns k results.
+            assert_eq!(cakes.mmr_search(query, k, 0.3, algo, k).unwrap().len(), k);
+        }
+    }
+}
+
+/// A custom layout which keeps several replicas of the same shard and sends
+/// each query to the next replica in turn.
+///
+/// Unless `expose_trees` is set, it reports no trees in memory, as a layout
+/// which keeps its shards elsewhere would.
+struct Replicated<D: Dataset<Vec<f32>, f32>> {
+    replicas: Vec<SingleShard<Vec<f32>, f32, D>>,
+    next: AtomicUsize,
+    expose_trees: bool,
+}
+
+impl<D: Dataset<Vec<f32>, f32>> Replicated<D> {
+    fn replica(&self) -> &SingleShard<Vec<f32>, f32, D> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        &self.replicas[i % self.replicas.len()]
+    }
+}
+
+impl<D: Dataset<Vec<f32>, f32>> Search<Vec<f32>, f32, D> for Replicated<D> {
+    fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path.join("num_replicas"), self.replicas.len().to_string()).map_err(|e| e.to_string())?;
+        self.replicas[0].save(path)
+    }
+
+    fn load(path: &Path, metric: fn(&Vec<f32>, &Vec<f32>) -> f32, is_expensive: bool) -> Result<Self, String> {
+        let num_replicas = std::fs::read_to_string(path.join("num_replicas"))
+            .map_err(|e| e.to_string())?
+            .parse::<usize>()
+            .map_err(|e| e.to_string())?;
+        let replicas = (0..num_replicas)
+            .map(|_| SingleShard::load(path, metric, is_expensive))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            replicas,
+            next: AtomicUsize::new(0),
+            expose_trees: true,
+        })
+    }
+
+    fn trees(&self) -> Vec<&Tree<Vec<f32>, f32, D, UniBall<f32>>> {
+        if self.expose_trees {
+            self.replicas[0].trees()
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn instance(&self, index: usize) -> Result<Cow<'_, Vec<f32>>, String> {
+        self.replicas[0].instance(index)
+    }
+
+    fn shard_cardinalities(&self) -> Vec<usize> {
+        self.replicas[0].shard_cardinalities()
+    }
+
+    fn try_budgeted_rnn_search(
+        &self,
+        query: &Vec<f32>,
+        radius: f32,
+        algo: rnn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, f32)>, String> {
+        self.replica().try_budgeted_rnn_search(query, radius, algo, tracker)
+    }
+
+    fn try_budgeted_knn_search(
+        &self,
+        query: &Vec<f32>,
+        k: usize,
+        algo: knn::Algorithm,
+        tracker: &Tracker,
+    ) -> Result<Vec<(usize, f32)>, String> {
+        self.replica().try_budgeted_knn_search(query, k, algo, tracker)
+    }
+
+    fn tuning_profile(&self) -> &TuningProfile<f32> {
+        self.replicas[0].tuning_profile()
+    }
+
+    fn tuning_profile_mut(&mut self) -> &mut TuningProfile<f32> {
+        self.replicas[0].tuning_profile_mut()
+    }
+}
+
+#[test]
+fn custom_layout() {
+    let seed = 42;
+    let (cardinality, k, radius) = (1_000, 10, 0.2);
+
+    let data = utils::gen_dataset(cardinality, 3, seed, utils::euclidean);
+    let queries = utils::gen_dataset(10, 3, seed + 1, utils::euclidean);
+    let queries = (0..queries.cardinality()).map(|i| &queries[i]).collect::<Vec<_>>();
+
+    let criteria = PartitionCriteria::default();
+    let single = Cakes::new(data.clone(), Some(seed), &criteria);
+    let replicas = (0..3)
+        .map(|_| SingleShard::new(data.clone(), Some(seed), &criteria))
+        .collect();
+    let mut custom = Cakes::from_search(Replicated {
+        replicas,
+        next: AtomicUsize::new(0),
+        expose_trees: true,
+    });
+
+    assert_eq!(custom.num_shards(), 1);
+    assert_eq!(custom.total_cardinality(), cardinality);
+    assert_eq!(custom.trees().len(), 1);
+    assert_eq!(custom.instance(7).unwrap(), single.instance(7).unwrap());
+
+    let sorted = |mut hits: Vec<(usize, f32)>| {
+        hits.sort_by_key(|&(i, _)| i);
+        hits
+    };
+
+    // The batched and tuned helpers of `Cakes` work with the custom layout.
+    let expected = single.batch_knn_search(&queries, k, knn::Algorithm::default());
+    let actual = custom.batch_knn_search(&queries, k, knn::Algorithm::default());
+    for (e, a) in expected.into_iter().zip(actual) {
+        assert_eq!(sorted(e), sorted(a));
+    }
+    let expected = single.batch_rnn_search(&queries, radius, rnn::Algorithm::default());
+    let actual = custom.batch_tuned_rnn_search(&queries, radius);
+    for (e, a) in expected.into_iter().zip(actual) {
+        assert_eq!(sorted(e), sorted(a));
+    }
+    let hits = custom.batch_dual_tree_knn_search(&queries, k, Some(seed));
+    assert!(hits.iter().all(|h| h.len() == k));
+
+    // The searches which the layout does not implement search its trees.
+    for algo in [kfn::Algorithm::Linear, kfn::Algorithm::Clustered] {
+        assert_eq!(
+            sorted(custom.kfn_search(queries[0], k, algo)),
+            sorted(single.kfn_search(queries[0], k, algo))
+        );
+    }
+    for algo in [rknn::Algorithm::Linear, rknn::Algorithm::Clustered] {
+        assert_eq!(
+            sorted(custom.rknn_search(queries[0], k, algo)),
+            sorted(single.rknn_search(queries[0], k, algo))
+        );
+    }
+
+    // Without a shard in memory to build the query tree with, the queries are
+    // searched for independently.
+    let hidden = Cakes::from_search(Replicated {
+        replicas: vec![SingleShard::new(data.clone(), Some(seed), &criteria)],
+        next: AtomicUsize::new(0),
+        expose_trees: false,
+    });
+    assert!(hidden.shards().is_empty());
+    let expected = single.batch_tuned_knn_search(&queries, k);
+    let actual = hidden.batch_dual_tree_knn_search(&queries, k, Some(seed));
+    for (e, a) in expected.into_iter().zip(actual) {
+        assert_eq!(sorted(e), sorted(a));
+    }
+
+    // The distances between the candidates of MMR search need the metric of
+    // a shard in memory.
+    let algo = knn::Algorithm::Linear;
+    assert_eq!(
+        custom.mmr_search(queries[0], k, 0.5, algo, 100),
+        single.mmr_search(queries[0], k, 0.5, algo, 100)
+    );
+    assert!(hidden.mmr_search(queries[0], k, 0.5, algo, 100).is_err());
+    assert!(hidden.batch_mmr_search(&queries, k, 0.5, algo, 100).is_err());
+
+    let budgeted = custom.knn_search_with_budget(queries[0], k, knn::Algorithm::default(), &Budget::unlimited());
+    assert!(budgeted.is_complete);
+
+    custom.auto_tune_knn_on(k, &queries);
+    assert!(!custom.tuning_profile().is_empty());
+    assert_eq!(custom.tuned_knn_search(queries[0], k).len(), k);
+
+    let tmp_dir = tempdir::TempDir::new("custom-cakes-test").unwrap();
+    custom.save(tmp_dir.path()).unwrap();
+    let loaded =
+        Cakes::load_with::<Replicated<VecDataset<_, _, usize>>>(tmp_dir.path(), utils::euclidean, false).unwrap();
+    assert_eq!(loaded.shard_cardinalities(), custom.shard_cardinalities());
+    assert_eq!(
+        sorted(loaded.knn_search(queries[0], k, knn::Algorithm::Linear)),
+        sorted(single.knn_search(queries[0], k, knn::Algorithm::Linear))
+    );
+}
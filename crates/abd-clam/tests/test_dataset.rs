@@ -1,6 +1,7 @@
 //! Tests for the dataset module.
 
-use abd_clam::{Dataset, VecDataset};
+use abd_clam::{Cakes, Dataset, PartitionCriteria, VecDataset};
+use distances::Number;
 use rand::prelude::*;
 use tempdir::TempDir;
 use test_case::test_case;
@@ -129,3 +130,48 @@ fn load_errors() {
     let other = VecDataset::<Vec<f32>, f32, usize>::load(&tmp_file, utils::euclidean, false);
     assert!(other.is_err());
 }
+
+#[test]
+fn validate_metric() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let report = abd_clam::validate_metric(&data, 30, Some(42));
+    assert_eq!(report.num_samples, 30);
+    assert_eq!(report.num_triples, 30 * 29 * 28);
+    assert!(report.is_metric(), "{report:?}");
+    assert!(report.worst_triangle_slack >= -report.tolerance);
+    assert!(report.worst_triangle.is_some());
+
+    // Squared euclidean distance does not obey the triangle inequality.
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean_sq);
+    let report = abd_clam::validate_metric(&data, 30, Some(42));
+    assert!(!report.is_metric());
+    assert_eq!(report.identity_violations, 0);
+    assert_eq!(report.symmetry_violations, 0);
+    assert!(report.triangle_violations > 0);
+    assert!(report.worst_triangle_slack < 0.0);
+    let [x, y, z] = report.worst_triangle.unwrap();
+    let d = |a: usize, b: usize| data.one_to_one(a, b);
+    assert!((d(x, y) + d(y, z) - d(x, z) - report.worst_triangle_slack.as_f32()).abs() < 1e-4);
+
+    // An asymmetric distance, which also violates identity.
+    let data = utils::gen_dataset(100, 3, 42, |x: &Vec<f32>, y: &Vec<f32>| {
+        utils::euclidean::<_, f32>(x, y) + if x[0] < y[0] { 1.0 } else { 0.5 }
+    });
+    let report = abd_clam::validate_metric(&data, 20, Some(42));
+    assert_eq!(report.identity_violations, 20);
+    assert_eq!(report.symmetry_violations, 20 * 19 / 2);
+    assert!((report.worst_asymmetry - 0.5).abs() < 1e-6);
+
+    // Integer distances between strings are compared exactly.
+    let strings = (0..200).map(|i| format!("{:b}", i * 37 % 256)).collect::<Vec<_>>();
+    let data = VecDataset::new("strings".to_string(), strings, utils::levenshtein::<u16>, true);
+    let report = abd_clam::validate_metric(&data, 25, Some(42));
+    assert_eq!(report.tolerance, 0.0);
+    assert!(report.is_metric(), "{report:?}");
+
+    // Building CAKES with a non-metric reports it, and still builds the index.
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean_sq);
+    let (cakes, report) = Cakes::new_validated(data, Some(42), &PartitionCriteria::default(), 30);
+    assert!(!report.is_metric());
+    assert_eq!(cakes.total_cardinality(), 1000);
+}
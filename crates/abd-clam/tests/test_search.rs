@@ -1,6 +1,6 @@
 //! Tests for the Search algorithms.
 
-use abd_clam::{cakes::knn, cakes::rnn, PartitionCriteria, Tree, UniBall};
+use abd_clam::{cakes::kfn, cakes::knn, cakes::rknn, cakes::rnn, Dataset, PartitionCriteria, Tree, UniBall};
 use distances::Number;
 use float_cmp::assert_approx_eq;
 use test_case::test_case;
@@ -97,3 +97,115 @@ fn variants(cardinality: usize, dimensionality: usize) {
         }
     }
 }
+
+#[test_case(1000, 10; "1k_10")]
+#[test_case(1000, 100; "1k_100")]
+#[test_case(10_000, 10; "10k_10")]
+fn farthest(cardinality: usize, dimensionality: usize) {
+    let seed = 42;
+
+    let data = utils::gen_dataset(cardinality, dimensionality, seed, utils::euclidean);
+    let queries = utils::gen_dataset(10, dimensionality, seed + 1, utils::euclidean);
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+
+    for k in [1, 10, 100] {
+        for i in 0..queries.cardinality() {
+            let query = &queries[i];
+
+            // Brute-force: sort all distances in decreasing order.
+            let true_distances = {
+                let mut distances = (0..tree.cardinality())
+                    .map(|j| tree.data().query_to_one(query, j))
+                    .collect::<Vec<_>>();
+                distances.sort_by(|a, b| b.partial_cmp(a).unwrap());
+                distances.truncate(k);
+                distances
+            };
+
+            for &algo in core::iter::once(&kfn::Algorithm::Linear).chain(kfn::Algorithm::variants()) {
+                let distances = {
+                    let mut distances = algo
+                        .search(&tree, query, k)
+                        .into_iter()
+                        .map(|(_, d)| d)
+                        .collect::<Vec<_>>();
+                    distances.sort_by(|a, b| b.partial_cmp(a).unwrap());
+                    distances
+                };
+
+                assert_eq!(
+                    distances,
+                    true_distances,
+                    "{} KFN failed for query {i} with k = {k}",
+                    algo.name()
+                );
+            }
+        }
+    }
+}
+
+#[test_case(1000, 2; "1k_2")]
+#[test_case(1000, 10; "1k_10")]
+#[test_case(2000, 10; "2k_10")]
+fn reverse_nearest(cardinality: usize, dimensionality: usize) {
+    let seed = 42;
+
+    let data = utils::gen_dataset(cardinality, dimensionality, seed, utils::euclidean);
+    let queries = utils::gen_dataset(10, dimensionality, seed + 1, utils::euclidean);
+
+    let criteria = PartitionCriteria::default();
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(seed)).partition(&criteria, Some(seed));
+    let data = tree.data();
+
+    for k in [1, 5, 20] {
+        // Brute-force: the distance from each instance to its k-th nearest neighbor.
+        let knn_radii = (0..data.cardinality())
+            .map(|i| {
+                let mut distances = (0..data.cardinality())
+                    .filter(|&j| j != i)
+                    .map(|j| data.one_to_one(i, j))
+                    .collect::<Vec<_>>();
+                distances.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                distances[k - 1]
+            })
+            .collect::<Vec<_>>();
+
+        for i in 0..queries.cardinality() {
+            let query = &queries[i];
+
+            let true_hits = (0..data.cardinality())
+                .filter(|&j| data.query_to_one(query, j) <= knn_radii[j])
+                .collect::<Vec<_>>();
+
+            for &algo in core::iter::once(&rknn::Algorithm::Linear).chain(rknn::Algorithm::variants()) {
+                let hits = {
+                    let mut hits = algo
+                        .search(&tree, query, k)
+                        .into_iter()
+                        .map(|(j, _)| j)
+                        .collect::<Vec<_>>();
+                    hits.sort_unstable();
+                    hits
+                };
+
+                assert_eq!(
+                    hits,
+                    true_hits,
+                    "{} RKNN failed for query {i} with k = {k}",
+                    algo.name()
+                );
+            }
+        }
+    }
+
+    // With k = 0 no instance has any neighbors, so none has the query among them.
+    for &algo in core::iter::once(&rknn::Algorithm::Linear).chain(rknn::Algorithm::variants()) {
+        assert!(
+            algo.search(&tree, &queries[0], 0).is_empty(),
+            "{} RKNN with k = 0",
+            algo.name()
+        );
+    }
+}
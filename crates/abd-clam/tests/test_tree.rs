@@ -1,7 +1,8 @@
 //! Tests on the tree module.
 
-use abd_clam::{Cluster, Dataset, Instance, PartitionCriteria, Tree, UniBall, VecDataset};
+use abd_clam::{Cluster, Dataset, Instance, LfdEstimator, PartitionCriteria, Tree, UniBall, VecDataset};
 use distances::Number;
+use rand::SeedableRng;
 use tempdir::TempDir;
 
 mod utils;
@@ -147,3 +148,72 @@ fn get_cluster() {
         }
     }
 }
+
+#[test]
+fn save_load_lfd_estimator() {
+    let data = utils::gen_dataset(1000, 10, 42, utils::euclidean);
+    let metric = data.metric();
+
+    let estimator = LfdEstimator::MaximumLikelihood { k: 50 };
+    let criteria = PartitionCriteria::default();
+    let raw_tree = Tree::<_, _, _, UniBall<_>>::new_with_lfd(data, Some(42), estimator).partition(&criteria, Some(42));
+    assert_eq!(raw_tree.lfd_estimator(), estimator);
+
+    let tree_dir = TempDir::new("tree_lfd").unwrap();
+    raw_tree.save(tree_dir.path()).unwrap();
+    let rec_tree = Tree::<_, _, VecDataset<_, _, usize>, UniBall<_>>::load(tree_dir.path(), metric, false).unwrap();
+    assert_eq!(rec_tree.lfd_estimator(), estimator);
+
+    // Trees saved without an estimator used the default.
+    std::fs::remove_file(tree_dir.path().join("lfd_estimator")).unwrap();
+    let rec_tree = Tree::<_, _, VecDataset<_, _, usize>, UniBall<_>>::load(tree_dir.path(), metric, false).unwrap();
+    assert_eq!(rec_tree.lfd_estimator(), LfdEstimator::HalfRadius);
+
+    // The children use the same estimator as the root.
+    let default_tree = Tree::<_, _, _, UniBall<_>>::new(utils::gen_dataset(1000, 10, 42, utils::euclidean), Some(42))
+        .partition(&criteria, Some(42));
+    let lfds = |tree: &Tree<_, _, _, UniBall<_>>| tree.root().subtree().iter().map(|c| c.lfd()).collect::<Vec<_>>();
+    assert_ne!(lfds(&raw_tree), lfds(&default_tree));
+}
+
+/// The mean relative error of the local fractal dimension of the roots of
+/// trees built on samples of `cardinality` points from a `dim`-ball, over ten
+/// seeds.
+fn lfd_error(estimator: LfdEstimator, dim: usize, cardinality: usize) -> f64 {
+    let num_seeds = 10;
+    let total = (0..num_seeds)
+        .map(|seed| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+            let data = (0..cardinality)
+                .map(|_| symagen::random_data::n_ball(dim, 1., &mut rng))
+                .collect::<Vec<_>>();
+            let data = VecDataset::new("n-ball".to_string(), data, utils::euclidean::<f64, f64>, false);
+            let tree = Tree::<_, _, _, UniBall<_>>::new_with_lfd(data, Some(seed), estimator);
+            (tree.root().lfd() - dim.as_f64()).abs() / dim.as_f64()
+        })
+        .sum::<f64>();
+    total / num_seeds.as_f64()
+}
+
+#[test]
+fn lfd_estimators() {
+    // The center of the root is an instance near, but not at, the center of
+    // the ball, so every estimator underestimates the dimension, and more so
+    // in higher dimensions.
+    let estimators = [
+        LfdEstimator::HalfRadius,
+        LfdEstimator::MultiScale { num_scales: 8 },
+        LfdEstimator::MaximumLikelihood { k: 50 },
+    ];
+    for (dim, max_errors) in [(2, [0.25, 0.1, 0.2]), (4, [0.5, 0.2, 0.15]), (8, [0.6, 0.35, 0.2])] {
+        let errors = estimators.map(|e| lfd_error(e, dim, 1_000));
+        for ((estimator, error), max_error) in estimators.iter().zip(errors).zip(max_errors) {
+            assert!(
+                error <= max_error,
+                "{estimator:?} has a relative error of {error:.3} in {dim} dimensions."
+            );
+        }
+        // The new estimators improve on the default.
+        assert!(errors[1] < errors[0] && errors[2] < errors[0], "{errors:?}");
+    }
+}
@@ -41,6 +41,12 @@ pub fn needleman_wunsch<T: UInt>(x: &String, y: &String) -> T {
     distances::strings::needleman_wunsch::nw_distance(x, y)
 }
 
+/// Lower bound on the edit distance between two Strings.
+#[allow(clippy::ptr_arg)]
+pub fn char_histogram<T: UInt>(x: &String, y: &String) -> T {
+    distances::strings::lower_bounds::char_histogram(x, y)
+}
+
 /// Generate a dataset with the given cardinality and dimensionality.
 pub fn gen_dataset(
     cardinality: usize,
@@ -0,0 +1,555 @@
+//! The implementations of the subcommands.
+//!
+//! Each subcommand reads the manifest, or the metric name, to decide the kind
+//! of instances and then calls a function which is generic over the instances
+//! and distance values.
+
+use core::cmp::Ordering;
+use std::{
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use abd_clam::{
+    cakes::{knn, rnn},
+    pancakes::{decode_general, encode_general, CodecBuilder, CodecData, LeafFormat, SquishyBall},
+    Cakes, Cluster, Dataset, Instance, PartitionCriteria, Tree, UniBall, VecDataset,
+};
+use distances::Number;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::{
+    files,
+    manifest::Manifest,
+    metrics::{self, Kind, StringMetric},
+    BuildArgs, CompressArgs, DecompressArgs, InfoArgs, OutputFormat, SearchArgs, TreeArgs,
+    TuneArgs, VerifyArgs,
+};
+
+/// The dataset of an index built by the tool, whose metadata are the ids of
+/// the instances.
+pub type Data<I, U> = VecDataset<I, U, String>;
+
+/// Hits from a search, as the index and distance of each hit.
+pub type Hits<U> = Vec<Vec<(usize, U)>>;
+
+/// The kind of search to run.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    /// KNN search for this `k`.
+    Knn(usize),
+    /// RNN search with this radius.
+    Rnn(f64),
+}
+
+/// Builds a CAKES index from a dataset and saves it.
+pub fn build(args: &BuildArgs) -> Result<(), String> {
+    let manifest = Manifest::new(&args.metric, false)?;
+    check_kind(&args.input, manifest.kind, &args.metric)?;
+    let name = dataset_name(&args.input);
+
+    check_output(&args.output, args.force)?;
+    std::fs::create_dir_all(&args.output).map_err(|e| e.to_string())?;
+    match manifest.kind {
+        Kind::Vectors => {
+            let (data, ids) = files::read_vectors(&args.input, args.header)?;
+            let (metric, is_expensive) = metrics::vector_metric(&args.metric)?;
+            let data = VecDataset::new(name, data, metric, is_expensive).assign_metadata(ids)?;
+            build_cakes(data, args)?.save(&args.output)?;
+        }
+        Kind::Strings => {
+            let (data, ids) = files::read_fasta(&args.input)?;
+            let (metric, is_expensive) = metrics::string_metric(&args.metric)?;
+            let data = VecDataset::new(name, data, metric, is_expensive).assign_metadata(ids)?;
+            build_cakes(data, args)?.save(&args.output)?;
+        }
+    }
+    manifest.save(&args.output)
+}
+
+/// Runs KNN or RNN search on an index and writes the hits.
+pub fn search(args: &SearchArgs) -> Result<(), String> {
+    let manifest = load_manifest(&args.index, false)?;
+    check_kind(&args.queries, manifest.kind, &manifest.metric)?;
+    let mode = match (args.k, args.radius) {
+        (Some(k), _) => Mode::Knn(k),
+        (None, Some(radius)) => Mode::Rnn(radius),
+        (None, None) => return Err("One of `--k` or `--radius` is required.".to_string()),
+    };
+    let algorithm = args.algorithm.as_deref();
+
+    match manifest.kind {
+        Kind::Vectors => {
+            let (queries, query_ids) = files::read_vectors(&args.queries, args.header)?;
+            let (metric, is_expensive) = metrics::vector_metric(&manifest.metric)?;
+            let cakes = Cakes::<_, _, Data<_, _>>::load(&args.index, metric, is_expensive)?;
+            let hits = search_cakes(&cakes, &queries, mode, algorithm)?;
+            write_hits(&ids_of(&cakes), &query_ids, &hits, args)
+        }
+        Kind::Strings => {
+            let (queries, query_ids) = files::read_fasta(&args.queries)?;
+            let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+            let cakes = Cakes::<_, _, Data<_, _>>::load(&args.index, metric, is_expensive)?;
+            let hits = search_cakes(&cakes, &queries, mode, algorithm)?;
+            write_hits(&ids_of(&cakes), &query_ids, &hits, args)
+        }
+    }
+}
+
+/// Tunes the search algorithms of an index and saves it again.
+pub fn tune(args: &TuneArgs) -> Result<(), String> {
+    let manifest = load_manifest(&args.index, false)?;
+
+    match manifest.kind {
+        Kind::Vectors => {
+            let (metric, is_expensive) = metrics::vector_metric(&manifest.metric)?;
+            let cakes = Cakes::<_, _, Data<_, _>>::load(&args.index, metric, is_expensive)?;
+            tune_cakes(cakes, args)
+        }
+        Kind::Strings => {
+            let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+            let cakes = Cakes::<_, _, Data<_, _>>::load(&args.index, metric, is_expensive)?;
+            tune_cakes(cakes, args)
+        }
+    }
+}
+
+/// Prints statistics about an index.
+pub fn info(args: &InfoArgs) -> Result<(), String> {
+    let manifest = Manifest::load(&args.index)?;
+    println!("kind: {:?}", manifest.kind);
+    println!("metric: {}", manifest.metric);
+
+    if manifest.compressed {
+        let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+        let codec = load_codec(&args.index, metric, is_expensive)?;
+        return print_codec_info(&codec);
+    }
+
+    match manifest.kind {
+        Kind::Vectors => {
+            let (metric, is_expensive) = metrics::vector_metric(&manifest.metric)?;
+            print_cakes_info(&Cakes::<_, _, Data<_, _>>::load(
+                &args.index,
+                metric,
+                is_expensive,
+            )?);
+        }
+        Kind::Strings => {
+            let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+            print_cakes_info(&Cakes::<_, _, Data<_, _>>::load(
+                &args.index,
+                metric,
+                is_expensive,
+            )?);
+        }
+    }
+    Ok(())
+}
+
+/// Compresses a FASTA file with PANCAKES and saves the compressed dataset.
+pub fn compress(args: &CompressArgs) -> Result<(), String> {
+    let manifest = Manifest::new(&args.metric, true)?;
+    if manifest.kind != Kind::Strings {
+        return Err(format!(
+            "Compression needs a metric for strings, not '{}'.",
+            args.metric
+        ));
+    }
+    check_kind(&args.input, manifest.kind, &args.metric)?;
+    check_output(&args.output, args.force)?;
+    let leaf_format = LeafFormat::from_name(&args.leaf_format)?;
+
+    let (data, ids) = files::read_fasta(&args.input)?;
+    let (metric, is_expensive) = metrics::string_metric(&args.metric)?;
+    let data = VecDataset::new(dataset_name(&args.input), data, metric, is_expensive)
+        .assign_metadata(ids)?;
+    let tree = Tree::<_, _, _, UniBall<_>>::new(data, Some(args.tree.seed))
+        .partition(&criteria(&args.tree), Some(args.tree.seed));
+
+    let data = tree.data();
+    let root = SquishyBall::from_base_tree(tree.root().clone(), data);
+    let codec = CodecBuilder::new(
+        encode_general::<u32>,
+        decode_general,
+        data.metadata().to_vec(),
+    )
+    .with_leaf_format(leaf_format)
+    .build(root, data)?;
+
+    // `CodecData::save` needs an existing parent directory, and replaces the
+    // output directory, so the manifest is saved after it.
+    let output = Path::new(".").join(&args.output);
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    codec.save(&output)?;
+    manifest.save(&output)
+}
+
+/// Decompresses a PANCAKES dataset into a FASTA file, in the original order.
+pub fn decompress(args: &DecompressArgs) -> Result<(), String> {
+    let manifest = load_manifest(&args.index, true)?;
+    let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+    let codec = load_codec(&args.index, metric, is_expensive)?;
+
+    let permutation = codec.permuted_indices();
+    let mut sequences = vec![String::new(); permutation.len()];
+    for leaf in codec.root().compressible_leaves() {
+        let instances = codec.load_leaf_data(leaf)?;
+        for (i, instance) in codec.leaf_indices(leaf).into_iter().zip(instances) {
+            sequences[permutation[i]] = instance;
+        }
+    }
+    let mut ids = vec![String::new(); permutation.len()];
+    for (&original, id) in permutation.iter().zip(codec.metadata()) {
+        ids[original].clone_from(id);
+    }
+
+    files::write_fasta(&args.output, &sequences, &ids)
+}
+
+/// Checks that every sequence in a PANCAKES dataset decompresses exactly to
+/// the same sequence in a FASTA file, and prints any mismatches.
+pub fn verify(args: &VerifyArgs) -> Result<(), String> {
+    let manifest = load_manifest(&args.index, true)?;
+    let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+    let codec = load_codec(&args.index, metric, is_expensive)?;
+
+    let (data, _) = files::read_fasta(&args.input)?;
+    let data = VecDataset::new(dataset_name(&args.input), data, metric, is_expensive);
+    let report = codec.verify(&data)?;
+    for mismatch in &report.mismatches {
+        println!("mismatch at {}: {}", mismatch.index, mismatch.reason);
+    }
+    println!("checked: {}", report.num_checked);
+    println!("mismatches: {}", report.mismatches.len());
+
+    if report.is_exact() {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} of {} sequences did not decompress exactly.",
+            report.mismatches.len(),
+            report.num_checked
+        ))
+    }
+}
+
+/// Returns an error if the file does not hold instances of the given kind.
+fn check_kind(path: &Path, kind: Kind, metric: &str) -> Result<(), String> {
+    let file_kind = files::kind_of(path)?;
+    if file_kind == kind {
+        Ok(())
+    } else {
+        Err(format!(
+            "The metric '{metric}' is for {kind:?} but '{}' holds {file_kind:?}.",
+            path.display()
+        ))
+    }
+}
+
+/// Returns an error if the output directory holds files other than an index
+/// saved by the tool, unless `force` is set, since saving an index there may
+/// replace them.
+fn check_output(dir: &Path, force: bool) -> Result<(), String> {
+    let is_empty = std::fs::read_dir(dir).map_or(true, |mut entries| entries.next().is_none());
+    if force || is_empty || Manifest::exists_in(dir) {
+        Ok(())
+    } else {
+        Err(format!(
+            "'{}' is not empty and does not hold an index saved by `clam`. Use `--force` to replace it.",
+            dir.display()
+        ))
+    }
+}
+
+/// Loads the manifest of an index, checking whether it is compressed.
+pub fn load_manifest(dir: &Path, compressed: bool) -> Result<Manifest, String> {
+    let manifest = Manifest::load(dir)?;
+    match (compressed, manifest.compressed) {
+        (true, false) => Err(format!(
+            "'{}' is a CAKES index, not a compressed dataset.",
+            dir.display()
+        )),
+        (false, true) => Err(format!(
+            "'{}' is a compressed dataset, not a CAKES index.",
+            dir.display()
+        )),
+        _ => Ok(manifest),
+    }
+}
+
+/// Returns the name of the dataset in a file.
+fn dataset_name(path: &Path) -> String {
+    path.file_stem().map_or_else(
+        || "dataset".to_string(),
+        |s| s.to_string_lossy().to_string(),
+    )
+}
+
+/// Returns the partition criteria given on the command line.
+fn criteria<U: Number>(args: &TreeArgs) -> PartitionCriteria<U> {
+    let mut criteria = PartitionCriteria::default();
+    if let Some(depth) = args.max_depth {
+        criteria = criteria.with_max_depth(depth);
+    }
+    if let Some(cardinality) = args.min_cardinality {
+        criteria = criteria.with_min_cardinality(cardinality);
+    }
+    criteria
+}
+
+/// Builds CAKES on a dataset, with random shards if a maximum shard
+/// cardinality was given.
+///
+/// The instances are shuffled with the seed of the tree before sharding,
+/// because `make_shards` splits the dataset into contiguous blocks.
+fn build_cakes<I: Instance, U: Number>(
+    mut data: Data<I, U>,
+    args: &BuildArgs,
+) -> Result<Cakes<I, U, Data<I, U>>, String> {
+    let criteria = criteria(&args.tree);
+    let seed = Some(args.tree.seed);
+    let cakes = match args.max_shard_cardinality {
+        Some(max_cardinality) if max_cardinality < data.cardinality() => {
+            let mut permutation = (0..data.cardinality()).collect::<Vec<_>>();
+            permutation.shuffle(&mut StdRng::seed_from_u64(args.tree.seed));
+            data.permute_instances(&permutation)?;
+            Cakes::new_randomly_sharded(data.make_shards(max_cardinality), seed, &criteria)
+        }
+        _ => Cakes::new(data, seed, &criteria),
+    };
+    Ok(cakes)
+}
+
+/// Returns the ids of the instances in CAKES, by the indices used in hits.
+pub fn ids_of<I: Instance, U: Number>(cakes: &Cakes<I, U, Data<I, U>>) -> Vec<&String> {
+    cakes
+        .shards()
+        .into_iter()
+        .flat_map(VecDataset::metadata)
+        .collect()
+}
+
+/// Runs the search for every query, with the named algorithm or with the
+/// tuned algorithm if none is named.
+///
+/// The hits for each query are sorted by increasing distance.
+pub fn search_cakes<I: Instance, U: Number>(
+    cakes: &Cakes<I, U, Data<I, U>>,
+    queries: &[I],
+    mode: Mode,
+    algorithm: Option<&str>,
+) -> Result<Hits<U>, String> {
+    let queries = queries.iter().collect::<Vec<_>>();
+    let hits = match mode {
+        // Asking for more neighbors than there are instances gets all of them.
+        Mode::Knn(k) => match (algorithm, k.min(cakes.total_cardinality())) {
+            (Some(name), k) => {
+                cakes.batch_knn_search(&queries, k, knn::Algorithm::from_name(name)?)
+            }
+            (None, k) => cakes.batch_tuned_knn_search(&queries, k),
+        },
+        Mode::Rnn(radius) => {
+            let radius = whole_radius::<U>(radius)?;
+            match algorithm {
+                Some(name) => {
+                    cakes.batch_rnn_search(&queries, radius, rnn::Algorithm::from_name(name)?)
+                }
+                None => cakes.batch_tuned_rnn_search(&queries, radius),
+            }
+        }
+    };
+
+    Ok(hits
+        .into_iter()
+        .map(|mut hits| {
+            hits.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
+            hits
+        })
+        .collect())
+}
+
+/// Writes the hits for every query in the requested format.
+fn write_hits<U: Number>(
+    ids: &[&String],
+    query_ids: &[String],
+    hits: &Hits<U>,
+    args: &SearchArgs,
+) -> Result<(), String> {
+    let handle: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(std::fs::File::create(path).map_err(|e| e.to_string())?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut handle = BufWriter::new(handle);
+
+    match args.format {
+        OutputFormat::Csv => {
+            writeln!(handle, "query,index,id,distance").map_err(|e| e.to_string())?;
+            for (query, hits) in query_ids.iter().zip(hits) {
+                for &(i, d) in hits {
+                    writeln!(handle, "{},{i},{},{d}", csv_field(query), csv_field(ids[i]))
+                        .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let results = query_ids
+                .iter()
+                .zip(hits)
+                .map(|(query, hits)| serde_json::json!({ "query": query, "hits": hits_to_json(ids, hits) }))
+                .collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut handle, &results).map_err(|e| e.to_string())?;
+            writeln!(handle).map_err(|e| e.to_string())?;
+        }
+    }
+
+    handle.flush().map_err(|e| e.to_string())
+}
+
+/// Converts a radius to the type of the distance values.
+///
+/// # Errors
+///
+/// * If the distance values are integers and the radius is not a whole number,
+///   which would otherwise be truncated.
+fn whole_radius<U: Number>(radius: f64) -> Result<U, String> {
+    // Only integer types truncate a half to zero.
+    let is_integer = U::from(0.5) == U::zero();
+    if is_integer && radius.fract() > 0.0 {
+        Err(format!(
+            "The radius {radius} is not a whole number, but the metric has integer distances."
+        ))
+    } else {
+        Ok(U::from(radius))
+    }
+}
+
+/// Converts the hits for a query to JSON objects with the index, id and
+/// distance of each hit.
+pub fn hits_to_json<S: AsRef<str>, U: Number>(
+    ids: &[S],
+    hits: &[(usize, U)],
+) -> Vec<serde_json::Value> {
+    hits.iter()
+        .map(|&(i, d)| serde_json::json!({ "index": i, "id": ids[i].as_ref(), "distance": d.as_f64() }))
+        .collect()
+}
+
+/// Quotes a CSV field if it contains a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Tunes CAKES for every `k` and radius given on the command line.
+fn tune_cakes<I: Instance, U: Number>(
+    mut cakes: Cakes<I, U, Data<I, U>>,
+    args: &TuneArgs,
+) -> Result<(), String> {
+    for &k in &args.k {
+        cakes.auto_tune_knn(k, args.depth);
+        println!(
+            "knn k={k}: {}",
+            cakes.search().tuned_knn_algorithm_for(k).name()
+        );
+    }
+    for &radius in &args.radius {
+        let radius = whole_radius::<U>(radius)?;
+        cakes.auto_tune_rnn(radius, args.depth);
+        println!(
+            "rnn radius={radius}: {}",
+            cakes.search().tuned_rnn_algorithm_for(radius).name()
+        );
+    }
+    cakes.save(&args.index)
+}
+
+/// Prints statistics about CAKES and each of its trees.
+fn print_cakes_info<I: Instance, U: Number>(cakes: &Cakes<I, U, Data<I, U>>) {
+    println!("cardinality: {}", cakes.total_cardinality());
+    println!("shards: {}", cakes.num_shards());
+    for (i, tree) in cakes.trees().into_iter().enumerate() {
+        println!("shard {i}:");
+        print_tree_info(tree);
+    }
+    println!(
+        "tuned knn algorithm: {}",
+        cakes.tuned_knn_algorithm().name()
+    );
+    println!(
+        "tuned rnn algorithm: {}",
+        cakes.tuned_rnn_algorithm().name()
+    );
+}
+
+/// Prints statistics about a tree.
+fn print_tree_info<I: Instance, U: Number>(tree: &Tree<I, U, Data<I, U>, UniBall<U>>) {
+    let clusters = tree.root().subtree();
+    let leaves = clusters.iter().filter(|c| c.is_leaf()).collect::<Vec<_>>();
+    let mean_leaf_cardinality = tree.cardinality().as_f64() / leaves.len().as_f64();
+    let max_leaf_depth = leaves.iter().map(|c| c.depth()).max().unwrap_or_default();
+    let mean_lfd = clusters.iter().map(|c| c.lfd()).sum::<f64>() / clusters.len().as_f64();
+
+    println!("  cardinality: {}", tree.cardinality());
+    println!("  depth: {max_leaf_depth}");
+    println!("  clusters: {}", clusters.len());
+    println!("  leaves: {}", leaves.len());
+    println!("  mean leaf cardinality: {mean_leaf_cardinality:.2}");
+    println!("  root radius: {}", tree.radius());
+    println!("  root lfd: {:.2}", tree.root().lfd());
+    println!("  mean lfd: {mean_lfd:.2}");
+}
+
+/// Loads a compressed dataset saved by the tool.
+fn load_codec(
+    dir: &Path,
+    metric: StringMetric,
+    is_expensive: bool,
+) -> Result<CodecData<String, u32, String>, String> {
+    CodecData::load(
+        dir,
+        metric,
+        is_expensive,
+        encode_general::<u32>,
+        decode_general,
+    )
+}
+
+/// Prints statistics about a compressed dataset.
+fn print_codec_info(codec: &CodecData<String, u32, String>) -> Result<(), String> {
+    let root = codec.root();
+    let stats = codec.stats();
+    println!("cardinality: {}", stats.cardinality);
+    println!("appended: {}", stats.num_appended);
+    println!("leaf format: {}", codec.leaf_format().name());
+    println!(
+        "compressible clusters: {}",
+        root.compressible_subtree().len()
+    );
+    println!("squished clusters: {}", stats.num_squished);
+    println!(
+        "squished depths: mean {:.2}, max {}",
+        stats.mean_leaf_depth(),
+        stats.max_leaf_depth()
+    );
+    println!("centers: {}", stats.num_centers);
+    println!("recursive cost: {}", root.recursive_cost());
+    println!("unitary cost: {}", root.unitary_cost());
+    match stats.raw_bytes {
+        Some(raw_bytes) => println!("raw bytes: {raw_bytes}"),
+        None => println!("raw bytes: unknown"),
+    }
+    println!("center bytes: {}", stats.center_bytes);
+    println!("leaf bytes: {}", stats.leaf_bytes);
+    println!("overflow bytes: {}", stats.overflow_bytes);
+    match stats.compression_factor() {
+        Some(factor) => println!("compression factor: {factor:.4}"),
+        None => println!("compression factor: unknown"),
+    }
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! Reading datasets and queries from, and writing them to, files.
+//!
+//! Vectors are read from npy files, holding a 2-d array of `f32` or `f64` in
+//! C order, or from CSV files with one vector per line and, if the caller says
+//! so, a header line.
+//! Strings are read from FASTA files. Every instance is given an id, which is
+//! the header of its FASTA record or its row number in an npy or CSV file.
+
+use std::{
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use crate::metrics::Kind;
+
+/// Vectors read from a file, and their ids.
+pub type Vectors = (Vec<Vec<f32>>, Vec<String>);
+
+/// Strings read from a file, and their ids.
+pub type Strings = (Vec<String>, Vec<String>);
+
+/// Returns the kind of instances stored in the file, based on its extension.
+///
+/// # Errors
+///
+/// * If the extension is not one of the supported formats.
+pub fn kind_of(path: &Path) -> Result<Kind, String> {
+    match extension(path).as_str() {
+        "npy" | "csv" => Ok(Kind::Vectors),
+        "fasta" | "fa" | "fna" | "faa" => Ok(Kind::Strings),
+        ext => Err(format!(
+            "Unsupported file extension '{ext}' for '{}'. Expected npy, csv or fasta.",
+            path.display()
+        )),
+    }
+}
+
+/// Reads vectors from an npy or CSV file.
+///
+/// The first line of a CSV file is skipped if `header` is set.
+///
+/// # Errors
+///
+/// * If the file cannot be read.
+/// * If the file is not a valid npy or CSV file of vectors.
+pub fn read_vectors(path: &Path, header: bool) -> Result<Vectors, String> {
+    let vectors = match extension(path).as_str() {
+        "npy" => read_npy(path)?,
+        "csv" => read_csv(path, header)?,
+        ext => return Err(format!("Cannot read vectors from a '{ext}' file.")),
+    };
+    if let Some(first) = vectors.first() {
+        if let Some(i) = vectors.iter().position(|v| v.len() != first.len()) {
+            return Err(format!(
+                "Row {i} of '{}' has {} values but row 0 has {}.",
+                path.display(),
+                vectors[i].len(),
+                first.len()
+            ));
+        }
+    }
+    let ids = (0..vectors.len()).map(|i| i.to_string()).collect();
+    Ok((vectors, ids))
+}
+
+/// Reads strings, and their headers as ids, from a FASTA file.
+///
+/// # Errors
+///
+/// * If the file cannot be read.
+/// * If there are sequence lines before the first header.
+pub fn read_fasta(path: &Path) -> Result<Strings, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Could not open '{}': {e}", path.display()))?;
+
+    let (mut sequences, mut ids) = (Vec::new(), Vec::<String>::new());
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        let line = line.trim_end();
+        if let Some(header) = line.strip_prefix('>') {
+            ids.push(header.trim().to_string());
+            sequences.push(String::new());
+        } else if !line.is_empty() {
+            let sequence = sequences.last_mut().ok_or_else(|| {
+                format!(
+                    "'{}' has a sequence before its first header.",
+                    path.display()
+                )
+            })?;
+            sequence.push_str(line);
+        }
+    }
+
+    Ok((sequences, ids))
+}
+
+/// Writes strings, with their ids as headers, to a FASTA file.
+///
+/// # Errors
+///
+/// * If the file cannot be written.
+pub fn write_fasta(path: &Path, sequences: &[String], ids: &[String]) -> Result<(), String> {
+    let file = std::fs::File::create(path)
+        .map_err(|e| format!("Could not create '{}': {e}", path.display()))?;
+    let mut handle = BufWriter::new(file);
+    for (sequence, id) in sequences.iter().zip(ids) {
+        writeln!(handle, ">{id}\n{sequence}").map_err(|e| e.to_string())?;
+    }
+    handle.flush().map_err(|e| e.to_string())
+}
+
+/// Returns the lowercase extension of the file.
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase)
+        .unwrap_or_default()
+}
+
+/// Reads the rows of a CSV file of numbers, skipping the first line if it is
+/// a `header`.
+fn read_csv(path: &Path, header: bool) -> Result<Vec<Vec<f32>>, String> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| format!("Could not open '{}': {e}", path.display()))?;
+
+    let mut rows = Vec::new();
+    for (i, line) in BufReader::new(file)
+        .lines()
+        .enumerate()
+        .skip(usize::from(header))
+    {
+        let line = line.map_err(|e| e.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row = line
+            .split(',')
+            .map(|v| v.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| {
+                let hint = if i == 0 {
+                    " Use `--header` if it is a header."
+                } else {
+                    ""
+                };
+                format!("Line {} of '{}': {e}.{hint}", i + 1, path.display())
+            })?;
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// Reads a 2-d array of `f32` or `f64` from an npy file.
+#[allow(clippy::cast_possible_truncation)]
+fn read_npy(path: &Path) -> Result<Vec<Vec<f32>>, String> {
+    let bytes =
+        std::fs::read(path).map_err(|e| format!("Could not read '{}': {e}", path.display()))?;
+
+    if bytes.len() < 10 || &bytes[..6] != b"\x93NUMPY" {
+        return Err(format!("'{}' is not an npy file.", path.display()));
+    }
+
+    // The length of the header is stored in 2 bytes in version 1 and in 4
+    // bytes in later versions.
+    let (header_len, header_start) = if bytes[6] == 1 {
+        (usize::from(u16::from_le_bytes([bytes[8], bytes[9]])), 10)
+    } else {
+        let len = bytes
+            .get(8..12)
+            .ok_or("The npy header is truncated.")?
+            .try_into()
+            .map_err(|_| "The npy header is truncated.")?;
+        (u32::from_le_bytes(len) as usize, 12)
+    };
+    let header = bytes
+        .get(header_start..header_start + header_len)
+        .ok_or("The npy header is truncated.")?;
+    let header = std::str::from_utf8(header).map_err(|e| e.to_string())?;
+
+    if header.contains("'fortran_order': True") {
+        return Err("Arrays in Fortran order are not supported.".to_string());
+    }
+    let width = if header.contains("'<f4'") {
+        4
+    } else if header.contains("'<f8'") {
+        8
+    } else {
+        return Err(format!("Unsupported npy dtype in header: {header}"));
+    };
+    let [rows, cols] = npy_shape(header)?;
+
+    let data = &bytes[header_start + header_len..];
+    let num_bytes = rows
+        .checked_mul(cols)
+        .and_then(|n| n.checked_mul(width))
+        .ok_or_else(|| format!("The npy shape ({rows}, {cols}) is too large."))?;
+    if data.len() != num_bytes {
+        return Err(format!(
+            "Expected {num_bytes} bytes of data for a {rows}x{cols} array, found {}.",
+            data.len()
+        ));
+    }
+
+    let values = data.chunks_exact(width).map(|b| {
+        if width == 4 {
+            f32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32
+        }
+    });
+    let values = values.collect::<Vec<_>>();
+
+    Ok(if cols == 0 {
+        vec![Vec::new(); rows]
+    } else {
+        values.chunks_exact(cols).map(<[f32]>::to_vec).collect()
+    })
+}
+
+/// Parses the shape of a 2-d array from an npy header.
+fn npy_shape(header: &str) -> Result<[usize; 2], String> {
+    let shape = header
+        .split("'shape':")
+        .nth(1)
+        .and_then(|s| s.split(')').next())
+        .map(|s| s.trim().trim_start_matches('('))
+        .ok_or_else(|| format!("Could not find the shape in the npy header: {header}"))?;
+
+    let dims = shape
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse::<usize>().map_err(|e| e.to_string()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match dims.as_slice() {
+        &[rows, cols] => Ok([rows, cols]),
+        _ => Err(format!("Expected a 2-d array, found shape ({shape}).")),
+    }
+}
@@ -0,0 +1,215 @@
+//! `clam`: a command-line tool for building, searching and compressing CLAM
+//! indices.
+//!
+//! Indices are saved with `Cakes::save` and `CodecData::save`, along with a
+//! small JSON manifest naming the metric, so that they can be loaded again by
+//! the tool or by the library.
+
+mod commands;
+mod files;
+mod manifest;
+mod metrics;
+mod serve;
+
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+
+/// Build, search and compress CLAM indices.
+#[derive(Parser, Debug)]
+#[command(name = "clam", version, about)]
+struct Cli {
+    /// The command to run.
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// The subcommands of the tool.
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Build a CAKES index from a dataset and save it.
+    Build(BuildArgs),
+    /// Run KNN or RNN search on an index for the queries in a file.
+    Search(SearchArgs),
+    /// Tune the search algorithms of an index and save it again.
+    Tune(TuneArgs),
+    /// Print statistics about an index.
+    Info(InfoArgs),
+    /// Compress a FASTA file with PANCAKES.
+    Compress(CompressArgs),
+    /// Decompress a PANCAKES dataset back into a FASTA file.
+    Decompress(DecompressArgs),
+    /// Check that a PANCAKES dataset decompresses exactly to a FASTA file.
+    Verify(VerifyArgs),
+    /// Serve KNN and RNN search on an index over local HTTP.
+    Serve(ServeArgs),
+}
+
+/// The criteria for partitioning the tree.
+#[derive(Args, Debug)]
+pub struct TreeArgs {
+    /// The seed for the random number generator.
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+    /// The maximum depth of the tree.
+    #[arg(long)]
+    pub max_depth: Option<usize>,
+    /// The minimum cardinality of a cluster for it to be partitioned.
+    #[arg(long)]
+    pub min_cardinality: Option<usize>,
+}
+
+/// The arguments for `clam build`.
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// The dataset, as an npy, CSV or FASTA file.
+    pub input: PathBuf,
+    /// The directory in which to save the index.
+    pub output: PathBuf,
+    /// The name of the metric, e.g. euclidean, cosine or levenshtein.
+    #[arg(long)]
+    pub metric: String,
+    /// Randomly shard the dataset into shards of at most this cardinality.
+    #[arg(long)]
+    pub max_shard_cardinality: Option<usize>,
+    /// Skip the first line of a CSV file, which holds the column names.
+    #[arg(long)]
+    pub header: bool,
+    /// Save the index even if the output directory holds other files.
+    #[arg(long)]
+    pub force: bool,
+    /// The criteria for partitioning the tree.
+    #[command(flatten)]
+    pub tree: TreeArgs,
+}
+
+/// The format of search results.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One line per hit, with the columns `query,index,id,distance`.
+    Csv,
+    /// An array with one object per query, holding its hits.
+    Json,
+}
+
+/// The arguments for `clam search`.
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("mode").required(true).args(["k", "radius"])))]
+pub struct SearchArgs {
+    /// The directory of the index.
+    pub index: PathBuf,
+    /// The queries, in a file of the same kind as the dataset.
+    pub queries: PathBuf,
+    /// Find the `k` nearest neighbors of each query.
+    #[arg(long)]
+    pub k: Option<usize>,
+    /// Find the neighbors within this radius of each query.
+    #[arg(long)]
+    pub radius: Option<f64>,
+    /// The name of the search algorithm. The tuned algorithm is used by default.
+    #[arg(long)]
+    pub algorithm: Option<String>,
+    /// The format of the results.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Csv)]
+    pub format: OutputFormat,
+    /// The file to write the results to, instead of stdout.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Skip the first line of a CSV file, which holds the column names.
+    #[arg(long)]
+    pub header: bool,
+}
+
+/// The arguments for `clam tune`.
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("mode").required(true).multiple(true).args(["k", "radius"])))]
+pub struct TuneArgs {
+    /// The directory of the index.
+    pub index: PathBuf,
+    /// Tune KNN search for these values of `k`.
+    #[arg(long, num_args = 1..)]
+    pub k: Vec<usize>,
+    /// Tune RNN search for these radii.
+    #[arg(long, num_args = 1..)]
+    pub radius: Vec<f64>,
+    /// The depth of the clusters whose centers are used as tuning queries.
+    #[arg(long, default_value_t = 10)]
+    pub depth: usize,
+}
+
+/// The arguments for `clam info`.
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// The directory of the index.
+    pub index: PathBuf,
+}
+
+/// The arguments for `clam compress`.
+#[derive(Args, Debug)]
+pub struct CompressArgs {
+    /// The FASTA file to compress.
+    pub input: PathBuf,
+    /// The directory in which to save the compressed dataset.
+    pub output: PathBuf,
+    /// The name of the metric for strings.
+    #[arg(long, default_value = "levenshtein")]
+    pub metric: String,
+    /// The layout of the leaf data: fixed, varint, deflate or deflate-<level>
+    /// with a level of at most 10.
+    #[arg(long, default_value = "fixed")]
+    pub leaf_format: String,
+    /// Replace the output directory even if it holds other files.
+    #[arg(long)]
+    pub force: bool,
+    /// The criteria for partitioning the tree.
+    #[command(flatten)]
+    pub tree: TreeArgs,
+}
+
+/// The arguments for `clam decompress`.
+#[derive(Args, Debug)]
+pub struct DecompressArgs {
+    /// The directory of the compressed dataset.
+    pub index: PathBuf,
+    /// The FASTA file to write the decompressed dataset to.
+    pub output: PathBuf,
+}
+
+/// The arguments for `clam verify`.
+#[derive(Args, Debug)]
+pub struct VerifyArgs {
+    /// The directory of the compressed dataset.
+    pub index: PathBuf,
+    /// The FASTA file from which the dataset was compressed.
+    pub input: PathBuf,
+}
+
+/// The arguments for `clam serve`.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// The directory of the index.
+    pub index: PathBuf,
+    /// The address to listen on. Use port 0 to let the OS choose a port.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub addr: String,
+    /// The number of threads handling requests. Defaults to the number of CPUs.
+    #[arg(long)]
+    pub workers: Option<usize>,
+    /// The directory under which `/reload` may load indices. Defaults to the
+    /// directory of the index, so that only that index may be reloaded.
+    #[arg(long)]
+    pub reload_root: Option<PathBuf>,
+}
+
+fn main() -> Result<(), String> {
+    match Cli::parse().command {
+        Command::Build(args) => commands::build(&args),
+        Command::Search(args) => commands::search(&args),
+        Command::Tune(args) => commands::tune(&args),
+        Command::Info(args) => commands::info(&args),
+        Command::Compress(args) => commands::compress(&args),
+        Command::Decompress(args) => commands::decompress(&args),
+        Command::Verify(args) => commands::verify(&args),
+        Command::Serve(args) => serve::serve(&args),
+    }
+}
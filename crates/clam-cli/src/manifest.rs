@@ -0,0 +1,73 @@
+//! The manifest which records how to load an index saved by the tool.
+//!
+//! `Cakes` and `CodecData` are saved without their distance functions, so the
+//! name of the metric, and the kind of instances it is defined on, are saved
+//! alongside them in a small JSON file.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::{self, Kind};
+
+/// The name of the manifest file in an index directory.
+const FILE_NAME: &str = "clam.json";
+
+/// Describes an index saved by the tool.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// The kind of instances in the index.
+    pub kind: Kind,
+    /// The name of the metric used to build the index.
+    pub metric: String,
+    /// Whether the index is a compressed PANCAKES dataset rather than CAKES.
+    pub compressed: bool,
+}
+
+impl Manifest {
+    /// Creates a manifest for an index built with the named metric.
+    ///
+    /// # Errors
+    ///
+    /// * If there is no metric with the given name.
+    pub fn new(metric: &str, compressed: bool) -> Result<Self, String> {
+        Ok(Self {
+            kind: metrics::kind_of(metric)?,
+            metric: metric.to_string(),
+            compressed,
+        })
+    }
+
+    /// Saves the manifest in the index directory.
+    ///
+    /// # Errors
+    ///
+    /// * If the manifest cannot be written.
+    pub fn save(&self, dir: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(dir.join(FILE_NAME), json).map_err(|e| e.to_string())
+    }
+
+    /// Whether the directory holds a manifest, i.e. an index saved by the tool.
+    pub fn exists_in(dir: &Path) -> bool {
+        dir.join(FILE_NAME).is_file()
+    }
+
+    /// Loads the manifest from the index directory.
+    ///
+    /// # Errors
+    ///
+    /// * If the directory does not contain a manifest.
+    /// * If the manifest cannot be parsed.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(FILE_NAME);
+        let json = std::fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "Could not read '{}', was the index built with `clam`? {e}",
+                path.display()
+            )
+        })?;
+        serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid manifest '{}': {e}", path.display()))
+    }
+}
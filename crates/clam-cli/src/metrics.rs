@@ -0,0 +1,117 @@
+//! The distance functions which may be used with the tool, by name.
+
+use serde::{Deserialize, Serialize};
+
+/// The kind of instances in a dataset, which determines the metrics and the
+/// file formats which may be used with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Vectors of `f32`, read from npy or CSV files.
+    Vectors,
+    /// Strings, read from FASTA files.
+    Strings,
+}
+
+/// The names of the metrics for vectors.
+pub const VECTOR_METRICS: &[&str] = &["euclidean", "manhattan", "chebyshev", "cosine"];
+
+/// The names of the metrics for strings.
+pub const STRING_METRICS: &[&str] = &["levenshtein", "hamming", "needleman_wunsch"];
+
+/// A distance function between vectors.
+pub type VectorMetric = fn(&Vec<f32>, &Vec<f32>) -> f32;
+
+/// A distance function between strings.
+pub type StringMetric = fn(&String, &String) -> u32;
+
+/// Returns the kind of instances on which the named metric is defined.
+///
+/// # Errors
+///
+/// * If there is no metric with the given name.
+pub fn kind_of(name: &str) -> Result<Kind, String> {
+    if VECTOR_METRICS.contains(&name) {
+        Ok(Kind::Vectors)
+    } else if STRING_METRICS.contains(&name) {
+        Ok(Kind::Strings)
+    } else {
+        Err(format!(
+            "Unknown metric '{name}'. Expected one of {} for vectors or {} for strings.",
+            VECTOR_METRICS.join(", "),
+            STRING_METRICS.join(", ")
+        ))
+    }
+}
+
+/// Returns the named metric for vectors and whether it is expensive to compute.
+///
+/// # Errors
+///
+/// * If there is no metric for vectors with the given name.
+pub fn vector_metric(name: &str) -> Result<(VectorMetric, bool), String> {
+    let metric: VectorMetric = match name {
+        "euclidean" => euclidean,
+        "manhattan" => manhattan,
+        "chebyshev" => chebyshev,
+        "cosine" => cosine,
+        _ => return Err(format!("Unknown metric for vectors: '{name}'.")),
+    };
+    Ok((metric, false))
+}
+
+/// Returns the named metric for strings and whether it is expensive to compute.
+///
+/// # Errors
+///
+/// * If there is no metric for strings with the given name.
+pub fn string_metric(name: &str) -> Result<(StringMetric, bool), String> {
+    match name {
+        "levenshtein" => Ok((levenshtein, true)),
+        "hamming" => Ok((hamming, false)),
+        "needleman_wunsch" => Ok((needleman_wunsch, true)),
+        _ => Err(format!("Unknown metric for strings: '{name}'.")),
+    }
+}
+
+/// Euclidean distance between two vectors.
+#[allow(clippy::ptr_arg)]
+fn euclidean(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::euclidean(x, y)
+}
+
+/// Manhattan distance between two vectors.
+#[allow(clippy::ptr_arg)]
+fn manhattan(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::manhattan(x, y)
+}
+
+/// Chebyshev distance between two vectors.
+#[allow(clippy::ptr_arg)]
+fn chebyshev(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::chebyshev(x, y)
+}
+
+/// Cosine distance between two vectors.
+#[allow(clippy::ptr_arg)]
+fn cosine(x: &Vec<f32>, y: &Vec<f32>) -> f32 {
+    distances::vectors::cosine(x, y)
+}
+
+/// Levenshtein distance between two strings.
+#[allow(clippy::ptr_arg)]
+fn levenshtein(x: &String, y: &String) -> u32 {
+    distances::strings::levenshtein(x, y)
+}
+
+/// Hamming distance between two strings.
+#[allow(clippy::ptr_arg)]
+fn hamming(x: &String, y: &String) -> u32 {
+    distances::strings::hamming(x, y)
+}
+
+/// Needleman-Wunsch distance between two strings.
+#[allow(clippy::ptr_arg)]
+fn needleman_wunsch(x: &String, y: &String) -> u32 {
+    distances::strings::needleman_wunsch::nw_distance(x, y)
+}
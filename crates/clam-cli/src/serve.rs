@@ -0,0 +1,351 @@
+//! A local HTTP server for searching an index built by the tool.
+//!
+//! The server answers JSON requests on these endpoints:
+//!
+//! * `GET /health`: whether the server is up.
+//! * `GET /info`: the metric, shard cardinalities and tuned algorithms.
+//! * `POST /knn`: KNN search, with a body like `{"queries": [...], "k": 10}`.
+//! * `POST /rnn`: RNN search, with a body like `{"queries": [...], "radius": 0.5}`.
+//! * `POST /reload`: loads the index again, or the index in the directory
+//!   given as `{"index": "path/to/index"}`, which must be under the reload
+//!   root.
+//!
+//! The queries are arrays of numbers for vectors and strings for strings. Both
+//! search endpoints take an optional `"algorithm"` name, and respond with
+//! `{"results": [[{"index", "id", "distance"}, ...], ...]}`, with one array of
+//! hits per query.
+//!
+//! Requests are handled by a pool of worker threads, and each batch of queries
+//! is searched in parallel with rayon. A reload builds the new index before
+//! swapping it in, so requests which are in flight finish with the old index.
+//! Request bodies are limited to `MAX_BODY_BYTES`, `k` is clamped to the
+//! cardinality of the index, and a request which panics gets a "500 Internal
+//! Server Error" without taking down its worker.
+
+use std::{
+    io::{Read, Write},
+    panic::AssertUnwindSafe,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+};
+
+use abd_clam::{Cakes, Instance};
+use distances::Number;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    commands::{self, Data, Mode},
+    manifest::Manifest,
+    metrics::{self, Kind},
+    ServeArgs,
+};
+
+/// An error to send back, with its HTTP status code.
+type HttpError = (u16, String);
+
+/// The maximum number of bytes in a request body.
+const MAX_BODY_BYTES: u64 = 16 << 20;
+
+/// The state shared by the workers.
+struct State {
+    /// The index which is currently being served.
+    loaded: RwLock<Arc<Loaded>>,
+    /// The canonical directory under which `/reload` may load indices.
+    reload_root: PathBuf,
+}
+
+/// A loaded index, in the directory it was loaded from.
+struct Loaded {
+    /// The directory of the index.
+    dir: PathBuf,
+    /// The manifest of the index.
+    manifest: Manifest,
+    /// The index itself.
+    index: Index,
+    /// The ids of the instances in the index, by the indices used in hits.
+    ids: Vec<String>,
+}
+
+/// An index of either kind of instances.
+enum Index {
+    /// An index of vectors.
+    Vectors(Cakes<Vec<f32>, f32, Data<Vec<f32>, f32>>),
+    /// An index of strings.
+    Strings(Cakes<String, u32, Data<String, u32>>),
+}
+
+impl Loaded {
+    /// Loads the index in the given directory.
+    fn load(dir: &Path) -> Result<Self, String> {
+        let manifest = commands::load_manifest(dir, false)?;
+        let (index, ids) = match manifest.kind {
+            Kind::Vectors => {
+                let (metric, is_expensive) = metrics::vector_metric(&manifest.metric)?;
+                let cakes = Cakes::load(dir, metric, is_expensive)?;
+                let ids = owned_ids(&cakes);
+                (Index::Vectors(cakes), ids)
+            }
+            Kind::Strings => {
+                let (metric, is_expensive) = metrics::string_metric(&manifest.metric)?;
+                let cakes = Cakes::load(dir, metric, is_expensive)?;
+                let ids = owned_ids(&cakes);
+                (Index::Strings(cakes), ids)
+            }
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+            index,
+            ids,
+        })
+    }
+
+    /// Describes the index.
+    fn info(&self) -> Value {
+        let mut info = match &self.index {
+            Index::Vectors(cakes) => cakes_info(cakes),
+            Index::Strings(cakes) => cakes_info(cakes),
+        };
+        info["index"] = json!(self.dir.display().to_string());
+        info["kind"] = json!(self.manifest.kind);
+        info["metric"] = json!(self.manifest.metric);
+        info
+    }
+
+    /// Searches the index for the queries in a request body.
+    fn search(
+        &self,
+        queries: &Value,
+        mode: Mode,
+        algorithm: Option<&str>,
+    ) -> Result<Value, String> {
+        let results = match &self.index {
+            Index::Vectors(cakes) => search(
+                cakes,
+                &self.ids,
+                parse_queries(queries, "arrays of numbers")?,
+                mode,
+                algorithm,
+            )?,
+            Index::Strings(cakes) => search(
+                cakes,
+                &self.ids,
+                parse_queries(queries, "strings")?,
+                mode,
+                algorithm,
+            )?,
+        };
+        Ok(json!({ "results": results }))
+    }
+}
+
+/// Serves the index until the process is stopped.
+pub fn serve(args: &ServeArgs) -> Result<(), String> {
+    let reload_root = args.reload_root.as_ref().unwrap_or(&args.index);
+    let state = State {
+        loaded: RwLock::new(Arc::new(Loaded::load(&args.index)?)),
+        reload_root: reload_root
+            .canonicalize()
+            .map_err(|e| format!("Invalid reload root '{}': {e}", reload_root.display()))?,
+    };
+
+    let server = Server::http(&args.addr)
+        .map_err(|e| format!("Could not listen on '{}': {e}", args.addr))?;
+    let addr = server
+        .server_addr()
+        .to_ip()
+        .ok_or("The server is not listening on an IP address.")?;
+    // Tests and scripts read this line to find the port when it was chosen
+    // by the OS.
+    println!("listening on http://{addr}");
+    std::io::stdout().flush().map_err(|e| e.to_string())?;
+
+    let workers = args.workers.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, core::num::NonZeroUsize::get)
+    });
+    std::thread::scope(|scope| {
+        for _ in 0..workers.max(1) {
+            scope.spawn(|| {
+                for request in server.incoming_requests() {
+                    respond(&state, request);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+/// Handles a request and sends the response.
+fn respond(state: &State, mut request: Request) {
+    // A panic in a search must not take down the worker, so it is turned into
+    // an error response.
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| route(state, &mut request)))
+        .unwrap_or_else(|_| Err((500, "The request could not be handled.".to_string())));
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err((status, message)) => (status, json!({ "error": message })),
+    };
+    let header = Header::from_bytes("Content-Type", "application/json")
+        .unwrap_or_else(|()| unreachable!("the header is valid"));
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    // The client may have gone away, and there is no one else to tell.
+    let _ = request.respond(response);
+}
+
+/// Dispatches a request to its endpoint.
+fn route(state: &State, request: &mut Request) -> Result<Value, HttpError> {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    match (request.method(), path.as_str()) {
+        (Method::Get, "/health") => Ok(json!({ "status": "ok" })),
+        (Method::Get, "/info") => Ok(current(state).info()),
+        (Method::Post, "/knn") => {
+            let body = read_body(request)?;
+            let k = body["k"]
+                .as_u64()
+                .and_then(|k| usize::try_from(k).ok())
+                .ok_or_else(|| bad_request("`k` must be a non-negative integer."))?;
+            search_with(state, &body, Mode::Knn(k))
+        }
+        (Method::Post, "/rnn") => {
+            let body = read_body(request)?;
+            let radius = body["radius"]
+                .as_f64()
+                .filter(|r| *r >= 0.0)
+                .ok_or_else(|| bad_request("`radius` must be a non-negative number."))?;
+            search_with(state, &body, Mode::Rnn(radius))
+        }
+        (Method::Post, "/reload") => {
+            let body = read_body(request)?;
+            let dir = match body.get("index") {
+                Some(Value::String(dir)) => PathBuf::from(dir),
+                Some(_) => return Err(bad_request("`index` must be a path.")),
+                None => current(state).dir.clone(),
+            };
+            let dir = dir
+                .canonicalize()
+                .map_err(|e| bad_request(&format!("Invalid index '{}': {e}", dir.display())))?;
+            if !dir.starts_with(&state.reload_root) {
+                return Err((
+                    403,
+                    format!(
+                        "Indices may only be reloaded from under '{}'.",
+                        state.reload_root.display()
+                    ),
+                ));
+            }
+            // The new index is loaded before taking the lock, and the old
+            // index is kept if it cannot be loaded.
+            let loaded = Arc::new(Loaded::load(&dir).map_err(|e| bad_request(&e))?);
+            let info = loaded.info();
+            *state.loaded.write().unwrap_or_else(PoisonError::into_inner) = loaded;
+            Ok(info)
+        }
+        (_, "/health" | "/info" | "/knn" | "/rnn" | "/reload") => Err((
+            405,
+            format!("Method {} is not allowed for {path}.", request.method()),
+        )),
+        _ => Err((404, format!("No endpoint at {path}."))),
+    }
+}
+
+/// Returns the index which is currently being served.
+fn current(state: &State) -> Arc<Loaded> {
+    Arc::clone(&state.loaded.read().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// Runs the search in a request body on the current index.
+fn search_with(state: &State, body: &Value, mode: Mode) -> Result<Value, HttpError> {
+    let algorithm = match body.get("algorithm") {
+        Some(Value::String(name)) => Some(name.as_str()),
+        Some(_) => return Err(bad_request("`algorithm` must be a name.")),
+        None => None,
+    };
+    current(state)
+        .search(&body["queries"], mode, algorithm)
+        .map_err(|e| bad_request(&e))
+}
+
+/// Reads a JSON request body, treating an empty body as an empty object.
+fn read_body(request: &mut Request) -> Result<Value, HttpError> {
+    let too_large = || {
+        (
+            413,
+            format!("The request body is larger than {MAX_BODY_BYTES} bytes."),
+        )
+    };
+    if request
+        .body_length()
+        .is_some_and(|len| len as u64 > MAX_BODY_BYTES)
+    {
+        return Err(too_large());
+    }
+    // The declared length may be missing, e.g. for chunked bodies.
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_string(&mut body)
+        .map_err(|e| bad_request(&e.to_string()))?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(too_large());
+    }
+    if body.trim().is_empty() {
+        Ok(json!({}))
+    } else {
+        serde_json::from_str(&body).map_err(|e| bad_request(&format!("Invalid JSON: {e}")))
+    }
+}
+
+/// Returns a "400 Bad Request" error.
+fn bad_request(message: &str) -> HttpError {
+    (400, message.to_string())
+}
+
+/// Parses the queries in a request body.
+fn parse_queries<I: serde::de::DeserializeOwned>(
+    queries: &Value,
+    expected: &str,
+) -> Result<Vec<I>, String> {
+    serde_json::from_value(queries.clone())
+        .map_err(|_| format!("`queries` must be an array of {expected}."))
+}
+
+/// Returns copies of the ids of the instances in CAKES, so that they are
+/// collected only once per loaded index rather than once per request.
+fn owned_ids<I: Instance, U: Number>(cakes: &Cakes<I, U, Data<I, U>>) -> Vec<String> {
+    commands::ids_of(cakes).into_iter().cloned().collect()
+}
+
+/// Searches CAKES and converts the hits to JSON.
+fn search<I: Instance, U: Number>(
+    cakes: &Cakes<I, U, Data<I, U>>,
+    ids: &[String],
+    queries: Vec<I>,
+    mode: Mode,
+    algorithm: Option<&str>,
+) -> Result<Vec<Vec<Value>>, String> {
+    let hits = commands::search_cakes(cakes, &queries, mode, algorithm)?;
+    Ok(hits
+        .iter()
+        .map(|hits| commands::hits_to_json(ids, hits))
+        .collect())
+}
+
+/// Describes CAKES.
+fn cakes_info<I: Instance, U: Number>(cakes: &Cakes<I, U, Data<I, U>>) -> Value {
+    json!({
+        "cardinality": cakes.total_cardinality(),
+        "shard_cardinalities": cakes.shard_cardinalities(),
+        "tuned_knn_algorithm": cakes.tuned_knn_algorithm().name(),
+        "tuned_rnn_algorithm": cakes.tuned_rnn_algorithm().name(),
+    })
+}
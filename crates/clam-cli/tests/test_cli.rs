@@ -0,0 +1,248 @@
+//! Tests for the `clam` command-line tool.
+
+mod utils;
+
+use utils::{clam, rows, s, sequences, write_csv, write_fasta};
+
+#[test]
+fn vectors() {
+    let dir = tempdir::TempDir::new("clam-cli").unwrap();
+    let (data, queries, index) = (
+        dir.path().join("data.csv"),
+        dir.path().join("queries.csv"),
+        dir.path().join("index"),
+    );
+
+    let rows = rows(300, 3);
+    write_csv(&data, &rows);
+    write_csv(&queries, &rows[..2]);
+
+    clam(&[
+        "build",
+        s(&data),
+        s(&index),
+        "--metric",
+        "euclidean",
+        "--min-cardinality",
+        "4",
+        "--header",
+    ])
+    .unwrap();
+    assert!(index.join("clam.json").exists());
+
+    // The nearest neighbor of each query is the row it was copied from.
+    let csv = clam(&["search", s(&index), s(&queries), "--k", "3", "--header"]).unwrap();
+    let lines = csv.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "query,index,id,distance");
+    assert_eq!(lines.len(), 1 + 2 * 3);
+    for (q, line) in [(0, lines[1]), (1, lines[4])] {
+        let fields = line.split(',').collect::<Vec<_>>();
+        assert_eq!(fields[0], q.to_string());
+        assert_eq!(fields[2], q.to_string());
+        assert_eq!(fields[3], "0");
+    }
+
+    let json = clam(&[
+        "search",
+        s(&index),
+        s(&queries),
+        "--radius",
+        "0.1",
+        "--format",
+        "json",
+        "--header",
+    ])
+    .unwrap();
+    let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let results = json.as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    for result in results {
+        let hits = result["hits"].as_array().unwrap();
+        assert!(hits.iter().any(|h| h["id"] == result["query"]));
+        assert!(hits.iter().all(|h| h["distance"].as_f64().unwrap() <= 0.1));
+    }
+
+    let tuned = clam(&[
+        "tune",
+        s(&index),
+        "--k",
+        "5",
+        "--radius",
+        "0.1",
+        "--depth",
+        "4",
+    ])
+    .unwrap();
+    assert!(tuned.contains("knn k=5: "));
+    assert!(tuned.contains("rnn radius=0.1: "));
+
+    let info = clam(&["info", s(&index)]).unwrap();
+    assert!(info.contains("cardinality: 300"));
+    assert!(info.contains("shards: 1"));
+
+    // Sharded indices from npy files give the same nearest neighbors.
+    let npy = dir.path().join("data.npy");
+    let header = "{'descr': '<f4', 'fortran_order': False, 'shape': (300, 3), }";
+    let header = format!("{header:<117}\n");
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&u16::try_from(header.len()).unwrap().to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend(rows.iter().flatten().flat_map(|v| v.to_le_bytes()));
+    std::fs::write(&npy, bytes).unwrap();
+
+    let sharded = dir.path().join("sharded");
+    clam(&[
+        "build",
+        s(&npy),
+        s(&sharded),
+        "--metric",
+        "euclidean",
+        "--max-shard-cardinality",
+        "100",
+    ])
+    .unwrap();
+    assert!(clam(&["info", s(&sharded)]).unwrap().contains("shards: 3"));
+    let hits = clam(&[
+        "search",
+        s(&sharded),
+        s(&queries),
+        "--k",
+        "1",
+        "--algorithm",
+        "linear",
+        "--header",
+    ])
+    .unwrap();
+    let ids = hits
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(2).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(ids, ["0", "1"]);
+
+    // The metric must match the kind of the data.
+    let err = clam(&["build", s(&data), s(&index), "--metric", "levenshtein"]).unwrap_err();
+    assert!(err.contains("levenshtein"));
+
+    // Squared euclidean distance is not a metric.
+    let err = clam(&["build", s(&data), s(&index), "--metric", "euclidean_sq"]).unwrap_err();
+    assert!(err.contains("Unknown metric"));
+
+    // A header is only skipped when asked for.
+    let err = clam(&["build", s(&data), s(&index), "--metric", "euclidean"]).unwrap_err();
+    assert!(err.contains("Line 1") && err.contains("--header"));
+
+    // The shape of an npy file may not overflow the number of bytes.
+    let header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}, 4), }}",
+        usize::MAX / 2
+    );
+    let header = format!("{header:<117}\n");
+    let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+    bytes.extend_from_slice(&u16::try_from(header.len()).unwrap().to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    std::fs::write(&npy, bytes).unwrap();
+    let err = clam(&["build", s(&npy), s(&index), "--metric", "euclidean"]).unwrap_err();
+    assert!(err.contains("too large"));
+}
+
+#[test]
+fn output_dirs() {
+    let dir = tempdir::TempDir::new("clam-cli").unwrap();
+    let data = dir.path().join("data.fasta");
+    write_fasta(&data, &sequences(30));
+
+    // Directories which hold other files are not replaced without `--force`.
+    let output = dir.path().join("output");
+    std::fs::create_dir(&output).unwrap();
+    std::fs::write(output.join("notes.txt"), "keep me").unwrap();
+    for command in ["build", "compress"] {
+        let err = clam(&[command, s(&data), s(&output), "--metric", "levenshtein"]).unwrap_err();
+        assert!(err.contains("--force"));
+        assert!(output.join("notes.txt").exists());
+    }
+
+    clam(&["compress", s(&data), s(&output), "--force"]).unwrap();
+    assert!(!output.join("notes.txt").exists());
+
+    // Indices saved by the tool may be replaced.
+    clam(&["compress", s(&data), s(&output)]).unwrap();
+    let index = dir.path().join("index");
+    clam(&["build", s(&data), s(&index), "--metric", "levenshtein"]).unwrap();
+    clam(&["build", s(&data), s(&index), "--metric", "hamming"]).unwrap();
+}
+
+#[test]
+fn strings() {
+    let dir = tempdir::TempDir::new("clam-cli").unwrap();
+    let (data, queries) = (dir.path().join("data.fasta"), dir.path().join("queries.fa"));
+
+    let sequences = sequences(90);
+    write_fasta(&data, &sequences);
+    write_fasta(&queries, &sequences[..1]);
+
+    let index = dir.path().join("index");
+    clam(&["build", s(&data), s(&index), "--metric", "levenshtein"]).unwrap();
+    let hits = clam(&["search", s(&index), s(&queries), "--radius", "1"]).unwrap();
+    let mut ids = hits
+        .lines()
+        .skip(1)
+        .map(|l| l.split(',').nth(2).unwrap())
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, ["seq-0", "seq-1", "seq-2"]);
+
+    // Levenshtein distances are integers, so a fractional radius is rejected
+    // rather than truncated.
+    let error = clam(&["search", s(&index), s(&queries), "--radius", "1.5"]).unwrap_err();
+    assert!(error.contains("not a whole number"), "{error}");
+
+    // Compression is lossless and keeps the original order.
+    let compressed = dir.path().join("compressed");
+    let output = dir.path().join("output.fasta");
+    clam(&["compress", s(&data), s(&compressed)]).unwrap();
+    assert!(clam(&["info", s(&compressed)])
+        .unwrap()
+        .contains("cardinality: 90"));
+    clam(&["decompress", s(&compressed), s(&output)]).unwrap();
+
+    let expected = sequences
+        .iter()
+        .enumerate()
+        .map(|(i, s)| format!(">seq-{i}\n{s}\n"))
+        .collect::<String>();
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), expected);
+    assert!(clam(&["verify", s(&compressed), s(&data)])
+        .unwrap()
+        .contains("mismatches: 0"));
+
+    // Every leaf format is lossless.
+    for format in ["varint", "deflate-9"] {
+        let compressed = dir.path().join(format);
+        clam(&[
+            "compress",
+            s(&data),
+            s(&compressed),
+            "--leaf-format",
+            format,
+        ])
+        .unwrap();
+        let info = clam(&["info", s(&compressed)]).unwrap();
+        assert!(info.contains(&format!("leaf format: {format}")), "{info}");
+        assert!(clam(&["verify", s(&compressed), s(&data)])
+            .unwrap()
+            .contains("mismatches: 0"));
+    }
+    let unknown = dir.path().join("unknown");
+    assert!(clam(&["compress", s(&data), s(&unknown), "--leaf-format", "zip"]).is_err());
+
+    // Verification fails against a different file.
+    let changed = dir.path().join("changed.fasta");
+    let mut changed_sequences = sequences.clone();
+    changed_sequences[1].push('A');
+    write_fasta(&changed, &changed_sequences);
+    assert!(clam(&["verify", s(&compressed), s(&changed)]).is_err());
+
+    // Compressed datasets are not CAKES indices.
+    assert!(clam(&["search", s(&compressed), s(&queries), "--k", "1"]).is_err());
+}
@@ -0,0 +1,262 @@
+//! Tests for `clam serve`.
+
+mod utils;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+
+use serde_json::{json, Value};
+use utils::{clam, rows, s, sequences, write_csv, write_fasta};
+
+/// A running server, which is stopped when dropped.
+struct Server {
+    child: Child,
+    addr: String,
+}
+
+impl Server {
+    /// Starts serving the index on a port chosen by the OS, allowing reloads
+    /// from under `reload_root`.
+    fn start(index: &Path, reload_root: &Path) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_clam"))
+            .args(["serve", s(index), "--addr", "127.0.0.1:0", "--workers", "4"])
+            .args(["--reload-root", s(reload_root)])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        let addr = line
+            .trim()
+            .strip_prefix("listening on http://")
+            .unwrap()
+            .to_string();
+
+        Self { child, addr }
+    }
+
+    /// Sends a request and returns the status code and the JSON body of the
+    /// response.
+    fn request(&self, method: &str, path: &str, body: &Value) -> (u16, Value) {
+        let body = if body.is_null() {
+            String::new()
+        } else {
+            body.to_string()
+        };
+        let mut stream = TcpStream::connect(&self.addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            self.addr,
+            body.len()
+        )
+        .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let status = response[9..12].parse().unwrap();
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn get(&self, path: &str) -> (u16, Value) {
+        self.request("GET", path, &Value::Null)
+    }
+
+    fn post(&self, path: &str, body: &Value) -> (u16, Value) {
+        self.request("POST", path, body)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Returns the ids of the hits for each query.
+fn ids(results: &Value) -> Vec<Vec<String>> {
+    results["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|hits| {
+            hits.as_array()
+                .unwrap()
+                .iter()
+                .map(|h| h["id"].as_str().unwrap().to_string())
+                .collect()
+        })
+        .collect()
+}
+
+#[test]
+fn serve() {
+    let dir = tempdir::TempDir::new("clam-serve").unwrap();
+    let (vectors, strings) = (dir.path().join("vectors"), dir.path().join("strings"));
+
+    let rows = rows(300, 3);
+    let data = dir.path().join("data.csv");
+    write_csv(&data, &rows);
+    clam(&[
+        "build",
+        s(&data),
+        s(&vectors),
+        "--metric",
+        "euclidean",
+        "--header",
+    ])
+    .unwrap();
+
+    let sequences = sequences(60);
+    let data = dir.path().join("data.fasta");
+    write_fasta(&data, &sequences);
+    clam(&["build", s(&data), s(&strings), "--metric", "levenshtein"]).unwrap();
+
+    let server = Server::start(&vectors, dir.path());
+
+    assert_eq!(server.get("/health"), (200, json!({ "status": "ok" })));
+    let (status, info) = server.get("/info");
+    assert_eq!(status, 200);
+    assert_eq!(info["cardinality"], 300);
+    assert_eq!(info["shard_cardinalities"], json!([300]));
+    assert_eq!(info["kind"], "vectors");
+    assert_eq!(info["metric"], "euclidean");
+
+    // The nearest neighbor of each query is the row it was copied from.
+    let knn = json!({ "queries": [rows[0], rows[1]], "k": 3 });
+    let (status, results) = server.post("/knn", &knn);
+    assert_eq!(status, 200);
+    let knn_ids = ids(&results);
+    assert_eq!(knn_ids.len(), 2);
+    for (q, hits) in knn_ids.iter().enumerate() {
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0], q.to_string());
+        assert_eq!(results["results"][q][0]["distance"], 0.0);
+    }
+
+    // The tuned algorithm finds the same hits as linear search.
+    let rnn = json!({ "queries": [rows[2]], "radius": 0.2 });
+    let (status, tuned) = server.post("/rnn", &rnn);
+    assert_eq!(status, 200);
+    let mut linear = rnn.clone();
+    linear["algorithm"] = json!("linear");
+    let (status, linear) = server.post("/rnn", &linear);
+    assert_eq!(status, 200);
+    assert_eq!(ids(&tuned), ids(&linear));
+    assert!(ids(&tuned)[0].contains(&"2".to_string()));
+
+    // Concurrent requests all get the same answer.
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                for _ in 0..10 {
+                    let (status, results) = server.post("/knn", &knn);
+                    assert_eq!(status, 200);
+                    assert_eq!(ids(&results), knn_ids);
+                }
+            });
+        }
+    });
+
+    // Bad requests are rejected with an error.
+    assert_eq!(server.post("/knn", &json!({ "queries": [rows[0]] })).0, 400);
+    assert_eq!(
+        server
+            .post("/knn", &json!({ "queries": ["ACGT"], "k": 1 }))
+            .0,
+        400
+    );
+    assert_eq!(
+        server
+            .post("/rnn", &json!({ "queries": [rows[0]], "radius": -1.0 }))
+            .0,
+        400
+    );
+    let (status, error) = server.post(
+        "/knn",
+        &json!({ "queries": [rows[0]], "k": 1, "algorithm": "magic" }),
+    );
+    assert_eq!(status, 400);
+    assert!(error["error"].is_string());
+    assert_eq!(server.get("/knn").0, 405);
+
+    // Asking for more neighbors than there are instances gets all of them,
+    // instead of hanging the workers.
+    for _ in 0..4 {
+        let too_many = json!({ "queries": [rows[0]], "k": 1000, "algorithm": "repeatedrnn" });
+        let (status, results) = server.post("/knn", &too_many);
+        assert_eq!(status, 200);
+        assert_eq!(ids(&results)[0].len(), 300);
+    }
+    assert_eq!(server.get("/health").0, 200);
+
+    // Huge request bodies are rejected before they are read.
+    let huge = json!({ "queries": [vec![0.0; 5 << 20]], "k": 1 });
+    assert_eq!(server.post("/knn", &huge).0, 413);
+
+    // Indices outside of the reload root cannot be loaded.
+    let outside = tempdir::TempDir::new("clam-serve-outside").unwrap();
+    clam(&[
+        "build",
+        s(&data),
+        s(&outside.path().join("index")),
+        "--metric",
+        "levenshtein",
+    ])
+    .unwrap();
+    assert_eq!(
+        server
+            .post(
+                "/reload",
+                &json!({ "index": s(&outside.path().join("index")) })
+            )
+            .0,
+        403
+    );
+    assert_eq!(server.get("/nowhere").0, 404);
+
+    // Reloading switches to the new index, and a failed reload keeps the old one.
+    let (status, info) = server.post("/reload", &json!({ "index": s(&strings) }));
+    assert_eq!(status, 200);
+    assert_eq!(info["kind"], "strings");
+    assert_eq!(
+        server
+            .post(
+                "/reload",
+                &json!({ "index": s(&dir.path().join("missing")) })
+            )
+            .0,
+        400
+    );
+    assert_eq!(server.get("/info").1["cardinality"], 60);
+
+    // Searches keep working while the index is reloaded.
+    let knn = json!({ "queries": [sequences[3]], "k": 3 });
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            for _ in 0..5 {
+                assert_eq!(server.post("/reload", &Value::Null).0, 200);
+            }
+        });
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for _ in 0..10 {
+                    let (status, results) = server.post("/knn", &knn);
+                    assert_eq!(status, 200);
+                    let mut hits = ids(&results).remove(0);
+                    hits.sort_unstable();
+                    assert_eq!(hits, ["seq-3", "seq-4", "seq-5"]);
+                }
+            });
+        }
+    });
+}
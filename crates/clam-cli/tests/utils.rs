@@ -0,0 +1,83 @@
+#![allow(dead_code)]
+
+//! Utility functions for tests.
+
+use std::{path::Path, process::Command};
+
+/// Runs `clam` with the given arguments, returning its stdout or its stderr.
+pub fn clam(args: &[&str]) -> Result<String, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_clam"))
+        .args(args)
+        .output()
+        .map_err(|e| e.to_string())?;
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).to_string())
+    }
+}
+
+/// Returns the path as a `&str`.
+pub fn s(path: &Path) -> &str {
+    path.to_str()
+        .unwrap_or_else(|| unreachable!("temporary paths are valid UTF-8"))
+}
+
+/// Deterministic rows of numbers, for the vector datasets.
+pub fn rows(cardinality: usize, dimensionality: usize) -> Vec<Vec<f32>> {
+    (0..cardinality)
+        .map(|i| {
+            (0..dimensionality)
+                .map(|j| ((i * 7919 + j * 104_729) % 1000) as f32 / 1000.0)
+                .collect()
+        })
+        .collect()
+}
+
+/// Deterministic DNA sequences, in groups of three near-duplicates.
+pub fn sequences(cardinality: usize) -> Vec<String> {
+    let alphabet = ['A', 'C', 'G', 'T'];
+    (0..cardinality)
+        .map(|i| {
+            let mut state = (i / 3) as u64;
+            let mut s = (0..40)
+                .map(|_| {
+                    state = state
+                        .wrapping_mul(6_364_136_223_846_793_005)
+                        .wrapping_add(1_442_695_040_888_963_407);
+                    alphabet[(state >> 62) as usize]
+                })
+                .collect::<String>();
+            // The second of each group has one substitution and the third is
+            // a copy of the first.
+            if i % 3 == 1 {
+                s.replace_range(i % 40..=i % 40, "A");
+            }
+            s
+        })
+        .collect()
+}
+
+/// Writes the rows to a CSV file, with a header.
+pub fn write_csv(path: &Path, rows: &[Vec<f32>]) {
+    let lines = rows
+        .iter()
+        .map(|r| {
+            r.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        })
+        .collect::<Vec<_>>();
+    std::fs::write(path, format!("x,y,z\n{}\n", lines.join("\n"))).unwrap();
+}
+
+/// Writes the sequences to a FASTA file, with ids `seq-{i}`.
+pub fn write_fasta(path: &Path, sequences: &[String]) {
+    let records = sequences
+        .iter()
+        .enumerate()
+        .map(|(i, s)| format!(">seq-{i}\n{}\n{}\n", &s[..20], &s[20..]))
+        .collect::<String>();
+    std::fs::write(path, records).unwrap();
+}
@@ -0,0 +1,156 @@
+//! Cheap lower bounds on the string distance metrics.
+//!
+//! These bounds may be computed in linear time, and so they can be used to
+//! rule out candidates before computing an expensive metric such as
+//! `levenshtein` or `nw_distance`. `hamming` is itself computed in linear
+//! time, and so there is no need for a bound on it.
+
+use crate::number::UInt;
+
+/// Computes the difference in length between two strings.
+///
+/// This is a lower bound on the `levenshtein` and `nw_distance` (with the
+/// default penalties) distances, because each insertion or deletion changes
+/// the length of a string by one and substitutions do not change the length.
+///
+/// # Arguments
+///
+/// * `x`: The first string.
+/// * `y`: The second string.
+///
+/// # Examples
+///
+/// ```
+/// use distances::strings::{levenshtein, lower_bounds::length_difference};
+///
+/// let x = "NAJIBEATSPEPPERS";
+/// let y = "NAJIBEATS";
+///
+/// let bound: u16 = length_difference(x, y);
+/// assert_eq!(bound, 7);
+///
+/// let distance: u16 = levenshtein(x, y);
+/// assert!(bound <= distance);
+/// ```
+#[must_use]
+pub fn length_difference<U: UInt>(x: &str, y: &str) -> U {
+    let (x, y) = (x.chars().count(), y.chars().count());
+    U::from(x.abs_diff(y))
+}
+
+/// Computes a lower bound on the edit distance between two strings from the
+/// L1 distance between their character histograms.
+///
+/// This is a lower bound on the `levenshtein` and `nw_distance` (with the
+/// default penalties) distances. An insertion or deletion changes the L1
+/// distance between the histograms by at most one and changes the difference
+/// in length by exactly one, while a substitution changes the L1 distance by
+/// at most two and does not change the length. The edit distance is thus at
+/// least half of the sum of the L1 distance and the difference in length.
+///
+/// This bound is never smaller than `length_difference`.
+///
+/// # Arguments
+///
+/// * `x`: The first string.
+/// * `y`: The second string.
+///
+/// # Examples
+///
+/// ```
+/// use distances::strings::{levenshtein, lower_bounds::char_histogram};
+///
+/// let x = "NAJIBEATSPEPPERS";
+/// let y = "NAJIBPEPPERSEATS";
+///
+/// // The strings are anagrams, so the histograms are identical.
+/// let bound: u16 = char_histogram(x, y);
+/// assert_eq!(bound, 0);
+///
+/// let x = "AAAACCCC";
+/// let y = "GGGGTTTT";
+///
+/// let bound: u16 = char_histogram(x, y);
+/// assert_eq!(bound, 8);
+///
+/// let distance: u16 = levenshtein(x, y);
+/// assert!(bound <= distance);
+/// ```
+#[must_use]
+pub fn char_histogram<U: UInt>(x: &str, y: &str) -> U {
+    let l1 = histogram_l1(x, y);
+    let length_difference = x.chars().count().abs_diff(y.chars().count());
+    // The sum is always even, because both terms have the same parity as the
+    // sum of the lengths of the strings. The L1 distance is never smaller than
+    // the difference in lengths, so this is their mean without overflowing.
+    U::from(length_difference + (l1 - length_difference) / 2)
+}
+
+/// Computes the L1 distance between the character histograms of two strings.
+fn histogram_l1(x: &str, y: &str) -> usize {
+    if x.is_ascii() && y.is_ascii() {
+        let mut counts = [0_isize; 128];
+        x.bytes().for_each(|b| counts[usize::from(b)] += 1);
+        y.bytes().for_each(|b| counts[usize::from(b)] -= 1);
+        counts.iter().map(|c| c.unsigned_abs()).sum()
+    } else {
+        let mut x = x.chars().collect::<Vec<_>>();
+        let mut y = y.chars().collect::<Vec<_>>();
+        x.sort_unstable();
+        y.sort_unstable();
+
+        // Count the characters in common by merging the sorted characters.
+        let (mut i, mut j, mut common) = (0, 0, 0);
+        while i < x.len() && j < y.len() {
+            match x[i].cmp(&y[j]) {
+                core::cmp::Ordering::Less => i += 1,
+                core::cmp::Ordering::Greater => j += 1,
+                core::cmp::Ordering::Equal => {
+                    common += 1;
+                    i += 1;
+                    j += 1;
+                }
+            }
+        }
+        x.len() + y.len() - 2 * common
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::prelude::*;
+
+    use super::{char_histogram, length_difference};
+    use crate::strings::{levenshtein, nw_distance};
+
+    #[test]
+    fn bounds() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+        let alphabets = ["ACGT", "ACGTÅ∂ƒ"];
+        for alphabet in alphabets {
+            let alphabet = alphabet.chars().collect::<Vec<_>>();
+            let random_string = |rng: &mut StdRng| {
+                let len = rng.gen_range(0..20);
+                (0..len)
+                    .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                    .collect::<String>()
+            };
+
+            for _ in 0..1000 {
+                let (x, y) = (random_string(&mut rng), random_string(&mut rng));
+                let length: u32 = length_difference(&x, &y);
+                let histogram: u32 = char_histogram(&x, &y);
+                assert!(length <= histogram, "{x} {y}");
+
+                let distance: u32 = nw_distance(&x, &y);
+                assert!(histogram <= distance, "{x} {y}");
+
+                // `levenshtein` counts bytes rather than characters.
+                if x.is_ascii() && y.is_ascii() {
+                    let distance: u32 = levenshtein(&x, &y);
+                    assert!(histogram <= distance, "{x} {y}");
+                }
+            }
+        }
+    }
+}
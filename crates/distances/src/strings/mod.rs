@@ -4,6 +4,7 @@
 
 use crate::number::UInt;
 
+pub mod lower_bounds;
 pub mod needleman_wunsch;
 
 pub use needleman_wunsch::{_x_to_y, apply_edits, nw_distance, unaligned_x_to_y, Edit};
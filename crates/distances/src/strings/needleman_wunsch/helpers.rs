@@ -50,7 +50,8 @@ pub fn compute_table<U: UInt>(
     penalties: Penalties<U>,
 ) -> Vec<Vec<(U, Direction)>> {
     // Initializing table; the inner vectors represent rows in the table.
-    let mut table = vec![vec![(U::zero(), Direction::Diagonal); x.len() + 1]; y.len() + 1];
+    let (x_len, y_len) = (x.chars().count(), y.chars().count());
+    let mut table = vec![vec![(U::zero(), Direction::Diagonal); x_len + 1]; y_len + 1];
 
     // The top-left cell starts with a total penalty of zero and no direction.
     table[0][0] = (U::zero(), Direction::Diagonal);
@@ -116,7 +117,7 @@ pub fn trace_back_iterative<U: UInt>(
     table: &[Vec<(U, Direction)>],
     [x, y]: [&str; 2],
 ) -> (String, String) {
-    let (x, y) = (x.as_bytes(), y.as_bytes());
+    let (x, y) = (x.chars().collect::<Vec<_>>(), y.chars().collect::<Vec<_>>());
 
     let (mut row_i, mut col_i) = (y.len(), x.len());
     let (mut aligned_x, mut aligned_y) = (Vec::new(), Vec::new());
@@ -131,11 +132,11 @@ pub fn trace_back_iterative<U: UInt>(
             }
             Direction::Left => {
                 aligned_x.push(x[col_i - 1]);
-                aligned_y.push(b'-');
+                aligned_y.push('-');
                 col_i -= 1;
             }
             Direction::Up => {
-                aligned_x.push(b'-');
+                aligned_x.push('-');
                 aligned_y.push(y[row_i - 1]);
                 row_i -= 1;
             }
@@ -145,12 +146,10 @@ pub fn trace_back_iterative<U: UInt>(
     aligned_x.reverse();
     aligned_y.reverse();
 
-    let aligned_x = String::from_utf8(aligned_x)
-        .unwrap_or_else(|_| unreachable!("We know we added valid characters."));
-    let aligned_y = String::from_utf8(aligned_y)
-        .unwrap_or_else(|_| unreachable!("We know we added valid characters."));
-
-    (aligned_x, aligned_y)
+    (
+        aligned_x.into_iter().collect(),
+        aligned_y.into_iter().collect(),
+    )
 }
 
 /// Recursively traces back through the Needleman-Wunsch table to get the alignment of two sequences.
@@ -172,24 +171,23 @@ pub fn trace_back_recursive<U: UInt>(
     table: &[Vec<(U, Direction)>],
     [x, y]: [&str; 2],
 ) -> (String, String) {
+    let (x, y) = (x.chars().collect::<Vec<_>>(), y.chars().collect::<Vec<_>>());
     let (mut aligned_x, mut aligned_y) = (Vec::new(), Vec::new());
 
     _trace_back_recursive(
         table,
         [y.len(), x.len()],
-        [x.as_bytes(), y.as_bytes()],
+        [&x, &y],
         [&mut aligned_x, &mut aligned_y],
     );
 
     aligned_x.reverse();
     aligned_y.reverse();
 
-    let aligned_x = String::from_utf8(aligned_x)
-        .unwrap_or_else(|_| unreachable!("We know we added valid characters."));
-    let aligned_y = String::from_utf8(aligned_y)
-        .unwrap_or_else(|_| unreachable!("We know we added valid characters."));
-
-    (aligned_x, aligned_y)
+    (
+        aligned_x.into_iter().collect(),
+        aligned_y.into_iter().collect(),
+    )
 }
 
 /// Helper function for `trace_back_recursive`.
@@ -198,14 +196,14 @@ pub fn trace_back_recursive<U: UInt>(
 ///
 /// * `table`: The Needleman-Wunsch table.
 /// * `[row_i, col_i]`: mutable indices into the table.
-/// * `[x, y]`: The two sequences to align, passed as slices of bytes.
+/// * `[x, y]`: The two sequences to align, passed as slices of characters.
 /// * `[aligned_x, aligned_y]`: mutable aligned sequences that will be built
 /// up from initially empty vectors.
 fn _trace_back_recursive<U: UInt>(
     table: &[Vec<(U, Direction)>],
     [mut row_i, mut col_i]: [usize; 2],
-    [x, y]: [&[u8]; 2],
-    [aligned_x, aligned_y]: [&mut Vec<u8>; 2],
+    [x, y]: [&[char]; 2],
+    [aligned_x, aligned_y]: [&mut Vec<char>; 2],
 ) {
     if row_i > 0 || col_i > 0 {
         match table[row_i][col_i].1 {
@@ -217,11 +215,11 @@ fn _trace_back_recursive<U: UInt>(
             }
             Direction::Left => {
                 aligned_x.push(x[col_i - 1]);
-                aligned_y.push(b'-');
+                aligned_y.push('-');
                 col_i -= 1;
             }
             Direction::Up => {
-                aligned_x.push(b'-');
+                aligned_x.push('-');
                 aligned_y.push(y[row_i - 1]);
                 row_i -= 1;
             }
@@ -9,7 +9,7 @@ use crate::number::UInt;
 
 pub use helpers::{
     _x_to_y, apply_edits, compute_edits, compute_table, trace_back_iterative, trace_back_recursive,
-    unaligned_x_to_y, Edit,
+    unaligned_x_to_y, Direction, Edit,
 };
 
 /// Use a custom set of penalties to create a function to that calculates the
@@ -27,7 +27,7 @@ pub use helpers::{
 ///
 /// A function with the same signature as `nw_distance`.
 pub fn nw_distance_custom<U: UInt>(penalties: Penalties<U>) -> impl Fn(&str, &str) -> U {
-    move |x: &str, y: &str| compute_table(x, y, penalties)[y.len()][x.len()].0
+    move |x: &str, y: &str| compute_table(x, y, penalties)[y.chars().count()][x.chars().count()].0
 }
 
 /// Calculate the edit distance between two strings using Needleman-Wunsch table.
@@ -43,7 +43,7 @@ pub fn nw_distance_custom<U: UInt>(penalties: Penalties<U>) -> impl Fn(&str, &st
 /// * `y`: unaligned sequence represented as a `String`
 #[must_use]
 pub fn nw_distance<U: UInt>(x: &str, y: &str) -> U {
-    compute_table(x, y, Penalties::default())[y.len()][x.len()].0
+    compute_table(x, y, Penalties::default())[y.chars().count()][x.chars().count()].0
 }
 
 /// Use a custom set of penalties to create a function to that calculates the
@@ -68,7 +68,7 @@ pub fn edits_recursive_custom<U: UInt>(
                 unaligned_x_to_y(&aligned_x, &aligned_y),
                 unaligned_x_to_y(&aligned_y, &aligned_x),
             ],
-            table[y.len()][x.len()].0,
+            table[y.chars().count()][x.chars().count()].0,
         )
     }
 }
@@ -99,7 +99,7 @@ pub fn edits_recursive<U: UInt>(x: &str, y: &str) -> ([Vec<Edit>; 2], U) {
             unaligned_x_to_y(&aligned_x, &aligned_y),
             unaligned_x_to_y(&aligned_y, &aligned_x),
         ],
-        table[y.len()][x.len()].0,
+        table[y.chars().count()][x.chars().count()].0,
     )
 }
 
@@ -125,7 +125,7 @@ pub fn edits_iterative_custom<U: UInt>(
                 unaligned_x_to_y(&aligned_x, &aligned_y),
                 unaligned_x_to_y(&aligned_y, &aligned_x),
             ],
-            table[y.len()][x.len()].0,
+            table[y.chars().count()][x.chars().count()].0,
         )
     }
 }
@@ -156,7 +156,7 @@ pub fn edits_iterative<U: UInt>(x: &str, y: &str) -> ([Vec<Edit>; 2], U) {
             unaligned_x_to_y(&aligned_x, &aligned_y),
             unaligned_x_to_y(&aligned_y, &aligned_x),
         ],
-        table[y.len()][x.len()].0,
+        table[y.chars().count()][x.chars().count()].0,
     )
 }
 
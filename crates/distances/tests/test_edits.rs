@@ -1,4 +1,6 @@
-use distances::strings::{Edit, _x_to_y, apply_edits, unaligned_x_to_y};
+use distances::strings::{
+    Edit, _x_to_y, apply_edits, needleman_wunsch::edits_recursive, nw_distance, unaligned_x_to_y,
+};
 
 #[test]
 fn tiny_aligned() {
@@ -74,3 +76,16 @@ fn test_apply_edits() {
 
     assert_eq!(actual, expected);
 }
+
+#[test]
+fn unicode_edits() {
+    let x = "naïve café";
+    let y = "naive cafés";
+
+    assert_eq!(nw_distance::<u16>(x, y), 2);
+
+    let ([x_to_y, y_to_x], distance) = edits_recursive::<u16>(x, y);
+    assert_eq!(distance, 2);
+    assert_eq!(apply_edits(x, &x_to_y), y);
+    assert_eq!(apply_edits(y, &y_to_x), x);
+}
@@ -2,7 +2,9 @@ use std::io::Write;
 use std::path::Path;
 
 use abd_clam::{
-    pancakes::{decode_general, encode_general, rnn, CodecData, SquishyBall},
+    pancakes::{
+        decode_general, encode_general, rnn, CodecBuilder, CodecData, LeafFormat, SquishyBall,
+    },
     Cakes, Cluster, Dataset, PartitionCriteria, VecDataset,
 };
 use distances::{strings::Penalties, Number};
@@ -230,16 +232,16 @@ fn main() -> Result<(), String> {
         );
 
         let cakes_tree = cakes.trees()[0];
-        let dataset = cakes_tree.data();
+        let original = cakes_tree.data();
         let root = cakes_tree.root().clone();
         let tree_size = root.subtree().len();
-        let root = SquishyBall::from_base_tree(root, dataset);
+        let root = SquishyBall::from_base_tree(root, original);
 
         let compression_time = std::time::Instant::now();
-        let metadata = dataset.metadata().to_vec();
+        let metadata = original.metadata().to_vec();
         let dataset = CodecData::new(
-            root,
-            dataset,
+            root.clone(),
+            original,
             encode_general::<u16>,
             decode_general,
             metadata,
@@ -251,6 +253,20 @@ fn main() -> Result<(), String> {
         let compression_time = compression_time.elapsed().as_secs_f32();
         println!("Dataset compressed in {compression_time:.4}s");
 
+        // Compare the sizes of the leaf data with each layout, reusing the
+        // codec which was already built for the default layout.
+        print_leaf_sizes(&dataset);
+        for format in [
+            LeafFormat::Varint,
+            LeafFormat::Deflate(LeafFormat::DEFAULT_DEFLATE_LEVEL),
+        ] {
+            let metadata = original.metadata().to_vec();
+            let codec = CodecBuilder::new(encode_general::<u16>, decode_general, metadata)
+                .with_leaf_format(format)
+                .build(root.clone(), original)?;
+            print_leaf_sizes(&codec);
+        }
+
         // Reload the dataset and tree, and check that they are the same
         let decompression_time = std::time::Instant::now();
         let re_data = CodecData::<String, u16, String>::load(
@@ -263,6 +279,11 @@ fn main() -> Result<(), String> {
         let decompression_time = decompression_time.elapsed().as_secs_f32();
         println!("Dataset decompressed in {decompression_time:.4}s");
 
+        // Check that every instance round-trips through the reloaded dataset.
+        let report = re_data.verify(cakes_tree.data())?;
+        assert!(report.is_exact(), "Mismatches: {:?}", report.mismatches);
+        println!("Verified {} instances", report.num_checked);
+
         assert_eq!(dataset.root().subtree(), re_data.root().subtree());
         assert_eq!(dataset.centers(), re_data.centers());
         assert_eq!(dataset.metadata(), re_data.metadata());
@@ -341,10 +362,22 @@ fn main() -> Result<(), String> {
         let txt_size = expected_path.metadata().map_err(|e| e.to_string())?.len();
 
         let trimmed_size = dataset.root().compressible_subtree().len();
+        let stats = dataset.stats();
+
+        println!(
+            "Root: {}, Clusters: {tree_size}, Trimmed: {trimmed_size}, Squished: {}, Mean leaf depth: {:.2}, Max leaf depth: {}",
+            dataset.root().name(),
+            stats.num_squished,
+            stats.mean_leaf_depth(),
+            stats.max_leaf_depth()
+        );
 
         println!(
-            "Root: {}, Clusters: {tree_size}, Trimmed: {trimmed_size}",
-            dataset.root().name()
+            "Sizes: Raw: {}, Centers: {}, Leaves: {}, Compressed Factor: {:.2e}",
+            stats.raw_bytes.unwrap_or_default(),
+            stats.center_bytes,
+            stats.leaf_bytes,
+            stats.compression_factor().unwrap_or_default()
         );
 
         println!(
@@ -362,3 +395,21 @@ fn main() -> Result<(), String> {
 
     Ok(())
 }
+
+/// Prints the sizes of the leaf data of a codec with its leaf layout.
+///
+/// The factor is the stored size relative to the size of the headers and
+/// encodings before any compression of the leaf blocks.
+fn print_leaf_sizes(codec: &CodecData<String, u16, String>) {
+    if let Some(report) = codec.cost_report() {
+        let raw = report.header_total() + report.encodings_total();
+        println!(
+            "Leaf format: {}, Header: {}, Edits: {}, Stored: {}, Factor: {:.4}",
+            codec.leaf_format().name(),
+            report.header_total(),
+            report.encodings_total(),
+            report.stored_total(),
+            report.stored_total().as_f64() / raw.as_f64()
+        );
+    }
+}